@@ -0,0 +1,189 @@
+//! # Arpeggiator
+//!
+//! Play notes of a chord one by one on each clock trigger. Notes are MIDI pitches, either
+//! several signals on the stack or frames of a table, and they are repeated an octave higher
+//! for each of octaves (rounded, from 1 to `MAX_OCTAVES`). Output holds the current note, and
+//! follows chord changes between triggers.
+//!
+//! Sources to connect: notes of the chord (unless they are in a table), clock, octaves.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+
+const MAX_OCTAVES: Sample = 8.0;
+/// Table frames beyond that are not played.
+const MAX_TABLE_NOTES: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpPattern {
+    /// From the lowest note to the highest one.
+    Up,
+    Down,
+    /// Up and back down without repeating the highest and the lowest notes.
+    UpDown,
+    Random,
+    /// Notes in the order they are given.
+    Order,
+}
+
+pub struct Arp {
+    chord: Vec<Frame>,
+    /// Index of the current note in the sequence.
+    index: [usize; CHANNELS],
+    last_clock: Frame,
+    /// Sequence of the current channel, reused to avoid allocations on each frame.
+    notes: Vec<Sample>,
+    pattern: ArpPattern,
+    rng: SmallRng,
+    /// Triggers since the start, to find the place in the pattern.
+    step: [usize; CHANNELS],
+    table: Option<Arc<Mutex<Vec<Frame>>>>,
+}
+
+impl Arp {
+    /// Notes are taken from the table if there is one, otherwise that many from the stack.
+    pub fn new(pattern: ArpPattern, notes: usize, table: Option<Arc<Mutex<Vec<Frame>>>>) -> Self {
+        Arp::with_rng(pattern, notes, table, SmallRng::from_entropy())
+    }
+
+    /// Use it to get the same random pattern in each render.
+    pub fn with_rng(
+        pattern: ArpPattern,
+        notes: usize,
+        table: Option<Arc<Mutex<Vec<Frame>>>>,
+        rng: SmallRng,
+    ) -> Self {
+        Arp {
+            chord: vec![[0.0; CHANNELS]; if table.is_some() { 0 } else { notes }],
+            index: [0; CHANNELS],
+            last_clock: [0.0; CHANNELS],
+            notes: Vec::with_capacity(MAX_TABLE_NOTES * MAX_OCTAVES as usize),
+            pattern,
+            rng,
+            step: [0; CHANNELS],
+            table,
+        }
+    }
+}
+
+impl Op for Arp {
+    fn perform(&mut self, stack: &mut Stack) {
+        let octaves = stack.pop();
+        let clock = stack.pop();
+        for note in self.chord.iter_mut().rev() {
+            *note = stack.pop();
+        }
+        let table = self.table.as_ref().map(|table| table.lock().unwrap());
+        let mut frame = [0.0; CHANNELS];
+        for channel in 0..CHANNELS {
+            let notes = &mut self.notes;
+            notes.clear();
+            match &table {
+                Some(table) => notes.extend(
+                    table
+                        .iter()
+                        .take(MAX_TABLE_NOTES)
+                        .map(|frame| frame[channel]),
+                ),
+                None => notes.extend(self.chord.iter().map(|frame| frame[channel])),
+            }
+            notes.retain(|x| x.is_finite());
+            if self.pattern != ArpPattern::Order {
+                notes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            let chord = notes.len();
+            let octaves = if octaves[channel].is_finite() {
+                pure::clamp(octaves[channel].round(), 1.0, MAX_OCTAVES) as usize
+            } else {
+                1
+            };
+            for octave in 1..octaves {
+                for i in 0..chord {
+                    notes.push(notes[i] + 12.0 * octave as Sample);
+                }
+            }
+            let n = notes.len();
+            if n == 0 {
+                continue;
+            }
+            if self.last_clock[channel] <= 0.0 && clock[channel] > 0.0 {
+                let step = self.step[channel];
+                self.index[channel] = match self.pattern {
+                    ArpPattern::Up | ArpPattern::Order => step % n,
+                    ArpPattern::Down => n - 1 - step % n,
+                    ArpPattern::UpDown if n > 1 => {
+                        let i = step % (2 * n - 2);
+                        if i < n {
+                            i
+                        } else {
+                            2 * n - 2 - i
+                        }
+                    }
+                    ArpPattern::UpDown => 0,
+                    ArpPattern::Random => self.rng.gen_range(0, n),
+                };
+                self.step[channel] = step.wrapping_add(1);
+            }
+            frame[channel] = notes[self.index[channel] % n];
+        }
+        self.last_clock = clock;
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.index = other.index;
+            self.last_clock = other.last_clock;
+            self.step = other.step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+    use crate::Metro;
+
+    fn notes(chord: &[Sample], pattern: ArpPattern, octaves: Sample) -> Vec<Sample> {
+        let mut ops = chord.iter().map(|&x| constant(x)).collect::<Vec<_>>();
+        // Clock of a quarter of sample rate triggers on every other frame from the second.
+        ops.push(constant(24000.0));
+        ops.push(Box::new(Metro::new(48000)));
+        ops.push(constant(octaves));
+        let table = if chord.is_empty() {
+            Some(Arc::new(Mutex::new(vec![
+                [67.0; CHANNELS],
+                [60.0; CHANNELS],
+            ])))
+        } else {
+            None
+        };
+        let rng = SmallRng::seed_from_u64(0);
+        ops.push(Box::new(Arp::with_rng(pattern, chord.len(), table, rng)));
+        tops(&render(&mut ops, 16)).into_iter().step_by(2).collect()
+    }
+
+    #[test]
+    fn walks_the_chord() {
+        let chord = [64.0, 60.0, 67.0];
+        assert_eq!(
+            notes(&chord, ArpPattern::Up, 1.0),
+            [60.0, 60.0, 64.0, 67.0, 60.0, 64.0, 67.0, 60.0]
+        );
+        assert_eq!(
+            notes(&chord, ArpPattern::UpDown, 2.0),
+            [60.0, 60.0, 64.0, 67.0, 72.0, 76.0, 79.0, 76.0]
+        );
+        assert_eq!(
+            notes(&chord, ArpPattern::Order, 1.0),
+            [64.0, 64.0, 60.0, 67.0, 64.0, 60.0, 67.0, 64.0]
+        );
+        // Notes of the table.
+        assert_eq!(
+            notes(&[], ArpPattern::Down, 1.0),
+            [60.0, 67.0, 60.0, 67.0, 60.0, 67.0, 60.0, 67.0]
+        );
+    }
+}
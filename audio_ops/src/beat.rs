@@ -0,0 +1,220 @@
+//! # Beat
+//!
+//! Beat tracker to lock onto external material like a drummer or a DJ mix.
+//!
+//! Onset detection function is a half-wave rectified difference of log energy of consecutive
+//! hops. Tempo is the lag of the best peak of its autocorrelation weighted towards 120 BPM
+//! to resolve octave errors, and beat phase is the offset of a comb of that period which
+//! collects the most onsets, as in https://www.eecs.qmul.ac.uk/~markp/2007/DaviesPlumbley07-taslp.pdf
+//! Between analyses phase runs freely with the estimated tempo, so it's smooth and keeps going
+//! when input becomes silent.
+//!
+//! Sources to connect: input.
+//!
+//! Pushes tempo in Hz (beats per second) and then beat phase which goes from 0 to 1
+//! starting at each beat. When it syncs the program tempo, each estimate of tempo is also set
+//! as it, in beats per minute, so everything built on it follows the input.
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+/// Onset detection function frame size.
+const HOP: usize = 512;
+/// Seconds of onset detection function to analyze.
+const HISTORY: Sample = 6.0;
+/// Seconds between analyses.
+const ANALYSIS_PERIOD: Sample = 0.5;
+const MIN_BPM: Sample = 60.0;
+const MAX_BPM: Sample = 180.0;
+const PREFERRED_BPM: Sample = 120.0;
+/// Width of the tempo preference in octaves.
+const PREFERENCE_WIDTH: Sample = 1.0;
+/// How much of phase error is corrected on each analysis.
+const PHASE_CORRECTION: Sample = 0.5;
+/// Width of preference for the current tempo in octaves and for the current phase in periods,
+/// keeps estimates stable when the material is ambiguous, e.g. between half and double tempo.
+const TEMPO_CONTINUITY: Sample = 0.1;
+const PHASE_CONTINUITY: Sample = 0.1;
+
+pub struct Beat {
+    /// Onset detection function, circular.
+    odf: Vec<Sample>,
+    odf_index: usize,
+    /// Scratch for linearized onset detection function.
+    history: Vec<Sample>,
+    energy: Sample,
+    previous_log_energy: Sample,
+    hop_position: usize,
+    hops_to_analysis: usize,
+    analysis_period: usize,
+    /// Rate of onset detection function frames per second.
+    odf_rate: Sample,
+    sample_rate: Sample,
+    tempo: Sample,
+    phase: Sample,
+    /// Program tempo to set in beats per minute.
+    bpm: Option<Arc<Mutex<Sample>>>,
+}
+
+impl Beat {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let odf_rate = sample_rate / HOP as Sample;
+        let history_len = (HISTORY * odf_rate) as usize;
+        let analysis_period = ((ANALYSIS_PERIOD * odf_rate) as usize).max(1);
+        Beat {
+            odf: vec![0.0; history_len],
+            odf_index: 0,
+            history: vec![0.0; history_len],
+            energy: 0.0,
+            previous_log_energy: 0.0,
+            hop_position: 0,
+            hops_to_analysis: analysis_period,
+            analysis_period,
+            odf_rate,
+            sample_rate,
+            tempo: PREFERRED_BPM / 60.0,
+            phase: 0.0,
+            bpm: None,
+        }
+    }
+
+    /// Set the program tempo to the estimated one.
+    pub fn with_bpm(sample_rate: u32, bpm: Arc<Mutex<Sample>>) -> Self {
+        let mut beat = Beat::new(sample_rate);
+        beat.bpm = Some(bpm);
+        beat
+    }
+
+    fn analyze(&mut self) {
+        let n = self.odf.len();
+        // Oldest first.
+        for (i, x) in self.history.iter_mut().enumerate() {
+            *x = self.odf[(self.odf_index + i) % n];
+        }
+        let mean = self.history.iter().sum::<Sample>() / n as Sample;
+        if mean < 1e-6 {
+            // Nothing to track, keep going with the last estimate.
+            return;
+        }
+        for x in self.history.iter_mut() {
+            *x -= mean;
+        }
+
+        let min_lag = (self.odf_rate * 60.0 / MAX_BPM).floor() as usize;
+        let max_lag = ((self.odf_rate * 60.0 / MIN_BPM).ceil() as usize).min(n / 2);
+        let preferred_lag = self.odf_rate * 60.0 / PREFERRED_BPM;
+        let current_lag = self.odf_rate / self.tempo;
+        let history = &self.history;
+        let score = |lag: usize| {
+            let acf = history[lag..]
+                .iter()
+                .zip(history)
+                .map(|(x, y)| x * y)
+                .sum::<Sample>()
+                / (n - lag) as Sample;
+            let octaves = (lag as Sample / preferred_lag).log2() / PREFERENCE_WIDTH;
+            let change = (lag as Sample / current_lag).log2() / TEMPO_CONTINUITY;
+            acf * (-0.5 * octaves * octaves).exp() * (1.0 + (-0.5 * change * change).exp())
+        };
+        let mut best_lag = min_lag;
+        let mut best_score = Sample::NEG_INFINITY;
+        for lag in min_lag..=max_lag {
+            let s = score(lag);
+            if s > best_score {
+                best_score = s;
+                best_lag = lag;
+            }
+        }
+        if best_score <= 0.0 {
+            return;
+        }
+        // Refine lag between ODF frames.
+        let mut lag = best_lag as Sample;
+        if best_lag > min_lag && best_lag < max_lag {
+            let s0 = score(best_lag - 1);
+            let s2 = score(best_lag + 1);
+            let d = 2.0 * best_score - s0 - s2;
+            if d > 0.0 {
+                lag += 0.5 * (s2 - s0) / d;
+            }
+        }
+        self.tempo = self.odf_rate / lag;
+        if let Some(bpm) = &self.bpm {
+            *bpm.lock().unwrap() = 60.0 * self.tempo;
+        }
+
+        // How many frames ago the last beat was.
+        let phase_at = |offset: usize| {
+            // Onsets are somewhere within their ODF frames, take the middle on average.
+            let elapsed = (offset * HOP + HOP / 2 + self.hop_position) as Sample;
+            (elapsed / (lag * HOP as Sample)).fract()
+        };
+        let mut best_phase = self.phase;
+        let mut best_sum = Sample::NEG_INFINITY;
+        for offset in 0..(lag.ceil() as usize) {
+            let mut sum = 0.0;
+            let mut k = 0.0;
+            loop {
+                let ago = (offset as Sample + k * lag).round() as usize;
+                if ago >= n {
+                    break;
+                }
+                sum += self.history[n - 1 - ago] + mean;
+                k += 1.0;
+            }
+            let phase = phase_at(offset);
+            let mut change = phase - self.phase;
+            change -= change.round();
+            change /= PHASE_CONTINUITY;
+            sum *= 1.0 + (-0.5 * change * change).exp();
+            if sum > best_sum {
+                best_sum = sum;
+                best_phase = phase;
+            }
+        }
+        let mut error = best_phase - self.phase;
+        error -= error.round();
+        self.phase = (self.phase + PHASE_CORRECTION * error).rem_euclid(1.0);
+    }
+}
+
+impl Op for Beat {
+    fn perform(&mut self, stack: &mut Stack) {
+        let input = stack.pop();
+        let x = input.iter().sum::<Sample>() / CHANNELS as Sample;
+        self.energy += x * x;
+        self.hop_position += 1;
+        if self.hop_position == HOP {
+            let log_energy = (self.energy / HOP as Sample + 1e-10).ln();
+            let n = self.odf.len();
+            self.odf[self.odf_index] = (log_energy - self.previous_log_energy).max(0.0);
+            self.odf_index = (self.odf_index + 1) % n;
+            self.previous_log_energy = log_energy;
+            self.energy = 0.0;
+            self.hop_position = 0;
+            self.hops_to_analysis -= 1;
+            if self.hops_to_analysis == 0 {
+                self.hops_to_analysis = self.analysis_period;
+                self.analyze();
+            }
+        }
+        self.phase = (self.phase + self.tempo / self.sample_rate) % 1.0;
+        stack.push(&[self.tempo; CHANNELS]);
+        stack.push(&[self.phase; CHANNELS]);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.odf.len() == other.odf.len() {
+                self.odf.copy_from_slice(&other.odf);
+                self.odf_index = other.odf_index;
+            }
+            self.energy = other.energy;
+            self.previous_log_energy = other.previous_log_energy;
+            self.hop_position = other.hop_position;
+            self.hops_to_analysis = other.hops_to_analysis.min(self.analysis_period);
+            self.tempo = other.tempo;
+            self.phase = other.phase;
+        }
+    }
+}
@@ -1,6 +1,10 @@
 //! BiQuad Filters
 //!
+//! Coefficients are calculated as described in Audio EQ Cookbook
+//! https://shepazu.github.io/Audio-EQ-Cookbook/audio-eq-cookbook.html
+//!
 //! Sources to connect: input, cut-off frequency, Q.
+//! Peaking and shelving EQ additionally take gain in dB.
 use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use itertools::izip;
 
@@ -28,8 +32,90 @@ pub fn make_hpf_coefficients(
     (b0, b1, b0, 1.0 + alpha, -2.0 * cos_o, 1.0 - alpha)
 }
 
+/// Constant 0 dB peak gain.
+pub fn make_bpf_coefficients(
+    _sin_o: Sample,
+    cos_o: Sample,
+    alpha: Sample,
+) -> (Sample, Sample, Sample, Sample, Sample, Sample) {
+    (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_o, 1.0 - alpha)
+}
+
+pub fn make_notch_coefficients(
+    _sin_o: Sample,
+    cos_o: Sample,
+    alpha: Sample,
+) -> (Sample, Sample, Sample, Sample, Sample, Sample) {
+    let b1 = -2.0 * cos_o;
+    (1.0, b1, 1.0, 1.0 + alpha, b1, 1.0 - alpha)
+}
+
+type MakeGainCoefficients =
+    fn(Sample, Sample, Sample, Sample) -> (Sample, Sample, Sample, Sample, Sample, Sample);
+
+pub fn make_peak_eq_coefficients(
+    _sin_o: Sample,
+    cos_o: Sample,
+    alpha: Sample,
+    a: Sample,
+) -> (Sample, Sample, Sample, Sample, Sample, Sample) {
+    let b1 = -2.0 * cos_o;
+    (
+        1.0 + alpha * a,
+        b1,
+        1.0 - alpha * a,
+        1.0 + alpha / a,
+        b1,
+        1.0 - alpha / a,
+    )
+}
+
+pub fn make_low_shelf_coefficients(
+    _sin_o: Sample,
+    cos_o: Sample,
+    alpha: Sample,
+    a: Sample,
+) -> (Sample, Sample, Sample, Sample, Sample, Sample) {
+    let k = 2.0 * a.sqrt() * alpha;
+    let p = a + 1.0;
+    let m = a - 1.0;
+    (
+        a * (p - m * cos_o + k),
+        2.0 * a * (m - p * cos_o),
+        a * (p - m * cos_o - k),
+        p + m * cos_o + k,
+        -2.0 * (m + p * cos_o),
+        p + m * cos_o - k,
+    )
+}
+
+pub fn make_high_shelf_coefficients(
+    _sin_o: Sample,
+    cos_o: Sample,
+    alpha: Sample,
+    a: Sample,
+) -> (Sample, Sample, Sample, Sample, Sample, Sample) {
+    let k = 2.0 * a.sqrt() * alpha;
+    let p = a + 1.0;
+    let m = a - 1.0;
+    (
+        a * (p + m * cos_o + k),
+        -2.0 * a * (m + p * cos_o),
+        a * (p + m * cos_o - k),
+        p - m * cos_o + k,
+        2.0 * (m - p * cos_o),
+        p - m * cos_o - k,
+    )
+}
+
+enum Coefficients {
+    Plain(MakeCoefficients),
+    /// Takes amplitude of the gain input.
+    Gain(MakeGainCoefficients),
+}
+
 pub struct BiQuad {
-    make_coefficients: MakeCoefficients,
+    make_coefficients: Coefficients,
     sample_angular_period: Sample,
     x1: Frame,
     x2: Frame,
@@ -39,6 +125,15 @@ pub struct BiQuad {
 
 impl BiQuad {
     pub fn new(sample_rate: u32, make_coefficients: MakeCoefficients) -> Self {
+        Self::with_coefficients(sample_rate, Coefficients::Plain(make_coefficients))
+    }
+
+    /// Filter with an additional gain input, for peaking and shelving EQ.
+    pub fn with_gain(sample_rate: u32, make_coefficients: MakeGainCoefficients) -> Self {
+        Self::with_coefficients(sample_rate, Coefficients::Gain(make_coefficients))
+    }
+
+    fn with_coefficients(sample_rate: u32, make_coefficients: Coefficients) -> Self {
         let sample_angular_period = 2.0 * std::f64::consts::PI / Sample::from(sample_rate);
         BiQuad {
             make_coefficients,
@@ -53,14 +148,19 @@ impl BiQuad {
 
 impl Op for BiQuad {
     fn perform(&mut self, stack: &mut Stack) {
+        let gain = match self.make_coefficients {
+            Coefficients::Plain(_) => [0.0; CHANNELS],
+            Coefficients::Gain(_) => stack.pop(),
+        };
         let q = stack.pop();
         let cut_off_freq = stack.pop();
         let input = stack.pop();
-        for (y, &x, &frequency, &q, x1, x2, y2) in izip!(
+        for (y, &x, &frequency, &q, &gain, x1, x2, y2) in izip!(
             &mut self.y1,
             &input,
             &cut_off_freq,
             &q,
+            &gain,
             &mut self.x1,
             &mut self.x2,
             &mut self.y2
@@ -71,7 +171,10 @@ impl Op for BiQuad {
             let sin_o = o.sin();
             let cos_o = o.cos();
             let alpha = sin_o / (2.0 * q);
-            let (b0, b1, b2, a0, a1, a2) = (self.make_coefficients)(sin_o, cos_o, alpha);
+            let (b0, b1, b2, a0, a1, a2) = match self.make_coefficients {
+                Coefficients::Plain(make) => make(sin_o, cos_o, alpha),
+                Coefficients::Gain(make) => make(sin_o, cos_o, alpha, 10.0f64.powf(gain / 40.0)),
+            };
             *y = (x * b0 + *x1 * b1 + *x2 * b2 - y1 * a1 - *y2 * a2) / a0;
 
             *x2 = *x1;
@@ -6,6 +6,8 @@ pub struct Buffer<T> {
 
 impl<T: Copy> Buffer<T> {
     pub fn new(z: T, len: usize) -> Self {
+        // Reads and writes are unchecked, they rely on the buffer being non-empty.
+        assert!(len > 0, "Buffer must not be empty.");
         Buffer {
             data: vec![z; len],
             cursor: 0,
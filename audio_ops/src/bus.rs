@@ -0,0 +1,57 @@
+//! # Buses
+//!
+//! Named signals shared across the program, to tap a signal computed once at several points
+//! and to feed effects from several sources. `send` adds x scaled by level to the bus and
+//! passes x through, so it works as an effect send with level as its knob. `recv` pushes the sum
+//! of the sends. The first send to the bus in the program clears it each frame: `recv` after
+//! the sends hears them in the same frame and `recv` before them hears the previous frame,
+//! which closes feedback loops with one frame delay.
+//!
+//! Sources to connect: x and level for `send`, none for `recv`.
+use audio_vm::{Frame, Op, Stack, CHANNELS};
+use itertools::izip;
+use std::sync::{Arc, Mutex};
+
+pub struct BusSend {
+    bus: Arc<Mutex<Frame>>,
+    /// Whether it's the first send to the bus which clears it.
+    first: bool,
+}
+
+impl BusSend {
+    pub fn new(bus: Arc<Mutex<Frame>>, first: bool) -> Self {
+        BusSend { bus, first }
+    }
+}
+
+impl Op for BusSend {
+    fn perform(&mut self, stack: &mut Stack) {
+        let level = stack.pop();
+        let x = stack.pop();
+        let mut bus = self.bus.lock().unwrap();
+        if self.first {
+            *bus = [0.0; CHANNELS];
+        }
+        for (y, &x, &level) in izip!(bus.iter_mut(), &x, &level) {
+            *y += level * x;
+        }
+        stack.push(&x);
+    }
+}
+
+pub struct BusReceive {
+    bus: Arc<Mutex<Frame>>,
+}
+
+impl BusReceive {
+    pub fn new(bus: Arc<Mutex<Frame>>) -> Self {
+        BusReceive { bus }
+    }
+}
+
+impl Op for BusReceive {
+    fn perform(&mut self, stack: &mut Stack) {
+        let frame = *self.bus.lock().unwrap();
+        stack.push(&frame);
+    }
+}
@@ -1,4 +1,8 @@
-use audio_vm::{Op, Stack, CHANNELS};
+use crate::pure;
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+
+/// Levels of mid and side for the mono-compatibility check of `Width` are averaged over it.
+const WIDTH_WINDOW: Sample = 0.05;
 
 pub struct Channel {
     channel: usize,
@@ -17,3 +21,221 @@ impl Op for Channel {
         stack.push(&frame);
     }
 }
+
+/// Downmix to mono: every channel gets the mean of all of them, so a signal which is already
+/// mono keeps its level and opposite channels cancel out.
+pub struct Mono;
+
+impl Mono {
+    pub fn new() -> Self {
+        Mono {}
+    }
+}
+
+impl Default for Mono {
+    fn default() -> Self {
+        Mono::new()
+    }
+}
+
+impl Op for Mono {
+    fn perform(&mut self, stack: &mut Stack) {
+        let frame = stack.pop();
+        let x = frame.iter().sum::<Sample>() / CHANNELS as Sample;
+        stack.push(&[x; CHANNELS]);
+    }
+}
+
+/// Upmix mono to stereo: the first channel is copied to all of them, the rest are ignored.
+pub struct Stereo;
+
+impl Stereo {
+    pub fn new() -> Self {
+        Stereo {}
+    }
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Stereo::new()
+    }
+}
+
+impl Op for Stereo {
+    fn perform(&mut self, stack: &mut Stack) {
+        let frame = stack.pop();
+        stack.push(&[frame[0]; CHANNELS]);
+    }
+}
+
+/// Mirror the stereo space: channels come in the reverse order, left becomes right.
+pub struct SwapSpace;
+
+impl SwapSpace {
+    pub fn new() -> Self {
+        SwapSpace {}
+    }
+}
+
+impl Default for SwapSpace {
+    fn default() -> Self {
+        SwapSpace::new()
+    }
+}
+
+impl Op for SwapSpace {
+    fn perform(&mut self, stack: &mut Stack) {
+        let mut frame = stack.pop();
+        frame.reverse();
+        stack.push(&frame);
+    }
+}
+
+/// Encode left and right into mid and side: the left channel gets their mean and the right one
+/// half of their difference, so mid is the same as `Mono` gives and mono signal has no side.
+pub struct MidSideEncode;
+
+impl MidSideEncode {
+    pub fn new() -> Self {
+        MidSideEncode {}
+    }
+}
+
+impl Default for MidSideEncode {
+    fn default() -> Self {
+        MidSideEncode::new()
+    }
+}
+
+impl Op for MidSideEncode {
+    fn perform(&mut self, stack: &mut Stack) {
+        let [l, r] = stack.pop();
+        stack.push(&[0.5 * (l + r), 0.5 * (l - r)]);
+    }
+}
+
+/// Decode mid and side back into left and right, the inverse of `MidSideEncode`.
+pub struct MidSideDecode;
+
+impl MidSideDecode {
+    pub fn new() -> Self {
+        MidSideDecode {}
+    }
+}
+
+impl Default for MidSideDecode {
+    fn default() -> Self {
+        MidSideDecode::new()
+    }
+}
+
+impl Op for MidSideDecode {
+    fn perform(&mut self, stack: &mut Stack) {
+        let [m, s] = stack.pop();
+        stack.push(&[m + s, m - s]);
+    }
+}
+
+/// Stereo width from 0 (mono) through 1 (as is) to 2 (sides twice as loud), by scaling side and
+/// keeping mid. Mono sum doesn't change, but when the widened side gets louder than mid, most of
+/// the signal would be lost in it, so widening is held back to keep side at the level of mid,
+/// unless it's already wider as is.
+pub struct Width {
+    /// Mean squares of mid and side.
+    mid: Sample,
+    side: Sample,
+    /// Share of the new square in the mean per frame.
+    rate: Sample,
+}
+
+impl Width {
+    pub fn new(sample_rate: u32) -> Self {
+        Width {
+            mid: 0.0,
+            side: 0.0,
+            rate: 1.0 - (-1.0 / (WIDTH_WINDOW * Sample::from(sample_rate))).exp(),
+        }
+    }
+}
+
+impl Op for Width {
+    fn perform(&mut self, stack: &mut Stack) {
+        let width = stack.pop()[0];
+        let [l, r] = stack.pop();
+        let (m, s) = (0.5 * (l + r), 0.5 * (l - r));
+        self.mid += self.rate * (m * m - self.mid);
+        self.side += self.rate * (s * s - self.side);
+        // Keep NaN of bad inputs from sticking in levels.
+        if !self.mid.is_finite() || !self.side.is_finite() {
+            self.mid = 0.0;
+            self.side = 0.0;
+        }
+        let width = pure::clamp_or(width, 0.0, 2.0, 0.0);
+        let width = if width > 1.0 && self.side > 0.0 {
+            width.min((self.mid / self.side).sqrt().max(1.0))
+        } else {
+            width
+        };
+        let s = width * s;
+        stack.push(&[m + s, m - s]);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.mid = other.mid;
+            self.side = other.side;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, frame, render};
+    use crate::Dup;
+
+    #[test]
+    fn channel_laws() {
+        let mut ops = vec![frame([1.0, 0.0])];
+        ops.push(Box::new(SwapSpace::new()));
+        ops.push(frame([1.0, 0.0]));
+        ops.push(Box::new(Mono::new()));
+        ops.push(frame([1.0, 0.0]));
+        ops.push(Box::new(Stereo::new()));
+        assert_eq!(render(&mut ops, 1)[0], [[0.0, 1.0], [0.5, 0.5], [1.0, 1.0]]);
+    }
+
+    #[test]
+    fn mid_side_round_trips() {
+        let mut ops = vec![frame([1.0, 0.5])];
+        ops.push(Box::new(MidSideEncode::new()));
+        ops.push(Box::new(Dup::new()));
+        ops.push(Box::new(MidSideDecode::new()));
+        // Mono has no side.
+        ops.push(constant(0.3));
+        ops.push(Box::new(MidSideEncode::new()));
+        assert_eq!(
+            render(&mut ops, 1)[0],
+            [[0.75, 0.25], [1.0, 0.5], [0.3, 0.0]]
+        );
+    }
+
+    #[test]
+    fn width_scales_sides_and_keeps_mono() {
+        let width = |input, width| {
+            let mut ops = vec![frame(input), constant(width)];
+            ops.push(Box::new(Width::new(48000)));
+            render(&mut ops, 48000)[47999][0]
+        };
+        // Sides which are quieter than mid are widened fully, mono sum stays.
+        let [l, r] = width([1.0, 0.5], 2.0);
+        assert!((l - 1.25).abs() < 1e-9 && (r - 0.25).abs() < 1e-9);
+        assert_eq!(width([1.0, 0.5], 0.0), [0.75; CHANNELS]);
+        // Widening doesn't push side above mid.
+        let [l, r] = width([1.0, 0.2], 2.0);
+        assert!((l - 1.2).abs() < 1e-6 && (r - 0.0).abs() < 1e-6);
+        // But leaves wider ones as they are.
+        let [l, r] = width([1.0, -0.5], 2.0);
+        assert!((l - 1.0).abs() < 1e-6 && (r + 0.5).abs() < 1e-6);
+    }
+}
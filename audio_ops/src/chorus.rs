@@ -0,0 +1,99 @@
+//! # Chorus
+//!
+//! N voices read the input from a shared delay line with fractional delay times swept by a sine
+//! LFO around a base delay. Voices' LFO phases are evenly spaced to keep them apart, and voices are
+//! panned evenly from left to right by stereo spread.
+//!
+//! Sources to connect: input, rate, depth, spread, dry/wet.
+//! Rate is in Hz, depth, spread and dry/wet are from 0 to 1.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use std::f64::consts::PI;
+
+/// Delay time (in seconds) voices are swept around.
+const BASE_DELAY: Sample = 0.015;
+/// Sweep width (in seconds) at full depth.
+const MAX_DEPTH: Sample = 0.01;
+
+pub struct Chorus {
+    buffer: Vec<Sample>,
+    index: usize,
+    phase: Sample,
+    voices: usize,
+    sample_rate: Sample,
+}
+
+impl Chorus {
+    pub fn new(sample_rate: u32, voices: usize) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let voices = voices.max(1);
+        Chorus {
+            // +2 for interpolation and rounding.
+            buffer: vec![0.0; ((BASE_DELAY + MAX_DEPTH) * sample_rate) as usize + 2],
+            index: 0,
+            phase: 0.0,
+            voices,
+            sample_rate,
+        }
+    }
+
+    #[inline]
+    fn read(&self, delay: Sample) -> Sample {
+        let size = self.buffer.len();
+        let position = (self.index + size) as Sample - delay;
+        let i = position.floor();
+        let k = position - i;
+        let i = i as usize;
+        (1.0 - k) * self.buffer[i % size] + k * self.buffer[(i + 1) % size]
+    }
+}
+
+impl Op for Chorus {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let spread = stack.pop();
+        let depth = stack.pop();
+        let rate = stack.pop();
+        let input = stack.pop();
+        // Parameters are shared by voices, hence mono.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        let depth = unit(mean(depth)) * MAX_DEPTH * self.sample_rate;
+        let spread = unit(mean(spread));
+        let base = BASE_DELAY * self.sample_rate;
+        let mut frame: Frame = [0.0; CHANNELS];
+        for i in 0..self.voices {
+            let offset = i as Sample / self.voices as Sample;
+            let lfo = 0.5 + 0.5 * (2.0 * PI * (self.phase + offset)).sin();
+            let y = self.read(base + depth * lfo);
+            let position = if self.voices > 1 {
+                spread * (2.0 * i as Sample / (self.voices - 1) as Sample - 1.0)
+            } else {
+                0.0
+            };
+            // Equal power panning with unity gain in the center.
+            frame[0] += (1.0 - position).sqrt() * y;
+            frame[1] += (1.0 + position).sqrt() * y;
+        }
+        self.buffer[self.index] = mean(input);
+        self.index = (self.index + 1) % self.buffer.len();
+        let rate = pure::clamp_or(mean(rate), -self.sample_rate, self.sample_rate, 0.0);
+        self.phase = (self.phase + rate / self.sample_rate).rem_euclid(1.0);
+        let scale = 1.0 / self.voices as Sample;
+        for (output, &dry, &wet) in izip!(&mut frame, &input, &wet) {
+            *output = (1.0 - wet) * dry + wet * scale * *output;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.buffer.len() == other.buffer.len() {
+                self.buffer.copy_from_slice(&other.buffer);
+                self.index = other.index;
+            }
+            self.phase = other.phase;
+        }
+    }
+}
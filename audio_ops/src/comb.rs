@@ -0,0 +1,117 @@
+//! # Comb filters
+//!
+//! Feedforward: y[n] = x[n] + gain * x[n - delay]
+//! Feedback: y[n] = x[n] + gain * y[n - delay]
+//!
+//! Delay is read with linear interpolation, thus could be fractional and modulated.
+//!
+//! Sources to connect: input, delay time, gain.
+use crate::buffer::Buffer;
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+pub struct CombFF {
+    buffer: Buffer<Frame>,
+    mask: usize,
+    sample_rate: Sample,
+}
+
+impl CombFF {
+    pub fn new(sample_rate: u32, max_delay: f64) -> Self {
+        let (buffer, mask) = make_buffer(sample_rate, max_delay);
+        CombFF {
+            buffer,
+            mask,
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for CombFF {
+    fn perform(&mut self, stack: &mut Stack) {
+        let gain = stack.pop();
+        let delay = stack.pop();
+        let input = stack.pop();
+        self.buffer.push_front(input);
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &x, &delay, &gain)) in
+            izip!(&mut frame, &input, &delay, &gain).enumerate()
+        {
+            let z = (delay * self.sample_rate).max(0.0);
+            *output = x + gain * read(&self.buffer, self.mask, channel, z);
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.buffer.copy_forward(&other.buffer);
+        }
+    }
+}
+
+pub struct CombFB {
+    buffer: Buffer<Frame>,
+    mask: usize,
+    /// Gain is clamped to -max_gain..max_gain.
+    max_gain: Sample,
+    sample_rate: Sample,
+}
+
+impl CombFB {
+    pub fn new(sample_rate: u32, max_delay: f64, max_gain: Sample) -> Self {
+        let (buffer, mask) = make_buffer(sample_rate, max_delay);
+        CombFB {
+            buffer,
+            mask,
+            max_gain,
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for CombFB {
+    fn perform(&mut self, stack: &mut Stack) {
+        let gain = stack.pop();
+        let delay = stack.pop();
+        let input = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &x, &delay, &gain)) in
+            izip!(&mut frame, &input, &delay, &gain).enumerate()
+        {
+            // Buffer starts with the previous output, so the shortest delay is one sample.
+            let z = (delay * self.sample_rate - 1.0).max(0.0);
+            let gain = pure::clamp_or(gain, -self.max_gain, self.max_gain, 0.0);
+            *output = x + gain * read(&self.buffer, self.mask, channel, z);
+        }
+        self.buffer.push_front(frame);
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.buffer.copy_forward(&other.buffer);
+        }
+    }
+}
+
+fn make_buffer(sample_rate: u32, max_delay: f64) -> (Buffer<Frame>, usize) {
+    // +1 because interpolation looks for the next sample
+    // next_power_of_two to trade memory for speed by replacing `mod` with `&`
+    let max_delay_frames =
+        ((Sample::from(sample_rate) * max_delay) as usize + 1).next_power_of_two();
+    (
+        Buffer::new([0.0; CHANNELS], max_delay_frames),
+        max_delay_frames - 1,
+    )
+}
+
+#[inline]
+fn read(buffer: &Buffer<Frame>, mask: usize, channel: usize, z: Sample) -> Sample {
+    let delay = z as usize & mask;
+    let k = z.fract();
+    let a = buffer[delay][channel];
+    let b = buffer[(delay + 1) & mask][channel];
+    (1.0 - k) * a + k * b
+}
@@ -1,6 +1,7 @@
 //! # Convolution
 //!
 //! Convolve two signals by making dot-product of a N-sample sliding window on both.
+//! Dot-product is maintained as a running sum, so it's O(1) per sample regardless of N.
 //!
 //! Sources to connect: input and kernel, but roles are vague in this case.
 use crate::buffer::Buffer;
@@ -9,14 +10,32 @@ use itertools::izip;
 
 pub struct Convolution {
     window: Buffer<Frame>,
+    window_size: usize,
+    sum: Frame,
+    /// Frames until the running sum is recomputed from scratch to get rid of accumulated errors.
+    countdown: usize,
 }
 
 impl Convolution {
     pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(1);
         Convolution {
             window: Buffer::new([0.0; CHANNELS], window_size),
+            window_size,
+            sum: [0.0; CHANNELS],
+            countdown: window_size,
         }
     }
+
+    fn resum(&mut self) {
+        self.sum = [0.0; CHANNELS];
+        for xs in self.window.iter() {
+            for (sample, x) in izip!(&mut self.sum, xs) {
+                *sample += x;
+            }
+        }
+        self.countdown = self.window_size;
+    }
 }
 
 impl Op for Convolution {
@@ -27,20 +46,22 @@ impl Op for Convolution {
         for (sample, &x, &y) in izip!(&mut frame, &input, &kernel) {
             *sample = x * y;
         }
+        // The oldest product leaves the window.
+        for (sample, &x, &oldest) in izip!(&mut self.sum, &frame, &self.window[0]) {
+            *sample += x - oldest;
+        }
         self.window.push_back(frame);
-
-        let mut frame = [0.0; CHANNELS];
-        for xs in self.window.iter() {
-            for (sample, x) in izip!(&mut frame, xs) {
-                *sample += x;
-            }
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.resum();
         }
-        stack.push(&frame);
+        stack.push(&self.sum);
     }
 
     fn migrate(&mut self, other: &Box<dyn Op>) {
         if let Some(other) = other.downcast_ref::<Self>() {
             self.window.copy_backward(&other.window);
+            self.resum();
         }
     }
 }
@@ -52,6 +73,7 @@ pub struct ConvolutionM {
 
 impl ConvolutionM {
     pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(1);
         let zero = [0.0; CHANNELS];
         ConvolutionM {
             window: Buffer::new(zero, window_size),
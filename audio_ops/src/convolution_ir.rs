@@ -0,0 +1,155 @@
+//! # Convolution with impulse response
+//!
+//! Uniformly partitioned convolution in frequency domain: impulse response is split into blocks,
+//! spectra of input blocks are kept in a frequency-domain delay line and each output block
+//! is IFFT of the sum of their products with spectra of IR blocks (overlap-save).
+//! It makes realistic IR lengths affordable unlike time-domain `Convolution`,
+//! at cost of latency of one block.
+//!
+//! Sources to connect: input, dry/wet.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFT;
+use std::sync::Arc;
+
+/// Latency in frames, must be power of two!
+const BLOCK_SIZE: usize = 256;
+const FFT_SIZE: usize = 2 * BLOCK_SIZE;
+
+pub struct ConvolutionIR {
+    ir: Arc<Vec<Frame>>,
+    /// Spectra of IR partitions for each channel.
+    partitions: Vec<Vec<Vec<Complex<Sample>>>>,
+    /// Spectra of past input blocks for each channel, circular.
+    delay_line: Vec<Vec<Vec<Complex<Sample>>>>,
+    delay_line_index: usize,
+    /// Previous and current input blocks for each channel.
+    input: Vec<Vec<Sample>>,
+    /// Output block computed from the previous input block for each channel.
+    output: Vec<Vec<Sample>>,
+    position: usize,
+    scratch: Vec<Complex<Sample>>,
+    freq_buffer: Vec<Complex<Sample>>,
+    fft: Radix4<Sample>,
+    ifft: Radix4<Sample>,
+}
+
+impl ConvolutionIR {
+    /// Impulse response is expected to be at the target sample rate already.
+    pub fn new(ir: Arc<Vec<Frame>>) -> Self {
+        let fft = Radix4::new(FFT_SIZE, false);
+        let n_partitions = (ir.len().max(1) - 1) / BLOCK_SIZE + 1;
+        let mut scratch = vec![Complex::zero(); FFT_SIZE];
+        let partitions = (0..CHANNELS)
+            .map(|channel| {
+                (0..n_partitions)
+                    .map(|p| {
+                        let mut spectrum = vec![Complex::zero(); FFT_SIZE];
+                        for (i, x) in scratch.iter_mut().enumerate() {
+                            *x = if i < BLOCK_SIZE {
+                                ir.get(p * BLOCK_SIZE + i)
+                                    .map(|frame| Complex::from(frame[channel]))
+                                    .unwrap_or_else(Complex::zero)
+                            } else {
+                                Complex::zero()
+                            };
+                        }
+                        fft.process(&mut scratch, &mut spectrum);
+                        spectrum
+                    })
+                    .collect()
+            })
+            .collect();
+        ConvolutionIR {
+            ir,
+            partitions,
+            delay_line: vec![vec![vec![Complex::zero(); FFT_SIZE]; n_partitions]; CHANNELS],
+            delay_line_index: 0,
+            input: vec![vec![0.0; FFT_SIZE]; CHANNELS],
+            output: vec![vec![0.0; BLOCK_SIZE]; CHANNELS],
+            position: 0,
+            scratch,
+            freq_buffer: vec![Complex::zero(); FFT_SIZE],
+            fft,
+            ifft: Radix4::new(FFT_SIZE, true),
+        }
+    }
+
+    fn process_block(&mut self) {
+        let n_partitions = self.delay_line[0].len();
+        for (input, output, delay_line, partitions) in izip!(
+            &mut self.input,
+            &mut self.output,
+            &mut self.delay_line,
+            &self.partitions
+        ) {
+            for (x, &y) in self.scratch.iter_mut().zip(input.iter()) {
+                *x = Complex::from(y);
+            }
+            self.fft
+                .process(&mut self.scratch, &mut delay_line[self.delay_line_index]);
+            for x in self.freq_buffer.iter_mut() {
+                *x = Complex::zero();
+            }
+            for (p, partition) in partitions.iter().enumerate() {
+                let spectrum =
+                    &delay_line[(self.delay_line_index + n_partitions - p) % n_partitions];
+                for (y, a, b) in izip!(&mut self.freq_buffer, spectrum, partition) {
+                    *y += a * b;
+                }
+            }
+            self.ifft.process(&mut self.freq_buffer, &mut self.scratch);
+            // Overlap-save: the first half is aliased, IFFT is not normalized.
+            for (y, x) in output.iter_mut().zip(&self.scratch[BLOCK_SIZE..]) {
+                *y = x.re / FFT_SIZE as Sample;
+            }
+            // Current block becomes the previous one.
+            input.copy_within(BLOCK_SIZE.., 0);
+        }
+        self.delay_line_index = (self.delay_line_index + 1) % n_partitions;
+    }
+}
+
+impl Op for ConvolutionIR {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let input = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (sample, &x, &wet, block, output) in
+            izip!(&mut frame, &input, &wet, &mut self.input, &self.output)
+        {
+            block[BLOCK_SIZE + self.position] = x;
+            *sample = (1.0 - wet) * x + wet * output[self.position];
+        }
+        self.position += 1;
+        if self.position == BLOCK_SIZE {
+            self.position = 0;
+            self.process_block();
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            // Keep the tail ringing if IR is the same.
+            if Arc::ptr_eq(&self.ir, &other.ir) {
+                for (a, b) in self.delay_line.iter_mut().zip(&other.delay_line) {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        a.copy_from_slice(b);
+                    }
+                }
+                self.delay_line_index = other.delay_line_index;
+                for (a, b) in self.input.iter_mut().zip(&other.input) {
+                    a.copy_from_slice(b);
+                }
+                for (a, b) in self.output.iter_mut().zip(&other.output) {
+                    a.copy_from_slice(b);
+                }
+                self.position = other.position;
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! # Cross-synthesis
+//!
+//! Combines magnitudes of bins of one signal with phases of another, e.g. to play a drum loop
+//! through the timbre of a pad. Morph moves magnitudes from y's own (0) to x's (1). Output lags
+//! by one window.
+//!
+//! Sources to connect: x (magnitudes), y (phases), morph.
+use crate::pure;
+use crate::spectral_transform::{Stft, HOP, WINDOW_SIZE};
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use rustfft::num_complex::Complex;
+
+pub struct CrossSynthesis {
+    stft: Stft,
+}
+
+impl CrossSynthesis {
+    pub fn new() -> Self {
+        CrossSynthesis {
+            stft: Stft::new(2, WINDOW_SIZE, HOP),
+        }
+    }
+}
+
+impl Default for CrossSynthesis {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Op for CrossSynthesis {
+    fn perform(&mut self, stack: &mut Stack) {
+        let morph = stack.pop();
+        let y = stack.pop();
+        let x = stack.pop();
+        // Spectra are shared by channels, hence mono parameter.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let morph = pure::clamp_or(mean(morph), 0.0, 1.0, 0.0);
+        let frame = self.stft.process(&[x, y], |_, spectra| {
+            let (x, y) = spectra.split_at_mut(1);
+            for (x, y) in x[0].iter_mut().zip(&y[0]).take(WINDOW_SIZE / 2 + 1) {
+                let magnitude = (1.0 - morph) * y.norm() + morph * x.norm();
+                *x = Complex::from_polar(&magnitude, &y.arg());
+            }
+        });
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.stft.migrate(&other.stft);
+        }
+    }
+}
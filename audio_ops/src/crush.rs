@@ -0,0 +1,72 @@
+//! # Bitcrusher
+//!
+//! Reduce bit depth by quantizing input to steps of 2/2^bits in the range -1..1 and sample rate
+//! by holding every sample for downsample factor frames. Both are fractional for smooth
+//! modulation. Jitter randomly stretches and squeezes hold periods by up to the given fraction
+//! of the factor, like a cheap converter with unstable clock.
+//!
+//! Sources to connect: input, bits, downsample factor, jitter (0..1).
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+pub struct Crush {
+    held: Frame,
+    /// Frames left until the next sample is taken.
+    countdown: Sample,
+    rng: SmallRng,
+}
+
+impl Crush {
+    pub fn new() -> Self {
+        Crush {
+            held: [0.0; CHANNELS],
+            countdown: 0.0,
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
+    /// Use it to get the same jitter in each render.
+    pub fn with_rng(rng: SmallRng) -> Self {
+        Crush {
+            rng,
+            ..Crush::new()
+        }
+    }
+}
+
+impl Default for Crush {
+    fn default() -> Self {
+        Crush::new()
+    }
+}
+
+impl Op for Crush {
+    fn perform(&mut self, stack: &mut Stack) {
+        let jitter = stack.pop();
+        let factor = stack.pop();
+        let bits = stack.pop();
+        let input = stack.pop();
+        // Channels share the clock, like in a real converter.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        self.countdown -= 1.0;
+        if self.countdown <= 0.0 {
+            let jitter = pure::clamp_or(mean(jitter), 0.0, 1.0, 0.0);
+            let period = mean(factor).max(1.0) * (1.0 + jitter * self.rng.gen_range(-1.0, 1.0));
+            self.countdown += period.max(1.0);
+            for (held, &x, &bits) in izip!(&mut self.held, &input, &bits) {
+                let step = 2.0 / 2.0f64.powf(bits.max(1.0));
+                *held = (x / step).round() * step;
+            }
+        }
+        stack.push(&self.held);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.held = other.held;
+            self.countdown = other.countdown;
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::buffer::Buffer;
+use crate::pure;
 use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use itertools::izip;
+use std::f64::consts::PI;
 
 pub struct Delay {
     buffer: Buffer<Frame>,
@@ -34,9 +36,9 @@ impl Op for Delay {
         let mut frame = [0.0; CHANNELS];
         for (channel, (output, delay)) in izip!(frame.iter_mut(), delay.iter()).enumerate() {
             let z = delay * self.sample_rate;
-            let delay = z as usize;
+            let delay = z as usize & self.mask;
             let k = z.fract();
-            let a = self.buffer[delay & self.mask][channel];
+            let a = self.buffer[delay][channel];
             let b = self.buffer[(delay + 1) & self.mask][channel];
             *output = (1.0 - k) * a + k * b;
         }
@@ -51,6 +53,369 @@ impl Op for Delay {
     }
 }
 
+/// Delay for audio-rate modulation of time, e.g. vibrato, doubling or tuning of Karplus-Strong
+/// strings. Reads with cubic Hermite interpolation, which unlike the linear one of `Delay`
+/// doesn't dull the sound and doesn't add zipper noise when time is swept.
+///
+/// Sources to connect: input, time.
+pub struct VDelay {
+    delay: Delay,
+}
+
+impl VDelay {
+    pub fn new(sample_rate: u32, max_delay: f64) -> Self {
+        VDelay {
+            // +2 frames for the interpolation neighbours.
+            delay: Delay::new(sample_rate, max_delay + 2.0 / Sample::from(sample_rate)),
+        }
+    }
+}
+
+impl Op for VDelay {
+    fn perform(&mut self, stack: &mut Stack) {
+        let time = stack.pop();
+        let input = stack.pop();
+        let delay = &self.delay;
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, time)) in izip!(&mut frame, &time).enumerate() {
+            // The first neighbour is one frame closer than the read position.
+            // Unlike clamp, max maps NaN to the bound.
+            let z = (time * delay.sample_rate)
+                .max(1.0)
+                .min((delay.mask - 2) as Sample);
+            let i = z as usize;
+            let k = z.fract();
+            let y0 = delay.buffer[(i - 1) & delay.mask][channel];
+            let y1 = delay.buffer[i & delay.mask][channel];
+            let y2 = delay.buffer[(i + 1) & delay.mask][channel];
+            let y3 = delay.buffer[(i + 2) & delay.mask][channel];
+            let c1 = 0.5 * (y2 - y0);
+            let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+            *output = ((c3 * k + c2) * k + c1) * k + y1;
+        }
+        stack.push(&frame);
+        self.delay.buffer.push_front(input);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.migrate_same(&other.delay);
+        }
+    }
+}
+
+/// Longest delay of `Haas`, beyond it the delayed channel is heard as an echo.
+const HAAS_MAX_DELAY: Sample = 0.03;
+
+/// Panning by the precedence effect: the channel heard first tells where the sound comes from,
+/// so delaying the other one by up to 30 ms moves the image without changing levels. Position
+/// from -1 (left) to 1 (right) sets which channel is delayed and by how much, the left channel
+/// of it is used for both.
+///
+/// Sources to connect: input, position.
+pub struct Haas {
+    delay: VDelay,
+    /// `VDelay` reads at least a frame back, both channels are delayed by it to keep
+    /// the difference exact.
+    latency: Sample,
+}
+
+impl Haas {
+    pub fn new(sample_rate: u32) -> Self {
+        Haas {
+            delay: VDelay::new(
+                sample_rate,
+                HAAS_MAX_DELAY + 1.0 / Sample::from(sample_rate),
+            ),
+            latency: 1.0 / Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Haas {
+    fn perform(&mut self, stack: &mut Stack) {
+        let position = stack.pop()[0];
+        let position = if position.is_finite() {
+            pure::clamp(position, -1.0, 1.0)
+        } else {
+            0.0
+        };
+        // Input stays on the stack for the delay to take it.
+        stack.push(&[
+            self.latency + HAAS_MAX_DELAY * position.max(0.0),
+            self.latency + HAAS_MAX_DELAY * (-position).max(0.0),
+        ]);
+        self.delay.perform(stack);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.delay.migrate_same(&other.delay.delay);
+        }
+    }
+}
+
+/// Several taps reading one delay line, for rhythmic echoes without duplicating memory.
+///
+/// Sources to connect: input, then time and gain of each tap.
+pub struct MultiTap {
+    delay: Delay,
+    /// Times and gains of taps, reused to avoid allocations on each frame.
+    taps: Vec<(Frame, Frame)>,
+}
+
+impl MultiTap {
+    pub fn new(sample_rate: u32, taps: usize, max_delay: f64) -> Self {
+        MultiTap {
+            delay: Delay::new(sample_rate, max_delay),
+            taps: vec![([0.0; CHANNELS], [0.0; CHANNELS]); taps],
+        }
+    }
+}
+
+impl Op for MultiTap {
+    fn perform(&mut self, stack: &mut Stack) {
+        for (time, gain) in self.taps.iter_mut().rev() {
+            *gain = stack.pop();
+            *time = stack.pop();
+        }
+        let input = stack.pop();
+        let delay = &self.delay;
+        let mut frame = [0.0; CHANNELS];
+        for (time, gain) in &self.taps {
+            for (channel, (output, time, gain)) in izip!(&mut frame, time, gain).enumerate() {
+                let z = time * delay.sample_rate;
+                let i = z as usize & delay.mask;
+                let k = z.fract();
+                let a = delay.buffer[i][channel];
+                let b = delay.buffer[(i + 1) & delay.mask][channel];
+                *output += gain * ((1.0 - k) * a + k * b);
+            }
+        }
+        stack.push(&frame);
+        self.delay.buffer.push_front(input);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.migrate_same(&other.delay);
+        }
+    }
+}
+
+/// Stereo delay where echoes bounce between channels: the input enters the left line,
+/// the left line feeds the right one and the right line feeds back into the left one.
+///
+/// Sources to connect: input, time, feedback, dry/wet.
+pub struct PingPong {
+    delay: Delay,
+    /// Feedback is clamped to -max_gain..max_gain.
+    max_gain: Sample,
+}
+
+impl PingPong {
+    pub fn new(sample_rate: u32, max_delay: f64, max_gain: Sample) -> Self {
+        PingPong {
+            delay: Delay::new(sample_rate, max_delay),
+            max_gain,
+        }
+    }
+}
+
+impl Op for PingPong {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let feedback = stack.pop();
+        let time = stack.pop();
+        let input = stack.pop();
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let delay = &self.delay;
+        let max_time = (delay.mask - 1) as Sample;
+        let z = pure::clamp_or(mean(time) * delay.sample_rate, 0.0, max_time, 0.0);
+        let i = z as usize;
+        let k = z.fract();
+        let mut echo = [0.0; CHANNELS];
+        for (channel, output) in echo.iter_mut().enumerate() {
+            let a = delay.buffer[i][channel];
+            let b = delay.buffer[(i + 1) & delay.mask][channel];
+            *output = (1.0 - k) * a + k * b;
+        }
+        let feedback = pure::clamp_or(mean(feedback), -self.max_gain, self.max_gain, 0.0);
+        let mut next = [0.0; CHANNELS];
+        next[0] = mean(input) + feedback * echo[CHANNELS - 1];
+        for (next, &echo) in next.iter_mut().skip(1).zip(&echo) {
+            *next = feedback * echo;
+        }
+        self.delay.buffer.push_front(next);
+        let mut frame = [0.0; CHANNELS];
+        for (output, &dry, &wet, &echo) in izip!(&mut frame, &input, &wet, &echo) {
+            *output = (1.0 - wet) * dry + wet * echo;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.migrate_same(&other.delay);
+        }
+    }
+}
+
+/// Time (in seconds) for the tape delay to glide to a new delay time.
+const TAPE_GLIDE: Sample = 0.2;
+/// Frequencies (in Hz) and max depths (in seconds) of wow and flutter.
+const WOW_FREQUENCY: Sample = 0.5;
+const WOW_DEPTH: Sample = 0.005;
+const FLUTTER_FREQUENCY: Sample = 7.0;
+const FLUTTER_DEPTH: Sample = 0.0005;
+
+/// Delay modelled after a tape echo: changes of time glide smoothly and bend the pitch like moving
+/// the head would, time is wobbled by slow wow and fast flutter, and the signal is softly
+/// saturated each time it's written to the tape, so feedback grows warm instead of blowing up.
+///
+/// Sources to connect: input, time, feedback, wow, flutter, dry/wet.
+/// Wow and flutter are amounts from 0 to 1.
+pub struct TapeDelay {
+    delay: Delay,
+    /// Current delay time in frames, None until the first frame.
+    time: Option<Sample>,
+    wow_phase: Sample,
+    flutter_phase: Sample,
+    /// Feedback is clamped to -max_gain..max_gain.
+    max_gain: Sample,
+}
+
+impl TapeDelay {
+    pub fn new(sample_rate: u32, max_delay: f64, max_gain: Sample) -> Self {
+        TapeDelay {
+            delay: Delay::new(sample_rate, max_delay),
+            time: None,
+            wow_phase: 0.0,
+            flutter_phase: 0.0,
+            max_gain,
+        }
+    }
+}
+
+impl Op for TapeDelay {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let flutter = stack.pop();
+        let wow = stack.pop();
+        let feedback = stack.pop();
+        let time = stack.pop();
+        let input = stack.pop();
+        // There is one tape, hence mono parameters.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let sample_rate = self.delay.sample_rate;
+        // Glide keeps the last good time on NaN or infinite time.
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        let target = mean(time) * sample_rate;
+        if target.is_finite() {
+            let time = self.time.get_or_insert(target);
+            *time += (target - *time) * (1.0 - (-1.0 / (TAPE_GLIDE * sample_rate)).exp());
+        }
+        let time = self.time.unwrap_or(0.0);
+        let wobble = WOW_DEPTH * unit(mean(wow)) * (2.0 * PI * self.wow_phase).sin()
+            + FLUTTER_DEPTH * unit(mean(flutter)) * (2.0 * PI * self.flutter_phase).sin();
+        self.wow_phase = (self.wow_phase + WOW_FREQUENCY / sample_rate).fract();
+        self.flutter_phase = (self.flutter_phase + FLUTTER_FREQUENCY / sample_rate).fract();
+        let max_time = (self.delay.mask - 1) as Sample;
+        let z = pure::clamp(time + wobble * sample_rate, 0.0, max_time);
+        let i = z as usize;
+        let k = z.fract();
+        let delay = &self.delay;
+        let feedback = pure::clamp_or(mean(feedback), -self.max_gain, self.max_gain, 0.0);
+        let mut frame = [0.0; CHANNELS];
+        let mut next = [0.0; CHANNELS];
+        for (channel, (output, next, &dry, &wet)) in
+            izip!(&mut frame, &mut next, &input, &wet).enumerate()
+        {
+            let a = delay.buffer[i & delay.mask][channel];
+            let b = delay.buffer[(i + 1) & delay.mask][channel];
+            let echo = (1.0 - k) * a + k * b;
+            *next = (dry + feedback * echo).tanh();
+            *output = (1.0 - wet) * dry + wet * echo;
+        }
+        self.delay.buffer.push_front(next);
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.migrate_same(&other.delay);
+            self.time = other.time;
+            self.wow_phase = other.wow_phase;
+            self.flutter_phase = other.flutter_phase;
+        }
+    }
+}
+
+/// Fade (in seconds) at both ends of reversed chunks to avoid clicks.
+const REVERSE_FADE: Sample = 0.005;
+
+/// Reverse echo: the input is cut into chunks, and each chunk is played backwards while the next
+/// one is recorded. Chunk length is latched at the start of each chunk.
+///
+/// Sources to connect: input, chunk length, dry/wet.
+pub struct ReverseDelay {
+    delay: Delay,
+    /// Frames played from the current chunk.
+    position: usize,
+    /// Length of the current chunk in frames.
+    chunk: usize,
+    max_chunk: usize,
+}
+
+impl ReverseDelay {
+    pub fn new(sample_rate: u32, max_chunk: f64) -> Self {
+        // Reading the start of the previous chunk at the end of the current one needs twice
+        // the chunk length.
+        let delay = Delay::new(sample_rate, 2.0 * max_chunk);
+        ReverseDelay {
+            max_chunk: (delay.mask / 2).max(1),
+            delay,
+            position: 0,
+            chunk: 0,
+        }
+    }
+}
+
+impl Op for ReverseDelay {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let chunk = stack.pop();
+        let input = stack.pop();
+        if self.position >= self.chunk {
+            let chunk = chunk.iter().sum::<Sample>() / CHANNELS as Sample;
+            self.chunk = ((chunk * self.delay.sample_rate) as usize).clamp(1, self.max_chunk);
+            self.position = 0;
+        }
+        // Going one frame forward in time and two frames back in the buffer plays it backwards.
+        let offset = 2 * self.position;
+        let fade = (REVERSE_FADE * self.delay.sample_rate).max(1.0);
+        let edge = self.position.min(self.chunk - self.position) as Sample;
+        let gain = (edge / fade).min(1.0);
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &dry, &wet)) in izip!(&mut frame, &input, &wet).enumerate() {
+            let echo = gain * self.delay.buffer[offset & self.delay.mask][channel];
+            *output = (1.0 - wet) * dry + wet * echo;
+        }
+        self.position += 1;
+        self.delay.buffer.push_front(input);
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.delay.migrate_same(&other.delay);
+            self.position = other.position;
+            self.chunk = other.chunk.min(self.max_chunk);
+        }
+    }
+}
+
 pub struct Prime {
     previous: Frame,
 }
@@ -76,3 +441,25 @@ impl Op for Prime {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{click, constant, render};
+
+    #[test]
+    fn haas_delays_the_far_channel() {
+        let mut ops = vec![click(), constant(0.5)];
+        ops.push(Box::new(Haas::new(1000)));
+        let output = render(&mut ops, 40)
+            .into_iter()
+            .map(|stack| stack[0])
+            .collect::<Vec<_>>();
+        // Right is heard first, left comes 15 ms later at the same level.
+        assert_eq!(output[2][1], 1.0);
+        assert!(output
+            .iter()
+            .all(|frame| frame[0] == 0.0 || frame == &output[17]));
+        assert!((output[17][0] - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,212 @@
+//! # Noise reduction
+//!
+//! Cleanups for live input in untreated rooms.
+//!
+//! ## Denoise
+//!
+//! Spectral subtraction: while level of the window is below threshold, there is nothing but
+//! noise to hear, so the op learns its spectrum by averaging magnitudes of bins. Otherwise it
+//! attenuates each bin by how much of it is the learned noise, amount from 0 to 1 sets how deep.
+//! Gains are floored at -20 dB to keep the remaining noise from turning into warbling tones.
+//! Output lags by one window.
+//!
+//! Sources to connect: input, threshold (dB), amount.
+//!
+//! ## Hum
+//!
+//! Mains hum remover: cascade of narrow notches at the mains frequency and its harmonics,
+//! which ground loops and lights put into the signal.
+//!
+//! Sources to connect: input.
+use crate::biquad::make_notch_coefficients;
+use crate::pure;
+use crate::spectral_transform::{Stft, HOP, WINDOW_SIZE};
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+
+/// Share of the new window in the learned noise magnitudes, per hop.
+const LEARN_RATE: Sample = 0.1;
+/// Noise is subtracted twice to clean up its peaks.
+const OVERSUBTRACTION: Sample = 2.0;
+const MIN_GAIN: Sample = 0.1;
+
+pub struct Denoise {
+    /// Learned noise magnitudes of bins up to Nyquist, per channel.
+    noise: [Vec<Sample>; CHANNELS],
+    stft: Stft,
+}
+
+impl Denoise {
+    pub fn new() -> Self {
+        let mut noise: [Vec<Sample>; CHANNELS] = Default::default();
+        for bins in noise.iter_mut() {
+            *bins = vec![0.0; WINDOW_SIZE / 2 + 1];
+        }
+        Denoise {
+            noise,
+            stft: Stft::new(1, WINDOW_SIZE, HOP),
+        }
+    }
+}
+
+impl Default for Denoise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Op for Denoise {
+    fn perform(&mut self, stack: &mut Stack) {
+        let amount = stack.pop();
+        let threshold = stack.pop();
+        let input = stack.pop();
+        let noise = &mut self.noise;
+        let frame = self.stft.process(&[input], |channel, spectra| {
+            let spectrum = &mut spectra[0];
+            let noise = &mut noise[channel];
+            // Parseval with the energy of Hann window, which is 3/8 of its size.
+            let energy = spectrum.iter().map(|x| x.norm_sqr()).sum::<Sample>()
+                / (WINDOW_SIZE * WINDOW_SIZE) as Sample
+                / 0.375;
+            let level = 10.0 * energy.max(1e-12).log10();
+            if level < threshold[channel] {
+                for (noise, bin) in noise.iter_mut().zip(spectrum.iter()) {
+                    *noise += LEARN_RATE * (bin.norm() - *noise);
+                }
+            }
+            let amount = pure::clamp_or(amount[channel], 0.0, 1.0, 0.0);
+            for (noise, bin) in noise.iter().zip(spectrum.iter_mut()) {
+                let magnitude = bin.norm();
+                let gain = if magnitude > 0.0 {
+                    (1.0 - OVERSUBTRACTION * amount * noise / magnitude).max(MIN_GAIN)
+                } else {
+                    1.0
+                };
+                *bin *= gain.min(1.0);
+            }
+        });
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.noise.clone_from(&other.noise);
+            self.stft.migrate(&other.stft);
+        }
+    }
+}
+
+/// Harmonics of the mains frequency to notch out, hum above them is rare.
+const HUM_HARMONICS: usize = 8;
+const HUM_Q: Sample = 30.0;
+
+pub struct Hum {
+    /// Coefficients of notches normalized by a0: b0, b1, b2, a1, a2.
+    coefficients: Vec<[Sample; 5]>,
+    /// Last two inputs and outputs of each notch.
+    state: Vec<[Frame; 4]>,
+}
+
+impl Hum {
+    pub fn new(sample_rate: u32, frequency: Sample) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let coefficients = (1..=HUM_HARMONICS)
+            .map(|k| k as Sample * frequency)
+            .filter(|&f| 0.0 < f && f < 0.45 * sample_rate)
+            .map(|f| {
+                let o = 2.0 * std::f64::consts::PI * f / sample_rate;
+                let alpha = o.sin() / (2.0 * HUM_Q);
+                let (b0, b1, b2, a0, a1, a2) = make_notch_coefficients(o.sin(), o.cos(), alpha);
+                [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+            })
+            .collect::<Vec<_>>();
+        Hum {
+            state: vec![[[0.0; CHANNELS]; 4]; coefficients.len()],
+            coefficients,
+        }
+    }
+}
+
+impl Op for Hum {
+    fn perform(&mut self, stack: &mut Stack) {
+        let mut frame = stack.pop();
+        for ([b0, b1, b2, a1, a2], [x1, x2, y1, y2]) in
+            self.coefficients.iter().zip(self.state.iter_mut())
+        {
+            for channel in 0..CHANNELS {
+                let x = frame[channel];
+                let mut y = b0 * x + b1 * x1[channel] + b2 * x2[channel]
+                    - a1 * y1[channel]
+                    - a2 * y2[channel];
+                // Keep NaN of bad inputs from sticking in filters.
+                if !y.is_finite() {
+                    y = 0.0;
+                }
+                x2[channel] = x1[channel];
+                x1[channel] = if x.is_finite() { x } else { 0.0 };
+                y2[channel] = y1[channel];
+                y1[channel] = y;
+                frame[channel] = y;
+            }
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (state, other) in self.state.iter_mut().zip(&other.state) {
+                *state = *other;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+    use crate::{pure, Fn2, Osc, WhiteNoise};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn sine(frequency: Sample) -> Vec<Box<dyn Op>> {
+        vec![constant(frequency), Box::new(Osc::new(48000, pure::sine))]
+    }
+
+    fn quiet_noise() -> Vec<Box<dyn Op>> {
+        vec![
+            Box::new(WhiteNoise::with_rng(SmallRng::seed_from_u64(0))),
+            constant(0.01),
+            Box::new(Fn2::new(pure::mul)),
+        ]
+    }
+
+    /// RMS of the second half of a second.
+    fn rms(mut ops: Vec<Box<dyn Op>>) -> Sample {
+        let sum = tops(&render(&mut ops, 48000))
+            .iter()
+            .skip(24000)
+            .map(|x| x.powi(2))
+            .sum::<Sample>();
+        (sum / 24000.0).sqrt()
+    }
+
+    fn denoise(mut ops: Vec<Box<dyn Op>>) -> Vec<Box<dyn Op>> {
+        ops.push(constant(-20.0));
+        ops.push(constant(1.0));
+        ops.push(Box::new(Denoise::new()));
+        ops
+    }
+
+    #[test]
+    fn hum_and_denoise_clean_up_input() {
+        let hum = |mut ops: Vec<Box<dyn Op>>| {
+            ops.push(Box::new(Hum::new(48000, 60.0)));
+            ops
+        };
+        // Notch rings out in a fraction of a second, then the hum is 30 dB down.
+        assert!(rms(hum(sine(60.0))) < 0.02);
+        assert!(rms(hum(sine(1000.0))) > 0.69);
+        // Quiet noise is learned and removed down to the floor of -20 dB, loud tone passes.
+        assert!(rms(denoise(quiet_noise())) < 0.12 * rms(quiet_noise()));
+        assert!(rms(denoise(sine(440.0))) > 0.69);
+    }
+}
@@ -0,0 +1,71 @@
+//! # Dry/wet
+//!
+//! Mix knob for any effect chain: `dry` remembers the signal entering the chain and the matching
+//! `wet` after the chain crossfades from it to the chain output. The chain keeps running at any
+//! mix, so bypassing it with 0 and bringing it back keeps tails and phases intact.
+//!
+//! `probe` remembers the signal the same way as `dry` and `solo` pushes it, to listen to any
+//! point of the program in isolation while the rest of it keeps running.
+//!
+//! Sources to connect: x for `dry` and `probe`; chain output and mix for `wet`.
+use audio_vm::{Frame, Op, Stack};
+use itertools::izip;
+use std::sync::{Arc, Mutex};
+
+pub struct Dry {
+    send: Arc<Mutex<Frame>>,
+}
+
+impl Dry {
+    pub fn new(send: Arc<Mutex<Frame>>) -> Self {
+        Dry { send }
+    }
+}
+
+impl Op for Dry {
+    fn perform(&mut self, stack: &mut Stack) {
+        let x = stack.pop();
+        *self.send.lock().unwrap() = x;
+        stack.push(&x);
+    }
+}
+
+pub struct Wet {
+    send: Arc<Mutex<Frame>>,
+}
+
+impl Wet {
+    pub fn new(send: Arc<Mutex<Frame>>) -> Self {
+        Wet { send }
+    }
+}
+
+impl Op for Wet {
+    fn perform(&mut self, stack: &mut Stack) {
+        let mix = stack.pop();
+        let wet = stack.pop();
+        let dry = *self.send.lock().unwrap();
+        let mut frame = dry;
+        for (output, &dry, &wet, &mix) in izip!(&mut frame, &dry, &wet, &mix) {
+            *output = (1.0 - mix) * dry + mix * wet;
+        }
+        stack.push(&frame);
+    }
+}
+
+pub struct Solo {
+    send: Arc<Mutex<Frame>>,
+}
+
+impl Solo {
+    pub fn new(send: Arc<Mutex<Frame>>) -> Self {
+        Solo { send }
+    }
+}
+
+impl Op for Solo {
+    fn perform(&mut self, stack: &mut Stack) {
+        let frame = *self.send.lock().unwrap();
+        stack.push(&frame);
+    }
+}
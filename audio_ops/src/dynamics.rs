@@ -0,0 +1,576 @@
+//! # Dynamics
+//!
+//! ## Compressor
+//!
+//! Feed-forward compressor with gain computer and smooth branching peak detector
+//! both working on decibels, as in "Digital Dynamic Range Compressor Design — A Tutorial
+//! and Analysis" by Giannoulis, Massberg and Reiss. Detecting level in log domain makes attack
+//! and release sound the same regardless of how deep the gain reduction is.
+//! Channels are linked by the loudest one to keep stereo image in place.
+//!
+//! Sources to connect: input, threshold (dB), ratio, attack (s), release (s), makeup gain (dB).
+//!
+//! Sidechain compressor detects level of a separate key signal instead of the input,
+//! e.g. to duck pads by kick.
+//!
+//! Sources to connect: input, key, threshold (dB), ratio, attack (s), release (s),
+//! makeup gain (dB).
+//!
+//! ## Limiter
+//!
+//! Brickwall limiter with lookahead: input is delayed by lookahead time, while gain
+//! needed to keep every frame under the ceiling is held at its minimum over the lookahead window
+//! and then smoothed by moving average of the same length. This way gain reaches the required
+//! value right when the peak leaves the delay line, so output never exceeds the ceiling
+//! and there are no clicks of instant gain changes. Gain recovers exponentially afterwards.
+//!
+//! Sources to connect: input, ceiling (dB).
+//!
+//! ## Gate
+//!
+//! Noise gate and downward expander. Gate silences input while its level is below threshold,
+//! expander attenuates it by ratio instead, i.e. with ratio 2 every dB below threshold becomes
+//! two dB. Gain stays open for hold time after level falls below threshold to avoid chattering.
+//! Channels are linked.
+//!
+//! Sources to connect: input, threshold (dB), attack (s), hold (s), release (s).
+//!
+//! ## Glue
+//!
+//! Drum bus chain to drop right before the output: high-pass at 30 Hz to keep rumble from
+//! pumping the rest, gentle compression with slow attack to let transients through and soft
+//! saturation. Glue from 0 to 1 lowers threshold down to -24 dB and raises ratio up to 4,
+//! with makeup gain for half of the reduction at threshold. Drive from 0 to 1 pushes signal
+//! into tanh, peaks at 0 dB stay at 0 dB. Channels are linked.
+//!
+//! Sources to connect: input, glue, drive.
+//!
+//! ## Stabilizer
+//!
+//! Guard for feedback loops. It follows short-term and long-term power of the signal and pulls
+//! the gain down smoothly when the short-term one is above the ceiling or grows more than
+//! `STAB_GROWTH` dB over the long-term one, i.e. when the loop starts to run away. Recovery is
+//! slow, so the loop settles at the edge instead of pumping. Channels are linked.
+//!
+//! Sources to connect: input, ceiling (dB).
+use crate::buffer::Buffer;
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use std::collections::VecDeque;
+
+/// Floor for level detection to avoid log of zero, -120 dB.
+const MIN_AMPLITUDE: Sample = 1e-6;
+
+pub struct Compressor {
+    /// Smoothed gain reduction in dB.
+    reduction: Sample,
+    sample_rate: Sample,
+    sidechain: bool,
+}
+
+impl Compressor {
+    pub fn new(sample_rate: u32, sidechain: bool) -> Self {
+        Compressor {
+            reduction: 0.0,
+            sample_rate: Sample::from(sample_rate),
+            sidechain,
+        }
+    }
+
+    #[inline]
+    fn coefficient(&self, time: Sample) -> Sample {
+        if time > 0.0 {
+            (-1.0 / (time * self.sample_rate)).exp()
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Op for Compressor {
+    fn perform(&mut self, stack: &mut Stack) {
+        let makeup = stack.pop();
+        let release = stack.pop();
+        let attack = stack.pop();
+        let ratio = stack.pop();
+        let threshold = stack.pop();
+        let key = if self.sidechain {
+            Some(stack.pop())
+        } else {
+            None
+        };
+        let input = stack.pop();
+        // Parameters are linked as well as channels.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let threshold = mean(threshold);
+        let ratio = mean(ratio).max(1.0);
+        let level = key
+            .unwrap_or(input)
+            .iter()
+            .fold(MIN_AMPLITUDE, |level, x| level.max(x.abs()));
+        let level = 20.0 * level.log10();
+        let target = if level > threshold {
+            (level - threshold) * (1.0 - 1.0 / ratio)
+        } else {
+            0.0
+        };
+        let a = if target > self.reduction {
+            self.coefficient(mean(attack))
+        } else {
+            self.coefficient(mean(release))
+        };
+        self.reduction = a * self.reduction + (1.0 - a) * target;
+        let mut frame = [0.0; CHANNELS];
+        for (output, &x, &makeup) in izip!(&mut frame, &input, &makeup) {
+            *output = x * 10.0f64.powf((makeup - self.reduction) / 20.0);
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.reduction = other.reduction;
+        }
+    }
+}
+
+/// Time to recover from gain reduction, in seconds.
+const LIMITER_RELEASE: Sample = 0.1;
+
+pub struct Limiter {
+    delay: Buffer<Frame>,
+    /// Required gains in the lookahead window as (frame number, gain),
+    /// ascending in both, so the front is the minimum.
+    hold: VecDeque<(usize, Sample)>,
+    /// Released gains to average.
+    gains: Buffer<Sample>,
+    sum: Sample,
+    frame_number: usize,
+    lookahead: usize,
+    release: Sample,
+    envelope: Sample,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32, lookahead: f64) -> Self {
+        let lookahead = ((Sample::from(sample_rate) * lookahead) as usize).max(1);
+        Limiter {
+            delay: Buffer::new([0.0; CHANNELS], lookahead),
+            hold: VecDeque::with_capacity(lookahead + 1),
+            gains: Buffer::new(1.0, lookahead),
+            sum: lookahead as Sample,
+            frame_number: 0,
+            lookahead,
+            release: (-1.0 / (LIMITER_RELEASE * Sample::from(sample_rate))).exp(),
+            envelope: 1.0,
+        }
+    }
+}
+
+impl Op for Limiter {
+    fn perform(&mut self, stack: &mut Stack) {
+        let ceiling = stack.pop();
+        let input = stack.pop();
+        let ceiling = 10.0f64.powf(ceiling.iter().sum::<Sample>() / CHANNELS as Sample / 20.0);
+        // Fail safe to 0 dB on NaN ceiling.
+        let ceiling = if ceiling.is_nan() { 1.0 } else { ceiling };
+        let level = input
+            .iter()
+            .fold(0.0, |level: Sample, x| level.max(x.abs()));
+        let gain = if level > ceiling {
+            ceiling / level
+        } else {
+            1.0
+        };
+
+        while let Some(&(_, x)) = self.hold.back() {
+            if x < gain {
+                break;
+            }
+            self.hold.pop_back();
+        }
+        self.hold.push_back((self.frame_number, gain));
+        while self.hold[0].0 + self.lookahead < self.frame_number {
+            self.hold.pop_front();
+        }
+        self.frame_number += 1;
+        let gain = self.hold[0].1;
+
+        // Attack is instant here, the moving average makes it smooth.
+        self.envelope = gain.min(self.release * self.envelope + (1.0 - self.release) * gain);
+        self.sum += self.envelope - self.gains[0];
+        self.gains.push_back(self.envelope);
+        // Running sum could drift a bit, clipping keeps the promise.
+        let gain = (self.sum / self.lookahead as Sample).min(1.0);
+
+        let mut frame = self.delay[0];
+        self.delay.push_back(input);
+        for sample in frame.iter_mut() {
+            *sample = pure::clamp(*sample * gain, -ceiling, ceiling);
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.lookahead == other.lookahead {
+                self.delay.copy_backward(&other.delay);
+                self.hold.clone_from(&other.hold);
+                self.gains.copy_backward(&other.gains);
+                self.sum = other.sum;
+                self.frame_number = other.frame_number;
+                self.envelope = other.envelope;
+            }
+        }
+    }
+}
+
+/// Release of the gate's peak detector, short enough to follow the envelope
+/// but long enough to ride over zero crossings of low frequencies.
+const GATE_DETECTOR_RELEASE: Sample = 0.01;
+
+pub struct Gate {
+    /// Infinite for gate, finite for expander.
+    ratio: Sample,
+    level: Sample,
+    gain: Sample,
+    /// Frames to keep gate open.
+    countdown: Sample,
+    detector_release: Sample,
+    sample_rate: Sample,
+}
+
+impl Gate {
+    pub fn new(sample_rate: u32, ratio: f64) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        Gate {
+            ratio: ratio.max(1.0),
+            level: 0.0,
+            gain: 0.0,
+            countdown: 0.0,
+            detector_release: (-1.0 / (GATE_DETECTOR_RELEASE * sample_rate)).exp(),
+            sample_rate,
+        }
+    }
+
+    #[inline]
+    fn coefficient(&self, time: Sample) -> Sample {
+        if time > 0.0 {
+            (-1.0 / (time * self.sample_rate)).exp()
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Op for Gate {
+    fn perform(&mut self, stack: &mut Stack) {
+        let release = stack.pop();
+        let hold = stack.pop();
+        let attack = stack.pop();
+        let threshold = stack.pop();
+        let input = stack.pop();
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let peak = input.iter().fold(0.0, |peak: Sample, x| peak.max(x.abs()));
+        self.level = peak.max(self.detector_release * self.level);
+        let level = 20.0 * self.level.max(MIN_AMPLITUDE).log10();
+        let threshold = mean(threshold);
+        let target = if level >= threshold {
+            self.countdown = mean(hold) * self.sample_rate;
+            1.0
+        } else if self.countdown > 0.0 {
+            self.countdown -= 1.0;
+            1.0
+        } else if self.ratio.is_infinite() {
+            0.0
+        } else {
+            10.0f64.powf((level - threshold) * (self.ratio - 1.0) / 20.0)
+        };
+        let a = if target > self.gain {
+            self.coefficient(mean(attack))
+        } else {
+            self.coefficient(mean(release))
+        };
+        self.gain = a * self.gain + (1.0 - a) * target;
+        let mut frame = [0.0; CHANNELS];
+        for (output, &x) in frame.iter_mut().zip(&input) {
+            *output = x * self.gain;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.level = other.level;
+            self.gain = other.gain;
+            self.countdown = other.countdown;
+        }
+    }
+}
+
+const GLUE_HIGHPASS: Sample = 30.0;
+const GLUE_ATTACK: Sample = 0.01;
+const GLUE_RELEASE: Sample = 0.2;
+
+pub struct Glue {
+    attack: Sample,
+    /// Last input and output of the high-pass filter.
+    highpass: (Frame, Frame),
+    highpass_coefficient: Sample,
+    /// Smoothed gain reduction in dB.
+    reduction: Sample,
+    release: Sample,
+}
+
+impl Glue {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let coefficient = |time: Sample| (-1.0 / (time * sample_rate)).exp();
+        Glue {
+            attack: coefficient(GLUE_ATTACK),
+            highpass: ([0.0; CHANNELS], [0.0; CHANNELS]),
+            highpass_coefficient: 1.0
+                / (2.0 * std::f64::consts::PI * GLUE_HIGHPASS / sample_rate + 1.0),
+            reduction: 0.0,
+            release: coefficient(GLUE_RELEASE),
+        }
+    }
+}
+
+impl Op for Glue {
+    fn perform(&mut self, stack: &mut Stack) {
+        let drive = stack.pop();
+        let glue = stack.pop();
+        let input = stack.pop();
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let (last_input, output) = &mut self.highpass;
+        for (output, &x, last_input) in izip!(output.iter_mut(), &input, last_input.iter_mut()) {
+            *output = self.highpass_coefficient * (*output + x - *last_input);
+            *last_input = x;
+            // Keep NaN of bad inputs from sticking in the filter.
+            if !output.is_finite() {
+                *output = 0.0;
+                *last_input = 0.0;
+            }
+        }
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        let glue = unit(mean(glue));
+        let threshold = -24.0 * glue;
+        let slope = 1.0 - 1.0 / (1.0 + 3.0 * glue);
+        let level = output
+            .iter()
+            .fold(MIN_AMPLITUDE, |level, x| level.max(x.abs()));
+        let level = 20.0 * level.log10();
+        let target = (level - threshold).max(0.0) * slope;
+        let a = if target > self.reduction {
+            self.attack
+        } else {
+            self.release
+        };
+        self.reduction = a * self.reduction + (1.0 - a) * target;
+        let makeup = -0.5 * threshold * slope;
+        let gain = 10.0f64.powf((makeup - self.reduction) / 20.0);
+        let mut frame = [0.0; CHANNELS];
+        for (y, &x, &drive) in izip!(&mut frame, output.iter(), &drive) {
+            let drive = 0.1 + 3.9 * unit(drive);
+            *y = (drive * gain * x).tanh() / drive.tanh();
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.highpass = other.highpass;
+            self.reduction = other.reduction;
+        }
+    }
+}
+
+/// Growth of short-term power over long-term one allowed before reduction, in dB.
+const STAB_GROWTH: Sample = 6.0;
+const STAB_FAST: Sample = 0.01;
+const STAB_SLOW: Sample = 0.5;
+const STAB_ATTACK: Sample = 0.01;
+const STAB_RELEASE: Sample = 1.0;
+
+pub struct Stabilizer {
+    /// Short-term and long-term power.
+    power: (Sample, Sample),
+    /// Smoothed gain reduction in dB.
+    reduction: Sample,
+    /// Coefficients of one-pole smoothers for fast and slow power, attack and release.
+    coefficients: [Sample; 4],
+}
+
+impl Stabilizer {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let coefficient = |time: Sample| (-1.0 / (time * sample_rate)).exp();
+        Stabilizer {
+            power: (0.0, 0.0),
+            reduction: 0.0,
+            coefficients: [
+                coefficient(STAB_FAST),
+                coefficient(STAB_SLOW),
+                coefficient(STAB_ATTACK),
+                coefficient(STAB_RELEASE),
+            ],
+        }
+    }
+}
+
+impl Op for Stabilizer {
+    fn perform(&mut self, stack: &mut Stack) {
+        let ceiling = stack.pop();
+        let input = stack.pop();
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let [fast, slow, attack, release] = self.coefficients;
+        let power = input.iter().fold(0.0, |power: Sample, x| power.max(x * x));
+        // Keep NaN and infinity of the runaway loop from sticking in the detector.
+        let power = if power.is_finite() { power } else { 1.0 };
+        self.power.0 = fast * self.power.0 + (1.0 - fast) * power;
+        self.power.1 = slow * self.power.1 + (1.0 - slow) * power;
+        let db = |power: Sample| 10.0 * power.max(MIN_AMPLITUDE * MIN_AMPLITUDE).log10();
+        let level = db(self.power.0);
+        let growth = level - db(self.power.1);
+        let target = (level - mean(ceiling)).max(0.0) + (growth - STAB_GROWTH).max(0.0);
+        let a = if target > self.reduction {
+            attack
+        } else {
+            release
+        };
+        self.reduction = a * self.reduction + (1.0 - a) * target;
+        let gain = 10.0f64.powf(-self.reduction / 20.0);
+        let mut frame = [0.0; CHANNELS];
+        for (y, &x) in frame.iter_mut().zip(&input) {
+            *y = if x.is_finite() { x * gain } else { 0.0 };
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.power = other.power;
+            self.reduction = other.reduction;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, migrate, peak, render, tops};
+    use crate::{pure, Fn1, Fn2, Metro, Osc, Phasor, TableReader, TableWriter, WhiteNoise};
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn compressor_reduces_level_above_threshold_by_ratio() {
+        let compress = |amplitude| {
+            let mut ops = vec![constant(amplitude), constant(-20.0), constant(4.0)];
+            ops.extend(vec![constant(0.001), constant(0.1), constant(0.0)]);
+            ops.push(Box::new(Compressor::new(48000, false)));
+            tops(&render(&mut ops, 4800))[4799]
+        };
+        // 20 dB over threshold becomes 5 dB over it.
+        assert!((compress(1.0) - 10.0f64.powf(-15.0 / 20.0)).abs() < 1e-6);
+        // Below threshold it's transparent.
+        assert!((compress(0.05) - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn duck_reduces_input_by_level_of_key() {
+        let duck = |key| {
+            let mut ops = vec![constant(0.5), constant(key), constant(-20.0), constant(4.0)];
+            ops.extend(vec![constant(0.001), constant(0.1), constant(0.0)]);
+            ops.push(Box::new(Compressor::new(48000, true)));
+            tops(&render(&mut ops, 4800))[4799]
+        };
+        // Key 20 dB over threshold takes 15 dB off the input.
+        assert!((duck(1.0) - 0.5 * 10.0f64.powf(-15.0 / 20.0)).abs() < 1e-6);
+        // Silent key leaves the input as is.
+        assert!((duck(0.0) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn limiter_keeps_hot_input_under_ceiling() {
+        let mut ops = vec![constant(440.0)];
+        ops.push(Box::new(Osc::new(48000, pure::sine)));
+        ops.push(constant(4.0));
+        ops.push(Box::new(Fn2::new(pure::mul)));
+        ops.push(constant(-6.0));
+        ops.push(Box::new(Limiter::new(48000, 0.005)));
+        let output = tops(&render(&mut ops, 48000));
+        let ceiling = 10.0f64.powf(-6.0 / 20.0);
+        assert!(peak(&output) <= ceiling);
+        // Gain settles right under the ceiling.
+        assert!(peak(&output[24000..]) > 0.9 * ceiling);
+    }
+
+    #[test]
+    fn gate_opens_above_threshold_and_closes_after_hold() {
+        let gate = |amplitude| {
+            let mut ops = vec![constant(amplitude), constant(-40.0), constant(0.001)];
+            ops.extend(vec![constant(0.05), constant(0.01)]);
+            ops.push(Box::new(Gate::new(48000, f64::INFINITY)));
+            ops
+        };
+        let mut ops = gate(0.5);
+        assert!((tops(&render(&mut ops, 4800))[4799] - 0.5).abs() < 1e-6);
+        // Input drops to -60 dB, the gate holds it open for a while and then silences it.
+        let mut next = gate(0.001);
+        migrate(&mut next, &ops);
+        let output = tops(&render(&mut next, 9600));
+        assert!((output[2400] - 0.001).abs() < 1e-9);
+        assert!(output[9599] < 1e-6);
+    }
+
+    #[test]
+    fn glue_compresses_and_blocks_dc() {
+        let glue = |amplitude, glue| {
+            let mut ops = vec![constant(440.0)];
+            ops.push(Box::new(Osc::new(48000, pure::sine)));
+            ops.push(constant(amplitude));
+            ops.push(Box::new(Fn2::new(pure::mul)));
+            ops.push(constant(glue));
+            ops.push(constant(0.0));
+            ops.push(Box::new(Glue::new(48000)));
+            peak(&tops(&render(&mut ops, 48000))[24000..])
+        };
+        // Without glue and drive it's transparent.
+        assert!((glue(0.5, 0.0) - 0.5).abs() < 0.01);
+        // Up to 18 dB of reduction at 0 dB peaks minus 9 dB of makeup, sine slopes detect lower.
+        let glued = glue(1.0, 1.0);
+        assert!(glued > 10.0f64.powf(-9.0 / 20.0) && glued < 0.45);
+        let mut dc = vec![constant(1.0), constant(1.0), constant(1.0)];
+        dc.push(Box::new(Glue::new(48000)));
+        assert!(peak(&tops(&render(&mut dc, 48000))[24000..]) < 1e-3);
+    }
+
+    #[test]
+    fn stab_tames_runaway_feedback() {
+        // Loop through the table of 0.1 s with gain of 1.5 grows by 3.5 dB on each pass.
+        let table = Arc::new(Mutex::new(vec![[0.0; CHANNELS]; 4800]));
+        let mut ops = vec![constant(0.001)];
+        ops.push(Box::new(WhiteNoise::with_rng(SmallRng::seed_from_u64(1))));
+        ops.push(Box::new(Fn2::new(pure::mul)));
+        ops.push(constant(10.0));
+        ops.push(Box::new(Phasor::new(48000)));
+        ops.push(Box::new(Fn1::new(pure::unit)));
+        ops.push(constant(0.1));
+        ops.push(Box::new(Fn2::new(pure::mul)));
+        ops.push(Box::new(TableReader::new(48000, Arc::clone(&table))));
+        ops.push(constant(1.5));
+        ops.push(Box::new(Fn2::new(pure::mul)));
+        ops.push(Box::new(Fn2::new(pure::add)));
+        ops.push(constant(-12.0));
+        ops.push(Box::new(Stabilizer::new(48000)));
+        ops.push(constant(10.0));
+        ops.push(Box::new(Metro::new(48000)));
+        ops.push(Box::new(TableWriter::new(table, 0)));
+        let peak = peak(&tops(&render(&mut ops, 10 * 48000))[9 * 48000..]);
+        // Without stab it would be hundreds of dB up by now.
+        assert!(peak > 0.01 && peak < 1.0, "{}", peak);
+    }
+}
@@ -0,0 +1,63 @@
+//! # Each
+//!
+//! Run a sub-program independently for each channel: every channel gets its own copy of the
+//! sub-program with its own op state, fed with inputs of that channel broadcast to all lanes,
+//! and contributes only its own lane of the outputs. Noise, drifting LFOs and filters inside
+//! differ between left and right, which makes true stereo out of mono patches.
+//!
+//! Sources to connect: as many inputs as the sub-program takes.
+use audio_vm::{Frame, Op, Program, Stack, CHANNELS};
+
+pub struct Each {
+    inputs: Vec<Frame>,
+    outputs: Vec<Frame>,
+    /// Copies of the sub-program, one per channel.
+    programs: Vec<Program>,
+    stack: Stack,
+}
+
+impl Each {
+    pub fn new(programs: Vec<Program>, inputs: usize, outputs: usize) -> Self {
+        Each {
+            inputs: vec![[0.0; CHANNELS]; inputs],
+            outputs: vec![[0.0; CHANNELS]; outputs],
+            programs,
+            stack: Stack::new(),
+        }
+    }
+}
+
+impl Op for Each {
+    fn perform(&mut self, stack: &mut Stack) {
+        for input in self.inputs.iter_mut().rev() {
+            *input = stack.pop();
+        }
+        for (channel, program) in self.programs.iter_mut().enumerate() {
+            self.stack.reset();
+            for input in &self.inputs {
+                self.stack.push(&[input[channel]; CHANNELS]);
+            }
+            for statement in program.iter_mut() {
+                statement.op.perform(&mut self.stack);
+            }
+            for output in self.outputs.iter_mut().rev() {
+                output[channel] = self.stack.pop()[channel];
+            }
+        }
+        for output in &self.outputs {
+            stack.push(output);
+        }
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (program, other) in self.programs.iter_mut().zip(&other.programs) {
+                for statement in program.iter_mut() {
+                    if let Some(other) = other.iter().find(|other| other.id == statement.id) {
+                        statement.op.migrate(&other.op);
+                    }
+                }
+            }
+        }
+    }
+}
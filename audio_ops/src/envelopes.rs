@@ -1,5 +1,21 @@
 mod adsr;
+mod ar;
+mod envgen;
 mod impulse;
 
 pub use self::adsr::*;
+pub use self::ar::*;
+pub use self::envgen::*;
 pub use self::impulse::*;
+
+use audio_vm::Sample;
+
+/// Bend progress `x` from 0 to 1 by the curve `c`: 0 is linear, positive curves start slow
+/// and end fast, negative ones start fast and end slow.
+fn bend(x: Sample, c: Sample) -> Sample {
+    if c.abs() < 1e-3 {
+        x
+    } else {
+        (c * x).exp_m1() / c.exp_m1()
+    }
+}
@@ -1,91 +1,147 @@
+//! # ADSR
+//!
+//! Gate driven envelope with exponential segments like analog ones have: attack rises towards
+//! a target above 1 to end with a sharp knee, decay and release fall towards targets slightly
+//! below sustain and 0 to finish in time. Segment times are in seconds and read every frame.
+//!
+//! Gate rising while the envelope is still sounding starts attack from the current level instead
+//! of jumping to 0, so retriggering doesn't click. Stage and level survive commits.
+//!
+//! Sources to connect: gate, attack, decay, sustain, release.
+use crate::pure;
 use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use itertools::izip;
 
+/// How far above 1 attack aims, relative to 1. Smaller values give more curved attack.
+const ATTACK_TARGET_RATIO: Sample = 0.3;
+/// How far below their ends decay and release aim, relative to 1.
+const DECAY_TARGET_RATIO: Sample = 0.0001;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
 pub struct ADSR {
-    frame: usize,
-    gate_frame_on: [usize; CHANNELS],
-    gate_frame_off: [usize; CHANNELS],
     last_gate: Frame,
-    sample_period: Sample,
+    level: Frame,
+    sample_rate: Sample,
+    stage: [Stage; CHANNELS],
 }
 
 impl ADSR {
     pub fn new(sample_rate: u32) -> Self {
         ADSR {
-            frame: 0,
-            gate_frame_on: [0; CHANNELS],
-            gate_frame_off: [0; CHANNELS],
             last_gate: [0.0; CHANNELS],
-            sample_period: Sample::from(sample_rate).recip(),
+            level: [0.0; CHANNELS],
+            sample_rate: Sample::from(sample_rate),
+            stage: [Stage::Idle; CHANNELS],
+        }
+    }
+
+    /// One-pole coefficient to cover the distance to the target in `time` seconds when it
+    /// lies `ratio` beyond the end of the segment.
+    fn coefficient(&self, time: Sample, ratio: Sample) -> Sample {
+        if time > 0.0 {
+            (-((1.0 + ratio) / ratio).ln() / (time * self.sample_rate)).exp()
+        } else {
+            0.0
         }
     }
 }
 
 impl Op for ADSR {
     fn perform(&mut self, stack: &mut Stack) {
-        let mut frame = [0.0; CHANNELS];
         let r = stack.pop();
         let s = stack.pop();
         let d = stack.pop();
         let a = stack.pop();
         let gate = stack.pop();
-        let now = self.frame as Sample * self.sample_period;
-        for (output, &gate, &a, &d, &s, &r, last_gate, gate_frame_on, gate_frame_off) in izip!(
-            &mut frame,
-            &gate,
-            &a,
-            &d,
-            &s,
-            &r,
-            &mut self.last_gate,
-            &mut self.gate_frame_on,
-            &mut self.gate_frame_off
-        ) {
-            if *last_gate <= 0.0 && gate > 0.0 {
-                *gate_frame_on = self.frame;
+        for (channel, (&gate, &a, &d, &s, &r)) in izip!(&gate, &a, &d, &s, &r).enumerate() {
+            let s = pure::clamp_or(s, 0.0, 1.0, 0.0);
+            let last_gate = self.last_gate[channel];
+            let stage = &mut self.stage[channel];
+            if last_gate <= 0.0 && gate > 0.0 {
+                *stage = Stage::Attack;
             }
-            if *last_gate > 0.0 && gate <= 0.0 {
-                *gate_frame_off = self.frame;
-            }
-            *last_gate = gate;
-
-            let on = *gate_frame_on as Sample * self.sample_period;
-            let delta = now - on;
-
-            if delta <= a {
-                *output = delta / a;
-                continue;
-            }
-
-            let delta = delta - a;
-
-            if delta <= d {
-                *output = 1.0 - (1.0 - s) * delta / d;
-                continue;
-            }
-
-            if gate > 0.0 {
-                *output = s;
-                continue;
-            }
-
-            let off = *gate_frame_off as Sample * self.sample_period;
-            let delta = now - off.max(on + a + d);
-
-            if delta <= r {
-                *output = s * (1.0 - delta / r);
+            if last_gate > 0.0 && gate <= 0.0 {
+                *stage = Stage::Release;
             }
+            self.last_gate[channel] = gate;
+            let level = self.level[channel];
+            let level = match *stage {
+                Stage::Idle => 0.0,
+                Stage::Attack => {
+                    let c = self.coefficient(a, ATTACK_TARGET_RATIO);
+                    let level = (1.0 + ATTACK_TARGET_RATIO) * (1.0 - c) + level * c;
+                    if level >= 1.0 {
+                        self.stage[channel] = Stage::Decay;
+                        1.0
+                    } else {
+                        level
+                    }
+                }
+                Stage::Decay => {
+                    let c = self.coefficient(d, DECAY_TARGET_RATIO);
+                    let level = (s - DECAY_TARGET_RATIO) * (1.0 - c) + level * c;
+                    if level <= s {
+                        self.stage[channel] = Stage::Sustain;
+                        s
+                    } else {
+                        level
+                    }
+                }
+                Stage::Sustain => s,
+                Stage::Release => {
+                    let c = self.coefficient(r, DECAY_TARGET_RATIO);
+                    let level = -DECAY_TARGET_RATIO * (1.0 - c) + level * c;
+                    if level <= 0.0 {
+                        self.stage[channel] = Stage::Idle;
+                        0.0
+                    } else {
+                        level
+                    }
+                }
+            };
+            // Keep NaN of bad times from sticking.
+            self.level[channel] = if level.is_finite() { level } else { 0.0 };
         }
-        self.frame += 1;
-        stack.push(&frame);
+        stack.push(&self.level);
     }
 
     fn migrate(&mut self, other: &Box<dyn Op>) {
         if let Some(other) = other.downcast_ref::<Self>() {
-            self.frame = other.frame;
-            self.gate_frame_on = other.gate_frame_on;
-            self.gate_frame_off = other.gate_frame_off;
             self.last_gate = other.last_gate;
+            self.level = other.level;
+            self.stage = other.stage;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, migrate, render};
+
+    #[test]
+    fn keeps_its_stage_across_commits() {
+        let adsr = || {
+            let mut ops = [1.0, 0.01, 0.01, 0.5, 0.1]
+                .iter()
+                .map(|&x| constant(x))
+                .collect::<Vec<_>>();
+            ops.push(Box::new(ADSR::new(1000)));
+            ops
+        };
+        let mut ops = adsr();
+        assert_eq!(render(&mut ops, 100)[99], [[0.5; CHANNELS]]);
+        let mut next = adsr();
+        migrate(&mut next, &ops);
+        // Gate stays high, so no attack starts over from the new program.
+        assert_eq!(render(&mut next, 1)[0], [[0.5; CHANNELS]]);
+    }
+}
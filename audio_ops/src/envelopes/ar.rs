@@ -0,0 +1,121 @@
+//! # Attack-release envelope
+//!
+//! Trigger starts attack from the current level up to 1, release falls back to 0 right after
+//! it, so a pulse from `m` is enough to play a hit. Times are in seconds. Curve bends both
+//! segments like `envgen` does: negative curve makes them snappy, rising and falling fast
+//! first, which suits percussion; positive curve makes them swell instead, 0 is linear.
+//!
+//! Sources to connect: trigger, attack, release, curve.
+use super::bend;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Release,
+}
+
+pub struct AR {
+    /// Seconds spent in the current stage.
+    elapsed: Frame,
+    last_trigger: Frame,
+    level: Frame,
+    sample_period: Sample,
+    stage: [Stage; CHANNELS],
+    /// Level the attack started from.
+    start: Frame,
+}
+
+impl AR {
+    pub fn new(sample_rate: u32) -> Self {
+        AR {
+            elapsed: [0.0; CHANNELS],
+            last_trigger: [0.0; CHANNELS],
+            level: [0.0; CHANNELS],
+            sample_period: Sample::from(sample_rate).recip(),
+            stage: [Stage::Idle; CHANNELS],
+            start: [0.0; CHANNELS],
+        }
+    }
+}
+
+impl Op for AR {
+    fn perform(&mut self, stack: &mut Stack) {
+        let curve = stack.pop();
+        let release = stack.pop();
+        let attack = stack.pop();
+        let trigger = stack.pop();
+        for channel in 0..CHANNELS {
+            if self.last_trigger[channel] <= 0.0 && trigger[channel] > 0.0 {
+                self.stage[channel] = Stage::Attack;
+                self.start[channel] = self.level[channel];
+                self.elapsed[channel] = 0.0;
+            }
+            self.last_trigger[channel] = trigger[channel];
+            let c = curve[channel];
+            if self.stage[channel] == Stage::Attack {
+                let time = attack[channel].max(0.0);
+                if self.elapsed[channel] < time {
+                    let x = bend(self.elapsed[channel] / time, c);
+                    self.level[channel] = self.start[channel] + (1.0 - self.start[channel]) * x;
+                } else {
+                    self.stage[channel] = Stage::Release;
+                    self.elapsed[channel] -= time;
+                }
+            }
+            if self.stage[channel] == Stage::Release {
+                let time = release[channel].max(0.0);
+                if self.elapsed[channel] < time {
+                    self.level[channel] = 1.0 - bend(self.elapsed[channel] / time, c);
+                } else {
+                    self.stage[channel] = Stage::Idle;
+                }
+            }
+            if self.stage[channel] == Stage::Idle {
+                self.level[channel] = 0.0;
+            } else {
+                self.elapsed[channel] += self.sample_period;
+            }
+            // Keep NaN of bad inputs from sticking.
+            if !self.level[channel].is_finite() {
+                self.level[channel] = 0.0;
+            }
+        }
+        stack.push(&self.level);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.elapsed = other.elapsed;
+            self.last_trigger = other.last_trigger;
+            self.level = other.level;
+            self.stage = other.stage;
+            self.start = other.start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+
+    #[test]
+    fn plays_a_hit_on_trigger() {
+        let mut ops = vec![
+            constant(1.0),
+            constant(0.01),
+            constant(0.02),
+            constant(-4.0),
+        ];
+        ops.push(Box::new(AR::new(1000)));
+        let levels = tops(&render(&mut ops, 40));
+        assert_eq!(levels[0], 0.0);
+        assert!(levels[5] > 0.75);
+        assert!((levels[10] - 1.0).abs() < 1e-9);
+        // Negative curve falls fast first.
+        assert!(levels[20] < 0.25);
+        assert_eq!(levels[39], 0.0);
+    }
+}
@@ -0,0 +1,167 @@
+//! # Envelope generator
+//!
+//! Multi-segment breakpoint envelope. Trigger starts the first segment from the current level,
+//! each segment goes to its level in its time (seconds) and the last level is held until the
+//! next trigger. Curve bends a segment: 0 is linear, positive values start slow and end fast,
+//! negative ones start fast and end slow, like charging capacitors do.
+//!
+//! Segments come from the stack or from frames of a table, each three frames are level, time
+//! and curve of a segment, and changes of the table apply right away.
+//!
+//! Sources to connect: trigger, then level, time and curve of each segment (unless they are in
+//! a table).
+use super::bend;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+/// Table frames beyond that many segments are not played.
+const MAX_TABLE_SEGMENTS: usize = 64;
+
+pub struct EnvGen {
+    /// Seconds spent in the current segment.
+    elapsed: Frame,
+    last_trigger: Frame,
+    level: Frame,
+    sample_period: Sample,
+    /// Current segment, past the last one when the envelope is done.
+    segment: [usize; CHANNELS],
+    /// Levels, times and curves of segments, reused to avoid allocations on each frame.
+    segments: Vec<(Frame, Frame, Frame)>,
+    /// Level the current segment started from.
+    start: Frame,
+    table: Option<Arc<Mutex<Vec<Frame>>>>,
+}
+
+impl EnvGen {
+    pub fn new(sample_rate: u32, segments: usize) -> Self {
+        EnvGen {
+            elapsed: [0.0; CHANNELS],
+            last_trigger: [0.0; CHANNELS],
+            level: [0.0; CHANNELS],
+            sample_period: Sample::from(sample_rate).recip(),
+            segment: [segments; CHANNELS],
+            segments: vec![([0.0; CHANNELS], [0.0; CHANNELS], [0.0; CHANNELS]); segments],
+            start: [0.0; CHANNELS],
+            table: None,
+        }
+    }
+
+    /// Take segments from frames of the table.
+    pub fn with_table(sample_rate: u32, table: Arc<Mutex<Vec<Frame>>>) -> Self {
+        let mut envgen = EnvGen::new(sample_rate, MAX_TABLE_SEGMENTS);
+        envgen.table = Some(table);
+        envgen
+    }
+
+    /// Segment index of the done envelope.
+    fn done(&self) -> usize {
+        if self.table.is_some() {
+            MAX_TABLE_SEGMENTS
+        } else {
+            self.segments.len()
+        }
+    }
+}
+
+impl Op for EnvGen {
+    fn perform(&mut self, stack: &mut Stack) {
+        match &self.table {
+            Some(table) => {
+                let table = table.lock().unwrap();
+                self.segments.clear();
+                self.segments.extend(
+                    table
+                        .chunks_exact(3)
+                        .take(MAX_TABLE_SEGMENTS)
+                        .map(|x| (x[0], x[1], x[2])),
+                );
+            }
+            None => {
+                for (level, time, curve) in self.segments.iter_mut().rev() {
+                    *curve = stack.pop();
+                    *time = stack.pop();
+                    *level = stack.pop();
+                }
+            }
+        }
+        let trigger = stack.pop();
+        let done = self.done();
+        let n = self.segments.len();
+        for channel in 0..CHANNELS {
+            if self.last_trigger[channel] <= 0.0 && trigger[channel] > 0.0 {
+                self.segment[channel] = 0;
+                self.start[channel] = self.level[channel];
+                self.elapsed[channel] = 0.0;
+            }
+            self.last_trigger[channel] = trigger[channel];
+            // Segments missing from a shrunk table end the envelope.
+            if self.segment[channel] >= n {
+                self.segment[channel] = done;
+            }
+            while self.segment[channel] < n {
+                let (level, time, curve) = &self.segments[self.segment[channel]];
+                let time = time[channel].max(0.0);
+                if self.elapsed[channel] >= time {
+                    self.elapsed[channel] -= time;
+                    self.start[channel] = level[channel];
+                    self.level[channel] = level[channel];
+                    self.segment[channel] += 1;
+                    if self.segment[channel] == n {
+                        self.segment[channel] = done;
+                    }
+                    continue;
+                }
+                let x = bend(self.elapsed[channel] / time, curve[channel]);
+                self.level[channel] =
+                    self.start[channel] + (level[channel] - self.start[channel]) * x;
+                self.elapsed[channel] += self.sample_period;
+                break;
+            }
+            // Keep NaN of bad inputs from sticking.
+            if !self.level[channel].is_finite() {
+                self.level[channel] = 0.0;
+            }
+        }
+        stack.push(&self.level);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            let done = self.done();
+            self.elapsed = other.elapsed;
+            self.last_trigger = other.last_trigger;
+            self.level = other.level;
+            for (segment, &other) in self.segment.iter_mut().zip(&other.segment) {
+                *segment = other.min(done);
+            }
+            self.start = other.start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+
+    #[test]
+    fn goes_through_breakpoints() {
+        let breakpoints = [1.0, 0.01, 0.0, 0.5, 0.01, 4.0];
+        let mut ops = vec![constant(1.0)];
+        ops.extend(breakpoints.iter().map(|&x| constant(x)));
+        ops.push(Box::new(EnvGen::new(1000, 2)));
+        let levels = tops(&render(&mut ops, 30));
+        assert_eq!(levels[5], 0.5);
+        assert_eq!(levels[10], 1.0);
+        // Positive curve starts slow.
+        assert!(levels[15] > 0.75);
+        assert_eq!(levels[29], 0.5);
+        let table = breakpoints.iter().map(|&x| [x; CHANNELS]).collect();
+        let mut ops = vec![constant(1.0)];
+        ops.push(Box::new(EnvGen::with_table(
+            1000,
+            Arc::new(Mutex::new(table)),
+        )));
+        assert_eq!(tops(&render(&mut ops, 30)), levels);
+    }
+}
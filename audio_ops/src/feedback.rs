@@ -1,18 +1,22 @@
 use crate::delay::Delay;
-use audio_vm::{Frame, Op, Stack, CHANNELS};
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use itertools::izip;
 
 pub struct Feedback {
     delay: Delay,
     delay_input: Frame,
+    /// Gain is clamped to -max_gain..max_gain.
+    max_gain: Sample,
 }
 
 impl Feedback {
-    pub fn new(sample_rate: u32, max_delay: f64) -> Self {
+    pub fn new(sample_rate: u32, max_delay: f64, max_gain: Sample) -> Self {
         let delay = Delay::new(sample_rate, max_delay);
         Feedback {
             delay,
             delay_input: [0.0; CHANNELS],
+            max_gain,
         }
     }
 }
@@ -31,6 +35,7 @@ impl Op for Feedback {
         let delayed = stack.pop();
 
         for (sample, &x, &gain, &delayed) in izip!(&mut self.delay_input, &input, &gain, &delayed) {
+            let gain = pure::clamp_or(gain, -self.max_gain, self.max_gain, 0.0);
             *sample = x + gain * delayed;
         }
 
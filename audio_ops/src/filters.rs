@@ -3,6 +3,8 @@
 //! Basic IIR low/high-pass filters.
 //!
 //! Sources to connect: input, cut-off frequency.
+//! DCBlock is a high-pass filter with cut-off frequency fixed around 10 Hz,
+//! source to connect: input.
 use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use itertools::izip;
 
@@ -79,3 +81,38 @@ impl Op for HPF {
         }
     }
 }
+
+pub struct DCBlock {
+    a: Sample,
+    output: Frame,
+    x_prime: Frame,
+}
+
+impl DCBlock {
+    pub fn new(sample_rate: u32) -> Self {
+        let k = 2.0 * std::f64::consts::PI * 10.0 / Sample::from(sample_rate);
+        DCBlock {
+            a: 1.0 / (k + 1.0),
+            output: [0.0; CHANNELS],
+            x_prime: [0.0; CHANNELS],
+        }
+    }
+}
+
+impl Op for DCBlock {
+    fn perform(&mut self, stack: &mut Stack) {
+        let input = stack.pop();
+        for (output, &x, x_prime) in izip!(&mut self.output, &input, &mut self.x_prime) {
+            *output = self.a * (*output + x - *x_prime);
+            *x_prime = x;
+        }
+        stack.push(&self.output);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.output = other.output;
+            self.x_prime = other.x_prime;
+        }
+    }
+}
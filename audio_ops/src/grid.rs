@@ -0,0 +1,113 @@
+//! # Grid
+//!
+//! Step sequencer programmed by the host, e.g. from pads of a grid controller. The pattern has
+//! `GRID_ROWS` tracks of `GRID_STEPS` steps. Steps are sixteenth notes of the program tempo and
+//! follow the transport rate. `grid` pushes a signal per track, the first track at the bottom,
+//! with a trigger on the first frame of each active step. The step it plays is published for the
+//! host to light it up.
+//!
+//! Sources to connect: none.
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+pub const GRID_ROWS: usize = 8;
+pub const GRID_STEPS: usize = 16;
+
+/// Pattern shared by the host and `grid` ops, and the step they play.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GridPattern {
+    pub steps: [[bool; GRID_STEPS]; GRID_ROWS],
+    pub playing: Option<usize>,
+}
+
+pub type SharedGrid = Arc<Mutex<GridPattern>>;
+
+pub struct Grid {
+    pattern: SharedGrid,
+    bpm: Arc<Mutex<Sample>>,
+    warp: Arc<Mutex<Sample>>,
+    sample_rate: Sample,
+    /// Position in steps.
+    phase: Sample,
+    step: Option<usize>,
+}
+
+impl Grid {
+    pub fn new(
+        sample_rate: u32,
+        pattern: SharedGrid,
+        bpm: Arc<Mutex<Sample>>,
+        warp: Arc<Mutex<Sample>>,
+    ) -> Self {
+        Grid {
+            pattern,
+            bpm,
+            warp,
+            sample_rate: Sample::from(sample_rate),
+            phase: 0.0,
+            step: None,
+        }
+    }
+}
+
+impl Op for Grid {
+    fn perform(&mut self, stack: &mut Stack) {
+        let step = self.phase as usize % GRID_STEPS;
+        let start = self.step != Some(step);
+        self.step = Some(step);
+        let mut pattern = self.pattern.lock().unwrap();
+        pattern.playing = Some(step);
+        for row in pattern.steps.iter() {
+            let x = if start && row[step] { 1.0 } else { 0.0 };
+            stack.push(&[x; CHANNELS]);
+        }
+        // Four steps per beat.
+        let rate = *self.bpm.lock().unwrap() * *self.warp.lock().unwrap() / 15.0;
+        if rate.is_finite() && rate > 0.0 {
+            self.phase = (self.phase + rate / self.sample_rate).rem_euclid(GRID_STEPS as Sample);
+        }
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.phase = other.phase;
+            self.step = other.step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{migrate, render};
+
+    #[test]
+    fn grid_triggers_active_steps() {
+        let pattern = SharedGrid::default();
+        pattern.lock().unwrap().steps[0][0] = true;
+        pattern.lock().unwrap().steps[1][1] = true;
+        // 60 bpm at 4 Hz of sample rate is a frame per step.
+        let grid = || -> Vec<Box<dyn Op>> {
+            vec![Box::new(Grid::new(
+                4,
+                Arc::clone(&pattern),
+                Arc::new(Mutex::new(60.0)),
+                Arc::new(Mutex::new(1.0)),
+            ))]
+        };
+        let mut ops = grid();
+        let stacks = render(&mut ops, GRID_STEPS + 2);
+        let track = |row: usize| stacks.iter().map(|s| s[row][0]).collect::<Vec<_>>();
+        assert_eq!(stacks[0].len(), GRID_ROWS);
+        assert_eq!(track(0)[..3], [1.0, 0.0, 0.0]);
+        assert_eq!(track(1)[..3], [0.0, 1.0, 0.0]);
+        // The pattern loops.
+        assert_eq!(track(0)[GRID_STEPS..], [1.0, 0.0]);
+        assert_eq!(pattern.lock().unwrap().playing, Some(1));
+        // Commits don't restart the pattern.
+        let mut next = grid();
+        migrate(&mut next, &ops);
+        assert_eq!(render(&mut next, 1)[0][1][0], 0.0);
+        assert_eq!(pattern.lock().unwrap().playing, Some(2));
+    }
+}
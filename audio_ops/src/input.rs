@@ -0,0 +1,24 @@
+//! # Input
+//!
+//! Audio input frame provided by the host, silence if it has no input device.
+//!
+//! Sources to connect: none.
+use audio_vm::{Frame, Op, Stack};
+use std::sync::{Arc, Mutex};
+
+pub struct Input {
+    source: Arc<Mutex<Frame>>,
+}
+
+impl Input {
+    pub fn new(source: Arc<Mutex<Frame>>) -> Self {
+        Input { source }
+    }
+}
+
+impl Op for Input {
+    fn perform(&mut self, stack: &mut Stack) {
+        let frame = *self.source.lock().unwrap();
+        stack.push(&frame);
+    }
+}
@@ -0,0 +1,145 @@
+//! # Key
+//!
+//! Estimate musical key of the running material with Krumhansl-Schmuckler algorithm:
+//! accumulate chroma (energy of each of 12 pitch classes) from FFT of the input with slow decay
+//! to follow modulations, and pick the key whose Krumhansl-Kessler profile correlates best.
+//! http://rnhart.net/articles/key-finding/
+//!
+//! Sources to connect: input.
+//!
+//! Pushes mode (0 is major, 1 is minor) and then tonic pitch class (0 is C, 11 is B).
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFT;
+
+/// About 5.9 Hz resolution at 48 kHz, enough to tell semitones apart above ~100 Hz.
+const WINDOW_SIZE: usize = 8192;
+/// Must be power of two!
+const PERIOD: usize = 4096;
+const MIN_FREQUENCY: Sample = 100.0;
+const MAX_FREQUENCY: Sample = 5000.0;
+/// Time in seconds for chroma of the past material to decay by e.
+const MEMORY: Sample = 8.0;
+const MAJOR_PROFILE: [Sample; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [Sample; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+pub struct Key {
+    buffer: Vec<Sample>,
+    frame_number: usize,
+    input_scratch: Vec<Complex<Sample>>,
+    freq_buffer: Vec<Complex<Sample>>,
+    fft: Radix4<Sample>,
+    window: Vec<Sample>,
+    /// Pitch class of each FFT bin within the analyzed range.
+    pitch_classes: Vec<(usize, usize)>,
+    chroma: [Sample; 12],
+    decay: Sample,
+    mode: Sample,
+    tonic: Sample,
+}
+
+impl Key {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        let pitch_classes = (1..WINDOW_SIZE / 2)
+            .filter_map(|bin| {
+                let frequency = bin as Sample * sample_rate / WINDOW_SIZE as Sample;
+                if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                    return None;
+                }
+                let midi = (69.0 + 12.0 * (frequency / 440.0).log2()).round() as i64;
+                Some((bin, midi.rem_euclid(12) as usize))
+            })
+            .collect();
+        Key {
+            buffer: vec![0.0; WINDOW_SIZE],
+            frame_number: 0,
+            input_scratch: vec![Complex::zero(); WINDOW_SIZE],
+            freq_buffer: vec![Complex::zero(); WINDOW_SIZE],
+            fft: Radix4::new(WINDOW_SIZE, false),
+            window: apodize::hanning_iter(WINDOW_SIZE).collect(),
+            pitch_classes,
+            chroma: [0.0; 12],
+            decay: (-(PERIOD as Sample) / (MEMORY * sample_rate)).exp(),
+            mode: 0.0,
+            tonic: 0.0,
+        }
+    }
+
+    fn analyze(&mut self) {
+        // Buffer is circular and the oldest sample is at the current position.
+        let offset = self.frame_number % WINDOW_SIZE;
+        for (i, (x, a)) in self.input_scratch.iter_mut().zip(&self.window).enumerate() {
+            *x = Complex::from(self.buffer[(offset + i) % WINDOW_SIZE] * a);
+        }
+        self.fft
+            .process(&mut self.input_scratch, &mut self.freq_buffer);
+        for x in self.chroma.iter_mut() {
+            *x *= self.decay;
+        }
+        for &(bin, pitch_class) in &self.pitch_classes {
+            self.chroma[pitch_class] += self.freq_buffer[bin].norm();
+        }
+        // Keep the last estimate while there is nothing to analyze.
+        if self.chroma.iter().sum::<Sample>() < 1e-6 {
+            return;
+        }
+        let mut best = Sample::NEG_INFINITY;
+        for (mode, profile) in [MAJOR_PROFILE, MINOR_PROFILE].iter().enumerate() {
+            for tonic in 0..12 {
+                let r = correlation(&self.chroma, profile, tonic);
+                if r > best {
+                    best = r;
+                    self.mode = mode as Sample;
+                    self.tonic = tonic as Sample;
+                }
+            }
+        }
+    }
+}
+
+/// Pearson correlation of chroma with the profile rotated to the tonic.
+fn correlation(chroma: &[Sample; 12], profile: &[Sample; 12], tonic: usize) -> Sample {
+    let chroma_mean = chroma.iter().sum::<Sample>() / 12.0;
+    let profile_mean = profile.iter().sum::<Sample>() / 12.0;
+    let mut covariance = 0.0;
+    let mut chroma_variance = 0.0;
+    let mut profile_variance = 0.0;
+    for (i, x) in chroma.iter().enumerate() {
+        let x = x - chroma_mean;
+        let y = profile[(i + 12 - tonic) % 12] - profile_mean;
+        covariance += x * y;
+        chroma_variance += x * x;
+        profile_variance += y * y;
+    }
+    covariance / (chroma_variance * profile_variance).sqrt().max(1e-12)
+}
+
+impl Op for Key {
+    fn perform(&mut self, stack: &mut Stack) {
+        let input = stack.pop();
+        self.buffer[self.frame_number % WINDOW_SIZE] = input.iter().sum::<Sample>();
+        self.frame_number += 1;
+        if self.frame_number & (PERIOD - 1) == 0 {
+            self.analyze();
+        }
+        stack.push(&[self.mode; CHANNELS]);
+        stack.push(&[self.tonic; CHANNELS]);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.buffer.copy_from_slice(&other.buffer);
+            self.frame_number = other.frame_number;
+            self.chroma = other.chroma;
+            self.mode = other.mode;
+            self.tonic = other.tonic;
+        }
+    }
+}
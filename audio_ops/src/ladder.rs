@@ -0,0 +1,68 @@
+//! # Ladder filter
+//!
+//! Moog-style 4-pole resonant low-pass filter built from zero-delay feedback one-pole stages as
+//! described in Vadim Zavalishin's "The Art of VA Filter Design", with tanh saturation of the
+//! stages input. Resonance above 1 makes filter self-oscillate.
+//!
+//! Sources to connect: input, cut-off frequency, resonance, drive.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+pub struct Ladder {
+    nyquist: Sample,
+    sample_period: Sample,
+    state: [Frame; 4],
+}
+
+impl Ladder {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        Ladder {
+            nyquist: 0.5 * sample_rate,
+            sample_period: sample_rate.recip(),
+            state: [[0.0; CHANNELS]; 4],
+        }
+    }
+}
+
+impl Op for Ladder {
+    fn perform(&mut self, stack: &mut Stack) {
+        let drive = stack.pop();
+        let resonance = stack.pop();
+        let cut_off_freq = stack.pop();
+        let input = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &x, &frequency, &resonance, &drive)) in
+            izip!(&mut frame, &input, &cut_off_freq, &resonance, &drive).enumerate()
+        {
+            let frequency = pure::clamp(frequency, 0.0, 0.99 * self.nyquist);
+            let g = (std::f64::consts::PI * frequency * self.sample_period).tan();
+            let a = g / (1.0 + g);
+            let k = 4.0 * resonance.max(0.0);
+
+            // Resolve zero-delay feedback loop for the linear part of the cascade.
+            let mut s = 0.0;
+            for stage in &self.state {
+                s = a * s + stage[channel] / (1.0 + g);
+            }
+            let a4 = a * a * a * a;
+            let u = ((x - k * s) / (1.0 + k * a4) * drive.max(0.0)).tanh();
+
+            let mut y = u;
+            for stage in &mut self.state {
+                let v = (y - stage[channel]) * a;
+                y = v + stage[channel];
+                stage[channel] = y + v;
+            }
+            *output = y;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.state = other.state;
+        }
+    }
+}
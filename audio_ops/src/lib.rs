@@ -1,29 +1,70 @@
+mod arp;
+mod beat;
 mod biquad;
 mod buffer;
+mod bus;
 mod channel;
+mod chorus;
+mod comb;
 mod constant;
 mod convolution;
+mod convolution_ir;
+mod cross_synthesis;
+mod crush;
 mod delay;
+mod denoise;
+mod dry_wet;
+mod dynamics;
+mod each;
 mod envelopes;
 mod feedback;
 mod filters;
 mod function;
+mod grid;
+mod input;
+mod key;
+mod ladder;
+mod macro_osc;
+mod meter;
 mod metro;
+mod modal;
 mod noise;
 mod noop;
 mod osc;
 mod pan;
+mod phaser;
 mod phasor;
+mod pitch_shift;
+mod poly;
 mod pulse;
 pub mod pure;
+mod reverb;
 mod sample_and_hold;
 mod sampler;
+mod scale;
+mod slew;
+mod spectral_filter;
 mod spectral_transform;
 mod stack;
+mod stretch;
+mod svf;
+mod tempo;
+#[cfg(test)]
+mod testing;
+mod trigger;
+mod vocoder;
+mod vowel;
+mod wavefolder;
+mod waveguide;
 mod yin;
 
 pub use self::{
-    biquad::*, channel::*, constant::*, convolution::*, delay::*, envelopes::*, feedback::*,
-    filters::*, function::*, metro::*, noise::*, noop::*, osc::*, pan::*, phasor::*, pulse::*,
-    sample_and_hold::*, sampler::*, spectral_transform::*, stack::*, yin::*,
+    arp::*, beat::*, biquad::*, bus::*, channel::*, chorus::*, comb::*, constant::*,
+    convolution::*, convolution_ir::*, cross_synthesis::*, crush::*, delay::*, denoise::*,
+    dry_wet::*, dynamics::*, each::*, envelopes::*, feedback::*, filters::*, function::*, grid::*,
+    input::*, key::*, ladder::*, macro_osc::*, meter::*, metro::*, modal::*, noise::*, noop::*,
+    osc::*, pan::*, phaser::*, phasor::*, pitch_shift::*, poly::*, pulse::*, reverb::*,
+    sample_and_hold::*, sampler::*, scale::*, slew::*, spectral_filter::*, spectral_transform::*,
+    stack::*, stretch::*, svf::*, tempo::*, trigger::*, vocoder::*, vowel::*, wavefolder::*,
+    waveguide::*, yin::*,
 };
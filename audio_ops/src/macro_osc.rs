@@ -0,0 +1,277 @@
+//! # Macro oscillator
+//!
+//! A dozen synthesis models behind one token, in the spirit of Mutable Instruments Plaits.
+//! Model is rounded to the nearest one, timbre and morph go from 0 to 1 and mean what suits
+//! the model best:
+//!
+//! 0. virtual analog: pulse width, saw to pulse
+//! 1. waveshaper: fold amount, asymmetry
+//! 2. two-operator FM: index, ratio
+//! 3. formant: formant frequency, window sharpness
+//! 4. additive: brightness, even harmonics level
+//! 5. wavetable: phase distortion, sine to triangle to saw to square
+//! 6. chord of saws: saw to triangle, chord type
+//! 7. swarm of detuned saws: detune, saws to sines
+//! 8. filtered noise: resonance, low-pass to band-pass to high-pass
+//! 9. dust: decay, random to constant amplitude; frequency is the density of particles
+//! 10. bell of inharmonic partials: brightness, harmonic to bar-like partials
+//! 11. hard sync: slave frequency, saw to sine
+//!
+//! Sources to connect: frequency, model, timbre, morph.
+use crate::pure;
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::f64::consts::PI;
+
+const MODELS: Sample = 12.0;
+/// Most oscillators a model runs at once.
+const VOICES: usize = 8;
+/// Chords as semitones above the root, picked by morph.
+const CHORDS: [[Sample; 3]; 6] = [
+    [0.0, 12.0, 19.0],
+    [0.0, 7.0, 12.0],
+    [0.0, 5.0, 10.0],
+    [0.0, 3.0, 7.0],
+    [0.0, 4.0, 7.0],
+    [0.0, 4.0, 11.0],
+];
+/// Partials of a free bar, morph moves harmonic ones towards them.
+const BAR_PARTIALS: [Sample; 5] = [1.0, 2.756, 5.404, 8.933, 13.344];
+
+pub struct MacroOsc {
+    /// Phases from 0 to 1 of oscillators of the current model, per channel.
+    phases: [[Sample; VOICES]; CHANNELS],
+    /// Low-pass and band-pass states of the noise filter, or level of dust.
+    state: [[Sample; 2]; CHANNELS],
+    rng: SmallRng,
+    sample_rate: Sample,
+}
+
+impl MacroOsc {
+    pub fn new(sample_rate: u32) -> Self {
+        MacroOsc::with_rng(sample_rate, SmallRng::from_entropy())
+    }
+
+    /// Use it to get the same noise and dust in each render.
+    pub fn with_rng(sample_rate: u32, rng: SmallRng) -> Self {
+        MacroOsc {
+            phases: [[0.0; VOICES]; CHANNELS],
+            state: [[0.0; 2]; CHANNELS],
+            rng,
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+fn sine(phase: Sample) -> Sample {
+    (2.0 * PI * phase).sin()
+}
+
+fn saw(phase: Sample) -> Sample {
+    2.0 * phase - 1.0
+}
+
+fn triangle(phase: Sample) -> Sample {
+    1.0 - 4.0 * (phase - 0.5).abs()
+}
+
+fn pulse(phase: Sample, width: Sample) -> Sample {
+    if phase < width {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Advance the phase by the frequency in cycles per frame, return true if it wrapped.
+fn advance(phase: &mut Sample, dt: Sample) -> bool {
+    let next = *phase + dt;
+    *phase = next.rem_euclid(1.0);
+    !(0.0..1.0).contains(&next)
+}
+
+impl Op for MacroOsc {
+    fn perform(&mut self, stack: &mut Stack) {
+        let morph = stack.pop();
+        let timbre = stack.pop();
+        let model = stack.pop();
+        let freq = stack.pop();
+        let clamp = |x: Sample, min, max| pure::clamp_or(x, min, max, 0.0);
+        let mut frame = [0.0; CHANNELS];
+        for channel in 0..CHANNELS {
+            let dt = freq[channel] / self.sample_rate;
+            let timbre = clamp(timbre[channel], 0.0, 1.0);
+            let morph = clamp(morph[channel], 0.0, 1.0);
+            let phases = &mut self.phases[channel];
+            let state = &mut self.state[channel];
+            let output = match clamp(model[channel].round(), 0.0, MODELS - 1.0) as usize {
+                0 => {
+                    advance(&mut phases[0], dt);
+                    let width = 0.5 - 0.45 * timbre;
+                    (1.0 - morph) * saw(phases[0]) + morph * pulse(phases[0], width)
+                }
+                1 => {
+                    advance(&mut phases[0], dt);
+                    let x = triangle(phases[0]) * (1.0 + 4.0 * timbre) + morph;
+                    (0.5 * PI * x).sin()
+                }
+                2 => {
+                    let ratio = 0.5 * (1.0 + (morph * 8.0).min(7.0).floor());
+                    advance(&mut phases[0], dt);
+                    advance(&mut phases[1], dt * ratio);
+                    (2.0 * PI * phases[0] + 8.0 * timbre * sine(phases[1])).sin()
+                }
+                3 => {
+                    advance(&mut phases[0], dt);
+                    let ratio = (5.0 * timbre).exp2();
+                    let window = (1.0 - phases[0]).powf(1.0 + 4.0 * morph);
+                    sine(phases[0] * ratio) * window
+                }
+                4 => {
+                    advance(&mut phases[0], dt);
+                    let (mut sum, mut norm) = (0.0, 0.0);
+                    for n in 1..=VOICES {
+                        let n = n as Sample;
+                        // Skip harmonics above Nyquist to not alias.
+                        if (n * dt).abs() >= 0.5 {
+                            break;
+                        }
+                        let even = if n % 2.0 == 0.0 { morph } else { 1.0 };
+                        let amplitude = even * n.powf(-2.0 * (1.0 - timbre));
+                        sum += amplitude * sine((n * phases[0]).fract());
+                        norm += amplitude;
+                    }
+                    sum / norm.max(1.0)
+                }
+                5 => {
+                    advance(&mut phases[0], dt);
+                    let knee = 0.5 - 0.45 * timbre;
+                    let phase = if phases[0] < knee {
+                        0.5 * phases[0] / knee
+                    } else {
+                        0.5 + 0.5 * (phases[0] - knee) / (1.0 - knee)
+                    };
+                    let shapes = [sine(phase), triangle(phase), saw(phase), pulse(phase, 0.5)];
+                    let x = 3.0 * morph;
+                    let i = (x.floor() as usize).min(2);
+                    let k = x - i as Sample;
+                    (1.0 - k) * shapes[i] + k * shapes[i + 1]
+                }
+                6 => {
+                    let chord = CHORDS[((morph * 6.0) as usize).min(5)];
+                    let mut sum = 0.0;
+                    for (phase, interval) in phases.iter_mut().zip(&chord) {
+                        advance(phase, dt * (interval / 12.0).exp2());
+                        sum += (1.0 - timbre) * saw(*phase) + timbre * triangle(*phase);
+                    }
+                    sum / chord.len() as Sample
+                }
+                7 => {
+                    let mut sum = 0.0;
+                    for (i, phase) in phases.iter_mut().enumerate() {
+                        // Spread voices evenly up to a semitone either side of the frequency.
+                        let spread = i as Sample / (VOICES - 1) as Sample - 0.5;
+                        advance(phase, dt * (spread * timbre / 6.0).exp2());
+                        sum += (1.0 - morph) * saw(*phase) + morph * sine(*phase);
+                    }
+                    sum / (VOICES as Sample).sqrt() / 2.0
+                }
+                8 => {
+                    // Chamberlin state variable filter, stable up to a sixth of sample rate.
+                    let f = 2.0 * (PI * dt.abs().min(1.0 / 6.0)).sin();
+                    let q = 2.0 * (1.0 - timbre).max(0.02);
+                    let x = self.rng.gen_range(-1.0, 1.0);
+                    let [low, band] = state;
+                    *low += f * *band;
+                    let high = x - *low - q * *band;
+                    *band += f * high;
+                    let x = 2.0 * morph;
+                    if x < 1.0 {
+                        (1.0 - x) * *low + x * *band
+                    } else {
+                        (2.0 - x) * *band + (x - 1.0) * high
+                    }
+                }
+                9 => {
+                    let level = &mut state[0];
+                    if self.rng.gen::<Sample>() < dt.abs() {
+                        let amplitude = self.rng.gen_range(-1.0, 1.0);
+                        *level = (1.0 - morph) * amplitude + morph * amplitude.signum();
+                    } else {
+                        // Decay from a millisecond to a quarter second.
+                        let time = 0.001 * 250.0_f64.powf(timbre);
+                        *level *= (-1.0 / (time * self.sample_rate)).exp();
+                    }
+                    *level
+                }
+                10 => {
+                    let (mut sum, mut norm) = (0.0, 0.0);
+                    for (n, (phase, bar)) in phases.iter_mut().zip(&BAR_PARTIALS).enumerate() {
+                        let harmonic = (n + 1) as Sample;
+                        let ratio = (1.0 - morph) * harmonic + morph * bar;
+                        if (ratio * dt).abs() >= 0.5 {
+                            break;
+                        }
+                        advance(phase, dt * ratio);
+                        let amplitude = ratio.powf(-2.0 * (1.0 - timbre));
+                        sum += amplitude * sine(*phase);
+                        norm += amplitude;
+                    }
+                    sum / norm.max(1.0)
+                }
+                _ => {
+                    if advance(&mut phases[0], dt) {
+                        phases[1] = 0.0;
+                    }
+                    advance(&mut phases[1], dt * (4.0 * timbre).exp2());
+                    (1.0 - morph) * saw(phases[1]) + morph * sine(phases[1])
+                }
+            };
+            // Keep NaN of bad inputs from sticking in phases and filters.
+            if !output.is_finite() {
+                *phases = [0.0; VOICES];
+                *state = [0.0; 2];
+            }
+            frame[channel] = if output.is_finite() { output } else { 0.0 };
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.phases = other.phases;
+            self.state = other.state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+
+    #[test]
+    fn models_sound() {
+        for model in 0..12 {
+            let mut ops = vec![
+                constant(220.0),
+                constant(model as Sample),
+                constant(0.5),
+                constant(0.5),
+            ];
+            ops.push(Box::new(MacroOsc::with_rng(
+                48000,
+                SmallRng::seed_from_u64(0),
+            )));
+            let peak = tops(&render(&mut ops, 48000))
+                .iter()
+                .fold(0.0, |peak: Sample, x| peak.max(x.abs()));
+            assert!(
+                0.01 < peak && peak <= 1.5,
+                "model {} peaks at {}",
+                model,
+                peak
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! # Meter
+//!
+//! Measures running RMS and peak of the signal passing through and publishes them to the host,
+//! e.g. to show levels of a line in the UI. RMS averages squares over about 300 ms like VU meters
+//! do, peak jumps up immediately and falls back slowly to stay readable.
+//!
+//! Sources to connect: input.
+use audio_vm::{Frame, Levels, Op, Sample, Stack, CHANNELS};
+use std::sync::Arc;
+
+/// Time in seconds for RMS to settle by e.
+const RMS_TIME: Sample = 0.3;
+/// Time in seconds for peak to fall by e.
+const PEAK_TIME: Sample = 1.0;
+
+pub struct Meter {
+    levels: Arc<Levels>,
+    mean_square: Frame,
+    peak: Frame,
+    rms_coefficient: Sample,
+    peak_decay: Sample,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, levels: Arc<Levels>) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        Meter {
+            levels,
+            mean_square: [0.0; CHANNELS],
+            peak: [0.0; CHANNELS],
+            rms_coefficient: 1.0 - (-1.0 / (RMS_TIME * sample_rate)).exp(),
+            peak_decay: (-1.0 / (PEAK_TIME * sample_rate)).exp(),
+        }
+    }
+}
+
+impl Op for Meter {
+    fn perform(&mut self, stack: &mut Stack) {
+        let x = stack.pop();
+        let mut rms = [0.0; CHANNELS];
+        for channel in 0..CHANNELS {
+            let x = x[channel];
+            // Keep NaN and infinities out of the running state.
+            let x = if x.is_finite() { x } else { 0.0 };
+            self.mean_square[channel] += self.rms_coefficient * (x * x - self.mean_square[channel]);
+            self.peak[channel] = x.abs().max(self.peak[channel] * self.peak_decay);
+            rms[channel] = self.mean_square[channel].sqrt();
+        }
+        self.levels.publish(rms, self.peak);
+        stack.push(&x);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.mean_square = other.mean_square;
+            self.peak = other.peak;
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! # Modal synthesis
+//!
+//! Bank of resonators, each ringing at one mode of a vibrating body. Any signal could excite
+//! it, though triggers and short bursts of noise work best: a unit impulse makes modes ring at
+//! their amplitudes, while a sustained input keeps building up energy in the long decays.
+//!
+//! Material goes from 0 to 1 and moves modes from harmonic ones of a string to inharmonic ones
+//! of a free bar, at 0 upper modes die away fast like in wood and at 1 they ring as long as
+//! the fundamental like in metal. Brightness from 0 to 1 raises the level of upper modes.
+//! Decay is the time in seconds for the fundamental to fall by 60 dB.
+//!
+//! Sources to connect: excitation, frequency, decay, material, brightness.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use std::f64::consts::PI;
+
+pub struct Modal {
+    /// Last two outputs of each mode resonator.
+    modes: Vec<[[Sample; 2]; CHANNELS]>,
+    sample_rate: Sample,
+}
+
+impl Modal {
+    pub fn new(sample_rate: u32, modes: usize) -> Self {
+        Modal {
+            modes: vec![[[0.0; 2]; CHANNELS]; modes],
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Modal {
+    fn perform(&mut self, stack: &mut Stack) {
+        let brightness = stack.pop();
+        let material = stack.pop();
+        let decay = stack.pop();
+        let frequency = stack.pop();
+        let x = stack.pop();
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        let mut frame: Frame = [0.0; CHANNELS];
+        for channel in 0..CHANNELS {
+            let material = unit(material[channel]);
+            let brightness = unit(brightness[channel]);
+            let decay = decay[channel].max(0.0) * self.sample_rate;
+            let (mut sum, mut norm) = (0.0, 0.0);
+            for (n, mode) in self.modes.iter_mut().enumerate() {
+                let state = &mut mode[channel];
+                let harmonic = (n + 1) as Sample;
+                // Free bar partials are close to (2n + 1)^2 / 9 of the fundamental.
+                let bar = (2.0 * harmonic + 1.0).powi(2) / 9.0;
+                let ratio = (1.0 - material) * harmonic + material * bar;
+                let w = 2.0 * PI * frequency[channel] * ratio / self.sample_rate;
+                // Modes above Nyquist would alias, let them ring out silently.
+                if w.abs() >= 0.9 * PI {
+                    *state = [0.0; 2];
+                    continue;
+                }
+                let time = decay * ratio.powf(material - 1.0);
+                let r = if time > 0.0 {
+                    0.001_f64.powf(time.recip())
+                } else {
+                    0.0
+                };
+                let amplitude = ratio.powf(-2.0 * (1.0 - brightness));
+                // Input is scaled by sin(w) for a unit impulse to ring at unit amplitude.
+                let y = w.sin() * x[channel] + 2.0 * r * w.cos() * state[0] - r * r * state[1];
+                // Keep NaN of bad inputs from circulating in resonators.
+                *state = if y.is_finite() {
+                    [y, state[0]]
+                } else {
+                    [0.0; 2]
+                };
+                sum += amplitude * state[0];
+                norm += amplitude;
+            }
+            frame[channel] = sum / norm.max(1.0);
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (mode, other) in self.modes.iter_mut().zip(&other.modes) {
+                *mode = *other;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+    use crate::Metro;
+
+    #[test]
+    fn rings_and_decays() {
+        let mut ops = vec![constant(2.0)];
+        ops.push(Box::new(Metro::new(48000)));
+        ops.extend(vec![
+            constant(480.0),
+            constant(0.5),
+            constant(0.0),
+            constant(1.0),
+        ]);
+        ops.push(Box::new(Modal::new(48000, 1)));
+        let xs = tops(&render(&mut ops, 72000));
+        // Metro strikes in half a second.
+        let xs = &xs[24000..];
+        let peak = |xs: &[Sample]| xs.iter().fold(0.0, |a: Sample, x| a.max(x.abs()));
+        // Unit impulse rings at unit amplitude and falls by 60 dB in half a second, just before
+        // the next strike it is about 57 dB down.
+        assert!((peak(&xs[..1000]) - 1.0).abs() < 0.05);
+        let decayed = peak(&xs[22800..24000]) / peak(&xs[..1000]);
+        assert!(decayed > 0.001 && decayed < 0.002);
+        let crossings = xs[..4800]
+            .windows(2)
+            .filter(|w| w[0] <= 0.0 && w[1] > 0.0)
+            .count();
+        assert!((47..=49).contains(&crossings));
+    }
+}
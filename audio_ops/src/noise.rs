@@ -11,6 +11,11 @@ impl WhiteNoise {
             rng: SmallRng::from_entropy(),
         }
     }
+
+    /// Use it to get the same noise in each render.
+    pub fn with_rng(rng: SmallRng) -> Self {
+        WhiteNoise { rng }
+    }
 }
 
 impl Op for WhiteNoise {
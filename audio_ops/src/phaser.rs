@@ -0,0 +1,98 @@
+//! # Phaser
+//!
+//! Cascade of first-order allpass filters whose break frequency is swept by a sine LFO, mixed
+//! with the dry signal to get moving notches. Output of the cascade is fed back to its input for
+//! sharper notches. The right channel LFO is a quarter of the cycle ahead for stereo movement.
+//!
+//! Sources to connect: input, rate, center frequency, depth, feedback, dry/wet.
+//! Rate and center frequency are in Hz, depth is from 0 to 1 and sweeps up to 2 octaves
+//! around the center, feedback is from -1 to 1, dry/wet is from 0 to 1.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use std::f64::consts::PI;
+
+/// Sweep width (in octaves) up and down from the center at full depth.
+const MAX_OCTAVES: Sample = 2.0;
+
+#[derive(Clone, Copy, Default)]
+struct Allpass {
+    x1: Sample,
+    y1: Sample,
+}
+
+impl Allpass {
+    #[inline]
+    fn process(&mut self, x: Sample, a: Sample) -> Sample {
+        let y = a * x + self.x1 - a * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+pub struct Phaser {
+    stages: Vec<[Allpass; CHANNELS]>,
+    /// The last output of the cascade for feedback.
+    last: Frame,
+    phase: Sample,
+    max_feedback: Sample,
+    sample_rate: Sample,
+}
+
+impl Phaser {
+    /// Feedback is clamped to -max_feedback..max_feedback, and below 1 anyway.
+    pub fn new(sample_rate: u32, stages: usize, max_feedback: Sample) -> Self {
+        Phaser {
+            stages: vec![Default::default(); stages.max(1)],
+            last: [0.0; CHANNELS],
+            phase: 0.0,
+            max_feedback: max_feedback.min(0.99),
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Phaser {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let feedback = stack.pop();
+        let depth = stack.pop();
+        let freq = stack.pop();
+        let rate = stack.pop();
+        let input = stack.pop();
+        let clamp = |x: Sample, min, max| pure::clamp_or(x, min, max, 0.0);
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &x, &wet, &feedback, &depth, &freq)) in
+            izip!(&mut frame, &input, &wet, &feedback, &depth, &freq).enumerate()
+        {
+            let lfo = (2.0 * PI * (self.phase + 0.25 * channel as Sample)).sin();
+            let octaves = clamp(depth, 0.0, 1.0) * MAX_OCTAVES * lfo;
+            let f = clamp(freq * octaves.exp2(), 10.0, 0.45 * self.sample_rate);
+            let t = (PI * f / self.sample_rate).tan();
+            let a = (t - 1.0) / (t + 1.0);
+            let feedback = clamp(feedback, -self.max_feedback, self.max_feedback);
+            let mut y = x + feedback * self.last[channel];
+            for stage in self.stages.iter_mut() {
+                y = stage[channel].process(y, a);
+            }
+            self.last[channel] = y;
+            *output = (1.0 - wet) * x + wet * y;
+        }
+        let rate = rate.iter().sum::<Sample>() / CHANNELS as Sample;
+        if rate.is_finite() {
+            self.phase = (self.phase + rate / self.sample_rate).rem_euclid(1.0);
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (stage, other) in self.stages.iter_mut().zip(&other.stages) {
+                *stage = *other;
+            }
+            self.last = other.last;
+            self.phase = other.phase;
+        }
+    }
+}
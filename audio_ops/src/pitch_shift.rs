@@ -0,0 +1,86 @@
+//! # Pitch shifter
+//!
+//! Granular pitch shifter: two read heads sweep a delay line at the speed which transposes the
+//! input, each jumping back when it reaches the end of the window. Heads are half a window apart
+//! and crossfaded by Hann windows to hide the jumps. Works on live input with the latency of up
+//! to a window, longer windows smear transients but keep low notes steady.
+//!
+//! Sources to connect: input, shift in semitones, window size in seconds.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use std::f64::consts::PI;
+
+/// The shortest window (in seconds), shorter ones turn into ring modulation.
+const MIN_WINDOW: Sample = 0.005;
+
+pub struct PitchShift {
+    buffer: Vec<Frame>,
+    index: usize,
+    /// Phase of the first head in the window, from 0 to 1.
+    phase: Sample,
+    sample_rate: Sample,
+}
+
+impl PitchShift {
+    pub fn new(sample_rate: u32, max_window: f64) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        PitchShift {
+            // +2 for interpolation and rounding.
+            buffer: vec![
+                [0.0; CHANNELS];
+                (max_window.max(MIN_WINDOW) * sample_rate).ceil() as usize + 2
+            ],
+            index: 0,
+            phase: 0.0,
+            sample_rate,
+        }
+    }
+
+    #[inline]
+    fn read(&self, delay: Sample, channel: usize) -> Sample {
+        let size = self.buffer.len();
+        let position = (self.index + size) as Sample - delay;
+        let i = position.floor();
+        let k = position - i;
+        let i = i as usize;
+        (1.0 - k) * self.buffer[i % size][channel] + k * self.buffer[(i + 1) % size][channel]
+    }
+}
+
+impl Op for PitchShift {
+    fn perform(&mut self, stack: &mut Stack) {
+        let window = stack.pop();
+        let shift = stack.pop();
+        let input = stack.pop();
+        // Heads are shared by channels, hence mono parameters.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let max_window = (self.buffer.len() - 2) as Sample / self.sample_rate;
+        // Unlike clamp, max and min map NaN to the bounds and don't panic on a short buffer.
+        let window = mean(window).max(MIN_WINDOW).min(max_window) * self.sample_rate;
+        let ratio = (mean(shift) / 12.0).exp2();
+        self.buffer[self.index] = input;
+        let mut frame = [0.0; CHANNELS];
+        for (channel, output) in frame.iter_mut().enumerate() {
+            for &offset in &[0.0, 0.5] {
+                let phase = (self.phase + offset).fract();
+                // Delay shrinks when reading faster than writing, hence 1 - phase for upshift.
+                let delay = if ratio > 1.0 { 1.0 - phase } else { phase };
+                let gain = (PI * phase).sin().powi(2);
+                *output += gain * self.read(delay * window, channel);
+            }
+        }
+        self.index = (self.index + 1) % self.buffer.len();
+        // Delay changes by 1 - ratio frames each frame.
+        self.phase = (self.phase + (1.0 - ratio).abs() / window).fract();
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.buffer.len() == other.buffer.len() {
+                self.buffer.copy_from_slice(&other.buffer);
+                self.index = other.index;
+            }
+            self.phase = other.phase;
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! # Poly
+//!
+//! Voice allocator: run a copy of the sub-program for each voice and sum their outputs. Each copy
+//! has its own op state and gets note and gate of its voice as inputs. Notes come one by one as
+//! a stream of note and gate, like from a sequencer or `arp`: a new note starts when gate opens
+//! or note changes while it's open. It goes to the voice which started the longest time ago,
+//! while the voice of the previous note is released and keeps ringing out, so tails of notes
+//! overlap. When voices run out, the oldest tail is stolen. Note and gate are read from the left
+//! channel and passed to voices as they are.
+//!
+//! Sources to connect: note, gate.
+use audio_vm::{Frame, Op, Program, Sample, Stack, CHANNELS};
+
+struct Voice {
+    note: Frame,
+    gate: Frame,
+    /// Number of the note it plays, to find the oldest voice.
+    started: u64,
+}
+
+pub struct Poly {
+    /// Copies of the sub-program, one per voice.
+    programs: Vec<Program>,
+    voices: Vec<Voice>,
+    /// Voice of the note which is held now.
+    current: Option<usize>,
+    last_gate: Sample,
+    last_note: Sample,
+    notes: u64,
+    stack: Stack,
+}
+
+impl Poly {
+    pub fn new(programs: Vec<Program>) -> Self {
+        Poly {
+            voices: programs
+                .iter()
+                .map(|_| Voice {
+                    note: [0.0; CHANNELS],
+                    gate: [0.0; CHANNELS],
+                    started: 0,
+                })
+                .collect(),
+            programs,
+            current: None,
+            last_gate: 0.0,
+            last_note: 0.0,
+            notes: 0,
+            stack: Stack::new(),
+        }
+    }
+}
+
+impl Op for Poly {
+    fn perform(&mut self, stack: &mut Stack) {
+        let gate = stack.pop();
+        let note = stack.pop();
+        let on = gate[0] > 0.0;
+        let started =
+            on && (self.last_gate <= 0.0 || (note[0] != self.last_note && note[0].is_finite()));
+        if started {
+            if let Some(current) = self.current {
+                self.voices[current].gate = [0.0; CHANNELS];
+            }
+            // With a single voice the new note takes over the current one, legato.
+            let current = self.current;
+            let voice = (0..self.voices.len())
+                .filter(|&i| Some(i) != current)
+                .min_by_key(|&i| self.voices[i].started)
+                .or(current);
+            if let Some(i) = voice {
+                self.notes += 1;
+                self.voices[i].note = note;
+                self.voices[i].started = self.notes;
+            }
+            self.current = voice;
+        }
+        match self.current {
+            Some(i) if on => self.voices[i].gate = gate,
+            Some(i) => {
+                self.voices[i].gate = [0.0; CHANNELS];
+                self.current = None;
+            }
+            None => {}
+        }
+        self.last_gate = gate[0];
+        self.last_note = note[0];
+        let mut frame = [0.0; CHANNELS];
+        for (voice, program) in self.voices.iter().zip(self.programs.iter_mut()) {
+            self.stack.reset();
+            self.stack.push(&voice.note);
+            self.stack.push(&voice.gate);
+            for statement in program.iter_mut() {
+                statement.op.perform(&mut self.stack);
+            }
+            for (output, y) in frame.iter_mut().zip(&self.stack.pop()) {
+                *output += y;
+            }
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (program, other) in self.programs.iter_mut().zip(&other.programs) {
+                for statement in program.iter_mut() {
+                    if let Some(other) = other.iter().find(|other| other.id == statement.id) {
+                        statement.op.migrate(&other.op);
+                    }
+                }
+            }
+            for (voice, other) in self.voices.iter_mut().zip(&other.voices) {
+                voice.note = other.note;
+                voice.gate = other.gate;
+                voice.started = other.started;
+            }
+            self.current = other.current.filter(|&i| i < self.voices.len());
+            self.last_gate = other.last_gate;
+            self.last_note = other.last_note;
+            self.notes = other.notes;
+        }
+    }
+}
@@ -43,6 +43,61 @@ pub fn quantize(x: Sample, step: Sample) -> Sample {
     (x / step).round() * step
 }
 
+// Logic, signals above 0 are true and results are 1 or 0
+
+fn truth(x: bool) -> Sample {
+    if x {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[inline]
+pub fn gt(x: Sample, y: Sample) -> Sample {
+    truth(x > y)
+}
+
+#[inline]
+pub fn lt(x: Sample, y: Sample) -> Sample {
+    truth(x < y)
+}
+
+#[inline]
+pub fn ge(x: Sample, y: Sample) -> Sample {
+    truth(x >= y)
+}
+
+#[inline]
+pub fn le(x: Sample, y: Sample) -> Sample {
+    truth(x <= y)
+}
+
+#[inline]
+pub fn eq(x: Sample, y: Sample) -> Sample {
+    truth(x == y)
+}
+
+#[inline]
+pub fn and(x: Sample, y: Sample) -> Sample {
+    truth(x > 0.0 && y > 0.0)
+}
+
+#[inline]
+pub fn or(x: Sample, y: Sample) -> Sample {
+    truth(x > 0.0 || y > 0.0)
+}
+
+#[inline]
+pub fn xor(x: Sample, y: Sample) -> Sample {
+    truth((x > 0.0) != (y > 0.0))
+}
+
+#[inline]
+pub fn not(x: Sample) -> Sample {
+    truth(x <= 0.0)
+}
+
 // Trigonometry
 
 #[inline]
@@ -83,12 +138,34 @@ pub fn linlin(x: Sample, a: Sample, b: Sample, c: Sample, d: Sample) -> Sample {
     (d - c) * (x - a) / (b - a) + c
 }
 
+/// Assuming that x varies in the range a..b linearly project it into the range c..d
+/// exponentially, i.e. equal steps of x multiply the result by equal ratios.
+/// c and d must have the same sign.
+#[inline]
+pub fn linexp(x: Sample, a: Sample, b: Sample, c: Sample, d: Sample) -> Sample {
+    c * (d / c).powf((x - a) / (b - a))
+}
+
+/// Inverse of linexp: assuming that x varies in the range a..b exponentially
+/// project it into the range c..d linearly.
+#[inline]
+pub fn explin(x: Sample, a: Sample, b: Sample, c: Sample, d: Sample) -> Sample {
+    (d - c) * (x / a).ln() / (b / a).ln() + c
+}
+
 /// Assuming that x varies in the range -1..1 linearly project it into the range a..b
 #[inline]
 pub fn range(x: Sample, a: Sample, b: Sample) -> Sample {
     linlin(x, -1.0, 1.0, a, b)
 }
 
+/// Assuming that x varies in the range -1..1 linearly project it into the range a..b
+/// exponentially
+#[inline]
+pub fn exprange(x: Sample, a: Sample, b: Sample) -> Sample {
+    linexp(x, -1.0, 1.0, a, b)
+}
+
 /// Assuming that x varies in the range -1..1 linearly project it into the range 0..1
 #[inline]
 pub fn unit(x: Sample) -> Sample {
@@ -143,6 +220,35 @@ pub fn round(x: Sample) -> Sample {
     x.round()
 }
 
+/// Round down to the integer value
+#[inline]
+pub fn floor(x: Sample) -> Sample {
+    x.floor()
+}
+
+/// Round up to the integer value
+#[inline]
+pub fn ceil(x: Sample) -> Sample {
+    x.ceil()
+}
+
+#[inline]
+pub fn abs(x: Sample) -> Sample {
+    x.abs()
+}
+
+/// 1 for positive values, -1 for negative ones and 0 for 0
+#[inline]
+pub fn sign(x: Sample) -> Sample {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
 /// Convert MIDI pitch to frequency in Hz
 #[inline]
 pub fn midi2freq(x: Sample) -> Sample {
@@ -164,6 +270,13 @@ pub fn pan(l: Sample, r: Sample, c: Sample) -> (Sample, Sample) {
     )
 }
 
+/// Equal-power crossfade from a at position 0 to b at position 1
+#[inline]
+pub fn xfade(a: Sample, b: Sample, position: Sample) -> Sample {
+    let angle = 0.5 * PI * clamp(position, 0.0, 1.0);
+    angle.cos() * a + angle.sin() * b
+}
+
 /// Chebyshev polynomial of degree 2
 /// T_2(x) = 2x^2 - 1
 #[inline]
@@ -215,16 +328,27 @@ pub fn clamp(x: Sample, min: Sample, max: Sample) -> Sample {
     }
 }
 
-// Convert decibels to amplitude.
+/// Clamp `x` like `clamp`, but take `default` for NaN. Ops with state clamp their parameters
+/// with it, as NaN would stick in the state and carry over to next programs.
+#[inline]
+pub fn clamp_or(x: Sample, min: Sample, max: Sample, default: Sample) -> Sample {
+    if x.is_nan() {
+        default
+    } else {
+        clamp(x, min, max)
+    }
+}
+
+/// Convert decibels to amplitude.
 #[inline]
 pub fn db2amp(x: Sample) -> Sample {
-    20.0 * x.log10()
+    10.0f64.powf(x / 20.0)
 }
 
-// Convert amplitude to decibels.
+/// Convert amplitude to decibels.
 #[inline]
 pub fn amp2db(x: Sample) -> Sample {
-    10.0f64.powf(x / 20.0)
+    20.0 * x.log10()
 }
 
 #[inline]
@@ -250,10 +374,159 @@ pub fn clip(x: Sample) -> Sample {
 
 #[inline]
 pub fn wrap(x: Sample) -> Sample {
-    (x + 1.0) % 2.0 - 1.0
+    wrap_range(x, -1.0, 1.0)
+}
+
+/// Wrap x around the range a..b, like a phase.
+#[inline]
+pub fn wrap_range(x: Sample, a: Sample, b: Sample) -> Sample {
+    let width = b - a;
+    if width == 0.0 {
+        return a;
+    }
+    a + (x - a).rem_euclid(width.abs()) * width.signum()
+}
+
+/// Reflect x back from the edges of the range a..b as many times as needed.
+#[inline]
+pub fn fold_range(x: Sample, a: Sample, b: Sample) -> Sample {
+    let width = b - a;
+    if width == 0.0 {
+        return a;
+    }
+    let x = (x - a).rem_euclid(2.0 * width.abs());
+    a + (width.abs() - (x - width.abs()).abs()) * width.signum()
 }
 
 #[inline]
 pub fn exp(x: Sample) -> Sample {
     x.exp()
 }
+
+/// Natural logarithm
+#[inline]
+pub fn log(x: Sample) -> Sample {
+    x.ln()
+}
+
+#[inline]
+pub fn sqrt(x: Sample) -> Sample {
+    x.sqrt()
+}
+
+// Waveshapers, x is multiplied by drive before shaping
+
+#[inline]
+pub fn shape_tanh(x: Sample, drive: Sample) -> Sample {
+    (drive * x).tanh()
+}
+
+#[inline]
+pub fn shape_atan(x: Sample, drive: Sample) -> Sample {
+    std::f64::consts::FRAC_2_PI * (drive * x).atan()
+}
+
+#[inline]
+pub fn shape_clip(x: Sample, drive: Sample) -> Sample {
+    clip(drive * x)
+}
+
+/// Tanh with a bias which saturates positive half earlier, so it adds even harmonics
+/// unlike the symmetric curves. Output is shifted back to keep zero in place.
+#[inline]
+pub fn shape_asym(x: Sample, drive: Sample) -> Sample {
+    const BIAS: Sample = 0.5;
+    let y = (drive * x + BIAS).tanh() - BIAS.tanh();
+    // Scale the negative side to reach -1 as the positive one reaches 1.
+    if y < 0.0 {
+        y / (1.0 + BIAS.tanh())
+    } else {
+        y / (1.0 - BIAS.tanh())
+    }
+}
+
+/// Reflect everything outside of -1..1 back into the range, again and again.
+#[inline]
+pub fn shape_fold(x: Sample, drive: Sample) -> Sample {
+    let t = (drive * x + 1.0).rem_euclid(4.0);
+    if t < 2.0 {
+        t - 1.0
+    } else {
+        3.0 - t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparators_and_logic() {
+        assert_eq!(
+            [
+                gt(1.0, 2.0),
+                lt(1.0, 2.0),
+                ge(2.0, 2.0),
+                le(1.0, 2.0),
+                eq(2.0, 2.0),
+                and(1.0, 0.0),
+                or(1.0, 0.0),
+                xor(1.0, 1.0),
+                not(0.0)
+            ],
+            [0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn math_functions() {
+        assert_eq!(
+            [
+                abs(-2.0),
+                sign(-3.0),
+                sign(0.0),
+                floor(1.5),
+                ceil(1.5),
+                log(1.0),
+                sqrt(9.0),
+                wrap_range(2.5, 0.0, 1.0),
+                wrap_range(-0.25, 0.0, 1.0),
+                fold_range(1.25, 0.0, 1.0),
+                fold_range(-2.5, -1.0, 1.0)
+            ],
+            [2.0, -1.0, 0.0, 1.0, 2.0, 0.0, 3.0, 0.5, 0.75, 0.75, 0.5]
+        );
+    }
+
+    #[test]
+    fn wrap_keeps_negative_inputs_in_range() {
+        assert_eq!(
+            [wrap(-0.5), wrap(-1.5), wrap(-3.5), wrap(1.5), wrap(-1.0)],
+            [-0.5, 0.5, 0.5, -0.5, -1.0]
+        );
+    }
+
+    #[test]
+    fn level_and_exponential_mappings() {
+        let results = [
+            db2amp(-6.0),
+            amp2db(0.5),
+            linexp(0.5, 0.0, 1.0, 100.0, 10000.0),
+            explin(1000.0, 100.0, 10000.0, 0.0, 1.0),
+            exprange(0.0, 100.0, 400.0),
+        ];
+        let expected = [0.501, -6.02, 1000.0, 0.5, 200.0];
+        for (x, y) in results.iter().zip(&expected) {
+            assert!((x - y).abs() < 0.01 * y.abs(), "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn xfade_keeps_power() {
+        assert_eq!(xfade(1.0, 2.0, 0.0), 1.0);
+        assert!((xfade(1.0, 2.0, 1.0) - 2.0).abs() < 1e-9);
+        assert_eq!(xfade(1.0, 2.0, -1.0), 1.0);
+        // Uncorrelated signals keep their power in the middle, the same ones get 3 dB louder.
+        assert!((xfade(1.0, 1.0, 0.5) - 2.0f64.sqrt()).abs() < 1e-9);
+    }
+}
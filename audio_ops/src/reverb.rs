@@ -0,0 +1,341 @@
+//! # Reverb
+//!
+//! ## Freeverb
+//!
+//! Freeverb by Jezar at Dreampoint: 8 parallel low-pass feedback comb filters followed by
+//! 4 series all-pass filters for each channel. Right channel delay lines are slightly longer
+//! to decorrelate channels for stereo image.
+//! https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
+//!
+//! Sources to connect: input, room size, damping, dry/wet.
+//! Room size, damping and dry/wet are in the range from 0 to 1.
+//!
+//! Both reverbs stretch their tails by the factor shared with the host, which could let
+//! the sound ring out longer e.g. at the end of a set.
+//!
+//! ## Feedback delay network
+//!
+//! N delay lines of mutually different lengths feed back into each other through
+//! a Householder matrix `I - 2/N * 1 * 1^T`, which is lossless and mixes every line
+//! into all others at cost of O(N). Each line has a gain to decay by 60 dB in the given time,
+//! one-pole low-pass damping and slow modulation of its length which smears resonances
+//! for lush long tails. Even lines are heard on the left, odd lines on the right.
+//! https://ccrma.stanford.edu/~jos/pasp/FDN_Reverberation.html
+//!
+//! Sources to connect: input, decay time, damping, modulation depth, dry/wet.
+//! Decay time is in seconds, damping, modulation depth and dry/wet are from 0 to 1.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Original tunings are for 44100 Hz.
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const FIXED_GAIN: Sample = 0.015;
+const SCALE_WET: Sample = 3.0;
+const SCALE_DAMPING: Sample = 0.4;
+const SCALE_ROOM: Sample = 0.28;
+const OFFSET_ROOM: Sample = 0.7;
+const ALLPASS_FEEDBACK: Sample = 0.5;
+/// Tails are not shrunk below that to keep the reverb a reverb.
+const TAILS_MIN: Sample = 0.1;
+
+struct Comb {
+    buffer: Vec<Sample>,
+    index: usize,
+    filter_store: Sample,
+}
+
+impl Comb {
+    fn new(size: usize) -> Self {
+        Comb {
+            buffer: vec![0.0; size],
+            index: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: Sample, feedback: Sample, damping: Sample) -> Sample {
+        let y = self.buffer[self.index];
+        self.filter_store = y * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.index] = x + self.filter_store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+
+    fn migrate(&mut self, other: &Self) {
+        self.buffer.copy_from_slice(&other.buffer);
+        self.index = other.index;
+        self.filter_store = other.filter_store;
+    }
+}
+
+struct AllPass {
+    buffer: Vec<Sample>,
+    index: usize,
+}
+
+impl AllPass {
+    fn new(size: usize) -> Self {
+        AllPass {
+            buffer: vec![0.0; size],
+            index: 0,
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: Sample) -> Sample {
+        let b = self.buffer[self.index];
+        self.buffer[self.index] = x + b * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        b - x
+    }
+
+    fn migrate(&mut self, other: &Self) {
+        self.buffer.copy_from_slice(&other.buffer);
+        self.index = other.index;
+    }
+}
+
+pub struct Reverb {
+    combs: Vec<Vec<Comb>>,
+    allpasses: Vec<Vec<AllPass>>,
+    tails: Arc<Mutex<Sample>>,
+}
+
+impl Reverb {
+    pub fn new(sample_rate: u32, tails: Arc<Mutex<Sample>>) -> Self {
+        let scale = |tuning: usize, channel: usize| {
+            (((tuning + channel * STEREO_SPREAD) as Sample) * Sample::from(sample_rate) / 44100.0)
+                as usize
+        };
+        Reverb {
+            combs: (0..CHANNELS)
+                .map(|channel| {
+                    COMB_TUNINGS
+                        .iter()
+                        .map(|&tuning| Comb::new(scale(tuning, channel)))
+                        .collect()
+                })
+                .collect(),
+            allpasses: (0..CHANNELS)
+                .map(|channel| {
+                    ALLPASS_TUNINGS
+                        .iter()
+                        .map(|&tuning| AllPass::new(scale(tuning, channel)))
+                        .collect()
+                })
+                .collect(),
+            tails,
+        }
+    }
+}
+
+impl Op for Reverb {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let damping = stack.pop();
+        let room_size = stack.pop();
+        let input = stack.pop();
+        // Both channels are fed with the same mono mix as in the original.
+        let x = input.iter().sum::<Sample>() * FIXED_GAIN;
+        let tails = self.tails.lock().unwrap().max(TAILS_MIN);
+        let mut frame: Frame = [0.0; CHANNELS];
+        for (output, &dry, &room_size, &damping, &wet, combs, allpasses) in izip!(
+            &mut frame,
+            &input,
+            &room_size,
+            &damping,
+            &wet,
+            &mut self.combs,
+            &mut self.allpasses
+        ) {
+            let room_size = pure::clamp_or(room_size, 0.0, 1.0, 0.0);
+            // Decay time of a comb is about inversely proportional to the loss per loop.
+            let feedback = 1.0 - (1.0 - room_size * SCALE_ROOM - OFFSET_ROOM) / tails;
+            let damping = pure::clamp_or(damping, 0.0, 1.0, 0.0) * SCALE_DAMPING;
+            let mut y = combs
+                .iter_mut()
+                .map(|comb| comb.process(x, feedback, damping))
+                .sum::<Sample>();
+            for allpass in allpasses.iter_mut() {
+                y = allpass.process(y);
+            }
+            *output = (1.0 - wet) * dry + wet * SCALE_WET * y;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            for (combs, other_combs) in self.combs.iter_mut().zip(&other.combs) {
+                for (comb, other) in combs.iter_mut().zip(other_combs) {
+                    comb.migrate(other);
+                }
+            }
+            for (allpasses, other_allpasses) in self.allpasses.iter_mut().zip(&other.allpasses) {
+                for (allpass, other) in allpasses.iter_mut().zip(other_allpasses) {
+                    allpass.migrate(other);
+                }
+            }
+        }
+    }
+}
+
+/// Line lengths are spread exponentially between these.
+const FDN_MIN_DELAY: Sample = 0.03;
+const FDN_MAX_DELAY: Sample = 0.1;
+/// Max modulation depth in seconds.
+const FDN_MAX_MODULATION: Sample = 0.001;
+/// Modulation rates are spread between these.
+const FDN_MIN_RATE: Sample = 0.1;
+const FDN_MAX_RATE: Sample = 0.7;
+
+struct Line {
+    buffer: Vec<Sample>,
+    index: usize,
+    /// Nominal length in frames.
+    length: Sample,
+    filter_store: Sample,
+    phase: Sample,
+    /// Modulation phase increment per frame.
+    dphase: Sample,
+}
+
+impl Line {
+    #[inline]
+    fn read(&self, modulation: Sample) -> Sample {
+        let delay = self.length + modulation * self.phase.sin();
+        let size = self.buffer.len();
+        let position = (self.index + size) as Sample - delay;
+        let i = position.floor();
+        let k = position - i;
+        let i = i as usize;
+        (1.0 - k) * self.buffer[i % size] + k * self.buffer[(i + 1) % size]
+    }
+
+    #[inline]
+    fn write(&mut self, x: Sample) {
+        self.buffer[self.index] = x;
+        self.index = (self.index + 1) % self.buffer.len();
+        self.phase = (self.phase + self.dphase) % (2.0 * PI);
+    }
+}
+
+pub struct Fdn {
+    lines: Vec<Line>,
+    /// Line outputs, reused to avoid allocations on each frame.
+    outputs: Vec<Sample>,
+    sample_rate: Sample,
+    tails: Arc<Mutex<Sample>>,
+}
+
+impl Fdn {
+    pub fn new(sample_rate: u32, size: usize, tails: Arc<Mutex<Sample>>) -> Self {
+        // At least one line per channel.
+        let size = size.max(CHANNELS);
+        let sample_rate = Sample::from(sample_rate);
+        let modulation = FDN_MAX_MODULATION * sample_rate;
+        let lines = (0..size)
+            .map(|i| {
+                let t = if size > 1 {
+                    i as Sample / (size - 1) as Sample
+                } else {
+                    0.0
+                };
+                let delay = FDN_MIN_DELAY * (FDN_MAX_DELAY / FDN_MIN_DELAY).powf(t);
+                // Odd lengths in frames avoid the most obvious common divisors.
+                let length = ((delay * sample_rate / 2.0).floor() * 2.0 + 1.0).max(1.0);
+                let rate = FDN_MIN_RATE + (FDN_MAX_RATE - FDN_MIN_RATE) * t;
+                Line {
+                    buffer: vec![0.0; (length + modulation) as usize + 2],
+                    index: 0,
+                    length,
+                    filter_store: 0.0,
+                    phase: 2.0 * PI * i as Sample / size as Sample,
+                    dphase: 2.0 * PI * rate / sample_rate,
+                }
+            })
+            .collect();
+        Fdn {
+            lines,
+            outputs: vec![0.0; size],
+            sample_rate,
+            tails,
+        }
+    }
+}
+
+impl Op for Fdn {
+    fn perform(&mut self, stack: &mut Stack) {
+        let wet = stack.pop();
+        let modulation = stack.pop();
+        let damping = stack.pop();
+        let time = stack.pop();
+        let input = stack.pop();
+        // Parameters are shared by lines, hence mono.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let time = mean(time) * self.tails.lock().unwrap().max(TAILS_MIN);
+        let damping = pure::clamp_or(mean(damping), 0.0, 1.0, 0.0);
+        let modulation =
+            pure::clamp_or(mean(modulation), 0.0, 1.0, 0.0) * FDN_MAX_MODULATION * self.sample_rate;
+        let x = mean(input);
+        let size = self.lines.len();
+        for (output, line) in self.outputs.iter_mut().zip(&self.lines) {
+            *output = line.read(modulation);
+        }
+        let sum = self.outputs.iter().sum::<Sample>();
+        let mut frame: Frame = [0.0; CHANNELS];
+        for (i, (line, &y)) in self.lines.iter_mut().zip(&self.outputs).enumerate() {
+            frame[i % CHANNELS] += y;
+            let gain = if time > 0.0 {
+                0.001f64.powf(line.length / (time * self.sample_rate))
+            } else {
+                0.0
+            };
+            let feedback = y - 2.0 / size as Sample * sum;
+            line.filter_store = feedback * (1.0 - damping) + line.filter_store * damping;
+            // Alternate signs of the input to decorrelate lines.
+            let sign = (-1.0 as Sample).powi((i / CHANNELS) as i32);
+            line.write(sign * x + gain * line.filter_store);
+        }
+        let scale = (CHANNELS as Sample / size as Sample).sqrt();
+        for (output, &dry, &wet) in izip!(&mut frame, &input, &wet) {
+            *output = (1.0 - wet) * dry + wet * scale * *output;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.lines.len() != other.lines.len() {
+                return;
+            }
+            for (line, other) in self.lines.iter_mut().zip(&other.lines) {
+                line.buffer.copy_from_slice(&other.buffer);
+                line.index = other.index;
+                line.filter_store = other.filter_store;
+                line.phase = other.phase;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{click, constant, peak, render, tops};
+
+    #[test]
+    fn reverb_decays_in_oversized_room() {
+        let mut ops = vec![click(), constant(10.0), constant(0.5), constant(1.0)];
+        ops.push(Box::new(Reverb::new(44100, Arc::new(Mutex::new(1.0)))));
+        let output = tops(&render(&mut ops, 88200));
+        assert!(output.iter().all(|x| x.is_finite()));
+        assert!(peak(&output[44100..]) < 0.5 * peak(&output[..44100]));
+    }
+}
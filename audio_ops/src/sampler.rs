@@ -22,11 +22,15 @@ impl Op for TableReader {
         let mut frame = [0.0; CHANNELS];
         let table = self.table.lock().unwrap();
         let size = table.len();
+        if size == 0 {
+            stack.push(&frame);
+            return;
+        }
         for (channel, (sample, &ix)) in izip!(&mut frame, &index).enumerate() {
             let z = ix * self.sample_rate;
-            let i = z as usize;
+            let i = z as usize % size;
             let k = z.fract();
-            let a = table[(i % size)][channel];
+            let a = table[i][channel];
             let b = table[(i + 1) % size][channel];
             *sample = (1.0 - k) * a + k * b;
         }
@@ -37,15 +41,18 @@ impl Op for TableReader {
 pub struct TableWriter {
     frame: usize,
     last_trigger: Frame,
+    /// Input latency in frames to compensate, signal is written that much earlier in the table.
+    latency: usize,
     table: Arc<Mutex<Vec<Frame>>>,
     trigger_frame: [usize; CHANNELS],
 }
 
 impl TableWriter {
-    pub fn new(table: Arc<Mutex<Vec<Frame>>>) -> Self {
+    pub fn new(table: Arc<Mutex<Vec<Frame>>>, latency: usize) -> Self {
         TableWriter {
             frame: 0,
             last_trigger: [0.0; CHANNELS],
+            latency,
             table,
             trigger_frame: [0; CHANNELS],
         }
@@ -70,8 +77,8 @@ impl Op for TableWriter {
                 *trigger_frame = self.frame;
             }
             let ix = self.frame - *trigger_frame;
-            if ix < size {
-                table[ix][channel] = input;
+            if self.latency <= ix && ix < size + self.latency {
+                table[ix - self.latency][channel] = input;
             }
             *last_trigger = trigger;
         }
@@ -83,6 +90,13 @@ impl Op for TableWriter {
             self.frame = other.frame;
             self.last_trigger = other.last_trigger;
             self.trigger_frame = other.trigger_frame;
+            // The table was replaced to change its length, keep what was recorded.
+            if !Arc::ptr_eq(&self.table, &other.table) {
+                let other = other.table.lock().unwrap();
+                let mut table = self.table.lock().unwrap();
+                let n = table.len().min(other.len());
+                table[..n].copy_from_slice(&other[..n]);
+            }
         }
     }
 }
@@ -0,0 +1,139 @@
+//! # Scale quantizer
+//!
+//! Snap MIDI pitch to the nearest note of a scale built on root. Scale is either one of
+//! `SCALES` or a table with semitones above root of its notes in frames, in any order and
+//! octave, so tables could be different per channel and hold microtones. Table is re-read every
+//! `TABLE_REFRESH` frames to follow its writer, empty one passes pitch through.
+//!
+//! Sources to connect: pitch, root.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+/// Named scales as semitones above root.
+pub const SCALES: [(&str, &[Sample]); 10] = [
+    ("major", &[0.0, 2.0, 4.0, 5.0, 7.0, 9.0, 11.0]),
+    ("minor", &[0.0, 2.0, 3.0, 5.0, 7.0, 8.0, 10.0]),
+    ("dorian", &[0.0, 2.0, 3.0, 5.0, 7.0, 9.0, 10.0]),
+    ("phrygian", &[0.0, 1.0, 3.0, 5.0, 7.0, 8.0, 10.0]),
+    ("lydian", &[0.0, 2.0, 4.0, 6.0, 7.0, 9.0, 11.0]),
+    ("mixolydian", &[0.0, 2.0, 4.0, 5.0, 7.0, 9.0, 10.0]),
+    ("locrian", &[0.0, 1.0, 3.0, 5.0, 6.0, 8.0, 10.0]),
+    ("pentatonic", &[0.0, 2.0, 4.0, 7.0, 9.0]),
+    ("minor_pentatonic", &[0.0, 3.0, 5.0, 7.0, 10.0]),
+    (
+        "chromatic",
+        &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0],
+    ),
+];
+
+const TABLE_REFRESH: usize = 1024;
+
+/// Semitones of the named scale, if there is one.
+pub fn scale(name: &str) -> Option<&'static [Sample]> {
+    SCALES
+        .iter()
+        .find(|(scale, _)| *scale == name)
+        .map(|(_, degrees)| *degrees)
+}
+
+pub struct ScaleQuantizer {
+    /// Semitones of the scale within an octave, ascending, per channel.
+    degrees: [Vec<Sample>; CHANNELS],
+    /// Frames left until the table is re-read.
+    countdown: usize,
+    table: Option<Arc<Mutex<Vec<Frame>>>>,
+}
+
+impl ScaleQuantizer {
+    pub fn new(degrees: &[Sample]) -> Self {
+        let mut quantizer = ScaleQuantizer {
+            degrees: Default::default(),
+            countdown: 0,
+            table: None,
+        };
+        for channel in 0..CHANNELS {
+            quantizer.degrees[channel] = normalize(degrees.iter().copied());
+        }
+        quantizer
+    }
+
+    /// Use it to take the scale from the table.
+    pub fn with_table(table: Arc<Mutex<Vec<Frame>>>) -> Self {
+        ScaleQuantizer {
+            degrees: Default::default(),
+            countdown: 0,
+            table: Some(table),
+        }
+    }
+}
+
+/// Bring degrees into an octave, rounded to cents, and sort them without duplicates.
+fn normalize(degrees: impl Iterator<Item = Sample>) -> Vec<Sample> {
+    let mut degrees = degrees
+        .filter(|x| x.is_finite())
+        .map(|x| (x.rem_euclid(12.0) * 100.0).round() / 100.0 % 12.0)
+        .collect::<Vec<_>>();
+    degrees.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    degrees.dedup();
+    degrees
+}
+
+impl Op for ScaleQuantizer {
+    fn perform(&mut self, stack: &mut Stack) {
+        let root = stack.pop();
+        let pitch = stack.pop();
+        if let Some(table) = &self.table {
+            if self.countdown == 0 {
+                let table = table.lock().unwrap();
+                for channel in 0..CHANNELS {
+                    self.degrees[channel] = normalize(table.iter().map(|frame| frame[channel]));
+                }
+                self.countdown = TABLE_REFRESH;
+            }
+            self.countdown -= 1;
+        }
+        let mut frame = [0.0; CHANNELS];
+        for (channel, output) in frame.iter_mut().enumerate() {
+            let degrees = &self.degrees[channel];
+            let x = pitch[channel] - root[channel];
+            let octave = (x / 12.0).floor();
+            let x = x - 12.0 * octave;
+            // Neighbours of the octave are candidates too: 11.8 is closer to 12 than to 11.
+            let nearest = degrees
+                .iter()
+                .flat_map(|&d| [d - 12.0, d, d + 12.0])
+                .filter(|_| x.is_finite())
+                .min_by(|a, b| (a - x).abs().partial_cmp(&(b - x).abs()).unwrap());
+            *output = match nearest {
+                Some(degree) => root[channel] + 12.0 * octave + degree,
+                None => pitch[channel],
+            };
+        }
+        stack.push(&frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+
+    fn quantize(quantizer: ScaleQuantizer, pitch: Sample, root: Sample) -> Sample {
+        let mut ops = vec![constant(pitch), constant(root)];
+        ops.push(Box::new(quantizer));
+        tops(&render(&mut ops, 1))[0]
+    }
+
+    #[test]
+    fn quantizes_pitch() {
+        let named = |name| ScaleQuantizer::new(scale(name).unwrap());
+        let table = |frames: Vec<Frame>| ScaleQuantizer::with_table(Arc::new(Mutex::new(frames)));
+        assert_eq!(quantize(named("major"), 61.4, 60.0), 62.0);
+        assert_eq!(quantize(named("major"), 71.8, 60.0), 72.0);
+        assert_eq!(quantize(named("pentatonic"), 58.0, 48.0), 57.0);
+        let steps = vec![[15.0; CHANNELS], [-4.0; CHANNELS]];
+        assert_eq!(quantize(table(steps), 66.0, 60.0), 68.0);
+        // Empty scale passes pitch through.
+        assert_eq!(quantize(table(Vec::new()), 60.3, 0.0), 60.3);
+    }
+}
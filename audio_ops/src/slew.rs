@@ -0,0 +1,154 @@
+//! # Slew
+//!
+//! Exponential lag with separate rise and fall times, like LagUD in SuperCollider.
+//! Time is how long it takes for output to converge to the new input within -60 dB.
+//! Smoothes control signals to avoid zipper noise and clicks on their jumps.
+//!
+//! Sources to connect: input, rise time, fall time.
+//!
+//! ## Glide
+//!
+//! Portamento of mono-synth leads: when input jumps to a new value, output slides to it from
+//! where it is and arrives in exactly glide time, then sticks to input until the next jump.
+//! Between positive values the slide is exponential, i.e. at a steady rate of semitones for
+//! frequencies, and linear otherwise, e.g. for MIDI pitches.
+//!
+//! Sources to connect: input, glide time.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+pub struct Slew {
+    output: Frame,
+    sample_rate: Sample,
+}
+
+impl Slew {
+    pub fn new(sample_rate: u32) -> Self {
+        Slew {
+            output: [0.0; CHANNELS],
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Slew {
+    fn perform(&mut self, stack: &mut Stack) {
+        let fall = stack.pop();
+        let rise = stack.pop();
+        let input = stack.pop();
+        for (output, &x, &rise, &fall) in izip!(&mut self.output, &input, &rise, &fall) {
+            let time = if x > *output { rise } else { fall };
+            let frames = time * self.sample_rate;
+            if frames > 0.0 {
+                // 0.001 is -60 dB.
+                let a = (0.001f64.ln() / frames).exp();
+                *output = x + a * (*output - x);
+            } else {
+                *output = x;
+            }
+        }
+        stack.push(&self.output);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.output = other.output;
+        }
+    }
+}
+
+pub struct Glide {
+    /// Seconds since the last jump of input.
+    elapsed: Frame,
+    last_input: Frame,
+    output: Frame,
+    sample_period: Sample,
+    /// Output when the last jump of input happened.
+    start: Frame,
+}
+
+impl Glide {
+    pub fn new(sample_rate: u32) -> Self {
+        Glide {
+            elapsed: [0.0; CHANNELS],
+            last_input: [0.0; CHANNELS],
+            output: [0.0; CHANNELS],
+            sample_period: Sample::from(sample_rate).recip(),
+            start: [0.0; CHANNELS],
+        }
+    }
+}
+
+impl Op for Glide {
+    fn perform(&mut self, stack: &mut Stack) {
+        let time = stack.pop();
+        let input = stack.pop();
+        for (output, &x, &time, last_input, start, elapsed) in izip!(
+            &mut self.output,
+            &input,
+            &time,
+            &mut self.last_input,
+            &mut self.start,
+            &mut self.elapsed
+        ) {
+            if x != *last_input {
+                *last_input = x;
+                *start = *output;
+                *elapsed = 0.0;
+            }
+            let progress = if time > 0.0 {
+                (*elapsed / time).min(1.0)
+            } else {
+                1.0
+            };
+            *elapsed += self.sample_period;
+            *output = if progress >= 1.0 {
+                x
+            } else if *start > 0.0 && x > 0.0 {
+                *start * (x / *start).powf(progress)
+            } else {
+                *start + (x - *start) * progress
+            };
+            // Keep NaN of bad inputs from sticking.
+            if !output.is_finite() {
+                *output = 0.0;
+            }
+        }
+        stack.push(&self.output);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.elapsed = other.elapsed;
+            self.last_input = other.last_input;
+            self.output = other.output;
+            self.start = other.start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, migrate, render, tops};
+
+    #[test]
+    fn glide_arrives_in_time() {
+        let glide = |x| {
+            let mut ops = vec![constant(x), constant(0.01)];
+            ops.push(Box::new(Glide::new(1000)));
+            ops
+        };
+        let mut ops = glide(100.0);
+        let outputs = tops(&render(&mut ops, 11));
+        // Jump from 0 to 100 glides linearly.
+        assert_eq!(outputs[0], 0.0);
+        assert_eq!(outputs[10], 100.0);
+        // Jump from 100 to 400 Hz passes 200 Hz halfway.
+        let mut next = glide(400.0);
+        migrate(&mut next, &ops);
+        let outputs = tops(&render(&mut next, 11));
+        assert!((outputs[5] - 200.0).abs() < 1e-9);
+        assert_eq!(outputs[10], 400.0);
+    }
+}
@@ -0,0 +1,57 @@
+//! # Spectral filter
+//!
+//! Brickwall filter over FFT bins: bins above cutoff are removed, the rest pass as is or weighted
+//! by the mask shape drawn in a table, which is stretched over them. Cutoff and mask are read
+//! each hop, so both could be changed at audio rate. Output lags by one window.
+//!
+//! Sources to connect: input, cutoff from 0 to 1 of Nyquist.
+use crate::pure;
+use crate::spectral_transform::{Stft, HOP, WINDOW_SIZE};
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+pub struct SpectralFilter {
+    mask: Option<Arc<Mutex<Vec<Frame>>>>,
+    stft: Stft,
+}
+
+impl SpectralFilter {
+    pub fn new(mask: Option<Arc<Mutex<Vec<Frame>>>>) -> Self {
+        SpectralFilter {
+            mask,
+            stft: Stft::new(1, WINDOW_SIZE, HOP),
+        }
+    }
+}
+
+impl Op for SpectralFilter {
+    fn perform(&mut self, stack: &mut Stack) {
+        let cutoff = stack.pop();
+        let input = stack.pop();
+        // Bins are shared by channels, hence mono parameter.
+        let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        let cutoff = unit(mean(cutoff));
+        let mask = &self.mask;
+        let frame = self.stft.process(&[input], |_, spectra| {
+            let passband = (cutoff * (WINDOW_SIZE / 2) as Sample).round() as usize;
+            let mask = mask.as_ref().map(|mask| mask.lock().unwrap());
+            for (k, bin) in spectra[0].iter_mut().take(WINDOW_SIZE / 2 + 1).enumerate() {
+                *bin *= match &mask {
+                    _ if k > passband => 0.0,
+                    Some(mask) if !mask.is_empty() => {
+                        unit(mask[k * (mask.len() - 1) / passband.max(1)][0])
+                    }
+                    _ => 1.0,
+                };
+            }
+        });
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.stft.migrate(&other.stft);
+        }
+    }
+}
@@ -3,25 +3,23 @@
 //! Do a FFT of the input signal, transform bins, and produce an output signal with IFFT.
 //!
 //! Source to connect: input.
-use audio_vm::{Op, Sample, Stack, CHANNELS};
-use itertools::izip;
+//!
+//! Short-time Fourier transform beneath it is shared by all spectral ops. Each hop it takes
+//! spectra of the last window of each input for each channel, lets the op transform them into
+//! the output spectrum, and overlap-adds its IFFT. Output lags by one window.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 use rustfft::algorithm::Radix4;
 use rustfft::num_complex::Complex;
 use rustfft::num_traits::Zero;
 use rustfft::FFT;
-use std::collections::VecDeque;
+
+/// Window of spectral ops, must be power of two!
+pub(crate) const WINDOW_SIZE: usize = 1024;
+/// Overlap of 4 windows.
+pub(crate) const HOP: usize = WINDOW_SIZE / 4;
 
 pub struct SpectralTransform {
-    input_buffers: Vec<VecDeque<Complex<Sample>>>,
-    input_scratch: Vec<Complex<Sample>>,
-    freq_buffer: Vec<Complex<Sample>>,
-    output_buffers: Vec<Vec<Complex<Sample>>>,
-    fft: Radix4<Sample>,
-    ifft: Radix4<Sample>,
-    period_mask: usize,
-    period_offset: usize,
-    window: Vec<Complex<Sample>>,
-    frame_number: usize,
+    stft: Stft,
     transform: Box<dyn FnMut(&mut Vec<Complex<Sample>>) + Send>,
 }
 
@@ -36,23 +34,7 @@ impl SpectralTransform {
         transform: Box<dyn FnMut(&mut Vec<Complex<Sample>>) + Send>,
     ) -> Self {
         SpectralTransform {
-            input_buffers: vec![
-                std::iter::repeat(Complex::zero())
-                    .take(window_size)
-                    .collect();
-                CHANNELS
-            ],
-            input_scratch: vec![Complex::zero(); window_size],
-            freq_buffer: vec![Complex::zero(); window_size],
-            output_buffers: vec![vec![Complex::zero(); window_size]; CHANNELS],
-            fft: Radix4::new(window_size, false),
-            ifft: Radix4::new(window_size, true),
-            period_mask: period - 1,
-            period_offset: window_size - period,
-            frame_number: 0,
-            window: apodize::hanning_iter(window_size)
-                .map(Complex::from)
-                .collect(),
+            stft: Stft::new(1, window_size, period),
             transform,
         }
     }
@@ -60,33 +42,161 @@ impl SpectralTransform {
 
 impl Op for SpectralTransform {
     fn perform(&mut self, stack: &mut Stack) {
-        let mut frame = [0.0; CHANNELS];
-        let index = self.frame_number & self.period_mask;
-        for (output, input, input_buffer, output_buffer) in izip!(
-            &mut frame,
-            &stack.pop(),
-            &mut self.input_buffers,
-            &mut self.output_buffers
-        ) {
-            if index == 0 {
-                let mut scratch = &mut self.input_scratch;
-                let freq_buffer = &mut self.freq_buffer;
-                let input_slices = input_buffer.as_slices();
-                let n = input_slices.0.len();
-                scratch[..n].clone_from_slice(input_slices.0);
-                scratch[n..].clone_from_slice(input_slices.1);
-                for (x, a) in scratch.iter_mut().zip(&self.window) {
-                    *x *= a;
+        let input = stack.pop();
+        let transform = &mut self.transform;
+        let frame = self
+            .stft
+            .process(&[input], |_, spectra| transform(&mut spectra[0]));
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.stft.migrate(&other.stft);
+        }
+    }
+}
+
+/// STFT of signals coming frame by frame.
+pub(crate) struct Stft {
+    /// Last window of each input, the oldest frame is at the write position.
+    inputs: Vec<Vec<Frame>>,
+    position: usize,
+    spectra: Vec<Vec<Complex<Sample>>>,
+    overlap_add: OverlapAdd,
+}
+
+impl Stft {
+    pub(crate) fn new(inputs: usize, window_size: usize, hop: usize) -> Self {
+        Stft {
+            inputs: vec![vec![[0.0; CHANNELS]; window_size]; inputs],
+            position: 0,
+            spectra: vec![vec![Complex::zero(); window_size]; inputs],
+            overlap_add: OverlapAdd::new(window_size, hop),
+        }
+    }
+
+    /// Take the next frame of each input and return the next output frame. `transform` gets
+    /// the channel and spectra of inputs in it and should leave the output one in the first
+    /// spectrum. Only bins up to Nyquist matter, the rest are mirrored from them.
+    pub(crate) fn process<F>(&mut self, frames: &[Frame], mut transform: F) -> Frame
+    where
+        F: FnMut(usize, &mut [Vec<Complex<Sample>>]),
+    {
+        let window_size = self.overlap_add.window_size();
+        for (input, frame) in self.inputs.iter_mut().zip(frames) {
+            input[self.position] = *frame;
+        }
+        self.position = (self.position + 1) % window_size;
+        if self.overlap_add.hop_due() {
+            self.overlap_add.start_hop();
+            let position = self.position;
+            for channel in 0..CHANNELS {
+                for (input, spectrum) in self.inputs.iter().zip(self.spectra.iter_mut()) {
+                    self.overlap_add
+                        .analyze(|i| input[(position + i) % window_size][channel], spectrum);
                 }
-                self.fft.process(&mut scratch, freq_buffer);
-                (self.transform)(freq_buffer);
-                self.ifft.process(freq_buffer, output_buffer);
+                transform(channel, &mut self.spectra);
+                self.overlap_add.synthesize(channel, &mut self.spectra[0]);
             }
-            *output = output_buffer[self.period_offset + index].re;
-            input_buffer.pop_front();
-            input_buffer.push_back(Complex::from(input));
         }
-        self.frame_number += 1;
-        stack.push(&frame);
+        self.overlap_add.next_frame()
+    }
+
+    pub(crate) fn migrate(&mut self, other: &Self) {
+        if self.inputs.len() == other.inputs.len() && self.inputs[0].len() == other.inputs[0].len()
+        {
+            self.inputs.clone_from(&other.inputs);
+            self.position = other.position;
+        }
+        self.overlap_add.migrate(&other.overlap_add);
+    }
+}
+
+/// Windowed FFT of hops and overlap-add of IFFT of their spectra, for ops which read windows
+/// on their own, like table players.
+pub(crate) struct OverlapAdd {
+    hop: usize,
+    /// Overlap-add accumulator, the first hop of it is complete.
+    output: Vec<Frame>,
+    /// Frames played since the last hop.
+    index: usize,
+    /// Undoes the sum of overlapping windows and the scale of IFFT.
+    scale: Sample,
+    window: Vec<Sample>,
+    scratch: Vec<Complex<Sample>>,
+    fft: Radix4<Sample>,
+    ifft: Radix4<Sample>,
+}
+
+impl OverlapAdd {
+    /// Window size must be power of two and a multiple of hop.
+    pub(crate) fn new(window_size: usize, hop: usize) -> Self {
+        OverlapAdd {
+            hop,
+            output: vec![[0.0; CHANNELS]; window_size],
+            index: 0,
+            // Squared Hann windows sum up to 3/8 of their overlap.
+            scale: hop as Sample / (0.375 * (window_size * window_size) as Sample),
+            window: apodize::hanning_iter(window_size).collect(),
+            scratch: vec![Complex::zero(); window_size],
+            fft: Radix4::new(window_size, false),
+            ifft: Radix4::new(window_size, true),
+        }
+    }
+
+    pub(crate) fn window_size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Whether spectra of the next hop should be synthesized before the next frame.
+    pub(crate) fn hop_due(&self) -> bool {
+        self.index == 0
+    }
+
+    pub(crate) fn start_hop(&mut self) {
+        self.output.rotate_left(self.hop);
+        let window_size = self.window_size();
+        for frame in self.output.iter_mut().skip(window_size - self.hop) {
+            *frame = [0.0; CHANNELS];
+        }
+    }
+
+    /// Spectrum of the window of samples `x(0)..x(window_size)`.
+    pub(crate) fn analyze<F>(&mut self, x: F, spectrum: &mut [Complex<Sample>])
+    where
+        F: Fn(usize) -> Sample,
+    {
+        for (i, (s, w)) in self.scratch.iter_mut().zip(&self.window).enumerate() {
+            *s = Complex::from(x(i) * w);
+        }
+        self.fft.process(&mut self.scratch, spectrum);
+    }
+
+    /// Overlap-add IFFT of the spectrum to the channel of the current hop. Only bins up to
+    /// Nyquist matter, the rest are mirrored from them.
+    pub(crate) fn synthesize(&mut self, channel: usize, spectrum: &mut [Complex<Sample>]) {
+        let window_size = self.window_size();
+        // Real output needs conjugate symmetric spectrum.
+        for k in 1..window_size / 2 {
+            spectrum[window_size - k] = spectrum[k].conj();
+        }
+        self.ifft.process(spectrum, &mut self.scratch);
+        for ((frame, x), w) in self.output.iter_mut().zip(&self.scratch).zip(&self.window) {
+            frame[channel] += self.scale * w * x.re;
+        }
+    }
+
+    pub(crate) fn next_frame(&mut self) -> Frame {
+        let frame = self.output[self.index];
+        self.index = (self.index + 1) % self.hop;
+        frame
+    }
+
+    pub(crate) fn migrate(&mut self, other: &Self) {
+        if self.output.len() == other.output.len() && self.hop == other.hop {
+            self.output.copy_from_slice(&other.output);
+            self.index = other.index;
+        }
     }
 }
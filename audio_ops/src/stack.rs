@@ -10,7 +10,9 @@ impl Dup {
 
 impl Op for Dup {
     fn perform(&mut self, stack: &mut Stack) {
-        stack.push(&stack.peek());
+        let x = stack.pop();
+        stack.push(&x);
+        stack.push(&x);
     }
 }
 
@@ -84,8 +86,9 @@ impl Op for Dig {
         for x in self.t.iter_mut().rev().skip(1) {
             stack.push(&std::mem::replace(x, ZERO));
         }
-        let depth = self.t.len();
-        stack.push(&std::mem::replace(&mut self.t[depth - 1], ZERO));
+        if let Some(x) = self.t.last_mut() {
+            stack.push(&std::mem::replace(x, ZERO));
+        }
     }
 }
 
@@ -0,0 +1,104 @@
+//! # Time-stretch
+//!
+//! Phase vocoder playing a table in a loop with independent speed and pitch. Each hop it takes
+//! spectra of two windows of the table one hop apart at the read position, estimates true
+//! frequencies of bins from the difference of their phases, moves bins by pitch ratio and
+//! accumulates their phases at the estimated frequencies, then overlap-adds IFFT of the result.
+//! Read position advances by speed, so loops could be slowed down without dropping pitch.
+//! Output lags by one window.
+//!
+//! Sources to connect: speed (1 is the original one, negative plays backwards), pitch shift in
+//! semitones.
+use crate::spectral_transform::OverlapAdd;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Must be power of two!
+const WINDOW_SIZE: usize = 2048;
+/// Overlap of 4 windows.
+const HOP: usize = WINDOW_SIZE / 4;
+
+pub struct Stretch {
+    table: Arc<Mutex<Vec<Frame>>>,
+    /// Read position in the table in frames.
+    position: Sample,
+    /// Accumulated phases of output bins for each channel.
+    phases: Vec<Vec<Sample>>,
+    current: Vec<Complex<Sample>>,
+    next: Vec<Complex<Sample>>,
+    spectrum: Vec<Complex<Sample>>,
+    overlap_add: OverlapAdd,
+}
+
+impl Stretch {
+    pub fn new(table: Arc<Mutex<Vec<Frame>>>) -> Self {
+        Stretch {
+            table,
+            position: 0.0,
+            phases: vec![vec![0.0; WINDOW_SIZE / 2 + 1]; CHANNELS],
+            current: vec![Complex::zero(); WINDOW_SIZE],
+            next: vec![Complex::zero(); WINDOW_SIZE],
+            spectrum: vec![Complex::zero(); WINDOW_SIZE],
+            overlap_add: OverlapAdd::new(WINDOW_SIZE, HOP),
+        }
+    }
+
+    fn hop(&mut self, speed: Sample, ratio: Sample) {
+        self.overlap_add.start_hop();
+        let table = self.table.lock().unwrap();
+        let size = table.len();
+        if size == 0 {
+            return;
+        }
+        let start = self.position as usize;
+        for channel in 0..CHANNELS {
+            self.overlap_add
+                .analyze(|i| table[(start + i) % size][channel], &mut self.current);
+            self.overlap_add
+                .analyze(|i| table[(start + HOP + i) % size][channel], &mut self.next);
+            let phases = &mut self.phases[channel];
+            for x in self.spectrum.iter_mut() {
+                *x = Complex::zero();
+            }
+            for (k, phase) in phases.iter_mut().enumerate() {
+                let source = (k as Sample / ratio).round() as usize;
+                if source > WINDOW_SIZE / 2 {
+                    continue;
+                }
+                // Expected phase advance of the bin over a hop plus the deviation from it.
+                let expected = 2.0 * PI * source as Sample * HOP as Sample / WINDOW_SIZE as Sample;
+                let deviation = self.next[source].arg() - self.current[source].arg() - expected;
+                let deviation = deviation - 2.0 * PI * (deviation / (2.0 * PI)).round();
+                *phase = (*phase + ratio * (expected + deviation)).rem_euclid(2.0 * PI);
+                self.spectrum[k] = Complex::from_polar(&self.next[source].norm(), &*phase);
+            }
+            self.overlap_add.synthesize(channel, &mut self.spectrum);
+        }
+        self.position = (self.position + speed * HOP as Sample).rem_euclid(size as Sample);
+    }
+}
+
+impl Op for Stretch {
+    fn perform(&mut self, stack: &mut Stack) {
+        let pitch = stack.pop();
+        let speed = stack.pop();
+        if self.overlap_add.hop_due() {
+            // Bins are shared by channels, hence mono parameters.
+            let mean = |xs: Frame| xs.iter().sum::<Sample>() / CHANNELS as Sample;
+            let ratio = (mean(pitch) / 12.0).exp2().max(1e-3);
+            self.hop(mean(speed), ratio);
+        }
+        stack.push(&self.overlap_add.next_frame());
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.position = other.position;
+            self.phases.clone_from(&other.phases);
+            self.overlap_add.migrate(&other.overlap_add);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! # State variable filter
+//!
+//! Zero-delay feedback (topology-preserving transform) SVF as described by Andrew Simper
+//! https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+//! Unlike BiQuad it stays stable when cut-off frequency is modulated at audio rate.
+//!
+//! Sources to connect: input, cut-off frequency, Q.
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SVFMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+pub struct SVF {
+    mode: SVFMode,
+    ic1eq: Frame,
+    ic2eq: Frame,
+    nyquist: Sample,
+    sample_period: Sample,
+}
+
+impl SVF {
+    pub fn new(sample_rate: u32, mode: SVFMode) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        SVF {
+            mode,
+            ic1eq: [0.0; CHANNELS],
+            ic2eq: [0.0; CHANNELS],
+            nyquist: 0.5 * sample_rate,
+            sample_period: sample_rate.recip(),
+        }
+    }
+}
+
+impl Op for SVF {
+    fn perform(&mut self, stack: &mut Stack) {
+        let q = stack.pop();
+        let cut_off_freq = stack.pop();
+        let input = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (output, &x, &frequency, &q, ic1eq, ic2eq) in izip!(
+            &mut frame,
+            &input,
+            &cut_off_freq,
+            &q,
+            &mut self.ic1eq,
+            &mut self.ic2eq
+        ) {
+            // Keep tan away from its pole at Nyquist.
+            let frequency = pure::clamp(frequency, 0.0, 0.99 * self.nyquist);
+            let g = (std::f64::consts::PI * frequency * self.sample_period).tan();
+            let k = q.max(1e-3).recip();
+            let a1 = 1.0 / (1.0 + g * (g + k));
+            let a2 = g * a1;
+            let a3 = g * a2;
+
+            let v3 = x - *ic2eq;
+            let v1 = a1 * *ic1eq + a2 * v3;
+            let v2 = *ic2eq + a2 * *ic1eq + a3 * v3;
+            *ic1eq = 2.0 * v1 - *ic1eq;
+            *ic2eq = 2.0 * v2 - *ic2eq;
+
+            *output = match self.mode {
+                SVFMode::LowPass => v2,
+                SVFMode::HighPass => x - k * v1 - v2,
+                SVFMode::BandPass => k * v1,
+                SVFMode::Notch => x - k * v1,
+            };
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.ic1eq = other.ic1eq;
+            self.ic2eq = other.ic2eq;
+        }
+    }
+}
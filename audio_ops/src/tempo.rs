@@ -0,0 +1,73 @@
+//! # Tempo
+//!
+//! Program-level tempo in beats per minute provided by the host, so delay times and periods
+//! could be given in beats and stay locked when tempo changes.
+//!
+//! Host also provides transport rate, which warps the tempo like tape varispeed: at 0.5 the
+//! whole rhythmic structure built on beats goes twice as slow, while oscillators keep their
+//! pitches unless they are multiplied by `Warp` to follow. Clocks don't know the transport
+//! rate, so metronomes with periods in seconds or rates in Hz keep running as they are.
+//! Otherwise those driven by beats would be warped twice.
+//!
+//! Sources to connect: none for `Bpm` and `Warp`, duration in beats for `Beats`.
+use audio_vm::{Op, Sample, Stack, CHANNELS};
+use std::sync::{Arc, Mutex};
+
+pub struct Bpm {
+    source: Arc<Mutex<Sample>>,
+    warp: Arc<Mutex<Sample>>,
+}
+
+impl Bpm {
+    pub fn new(source: Arc<Mutex<Sample>>, warp: Arc<Mutex<Sample>>) -> Self {
+        Bpm { source, warp }
+    }
+}
+
+impl Op for Bpm {
+    fn perform(&mut self, stack: &mut Stack) {
+        let bpm = *self.source.lock().unwrap() * *self.warp.lock().unwrap();
+        stack.push(&[bpm; CHANNELS]);
+    }
+}
+
+/// Convert duration in beats to seconds.
+pub struct Beats {
+    source: Arc<Mutex<Sample>>,
+    warp: Arc<Mutex<Sample>>,
+}
+
+impl Beats {
+    pub fn new(source: Arc<Mutex<Sample>>, warp: Arc<Mutex<Sample>>) -> Self {
+        Beats { source, warp }
+    }
+}
+
+impl Op for Beats {
+    fn perform(&mut self, stack: &mut Stack) {
+        let seconds_per_beat = 60.0 / (*self.source.lock().unwrap() * *self.warp.lock().unwrap());
+        let mut frame = stack.pop();
+        for x in frame.iter_mut() {
+            *x *= seconds_per_beat;
+        }
+        stack.push(&frame);
+    }
+}
+
+/// Transport rate.
+pub struct Warp {
+    source: Arc<Mutex<Sample>>,
+}
+
+impl Warp {
+    pub fn new(source: Arc<Mutex<Sample>>) -> Self {
+        Warp { source }
+    }
+}
+
+impl Op for Warp {
+    fn perform(&mut self, stack: &mut Stack) {
+        let warp = *self.source.lock().unwrap();
+        stack.push(&[warp; CHANNELS]);
+    }
+}
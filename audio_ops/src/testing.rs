@@ -0,0 +1,74 @@
+//! Helpers for tests of ops.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+
+/// Source which pushes the same frame all the time.
+pub struct Hold(Frame);
+
+impl Op for Hold {
+    fn perform(&mut self, stack: &mut Stack) {
+        stack.push(&self.0);
+    }
+}
+
+/// Source which pushes 1 on the first frame and 0 after it.
+pub struct Click(bool);
+
+impl Op for Click {
+    fn perform(&mut self, stack: &mut Stack) {
+        let x = if self.0 { 0.0 } else { 1.0 };
+        self.0 = true;
+        stack.push(&[x; CHANNELS]);
+    }
+}
+
+pub fn constant(x: Sample) -> Box<dyn Op> {
+    Box::new(Hold([x; CHANNELS]))
+}
+
+pub fn frame(frame: Frame) -> Box<dyn Op> {
+    Box::new(Hold(frame))
+}
+
+pub fn click() -> Box<dyn Op> {
+    Box::new(Click(false))
+}
+
+/// Run ops one after another on the reset stack for the number of frames, the way VM does.
+/// Returns contents of the stack after each frame, bottom first.
+pub fn render(ops: &mut [Box<dyn Op>], frames: usize) -> Vec<Vec<Frame>> {
+    let mut stack = Stack::new();
+    (0..frames)
+        .map(|_| {
+            stack.reset();
+            for op in ops.iter_mut() {
+                op.perform(&mut stack);
+            }
+            let mut frames = Vec::new();
+            while !stack.is_empty() {
+                frames.push(stack.pop());
+            }
+            frames.reverse();
+            frames
+        })
+        .collect()
+}
+
+/// Left channel of the top of the stack after each frame.
+pub fn tops(stacks: &[Vec<Frame>]) -> Vec<Sample> {
+    stacks
+        .iter()
+        .map(|stack| stack[stack.len() - 1][0])
+        .collect()
+}
+
+/// Peak level of samples.
+pub fn peak(xs: &[Sample]) -> Sample {
+    xs.iter().fold(0.0, |peak: Sample, x| peak.max(x.abs()))
+}
+
+/// Carry state over from the ops of the previous program, the way commit does.
+pub fn migrate(ops: &mut [Box<dyn Op>], previous: &[Box<dyn Op>]) {
+    for (op, previous) in ops.iter_mut().zip(previous) {
+        op.migrate(previous);
+    }
+}
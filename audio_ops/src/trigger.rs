@@ -0,0 +1,247 @@
+//! # Trigger utilities
+//!
+//! Logic glue for sequencing which needs state between frames. Trigger is a rise of the input
+//! above 0, like pulses of `m`.
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+/// Gate which stays open for the given length in seconds after each trigger, a trigger while
+/// it's open restarts the length.
+///
+/// Sources to connect: trigger, length.
+pub struct TriggerToGate {
+    last_trigger: Frame,
+    /// Seconds left until the gate closes.
+    left: Frame,
+    sample_period: Sample,
+}
+
+impl TriggerToGate {
+    pub fn new(sample_rate: u32) -> Self {
+        TriggerToGate {
+            last_trigger: [0.0; CHANNELS],
+            left: [0.0; CHANNELS],
+            sample_period: Sample::from(sample_rate).recip(),
+        }
+    }
+}
+
+impl Op for TriggerToGate {
+    fn perform(&mut self, stack: &mut Stack) {
+        let length = stack.pop();
+        let trigger = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (output, &trigger, &length, last_trigger, left) in izip!(
+            &mut frame,
+            &trigger,
+            &length,
+            &mut self.last_trigger,
+            &mut self.left
+        ) {
+            if *last_trigger <= 0.0 && trigger > 0.0 {
+                *left = length;
+            }
+            *last_trigger = trigger;
+            *output = if *left > 0.0 { 1.0 } else { 0.0 };
+            *left -= self.sample_period;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.last_trigger = other.last_trigger;
+            self.left = other.left;
+        }
+    }
+}
+
+/// Number of triggers so far, wrapped to the modulus if there is one.
+///
+/// Sources to connect: trigger.
+pub struct Counter {
+    count: [u64; CHANNELS],
+    last_trigger: Frame,
+    modulus: Option<u64>,
+}
+
+impl Counter {
+    pub fn new(modulus: Option<u64>) -> Self {
+        Counter {
+            count: [0; CHANNELS],
+            last_trigger: [0.0; CHANNELS],
+            modulus,
+        }
+    }
+}
+
+impl Op for Counter {
+    fn perform(&mut self, stack: &mut Stack) {
+        let trigger = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (output, &trigger, last_trigger, count) in izip!(
+            &mut frame,
+            &trigger,
+            &mut self.last_trigger,
+            &mut self.count
+        ) {
+            if *last_trigger <= 0.0 && trigger > 0.0 {
+                *count = count.wrapping_add(1);
+            }
+            *last_trigger = trigger;
+            if let Some(modulus) = self.modulus {
+                *count %= modulus.max(1);
+            }
+            *output = *count as Sample;
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.count = other.count;
+            self.last_trigger = other.last_trigger;
+        }
+    }
+}
+
+/// Flip-flop which switches between 0 and 1 on each trigger.
+///
+/// Sources to connect: trigger.
+pub struct Toggle {
+    last_trigger: Frame,
+    state: Frame,
+}
+
+impl Toggle {
+    pub fn new() -> Self {
+        Toggle {
+            last_trigger: [0.0; CHANNELS],
+            state: [0.0; CHANNELS],
+        }
+    }
+}
+
+impl Default for Toggle {
+    fn default() -> Self {
+        Toggle::new()
+    }
+}
+
+impl Op for Toggle {
+    fn perform(&mut self, stack: &mut Stack) {
+        let trigger = stack.pop();
+        for (&trigger, last_trigger, state) in
+            izip!(&trigger, &mut self.last_trigger, &mut self.state)
+        {
+            if *last_trigger <= 0.0 && trigger > 0.0 {
+                *state = 1.0 - *state;
+            }
+            *last_trigger = trigger;
+        }
+        stack.push(&self.state);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.last_trigger = other.last_trigger;
+            self.state = other.state;
+        }
+    }
+}
+
+/// Comparator with hysteresis: turns 1 when x rises above the high threshold and back to 0 only
+/// when it falls below the low one, so noise around a single threshold doesn't chatter.
+///
+/// Sources to connect: x, low, high.
+pub struct Schmitt {
+    state: Frame,
+}
+
+impl Schmitt {
+    pub fn new() -> Self {
+        Schmitt {
+            state: [0.0; CHANNELS],
+        }
+    }
+}
+
+impl Default for Schmitt {
+    fn default() -> Self {
+        Schmitt::new()
+    }
+}
+
+impl Op for Schmitt {
+    fn perform(&mut self, stack: &mut Stack) {
+        let high = stack.pop();
+        let low = stack.pop();
+        let x = stack.pop();
+        for (&x, &low, &high, state) in izip!(&x, &low, &high, &mut self.state) {
+            if x > high {
+                *state = 1.0;
+            } else if x < low {
+                *state = 0.0;
+            }
+        }
+        stack.push(&self.state);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.state = other.state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, migrate, render, tops};
+    use crate::Metro;
+
+    /// Output of the op after the metro of the frequency at 1 kHz.
+    fn after_metro(frequency: Sample, op: Box<dyn Op>) -> Vec<Sample> {
+        let mut ops = vec![constant(frequency)];
+        ops.push(Box::new(Metro::new(1000)));
+        ops.push(op);
+        tops(&render(&mut ops, 8))
+    }
+
+    #[test]
+    fn utilities_follow_triggers() {
+        // Metro triggers every other frame starting from the third one.
+        assert_eq!(
+            after_metro(500.0, Box::new(Counter::new(Some(3)))),
+            [0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            after_metro(500.0, Box::new(Toggle::new())),
+            [0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0]
+        );
+        let mut ops = vec![constant(250.0)];
+        ops.push(Box::new(Metro::new(1000)));
+        ops.push(constant(0.002));
+        ops.push(Box::new(TriggerToGate::new(1000)));
+        assert_eq!(
+            tops(&render(&mut ops, 8)),
+            [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn schmitt_has_hysteresis() {
+        let schmitt = |x| {
+            let mut ops = vec![constant(x), constant(0.2), constant(0.6)];
+            ops.push(Box::new(Schmitt::new()));
+            ops
+        };
+        assert_eq!(tops(&render(&mut schmitt(0.3), 1)), [0.0]);
+        let mut ops = schmitt(0.7);
+        assert_eq!(tops(&render(&mut ops, 1)), [1.0]);
+        // Between thresholds the state holds, carried over by migration.
+        let mut next = schmitt(0.3);
+        migrate(&mut next, &ops);
+        assert_eq!(tops(&render(&mut next, 1)), [1.0]);
+    }
+}
@@ -0,0 +1,70 @@
+//! # Vocoder
+//!
+//! Imposes spectral envelope of the modulator onto the carrier, e.g. to make a synth talk. Each
+//! hop it takes spectra of the last window of both signals, groups bins into bands evenly spaced
+//! in octaves, and scales carrier bins of each band by the ratio of modulator to carrier band
+//! energy. Output lags by one window.
+//!
+//! Sources to connect: carrier, modulator.
+use crate::spectral_transform::{Stft, HOP, WINDOW_SIZE};
+use audio_vm::{Op, Sample, Stack};
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+
+/// Carrier band energy is taken at least as that to not blow up its noise floor.
+const MIN_ENERGY: Sample = 1e-12;
+
+pub struct Vocoder {
+    /// Band i spans bins from edges[i] up to edges[i + 1].
+    edges: Vec<usize>,
+    stft: Stft,
+}
+
+impl Vocoder {
+    pub fn new(bands: usize) -> Self {
+        let bands = bands.max(1);
+        let half = WINDOW_SIZE / 2;
+        // DC is left out, Nyquist goes to the last band.
+        let mut edges = vec![1];
+        for i in 1..bands {
+            let edge = (half as Sample).powf(i as Sample / bands as Sample).round() as usize;
+            // Low bands are narrower than a bin, give them one each.
+            edges.push(edge.max(edges[i - 1] + 1).min(half));
+        }
+        edges.push(half + 1);
+        Vocoder {
+            edges,
+            stft: Stft::new(2, WINDOW_SIZE, HOP),
+        }
+    }
+}
+
+impl Op for Vocoder {
+    fn perform(&mut self, stack: &mut Stack) {
+        let modulator = stack.pop();
+        let carrier = stack.pop();
+        let edges = &self.edges;
+        let energy = |xs: &[Complex<Sample>]| xs.iter().map(|x| x.norm_sqr()).sum::<Sample>();
+        let frame = self.stft.process(&[carrier, modulator], |_, spectra| {
+            let (carrier, modulator) = spectra.split_at_mut(1);
+            let (carrier, modulator) = (&mut carrier[0], &modulator[0]);
+            carrier[0] = Complex::zero();
+            for band in edges.windows(2) {
+                let (start, end) = (band[0], band[1]);
+                let gain = (energy(&modulator[start..end])
+                    / energy(&carrier[start..end]).max(MIN_ENERGY))
+                .sqrt();
+                for bin in &mut carrier[start..end] {
+                    *bin *= gain;
+                }
+            }
+        });
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.stft.migrate(&other.stft);
+        }
+    }
+}
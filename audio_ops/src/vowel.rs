@@ -0,0 +1,110 @@
+//! # Vowel
+//!
+//! Formant filter made of parallel band-pass filters tuned to the formants of a bass voice
+//! singing a, e, i, o, u as listed in the Csound manual
+//! http://www.csounds.com/manual/html/MiscFormants.html
+//! Formant frequencies, gains and bandwidths are interpolated between adjacent vowels.
+//!
+//! Band-pass filters use the same zero-delay feedback topology as SVF to stay stable while
+//! vowel is modulated.
+//!
+//! Sources to connect: input, vowel (0 is a, 0.25 is e, 0.5 is i, 0.75 is o, 1 is u).
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use itertools::izip;
+
+const FORMANTS: usize = 5;
+const VOWELS: usize = 5;
+
+/// Frequencies (Hz), gains (dB) and bandwidths (Hz) of formants for each vowel.
+const PRESETS: [[[Sample; FORMANTS]; 3]; VOWELS] = [
+    [
+        [600.0, 1040.0, 2250.0, 2450.0, 2750.0],
+        [0.0, -7.0, -9.0, -9.0, -20.0],
+        [60.0, 70.0, 110.0, 120.0, 130.0],
+    ],
+    [
+        [400.0, 1620.0, 2400.0, 2800.0, 3100.0],
+        [0.0, -12.0, -9.0, -12.0, -18.0],
+        [40.0, 80.0, 100.0, 120.0, 120.0],
+    ],
+    [
+        [250.0, 1750.0, 2600.0, 3050.0, 3340.0],
+        [0.0, -30.0, -16.0, -22.0, -28.0],
+        [60.0, 90.0, 100.0, 120.0, 120.0],
+    ],
+    [
+        [400.0, 750.0, 2400.0, 2600.0, 2900.0],
+        [0.0, -11.0, -21.0, -20.0, -40.0],
+        [40.0, 80.0, 100.0, 120.0, 120.0],
+    ],
+    [
+        [350.0, 600.0, 2400.0, 2675.0, 2950.0],
+        [0.0, -20.0, -32.0, -28.0, -36.0],
+        [40.0, 80.0, 100.0, 120.0, 120.0],
+    ],
+];
+
+pub struct Vowel {
+    ic1eq: [Frame; FORMANTS],
+    ic2eq: [Frame; FORMANTS],
+    nyquist: Sample,
+    sample_period: Sample,
+}
+
+impl Vowel {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = Sample::from(sample_rate);
+        Vowel {
+            ic1eq: [[0.0; CHANNELS]; FORMANTS],
+            ic2eq: [[0.0; CHANNELS]; FORMANTS],
+            nyquist: 0.5 * sample_rate,
+            sample_period: sample_rate.recip(),
+        }
+    }
+}
+
+impl Op for Vowel {
+    fn perform(&mut self, stack: &mut Stack) {
+        let vowel = stack.pop();
+        let input = stack.pop();
+        let mut frame = [0.0; CHANNELS];
+        for (channel, (output, &x, &vowel)) in izip!(&mut frame, &input, &vowel).enumerate() {
+            let position = pure::clamp(vowel, 0.0, 1.0) * (VOWELS - 1) as Sample;
+            let i = (position as usize).min(VOWELS - 2);
+            let t = position - i as Sample;
+            let [frequencies_a, gains_a, bandwidths_a] = &PRESETS[i];
+            let [frequencies_b, gains_b, bandwidths_b] = &PRESETS[i + 1];
+            for formant in 0..FORMANTS {
+                let frequency = (1.0 - t) * frequencies_a[formant] + t * frequencies_b[formant];
+                let gain = (1.0 - t) * gains_a[formant] + t * gains_b[formant];
+                let bandwidth = (1.0 - t) * bandwidths_a[formant] + t * bandwidths_b[formant];
+
+                let frequency = frequency.min(0.99 * self.nyquist);
+                let g = (std::f64::consts::PI * frequency * self.sample_period).tan();
+                let k = bandwidth / frequency;
+                let a1 = 1.0 / (1.0 + g * (g + k));
+                let a2 = g * a1;
+                let a3 = g * a2;
+
+                let ic1eq = &mut self.ic1eq[formant][channel];
+                let ic2eq = &mut self.ic2eq[formant][channel];
+                let v3 = x - *ic2eq;
+                let v1 = a1 * *ic1eq + a2 * v3;
+                let v2 = *ic2eq + a2 * *ic1eq + a3 * v3;
+                *ic1eq = 2.0 * v1 - *ic1eq;
+                *ic2eq = 2.0 * v2 - *ic2eq;
+
+                *output += 10.0f64.powf(gain / 20.0) * k * v1;
+            }
+        }
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.ic1eq = other.ic1eq;
+            self.ic2eq = other.ic2eq;
+        }
+    }
+}
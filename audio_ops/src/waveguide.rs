@@ -0,0 +1,213 @@
+//! # Waveguides
+//!
+//! Physical models of sustained acoustic voices after the STK ones: travelling waves circulate
+//! in delay lines tuned to the frequency, lose energy in a damping filter on each round trip
+//! and are kept alive by a non-linear exciter, a bow or a reed blown with pressure.
+//!
+//! Frequency is limited from below by `MIN_FREQUENCY` to allocate delay lines upfront.
+//!
+//! Sources to connect: frequency, force or pressure, damping.
+use crate::buffer::Buffer;
+use crate::pure;
+use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+const MIN_FREQUENCY: Sample = 20.0;
+/// Position of the bow as a ratio of the string length from the bridge.
+const BOW_POSITION: Sample = 0.127;
+
+/// Delay line long enough for the period of `MIN_FREQUENCY`, with a mask to wrap indices.
+fn make_line(sample_rate: u32) -> (Buffer<Frame>, usize) {
+    let len = ((Sample::from(sample_rate) / MIN_FREQUENCY) as usize + 2).next_power_of_two();
+    (Buffer::new([0.0; CHANNELS], len), len - 1)
+}
+
+/// Read `z` frames back with linear interpolation, 0 is the last pushed frame.
+fn read(line: &Buffer<Frame>, mask: usize, channel: usize, z: Sample) -> Sample {
+    let z = z.max(0.0).min(mask as Sample - 1.0);
+    let i = z as usize;
+    let k = z.fract();
+    (1.0 - k) * line[i][channel] + k * line[i + 1][channel]
+}
+
+/// Period in frames of the frequency, within what delay lines could hold.
+fn period(sample_rate: Sample, frequency: Sample) -> Sample {
+    let period = sample_rate / frequency.abs().max(MIN_FREQUENCY);
+    if period.is_finite() {
+        period
+    } else {
+        sample_rate / MIN_FREQUENCY
+    }
+}
+
+/// Bowed string: the bow sticks to the string and slips off it by the friction curve, string
+/// is split by the bow into the neck and bridge parts. Force goes from 0 to 1 and sets bow
+/// speed, damping from 0 to 1 darkens the tone and shortens its decay.
+pub struct Bow {
+    bridge: Buffer<Frame>,
+    /// State of the damping filter at the bridge.
+    lowpass: Frame,
+    mask: usize,
+    neck: Buffer<Frame>,
+    sample_rate: Sample,
+}
+
+impl Bow {
+    pub fn new(sample_rate: u32) -> Self {
+        let (bridge, mask) = make_line(sample_rate);
+        let (neck, _) = make_line(sample_rate);
+        Bow {
+            bridge,
+            lowpass: [0.0; CHANNELS],
+            mask,
+            neck,
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Bow {
+    fn perform(&mut self, stack: &mut Stack) {
+        let damping = stack.pop();
+        let force = stack.pop();
+        let frequency = stack.pop();
+        let mut neck = [0.0; CHANNELS];
+        let mut bridge = [0.0; CHANNELS];
+        let mut frame = [0.0; CHANNELS];
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        for channel in 0..CHANNELS {
+            // Each line adds a frame to the round trip, as it is read after pushing.
+            let period = period(self.sample_rate, frequency[channel]) - 2.0;
+            let damping = unit(damping[channel]);
+            let bridge_out = read(&self.bridge, self.mask, channel, period * BOW_POSITION);
+            let neck_out = read(
+                &self.neck,
+                self.mask,
+                channel,
+                period * (1.0 - BOW_POSITION),
+            );
+            let lowpass = &mut self.lowpass[channel];
+            *lowpass = (1.0 - 0.9 * damping) * bridge_out + 0.9 * damping * *lowpass;
+            let bridge_reflection = -(0.995 - 0.05 * damping) * *lowpass;
+            let neck_reflection = -neck_out;
+            let velocity = 0.25 * unit(force[channel]);
+            let delta = velocity - (bridge_reflection + neck_reflection);
+            // Friction: full grip at small speed differences, slipping at larger ones.
+            let friction = (delta.abs() * 3.0 + 0.75).powi(-4).min(1.0);
+            let excitation = delta * friction;
+            neck[channel] = bridge_reflection + excitation;
+            bridge[channel] = neck_reflection + excitation;
+            frame[channel] = bridge_out;
+        }
+        for x in neck.iter_mut().chain(&mut bridge).chain(&mut frame) {
+            // Keep NaN of bad inputs from circulating forever.
+            if !x.is_finite() {
+                *x = 0.0;
+            }
+        }
+        self.neck.push_front(neck);
+        self.bridge.push_front(bridge);
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.bridge.copy_forward(&other.bridge);
+            self.lowpass = other.lowpass;
+            self.neck.copy_forward(&other.neck);
+        }
+    }
+}
+
+/// Reed pipe like a clarinet: breath pressure opens and closes the reed against the pressure
+/// wave reflected from the open end of the bore. Pipe is closed at the reed, so it plays odd
+/// harmonics and its bore is half as long as the string of the same pitch. Pressure goes from
+/// 0 to 1, the reed starts to sound about 0.3, damping from 0 to 1 darkens the tone.
+pub struct Blow {
+    bore: Buffer<Frame>,
+    /// State of the damping filter at the open end.
+    lowpass: Frame,
+    mask: usize,
+    rng: SmallRng,
+    sample_rate: Sample,
+}
+
+impl Blow {
+    pub fn new(sample_rate: u32) -> Self {
+        Blow::with_rng(sample_rate, SmallRng::from_entropy())
+    }
+
+    /// Use it to get the same breath noise in each render.
+    pub fn with_rng(sample_rate: u32, rng: SmallRng) -> Self {
+        let (bore, mask) = make_line(sample_rate);
+        Blow {
+            bore,
+            lowpass: [0.0; CHANNELS],
+            mask,
+            rng,
+            sample_rate: Sample::from(sample_rate),
+        }
+    }
+}
+
+impl Op for Blow {
+    fn perform(&mut self, stack: &mut Stack) {
+        let damping = stack.pop();
+        let pressure = stack.pop();
+        let frequency = stack.pop();
+        let mut bore = [0.0; CHANNELS];
+        let mut frame = [0.0; CHANNELS];
+        let unit = |x: Sample| pure::clamp_or(x, 0.0, 1.0, 0.0);
+        for channel in 0..CHANNELS {
+            let period = 0.5 * period(self.sample_rate, frequency[channel]) - 1.0;
+            let damping = unit(damping[channel]);
+            let pressure = unit(pressure[channel]);
+            let breath = pressure * (1.0 + 0.1 * self.rng.gen_range(-1.0, 1.0));
+            let reflected = read(&self.bore, self.mask, channel, period);
+            let lowpass = &mut self.lowpass[channel];
+            *lowpass = 0.5 * (1.0 - 0.9 * damping) * reflected + (0.5 + 0.45 * damping) * *lowpass;
+            let difference = -0.95 * *lowpass - breath;
+            // Reed closes as the pressure difference grows.
+            let reed = pure::clamp(0.7 - 0.3 * difference, -1.0, 1.0);
+            bore[channel] = breath + difference * reed;
+            // Keep NaN of bad inputs from circulating forever.
+            if !bore[channel].is_finite() {
+                bore[channel] = 0.0;
+            }
+            frame[channel] = reflected;
+        }
+        self.bore.push_front(bore);
+        stack.push(&frame);
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            self.bore.copy_forward(&other.bore);
+            self.lowpass = other.lowpass;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render, tops};
+    use crate::DCBlock;
+
+    #[test]
+    fn sustains_at_frequency() {
+        let mut bow = vec![constant(220.0), constant(0.5), constant(0.3)];
+        bow.push(Box::new(Bow::new(48000)));
+        let mut blow = vec![constant(220.0), constant(0.6), constant(0.2)];
+        blow.push(Box::new(Blow::with_rng(48000, SmallRng::seed_from_u64(0))));
+        blow.push(Box::new(DCBlock::new(48000)));
+        for ops in &mut [bow, blow] {
+            let xs = tops(&render(ops, 48000 + 4096));
+            // Let it settle for a second, then it repeats every period of 218 frames.
+            let xs = &xs[48000..];
+            let correlation = |lag: usize| (0..2048).map(|i| xs[i] * xs[i + lag]).sum::<Sample>();
+            assert!(correlation(0) / 2048.0 > 0.01);
+            assert!(correlation(218) / correlation(0) > 0.95);
+        }
+    }
+}
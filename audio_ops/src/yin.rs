@@ -4,12 +4,20 @@
 //!
 //! Sources to connect: signal to detect pitch of.
 //!
+//! Pushes frequency, 0 when no period is found below the threshold, and optionally confidence
+//! which is 1 minus aperiodicity (cumulative mean normalized difference) of the best period.
+//! Both are updated each period and held in between.
+//!
 //! TODO use FFT and avoid O(n^2)
 use crate::buffer::Buffer;
+use crate::pure;
 use audio_vm::{Frame, Op, Sample, Stack, CHANNELS};
 
 pub struct Yin {
     buffer: Vec<Sample>,
+    /// Push confidence after frequency.
+    confidence: bool,
+    estimate: (Frame, Frame),
     period: usize,
     frame_number: usize,
     sample_rate: Sample,
@@ -20,10 +28,18 @@ pub struct Yin {
 impl Yin {
     // window_size = 1024
     // threshold = 0.2
-    pub fn new(sample_rate: u32, window_size: usize, period: usize, threshold: Sample) -> Self {
+    pub fn new(
+        sample_rate: u32,
+        window_size: usize,
+        period: usize,
+        threshold: Sample,
+        confidence: bool,
+    ) -> Self {
         Yin {
             buffer: vec![0.0; window_size / 2],
-            period,
+            confidence,
+            estimate: Default::default(),
+            period: period.max(1),
             frame_number: 0,
             sample_rate: Sample::from(sample_rate),
             threshold,
@@ -69,6 +85,15 @@ impl Yin {
         }
     }
 
+    /// Aperiodicity of the best period candidate, the one found by threshold or the global
+    /// minimum otherwise.
+    fn aperiodicity(&self, tau: Option<usize>) -> Sample {
+        match tau {
+            Some(tau) => self.buffer[tau],
+            None => self.buffer.iter().skip(2).cloned().fold(1.0, Sample::min),
+        }
+    }
+
     fn parabolic_interpolation(&self, x1: usize) -> Sample {
         let x0 = x1 - 1;
         let x2 = x1 + 1;
@@ -89,22 +114,57 @@ impl Yin {
 
 impl Op for Yin {
     fn perform(&mut self, stack: &mut Stack) {
-        let mut frame = [0.0; CHANNELS];
         let input = stack.pop();
         self.window.push_back(input);
-        if self.frame_number % self.period == 0 {
+        if self.frame_number % self.period == 0 && self.buffer.len() > 2 {
             for channel in 0..CHANNELS {
                 self.difference(channel);
                 self.cumulative_mean_normalized_difference();
-                frame[channel] = match self.absolute_threshold() {
+                let tau = self.absolute_threshold();
+                self.estimate.0[channel] = match tau {
                     Some(tau_estimate) => {
                         self.sample_rate / self.parabolic_interpolation(tau_estimate)
                     }
                     None => 0.0,
-                }
+                };
+                // NaN of silent windows means no confidence.
+                let confidence = 1.0 - self.aperiodicity(tau);
+                self.estimate.1[channel] = pure::clamp_or(confidence, 0.0, 1.0, 0.0);
             }
         }
         self.frame_number += 1;
-        stack.push(&frame);
+        stack.push(&self.estimate.0);
+        if self.confidence {
+            stack.push(&self.estimate.1);
+        }
+    }
+
+    fn migrate(&mut self, other: &Box<dyn Op>) {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if self.buffer.len() == other.buffer.len() {
+                self.window.copy_forward(&other.window);
+                self.frame_number = other.frame_number;
+                self.estimate = other.estimate;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{constant, render};
+    use crate::{pure, Osc};
+
+    #[test]
+    fn pushes_confidence() {
+        let mut ops = vec![constant(200.0)];
+        ops.push(Box::new(Osc::new(48000, pure::sine)));
+        ops.push(Box::new(Yin::new(48000, 1024, 64, 0.2, true)));
+        let stack = &render(&mut ops, 4096)[4095];
+        assert_eq!(stack.len(), 2);
+        let (frequency, confidence) = (stack[0], stack[1]);
+        assert!((frequency[0] - 200.0).abs() < 1.0);
+        assert!(confidence[0] > 0.9);
     }
 }
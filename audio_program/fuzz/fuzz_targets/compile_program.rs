@@ -0,0 +1,24 @@
+#![no_main]
+use audio_program::{compile_program, rewrite_terms, rewrite_words, Context, TextOp};
+use audio_vm::VM;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let ops = text
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_owned(),
+            })
+            .collect::<Vec<_>>();
+        let mut ctx = Context::new();
+        ctx.allow_files = false;
+        let mut vm = VM::new();
+        let ops = rewrite_words(&rewrite_terms(&ops), &mut ctx.words);
+        vm.load_program(compile_program(&ops, 48000, &mut ctx));
+        // Parameters could also break ops when they perform, not only when they are built.
+        vm.next_frame();
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use audio_program::{rewrite_terms, TextOp};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let ops = text
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_owned(),
+            })
+            .collect::<Vec<_>>();
+        rewrite_terms(&ops);
+    }
+});
@@ -0,0 +1,189 @@
+//! Export programs as Sporth or Faust code, to reuse patches in soundpipe-based or embedded
+//! environments. Only ops with close equivalents are supported, export of a program with other
+//! ops fails with the list of them.
+use crate::TextOp;
+use audio_vm::Sample;
+
+/// Sporth is stack-based too, so ops are translated one by one.
+pub fn to_sporth(ops: &[TextOp]) -> Result<String, Vec<String>> {
+    let mut words = Vec::new();
+    let mut unsupported = Vec::new();
+    for TextOp { op, .. } in ops {
+        if op.parse::<Sample>().is_ok() {
+            words.push(op.to_owned());
+            continue;
+        }
+        let word = match op.split(':').next().unwrap() {
+            "+" | "add" => "+",
+            "-" | "sub" => "-",
+            "*" | "mul" => "*",
+            "/" | "div" => "/",
+            "min" => "min",
+            "max" => "max",
+            "dup" => "dup",
+            "swap" => "swap",
+            "rot" => "rot",
+            "pop" => "drop",
+            "silence" => "0",
+            // Sporth oscillators take amplitude too.
+            "s" => "1 sine",
+            "w" => "1 saw",
+            "p" => "1 swap square",
+            "whiteNoise" | "noise" | "n" => "1 noise",
+            "lpf" => "tone",
+            "hpf" => "atone",
+            // No feedback.
+            "dl" | "delay" => "0 swap delay",
+            "metro" | "m" => "metro",
+            _ => {
+                unsupported.push(op.to_owned());
+                continue;
+            }
+        };
+        words.push(word.to_owned());
+    }
+    if unsupported.is_empty() {
+        Ok(words.join(" ") + "\n")
+    } else {
+        Err(unsupported)
+    }
+}
+
+/// Faust is functional, so the stack is simulated with each op result bound to a name,
+/// and the top of the stack is sent to both channels.
+pub fn to_faust(ops: &[TextOp]) -> Result<String, Vec<String>> {
+    let mut stack: Vec<String> = Vec::new();
+    let mut definitions = Vec::new();
+    let mut unsupported = Vec::new();
+    for TextOp { op, .. } in ops {
+        if op.parse::<Sample>().is_ok() {
+            // Negative numbers are parenthesized to keep them in infix expressions.
+            stack.push(if op.starts_with('-') {
+                format!("({})", op)
+            } else {
+                op.to_owned()
+            });
+            continue;
+        }
+        let tokens = op.split(':').collect::<Vec<_>>();
+        let inputs = match tokens[0] {
+            "silence" | "whiteNoise" | "noise" | "n" => 0,
+            "s" | "w" | "t" | "metro" | "m" | "pop" | "dup" => 1,
+            "+" | "add" | "-" | "sub" | "*" | "mul" | "/" | "div" | "min" | "max" | "swap"
+            | "lpf" | "hpf" | "dl" | "delay" => 2,
+            "rot" => 3,
+            _ => {
+                unsupported.push(op.to_owned());
+                continue;
+            }
+        };
+        // Underflow produces zeros as in VM.
+        let mut args = stack.split_off(stack.len().saturating_sub(inputs));
+        while args.len() < inputs {
+            args.insert(0, "0".to_owned());
+        }
+        let expression = match tokens[0] {
+            "silence" => "0".to_owned(),
+            "whiteNoise" | "noise" | "n" => "no.noise".to_owned(),
+            "s" => format!("os.osc({})", args[0]),
+            "w" => format!("os.sawtooth({})", args[0]),
+            "t" => format!("os.triangle({})", args[0]),
+            "metro" | "m" => format!("ba.pulse(ma.SR / {})", args[0]),
+            "+" | "add" => format!("{} + {}", args[0], args[1]),
+            "-" | "sub" => format!("{} - {}", args[0], args[1]),
+            "*" | "mul" => format!("{} * {}", args[0], args[1]),
+            "/" | "div" => format!("{} / {}", args[0], args[1]),
+            "min" => format!("min({}, {})", args[0], args[1]),
+            "max" => format!("max({}, {})", args[0], args[1]),
+            "lpf" => format!("fi.lowpass(1, {}, {})", args[1], args[0]),
+            "hpf" => format!("fi.highpass(1, {}, {})", args[1], args[0]),
+            "dl" | "delay" => {
+                let max_delay = tokens
+                    .get(1)
+                    .and_then(|x| x.parse::<Sample>().ok())
+                    .unwrap_or(60.0);
+                // Faust needs the max delay in samples at compile time, assume up to 48 kHz.
+                format!(
+                    "de.fdelay({}, {} * ma.SR, {})",
+                    (max_delay * 48000.0).ceil() as usize,
+                    args[1],
+                    args[0]
+                )
+            }
+            // Stack manipulations just move names around.
+            _ => {
+                match tokens[0] {
+                    "dup" => {
+                        stack.push(args[0].to_owned());
+                        stack.push(args[0].to_owned());
+                    }
+                    "swap" => {
+                        args.swap(0, 1);
+                        stack.extend(args);
+                    }
+                    "rot" => {
+                        args.rotate_left(1);
+                        stack.extend(args);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        let name = format!("v{}", definitions.len() + 1);
+        definitions.push(format!("    {} = {};\n", name, expression));
+        stack.push(name);
+    }
+    if !unsupported.is_empty() {
+        return Err(unsupported);
+    }
+    let output = stack.pop().unwrap_or_else(|| "0".to_owned());
+    Ok(format!(
+        "import(\"stdfaust.lib\");\n\nprocess = {} <: _, _\nwith {{\n{}}};\n",
+        output,
+        definitions.concat()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(program: &str) -> Vec<TextOp> {
+        program
+            .split_whitespace()
+            .map(|op| TextOp {
+                id: 0,
+                op: op.to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn to_sporth_translates_ops() {
+        assert_eq!(
+            to_sporth(&ops("440 s 0.5 * 2 dl:4")),
+            Ok("440 1 sine 0.5 * 2 0 swap delay\n".to_owned())
+        );
+        assert_eq!(
+            to_sporth(&ops("440 s 1 fold")),
+            Err(vec!["fold".to_owned()])
+        );
+    }
+
+    #[test]
+    fn to_faust_binds_results() {
+        assert_eq!(
+            to_faust(&ops("440 s dup *")),
+            Ok(concat!(
+                "import(\"stdfaust.lib\");\n\n",
+                "process = v2 <: _, _\n",
+                "with {\n",
+                "    v1 = os.osc(440);\n",
+                "    v2 = v1 * v1;\n",
+                "};\n"
+            )
+            .to_owned())
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Gain staging analysis: render the program for a while, measure the signal each op leaves on
+//! the top of the stack and flag audio stages which clip or are too quiet to survive later
+//! stages and recordings without noise, suggesting how much gain to insert after them.
+use crate::{compile_program, get_arities, op_arity, Context, TextOp};
+use audio_vm::{Sample, Stack, CHANNELS};
+
+/// Stages quieter than it (RMS in dBFS) drown in noise once brought up to a usable level.
+const QUIET_LEVEL: Sample = -60.0;
+/// Quiet stages are suggested to be raised to it (RMS in dBFS), leaving headroom for peaks.
+const NOMINAL_LEVEL: Sample = -18.0;
+/// Signals crossing zero less often (per second) are taken as controls like frequencies,
+/// envelopes or LFOs, which have no business being near full scale.
+const MIN_AUDIO_CROSSINGS: Sample = 40.0;
+
+/// Levels of the signal left on the stack by the op with the id.
+pub struct Stage {
+    pub id: u64,
+    /// Largest absolute value, full scale is 1.
+    pub peak: Sample,
+    pub rms: Sample,
+    /// Whether the signal is audio rather than control.
+    pub audio: bool,
+}
+
+impl Stage {
+    /// What is wrong with the stage and how much gain to insert after it, if anything.
+    pub fn hint(&self) -> Option<String> {
+        if !self.audio {
+            return None;
+        }
+        let peak = db(self.peak);
+        let rms = db(self.rms);
+        if peak > 0.0 {
+            Some(format!(
+                "Clips at {:+.1} dBFS, insert {:.1} dB of gain after it.",
+                peak, -peak
+            ))
+        } else if rms < QUIET_LEVEL {
+            Some(format!(
+                "Sits at {:.1} dBFS RMS near the noise floor, insert {:+.1} dB of gain after it.",
+                rms,
+                (NOMINAL_LEVEL - rms).min(-peak)
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+fn db(x: Sample) -> Sample {
+    20.0 * x.log10()
+}
+
+/// Render `frames` of the program compiled in `ctx` and measure each stage, in program order.
+/// Ops expanded from terms are measured separately under their own ids. Ops which push nothing
+/// or whose arity is unknown are skipped, the top of the stack isn't theirs to measure.
+pub fn analyze(ops: &[TextOp], sample_rate: u32, frames: usize, ctx: &mut Context) -> Vec<Stage> {
+    let mut program = compile_program(ops, sample_rate, ctx);
+    let arities = get_arities();
+    let pushes = program
+        .iter()
+        .map(|statement| {
+            ops.iter()
+                .find(|op| op.id == statement.id)
+                .and_then(|op| op_arity(&arities, &op.op))
+                .is_some_and(|arity| arity.outputs > 0)
+        })
+        .collect::<Vec<_>>();
+    let mut peak = vec![0.0; program.len()];
+    let mut square_sum = vec![0.0; program.len()];
+    let mut crossings = vec![0usize; program.len()];
+    let mut previous = vec![[0.0; CHANNELS]; program.len()];
+    let mut stack = Stack::new();
+    for _ in 0..frames {
+        stack.reset();
+        for (i, statement) in program.iter_mut().enumerate() {
+            statement.op.perform(&mut stack);
+            let frame = stack.peek();
+            for (&x, &x_prime) in frame.iter().zip(&previous[i]) {
+                // Keep NaN and infinities from hiding the levels.
+                let x = if x.is_finite() { x } else { 0.0 };
+                peak[i] = x.abs().max(peak[i]);
+                square_sum[i] += x * x;
+                if (x < 0.0) != (x_prime < 0.0) {
+                    crossings[i] += 1;
+                }
+            }
+            previous[i] = frame;
+        }
+    }
+    let seconds = frames.max(1) as Sample / Sample::from(sample_rate);
+    program
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| pushes[i])
+        .map(|(i, statement)| Stage {
+            id: statement.id,
+            peak: peak[i],
+            rms: (square_sum[i] / (frames.max(1) * CHANNELS) as Sample).sqrt(),
+            audio: crossings[i] as Sample / (CHANNELS as Sample * seconds) >= MIN_AUDIO_CROSSINGS,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(program: &str) -> Vec<TextOp> {
+        program
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_owned(),
+            })
+            .collect()
+    }
+
+    fn hints(program: &str) -> Vec<Option<String>> {
+        analyze(&ops(program), 48000, 48000, &mut Context::new())
+            .iter()
+            .map(Stage::hint)
+            .collect()
+    }
+
+    #[test]
+    fn controls_are_not_flagged() {
+        assert!(hints("440 0.5 s 100 * + s").iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn clipping_and_quiet_stages_are_flagged() {
+        let hints = hints("440 s 4 * 0.0001 *");
+        assert!(hints[0].is_none());
+        assert!(hints[1].is_none());
+        assert!(hints[3]
+            .as_ref()
+            .unwrap()
+            .starts_with("Clips at +12.0 dBFS"));
+        assert!(hints[5].as_ref().unwrap().starts_with("Sits at -71.0 dBFS"));
+    }
+
+    #[test]
+    fn ops_pushing_nothing_are_skipped() {
+        let stages = analyze(&ops("440 s 4 * pop"), 48000, 4800, &mut Context::new());
+        let ids = stages.iter().map(|stage| stage.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 2, 3]);
+    }
+}
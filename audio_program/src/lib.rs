@@ -1,22 +1,126 @@
+pub mod export;
+pub mod gain_staging;
+pub mod text;
+pub mod verify;
+
 use audio_ops::*;
-use audio_vm::{Frame, Op, Program, Sample, Statement, CHANNELS};
+use audio_vm::{Frame, Levels, Op, Program, Sample, Statement, Telemetry, CHANNELS};
 use fasthash::sea::Hash64;
 use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
 use regex::Regex;
 use smallvec::SmallVec;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 pub const HELP: &str = include_str!("help.adoc");
 
+/// Buffers are allocated upfront, so durations in parameters are limited in seconds
+/// to not run out of memory on a typo like `dl:1e9`.
+const MAX_DURATION: Sample = 300.0;
+/// Most voices, stages, taps, delay lines or stack depth a parameter could ask for.
+const MAX_COUNT: usize = 256;
+/// Pitch detection is quadratic in window size, larger ones can't keep up with audio.
+const MAX_PITCH_WINDOW: usize = 8192;
+/// Smaller pitch windows can't hold a period of anything audible.
+const MIN_PITCH_WINDOW: usize = 8;
+/// Terms could expand into each other without end, rewriting stops after that many ops.
+const MAX_REWRITTEN_OPS: usize = 1 << 16;
+
 pub struct Context {
+    /// Audio input frame, host should update it before computing each frame.
+    pub input: Arc<Mutex<Frame>>,
     pub tables: HashMap<String, Arc<Mutex<Vec<Frame>>>, Hash64>,
+    /// Signals of `send` and `recv` by names of their buses.
+    pub buses: HashMap<String, Arc<Mutex<Frame>>, Hash64>,
+    /// Ops of words defined by `: name ops ;` in the program rewritten last.
+    pub words: HashMap<String, Vec<TextOp>, Hash64>,
+    /// Impulse responses loaded by their paths, so commits don't reload them
+    /// and convolution tails survive.
+    pub impulse_responses: HashMap<String, Arc<Vec<Frame>>, Hash64>,
+    /// Let ops read files, hosts could forbid it e.g. for workshops.
+    pub allow_files: bool,
+    /// Gain of feedback loops is clamped to it, hosts could set it below 1
+    /// to prevent runaway feedback.
+    pub max_feedback_gain: Sample,
+    /// Tempo in beats per minute, host or `beat:sync` could change it while program runs.
+    pub bpm: Arc<Mutex<Sample>>,
+    /// Transport rate like tape varispeed, it scales the tempo `bpm` and `beats` follow while
+    /// pitches stay put, host could change it while program runs.
+    pub warp: Arc<Mutex<Sample>>,
+    /// Reverb tails are stretched by it, host could raise it while program runs to let the sound
+    /// ring out.
+    pub tails: Arc<Mutex<Sample>>,
+    /// Random ops are seeded with it to make renders reproducible, or from entropy when it's None.
+    pub seed: Option<u64>,
+    /// Pattern of `grid` ops, host could program it while program runs.
+    pub grid: SharedGrid,
+    /// Levels of `meter` ops by their names, host could read them while program runs.
+    pub telemetry: Telemetry,
+    /// Tables are saved here by `snapshot_tables` as <name>.wav and loaded back when a program
+    /// reads or writes them the first time, so recordings survive restarts. Within a session
+    /// tables keep their audio across commits anyway, hosts opt in to keep it across sessions.
+    pub tables_dir: Option<PathBuf>,
+    /// Hashes of table contents as they are on disk, to not rewrite unchanged ones.
+    saved_tables: HashMap<String, u64, Hash64>,
 }
 
 impl Context {
     pub fn new() -> Self {
         Context {
+            input: Arc::new(Mutex::new([0.0; CHANNELS])),
             tables: HashMap::with_hasher(Hash64),
+            buses: HashMap::with_hasher(Hash64),
+            words: HashMap::with_hasher(Hash64),
+            impulse_responses: HashMap::with_hasher(Hash64),
+            allow_files: true,
+            max_feedback_gain: f64::INFINITY,
+            bpm: Arc::new(Mutex::new(120.0)),
+            warp: Arc::new(Mutex::new(1.0)),
+            tails: Arc::new(Mutex::new(1.0)),
+            seed: None,
+            grid: Default::default(),
+            telemetry: Default::default(),
+            tables_dir: None,
+            saved_tables: HashMap::with_hasher(Hash64),
+        }
+    }
+
+    /// Copy to compile a program without committing to it, e.g. to audition a revision.
+    /// Signals, tables and buses which exist already are shared with the copy,
+    /// words and new tables or buses stay in it.
+    pub fn scratch(&self) -> Self {
+        fn copy<V: Clone>(map: &HashMap<String, V, Hash64>) -> HashMap<String, V, Hash64> {
+            let mut copy = HashMap::with_hasher(Hash64);
+            copy.extend(map.iter().map(|(k, v)| (k.to_owned(), v.clone())));
+            copy
+        }
+        Context {
+            input: Arc::clone(&self.input),
+            tables: copy(&self.tables),
+            buses: copy(&self.buses),
+            words: copy(&self.words),
+            impulse_responses: copy(&self.impulse_responses),
+            allow_files: self.allow_files,
+            max_feedback_gain: self.max_feedback_gain,
+            bpm: Arc::clone(&self.bpm),
+            warp: Arc::clone(&self.warp),
+            tails: Arc::clone(&self.tails),
+            seed: self.seed,
+            grid: Arc::clone(&self.grid),
+            telemetry: Arc::clone(&self.telemetry),
+            tables_dir: self.tables_dir.clone(),
+            saved_tables: copy(&self.saved_tables),
+        }
+    }
+
+    /// Random number generator for the op with the id, seeded according to `seed`.
+    pub fn rng(&self, id: u64) -> SmallRng {
+        match self.seed {
+            // Mix in id to not give ops of the same kind the same sequence.
+            Some(seed) => SmallRng::seed_from_u64(seed ^ id),
+            None => SmallRng::from_entropy(),
         }
     }
 }
@@ -34,7 +138,114 @@ pub struct TextOp {
 }
 
 pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> Program {
+    // Meters missing from the program are dropped, the rest keep their levels.
+    let mut meters = std::mem::take(&mut *ctx.telemetry.lock().unwrap());
+    // Tables read by the program, checked for writers once all ops are compiled.
+    let mut reads = Vec::new();
+    let writes = bind_written_tables(ops, sample_rate, ctx);
+    // Ids of the first sends to buses, which clear them each frame.
+    let mut first_sends = HashMap::new();
+    for op in ops {
+        if let Some(name) = bus_name(&op.op, "send") {
+            first_sends.entry(name).or_insert(op.id);
+        }
+    }
+    // Buses which lost their sends would hold the last frame, they are replaced with silent ones
+    // rather than cleared, as the running program writes to them until the new one takes over.
+    for (name, bus) in ctx.buses.iter_mut() {
+        if !first_sends.contains_key(name.as_str()) {
+            *bus = Arc::new(Mutex::new([0.0; CHANNELS]));
+        }
+    }
+    let program = compile_ops(
+        ops,
+        sample_rate,
+        ctx,
+        &mut meters,
+        &mut reads,
+        &mut first_sends.clone(),
+    );
+    // The running program keeps its own impulse responses until it's dropped.
+    ctx.impulse_responses.retain(|path, _| {
+        ops.iter()
+            .any(|op| op.op.starts_with("convir:") && op.op["convir:".len()..] == **path)
+    });
+    reads.sort_unstable();
+    reads.dedup();
+    for name in reads {
+        if !writes.contains(name) && ctx.tables[name].lock().unwrap().is_empty() {
+            log::warn!(
+                "Table {} has no writer, reading silence until one appears.",
+                name
+            );
+        }
+    }
+    let mut receives = ops
+        .iter()
+        .filter_map(|op| bus_name(&op.op, "recv"))
+        .filter(|name| !first_sends.contains_key(name))
+        .collect::<Vec<_>>();
+    receives.sort_unstable();
+    receives.dedup();
+    for name in receives {
+        log::warn!("Bus {} has no send, receiving silence.", name);
+    }
+    program
+}
+
+/// Length of the opening bracket of `each[` or `poly:<N>:[` the op starts with, ops could
+/// follow it without a space.
+fn block_opening(op: &str) -> Option<usize> {
+    if op.starts_with("each[") {
+        Some("each[".len())
+    } else if op.starts_with("poly:") {
+        op.find(":[").map(|i| i + ":[".len())
+    } else {
+        None
+    }
+}
+
+/// Ops up to the `]` which closes the block they are in, or all of them without one.
+fn block_body(ops: &[TextOp]) -> &[TextOp] {
+    let mut depth = 0;
+    let end = ops.iter().position(|TextOp { op, .. }| {
+        if block_opening(op) == Some(op.len()) {
+            depth += 1;
+        } else if op == "]" {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    });
+    &ops[..end.unwrap_or(ops.len())]
+}
+
+/// Name of the bus of the op with the kind, `send` or `recv`.
+fn bus_name<'a>(op: &'a str, kind: &str) -> Option<&'a str> {
+    let mut tokens = op.split(':');
+    if tokens.next()? == kind {
+        tokens.next().filter(|name| !name.is_empty())
+    } else {
+        None
+    }
+}
+
+/// Compile ops of the program or of a sub-program in `each[ ... ]`.
+fn compile_ops<'a>(
+    ops: &'a [TextOp],
+    sample_rate: u32,
+    ctx: &mut Context,
+    meters: &mut HashMap<String, Arc<Levels>>,
+    reads: &mut Vec<&'a str>,
+    first_sends: &mut HashMap<&'a str, u64>,
+) -> Program {
     let mut program = SmallVec::new();
+    // Dry signals of effect chains which are not closed by wet yet, innermost last.
+    let mut sends = Vec::new();
+    // Signal remembered by the last probe for solo.
+    let mut probe = None;
     macro_rules! push {
         ( $id:ident, $class:ident ) => {
             program.push(Statement {
@@ -48,7 +259,8 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
             program.push(Statement { id: $id, op: Box::new($class::new($($rest)*)) as Box<dyn Op> })
         };
     }
-    for TextOp { id, op } in ops {
+    let mut ops = ops.iter();
+    while let Some(TextOp { id, op }) = ops.next() {
         let id = *id;
         match op.as_str() {
             "*" | "mul" => push_args!(id, Fn2, pure::mul),
@@ -57,9 +269,26 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
             "/" | "div" => push_args!(id, Fn2, pure::div),
             "\\" => push_args!(id, Fn1, pure::recip),
             "^" | "pow" => push_args!(id, Fn2, pure::pow),
+            ">" => push_args!(id, Fn2, pure::gt),
+            "<" => push_args!(id, Fn2, pure::lt),
+            ">=" => push_args!(id, Fn2, pure::ge),
+            "<=" => push_args!(id, Fn2, pure::le),
+            "==" => push_args!(id, Fn2, pure::eq),
+            "abs" => push_args!(id, Fn1, pure::abs),
             "adsr" => push_args!(id, ADSR, sample_rate),
             "amp2db" | "a2db" => push_args!(id, Fn1, pure::amp2db),
+            "and" => push_args!(id, Fn2, pure::and),
+            "ar" => push_args!(id, AR, sample_rate),
+            "beat" | "beattrack" => push_args!(id, Beat, sample_rate),
+            "beats" => push_args!(id, Beats, Arc::clone(&ctx.bpm), Arc::clone(&ctx.warp)),
+            "blow" => program.push(Statement {
+                id,
+                op: Box::new(Blow::with_rng(sample_rate, ctx.rng(id))),
+            }),
+            "bow" => push_args!(id, Bow, sample_rate),
+            "bpm" => push_args!(id, Bpm, Arc::clone(&ctx.bpm), Arc::clone(&ctx.warp)),
             "c" => push_args!(id, Osc, sample_rate, pure::cosine),
+            "ceil" => push_args!(id, Fn1, pure::ceil),
             "cheb2" => push_args!(id, Fn1, pure::cheb2),
             "cheb3" => push_args!(id, Fn1, pure::cheb3),
             "cheb4" => push_args!(id, Fn1, pure::cheb4),
@@ -68,49 +297,155 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
             "circle" => push_args!(id, Fn1, pure::circle),
             "clamp" => push_args!(id, Fn3, pure::clamp),
             "clip" => push_args!(id, Fn1, pure::clip),
+            "compress" => push_args!(id, Compressor, sample_rate, false),
             "cos" => push_args!(id, Fn1, pure::cos),
             "cosh" => push_args!(id, Fn1, pure::cosh),
             "cosine" => push_args!(id, OscPhase, sample_rate, pure::cosine),
+            "crush" => program.push(Statement {
+                id,
+                op: Box::new(Crush::with_rng(ctx.rng(id))),
+            }),
             "db2amp" | "db2a" => push_args!(id, Fn1, pure::db2amp),
+            "dcblock" => push_args!(id, DCBlock, sample_rate),
+            "denoise" => push!(id, Denoise),
             "dm" | "dmetro" => push_args!(id, DMetro, sample_rate),
             "dmh" | "dmetro_hold" => push_args!(id, DMetroHold, sample_rate),
+            "duck" => push_args!(id, Compressor, sample_rate, true),
+            "dry" => {
+                let send = Arc::new(Mutex::new([0.0; CHANNELS]));
+                sends.push(Arc::clone(&send));
+                push_args!(id, Dry, send)
+            }
             "dup" => push!(id, Dup),
+            "each[" => {
+                let rest = ops.as_slice();
+                let body = block_body(rest);
+                if body.len() == rest.len() {
+                    log::warn!("Missing ] after each, it runs to the end of the program.");
+                }
+                ops = rest[(body.len() + 1).min(rest.len())..].iter();
+                let arities = get_arities();
+                match ops_arity(&arities, body) {
+                    Some(Arity { inputs, outputs }) => {
+                        let seed = ctx.seed;
+                        let programs = (0..CHANNELS)
+                            .map(|channel| {
+                                // Give random ops of each channel their own sequences.
+                                ctx.seed = seed.map(|seed| {
+                                    seed ^ (channel as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                                });
+                                compile_ops(body, sample_rate, ctx, meters, reads, first_sends)
+                            })
+                            .collect();
+                        ctx.seed = seed;
+                        push_args!(id, Each, programs, inputs, outputs)
+                    }
+                    None => {
+                        log::warn!("Can't tell how many inputs and outputs each has.");
+                    }
+                }
+            }
             "exp" => push_args!(id, Fn1, pure::exp),
+            "explin" | "exp2lin" => push_args!(id, Fn5, pure::explin),
+            "exprange" => push_args!(id, Fn3, pure::exprange),
             "f2m" | "freq2midi" => push_args!(id, Fn1, pure::freq2midi),
+            "floor" => push_args!(id, Fn1, pure::floor),
+            "bqbpf" => push_args!(id, BiQuad, sample_rate, make_bpf_coefficients),
+            "bqnotch" => push_args!(id, BiQuad, sample_rate, make_notch_coefficients),
+            "fold" => push!(id, Wavefolder),
+            "fold_range" => push_args!(id, Fn3, pure::fold_range),
+            "gate" => push_args!(id, Gate, sample_rate, f64::INFINITY),
+            "glide" => push_args!(id, Glide, sample_rate),
+            "glue" => push_args!(id, Glue, sample_rate),
+            "grid" => push_args!(
+                id,
+                Grid,
+                sample_rate,
+                Arc::clone(&ctx.grid),
+                Arc::clone(&ctx.bpm),
+                Arc::clone(&ctx.warp)
+            ),
             "h" | "bqhpf" => push_args!(id, BiQuad, sample_rate, make_hpf_coefficients),
+            "haas" => push_args!(id, Haas, sample_rate),
+            "highshelf" => program.push(Statement {
+                id,
+                op: Box::new(BiQuad::with_gain(sample_rate, make_high_shelf_coefficients)),
+            }),
             "hpf" => push_args!(id, HPF, sample_rate),
             "impulse" => push_args!(id, Impulse, sample_rate),
+            "in" | "input" => push_args!(id, Input, Arc::clone(&ctx.input)),
+            "key" => push_args!(id, Key, sample_rate),
+            "ladder" => push_args!(id, Ladder, sample_rate),
             "l" | "bqlpf" => push_args!(id, BiQuad, sample_rate, make_lpf_coefficients),
             "linlin" | "project" => push_args!(id, Fn5, pure::linlin),
+            "linexp" | "lin2exp" => push_args!(id, Fn5, pure::linexp),
+            "log" => push_args!(id, Fn1, pure::log),
+            "lowshelf" => program.push(Statement {
+                id,
+                op: Box::new(BiQuad::with_gain(sample_rate, make_low_shelf_coefficients)),
+            }),
             "lpf" => push_args!(id, LPF, sample_rate),
             "m" | "metro" => push_args!(id, Metro, sample_rate),
+            "macro" => program.push(Statement {
+                id,
+                op: Box::new(MacroOsc::with_rng(sample_rate, ctx.rng(id))),
+            }),
             "m2f" | "midi2freq" => push_args!(id, Fn1, pure::midi2freq),
             "max" => push_args!(id, Fn2, pure::max),
             "mh" | "metro_hold" => push_args!(id, MetroHold, sample_rate),
             "min" => push_args!(id, Fn2, pure::min),
-            "n" | "noise" | "whiteNoise" => push!(id, WhiteNoise),
+            "mono" => push!(id, Mono),
+            "ms_dec" => push!(id, MidSideDecode),
+            "ms_enc" => push!(id, MidSideEncode),
+            "not" => push_args!(id, Fn1, pure::not),
+            "n" | "noise" | "whiteNoise" => program.push(Statement {
+                id,
+                op: Box::new(WhiteNoise::with_rng(ctx.rng(id))),
+            }),
+            "or" => push_args!(id, Fn2, pure::or),
             "p" => push_args!(id, Pulse, sample_rate),
             "pan1" => push!(id, Pan1),
             "pan2" => push!(id, Pan2),
             "panx" => push!(id, Pan3),
-            "pitch" => push_args!(id, Yin, sample_rate, 1024, 64, 0.2),
+            "peak_eq" => program.push(Statement {
+                id,
+                op: Box::new(BiQuad::with_gain(sample_rate, make_peak_eq_coefficients)),
+            }),
+            "pitch" => push_args!(id, Yin, sample_rate, 1024, 64, 0.2, false),
             "pop" => push!(id, Pop),
             "prime" => push!(id, Prime),
+            "probe" => {
+                let send = Arc::new(Mutex::new([0.0; CHANNELS]));
+                probe = Some(Arc::clone(&send));
+                push_args!(id, Dry, send)
+            }
             "pulse" => push_args!(id, PulsePhase, sample_rate),
             "q" | "quantize" => push_args!(id, Fn2, pure::quantize),
             "r" | "range" => push_args!(id, Fn3, pure::range),
             "rot" => push!(id, Rot),
+            "reverb" => push_args!(id, Reverb, sample_rate, Arc::clone(&ctx.tails)),
             "round" => push_args!(id, Fn1, pure::round),
             "s" => push_args!(id, Osc, sample_rate, pure::sine),
             "saw" => push_args!(id, Phasor0, sample_rate),
+            "schmitt" => push!(id, Schmitt),
             "sh" | "sample&hold" => push!(id, SampleAndHold),
             "ssh" => push!(id, SmoothSampleAndHold),
+            "sign" => push_args!(id, Fn1, pure::sign),
             "silence" => push_args!(id, Constant, 0.0),
             "sin" => push_args!(id, Fn1, pure::sin),
             "sine" => push_args!(id, OscPhase, sample_rate, pure::sine),
             "sinh" => push_args!(id, Fn1, pure::sinh),
+            "sqrt" => push_args!(id, Fn1, pure::sqrt),
+            "slew" | "lag" => push_args!(id, Slew, sample_rate),
+            "solo" => {
+                let send = probe.clone().unwrap_or_else(|| {
+                    log::warn!("Missing probe before solo, playing silence.");
+                    Arc::new(Mutex::new([0.0; CHANNELS]))
+                });
+                push_args!(id, Solo, send)
+            }
             "spectral_shuffle" => {
-                let mut rng = Box::new(SmallRng::from_entropy());
+                let mut rng = Box::new(ctx.rng(id));
                 push_args!(
                     id,
                     SpectralTransform,
@@ -128,14 +463,33 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                     Box::new(|freqs| freqs.reverse()),
                 )
             }
+            "stab" => push_args!(id, Stabilizer, sample_rate),
+            "stereo" => push!(id, Stereo),
             "swap" => push!(id, Swap),
+            "swspace" => push!(id, SwapSpace),
             "t" => push_args!(id, Osc, sample_rate, pure::triangle),
+            "t2g" => push_args!(id, TriggerToGate, sample_rate),
             "tan" => push_args!(id, Fn1, pure::tan),
             "tanh" => push_args!(id, Fn1, pure::tanh),
             "tri" => push_args!(id, OscPhase, sample_rate, pure::triangle),
+            "toggle" => push!(id, Toggle),
             "unit" => push_args!(id, Fn1, pure::unit),
+            "vowel" => push_args!(id, Vowel, sample_rate),
             "w" => push_args!(id, Phasor, sample_rate),
+            "warp" => push_args!(id, Warp, Arc::clone(&ctx.warp)),
+            "wet" => {
+                let send = sends.pop().unwrap_or_else(|| {
+                    log::warn!("Missing dry before wet, mixing with silence.");
+                    Arc::new(Mutex::new([0.0; CHANNELS]))
+                });
+                push_args!(id, Wet, send)
+            }
+            "width" => push_args!(id, Width, sample_rate),
             "wrap" => push_args!(id, Fn1, pure::wrap),
+            "wrap_range" => push_args!(id, Fn3, pure::wrap_range),
+            "xor" => push_args!(id, Fn2, pure::xor),
+            "xfade" => push_args!(id, Fn3, pure::xfade),
+            "xsynth" => push!(id, CrossSynthesis),
             _ => match op.parse::<Sample>() {
                 Ok(x) => push_args!(id, Constant, x),
                 Err(_) => {
@@ -143,7 +497,7 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                     match tokens[0] {
                         "" | "dig" => match tokens.get(1) {
                             Some(x) => match x.parse::<usize>() {
-                                Ok(n) => push_args!(id, Dig, n),
+                                Ok(n) => push_args!(id, Dig, limit_count(n)),
                                 Err(_) => {
                                     log::warn!("Can't parse {} as depth", x);
                                 }
@@ -152,9 +506,21 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                                 log::warn!("Missing depth parameter.");
                             }
                         },
+                        "beat" | "beattrack" => match tokens.get(1) {
+                            Some(&"sync") => program.push(Statement {
+                                id,
+                                op: Box::new(Beat::with_bpm(sample_rate, Arc::clone(&ctx.bpm))),
+                            }),
+                            _ => {
+                                log::warn!("Unknown beat tracker flag in {}.", op);
+                            }
+                        },
                         "ch" | "channel" => match tokens.get(1) {
                             Some(x) => match x.parse::<usize>() {
-                                Ok(n) => push_args!(id, Channel, n),
+                                Ok(n) if n < CHANNELS => push_args!(id, Channel, n),
+                                Ok(n) => {
+                                    log::warn!("There is no channel {}.", n);
+                                }
                                 Err(_) => {
                                     log::warn!("Can't parse {} as channel number", x);
                                 }
@@ -165,40 +531,429 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                         },
                         "dl" | "delay" => match tokens.get(1) {
                             Some(x) => {
-                                push_args!(id, Delay, sample_rate, x.parse::<f64>().unwrap_or(60.0))
+                                push_args!(id, Delay, sample_rate, parse_duration(x, 60.0))
                             }
                             None => push_args!(id, Delay, sample_rate, 60.0),
                         },
+                        "vdelay" => push_args!(
+                            id,
+                            VDelay,
+                            sample_rate,
+                            tokens.get(1).map_or(1.0, |x| parse_duration(x, 1.0))
+                        ),
+                        "mtap" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(taps) => push_args!(
+                                    id,
+                                    MultiTap,
+                                    sample_rate,
+                                    limit_count(taps),
+                                    tokens.get(2).map_or(10.0, |x| parse_duration(x, 10.0))
+                                ),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of taps.", x);
+                                }
+                            },
+                            None => {
+                                log::warn!("Missing number of taps parameter.");
+                            }
+                        },
+                        "envgen" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(segments) => {
+                                    push_args!(id, EnvGen, sample_rate, limit_count(segments))
+                                }
+                                Err(_) if x.is_empty() => {
+                                    log::warn!("Missing number of segments parameter.");
+                                }
+                                Err(_) => {
+                                    reads.push(x);
+                                    let table = bind_table(ctx, x, sample_rate);
+                                    program.push(Statement {
+                                        id,
+                                        op: Box::new(EnvGen::with_table(sample_rate, table)),
+                                    });
+                                }
+                            },
+                            None => {
+                                log::warn!("Missing number of segments parameter.");
+                            }
+                        },
+                        "count" => match tokens.get(1) {
+                            Some(x) => match x.parse::<u64>() {
+                                Ok(n) => push_args!(id, Counter, Some(n)),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as counter modulus.", x);
+                                }
+                            },
+                            None => push_args!(id, Counter, None),
+                        },
+                        "hum" => match tokens.get(1) {
+                            Some(x) => match x.parse::<Sample>() {
+                                Ok(frequency) => push_args!(id, Hum, sample_rate, frequency),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as mains frequency.", x);
+                                }
+                            },
+                            None => push_args!(id, Hum, sample_rate, 50.0),
+                        },
+                        "ffcomb" => match tokens.get(1) {
+                            Some(x) => {
+                                push_args!(id, CombFF, sample_rate, parse_duration(x, 1.0))
+                            }
+                            None => push_args!(id, CombFF, sample_rate, 1.0),
+                        },
+                        "chorus" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(voices) => {
+                                    push_args!(id, Chorus, sample_rate, limit_count(voices))
+                                }
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of voices.", x);
+                                }
+                            },
+                            None => push_args!(id, Chorus, sample_rate, 3),
+                        },
+                        "fdn" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(size) => push_args!(
+                                    id,
+                                    Fdn,
+                                    sample_rate,
+                                    limit_count(size),
+                                    Arc::clone(&ctx.tails)
+                                ),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of delay lines.", x);
+                                }
+                            },
+                            None => push_args!(id, Fdn, sample_rate, 8, Arc::clone(&ctx.tails)),
+                        },
+                        "modal" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(modes) => {
+                                    push_args!(id, Modal, sample_rate, limit_count(modes))
+                                }
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of modes.", x);
+                                }
+                            },
+                            None => push_args!(id, Modal, sample_rate, 8),
+                        },
+                        "pingpong" => push_args!(
+                            id,
+                            PingPong,
+                            sample_rate,
+                            tokens.get(1).map_or(10.0, |x| parse_duration(x, 10.0)),
+                            ctx.max_feedback_gain
+                        ),
+                        "tape_delay" => push_args!(
+                            id,
+                            TapeDelay,
+                            sample_rate,
+                            tokens.get(1).map_or(10.0, |x| parse_duration(x, 10.0)),
+                            ctx.max_feedback_gain
+                        ),
+                        "revdl" => push_args!(
+                            id,
+                            ReverseDelay,
+                            sample_rate,
+                            tokens.get(1).map_or(1.0, |x| parse_duration(x, 1.0))
+                        ),
+                        "pitchshift" => push_args!(
+                            id,
+                            PitchShift,
+                            sample_rate,
+                            tokens.get(1).map_or(1.0, |x| parse_duration(x, 1.0))
+                        ),
+                        "vocoder" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(bands) => push_args!(id, Vocoder, limit_count(bands)),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of bands.", x);
+                                }
+                            },
+                            None => push_args!(id, Vocoder, 16),
+                        },
+                        "phaser" => match tokens.get(1) {
+                            Some(x) => match x.parse::<usize>() {
+                                Ok(stages) => push_args!(
+                                    id,
+                                    Phaser,
+                                    sample_rate,
+                                    limit_count(stages),
+                                    ctx.max_feedback_gain
+                                ),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as number of stages.", x);
+                                }
+                            },
+                            None => push_args!(id, Phaser, sample_rate, 4, ctx.max_feedback_gain),
+                        },
+                        "fbcomb" => match tokens.get(1) {
+                            Some(x) => {
+                                push_args!(
+                                    id,
+                                    CombFB,
+                                    sample_rate,
+                                    parse_duration(x, 1.0),
+                                    ctx.max_feedback_gain
+                                )
+                            }
+                            None => {
+                                push_args!(id, CombFB, sample_rate, 1.0, ctx.max_feedback_gain)
+                            }
+                        },
                         "fb" | "feedback" => match tokens.get(1) {
                             Some(x) => push_args!(
                                 id,
                                 Feedback,
                                 sample_rate,
-                                x.parse::<f64>().unwrap_or(60.0)
+                                parse_duration(x, 60.0),
+                                ctx.max_feedback_gain
                             ),
-                            None => push_args!(id, Feedback, sample_rate, 60.0),
+                            None => {
+                                push_args!(id, Feedback, sample_rate, 60.0, ctx.max_feedback_gain)
+                            }
+                        },
+                        "gate" => match tokens.get(1) {
+                            Some(x) => match x.parse::<f64>() {
+                                Ok(ratio) => push_args!(id, Gate, sample_rate, ratio),
+                                Err(_) => {
+                                    log::warn!("Can't parse {} as expansion ratio.", x);
+                                }
+                            },
+                            None => push_args!(id, Gate, sample_rate, f64::INFINITY),
+                        },
+                        "limit" => match tokens.get(1) {
+                            Some(x) => {
+                                push_args!(id, Limiter, sample_rate, parse_duration(x, 0.005))
+                            }
+                            None => push_args!(id, Limiter, sample_rate, 0.005),
                         },
-                        "rt" | "rtab" | "readtable" => {
-                            match tokens.get(1).and_then(|x| ctx.tables.get(*x)) {
-                                Some(table) => {
-                                    push_args!(id, TableReader, sample_rate, Arc::clone(table));
+                        "poly" if block_opening(op) == Some(op.len()) => {
+                            let rest = ops.as_slice();
+                            let body = block_body(rest);
+                            if body.len() == rest.len() {
+                                log::warn!(
+                                    "Missing ] after poly, it runs to the end of the program."
+                                );
+                            }
+                            ops = rest[(body.len() + 1).min(rest.len())..].iter();
+                            let voices = match tokens[1..tokens.len() - 1] {
+                                [] => Some(4),
+                                [x] => x.parse::<usize>().ok().map(|n| limit_count(n).max(1)),
+                                _ => None,
+                            };
+                            match voices {
+                                Some(voices) => {
+                                    let seed = ctx.seed;
+                                    let programs = (0..voices)
+                                        .map(|voice| {
+                                            // Give random ops of each voice their own sequences.
+                                            ctx.seed = seed.map(|seed| {
+                                                seed ^ (voice as u64)
+                                                    .wrapping_add(CHANNELS as u64)
+                                                    .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                                            });
+                                            compile_ops(
+                                                body,
+                                                sample_rate,
+                                                ctx,
+                                                meters,
+                                                reads,
+                                                first_sends,
+                                            )
+                                        })
+                                        .collect();
+                                    ctx.seed = seed;
+                                    push_args!(id, Poly, programs)
                                 }
                                 None => {
-                                    log::warn!("Missing table name parameter.");
+                                    log::warn!("Can't parse number of voices in {}.", op);
                                 }
                             }
                         }
+                        "send" => match tokens.get(1).filter(|name| !name.is_empty()) {
+                            Some(name) => {
+                                let bus = bind_bus(ctx, name);
+                                // Copies of the send in `each[` and `poly` share its id, only the
+                                // first compiled one clears the bus.
+                                let first = first_sends.get(name) == Some(&id);
+                                if first {
+                                    first_sends.remove(name);
+                                }
+                                push_args!(id, BusSend, bus, first);
+                            }
+                            None => {
+                                log::warn!("Missing bus name parameter.");
+                            }
+                        },
+                        "recv" => match tokens.get(1).filter(|name| !name.is_empty()) {
+                            Some(name) => push_args!(id, BusReceive, bind_bus(ctx, name)),
+                            None => {
+                                log::warn!("Missing bus name parameter.");
+                            }
+                        },
+                        "rt" | "rtab" | "readtable" => match tokens.get(1) {
+                            Some(name) => {
+                                reads.push(name);
+                                let table = bind_table(ctx, name, sample_rate);
+                                push_args!(id, TableReader, sample_rate, table);
+                            }
+                            None => {
+                                log::warn!("Missing table name parameter.");
+                            }
+                        },
+                        "arp" => {
+                            let pattern = match tokens.get(1) {
+                                None | Some(&"up") => Some(ArpPattern::Up),
+                                Some(&"down") => Some(ArpPattern::Down),
+                                Some(&"updown") => Some(ArpPattern::UpDown),
+                                Some(&"random") => Some(ArpPattern::Random),
+                                Some(&"order") => Some(ArpPattern::Order),
+                                Some(x) => {
+                                    log::warn!("Unknown arpeggio pattern {}.", x);
+                                    None
+                                }
+                            };
+                            let notes = match tokens.get(2) {
+                                None => Some((3, None)),
+                                Some(x) => match x.parse::<usize>() {
+                                    Ok(notes) => Some((limit_count(notes), None)),
+                                    Err(_) if x.is_empty() => {
+                                        log::warn!("Missing table name parameter.");
+                                        None
+                                    }
+                                    Err(_) => {
+                                        reads.push(x);
+                                        Some((0, Some(bind_table(ctx, x, sample_rate))))
+                                    }
+                                },
+                            };
+                            if let (Some(pattern), Some((notes, table))) = (pattern, notes) {
+                                program.push(Statement {
+                                    id,
+                                    op: Box::new(Arp::with_rng(pattern, notes, table, ctx.rng(id))),
+                                });
+                            }
+                        }
+                        "scale" | "scaleq" => match tokens.get(1) {
+                            Some(name) => match scale(name) {
+                                Some(degrees) => push_args!(id, ScaleQuantizer, degrees),
+                                None => {
+                                    reads.push(name);
+                                    let table = bind_table(ctx, name, sample_rate);
+                                    program.push(Statement {
+                                        id,
+                                        op: Box::new(ScaleQuantizer::with_table(table)),
+                                    });
+                                }
+                            },
+                            None => push_args!(id, ScaleQuantizer, scale("major").unwrap()),
+                        },
+                        "spectral_filter" => match tokens.get(1) {
+                            Some(name) => {
+                                reads.push(name);
+                                let table = bind_table(ctx, name, sample_rate);
+                                push_args!(id, SpectralFilter, Some(table))
+                            }
+                            None => push_args!(id, SpectralFilter, None),
+                        },
+                        "meter" => match tokens.get(1) {
+                            Some(name) => {
+                                let levels = Arc::clone(
+                                    ctx.telemetry
+                                        .lock()
+                                        .unwrap()
+                                        .entry(name.to_string())
+                                        .or_insert_with(|| {
+                                            meters.remove(*name).unwrap_or_default()
+                                        }),
+                                );
+                                push_args!(id, Meter, sample_rate, levels)
+                            }
+                            None => {
+                                log::warn!("Missing meter name parameter.");
+                            }
+                        },
+                        "pitch" => match tokens[1].parse::<usize>() {
+                            Ok(window_size) => {
+                                let window_size = if window_size > MAX_PITCH_WINDOW {
+                                    log::warn!(
+                                        "Pitch window is limited to {} samples.",
+                                        MAX_PITCH_WINDOW
+                                    );
+                                    MAX_PITCH_WINDOW
+                                } else if window_size < MIN_PITCH_WINDOW {
+                                    log::warn!(
+                                        "Pitch window is at least {} samples.",
+                                        MIN_PITCH_WINDOW
+                                    );
+                                    MIN_PITCH_WINDOW
+                                } else {
+                                    window_size
+                                };
+                                let threshold = tokens
+                                    .get(2)
+                                    .and_then(|x| x.parse::<Sample>().ok())
+                                    .unwrap_or(0.2);
+                                push_args!(id, Yin, sample_rate, window_size, 64, threshold, true)
+                            }
+                            Err(_) => {
+                                log::warn!("Can't parse {} as pitch window.", tokens[1]);
+                            }
+                        },
+                        "stretch" => match tokens.get(1) {
+                            Some(name) => {
+                                reads.push(name);
+                                let table = bind_table(ctx, name, sample_rate);
+                                push_args!(id, Stretch, table)
+                            }
+                            None => {
+                                log::warn!("Missing table name parameter.");
+                            }
+                        },
+                        "shape" => {
+                            let f: Option<fn(Sample, Sample) -> Sample> = match tokens.get(1) {
+                                None | Some(&"tanh") => Some(pure::shape_tanh),
+                                Some(&"atan") => Some(pure::shape_atan),
+                                Some(&"clip") => Some(pure::shape_clip),
+                                Some(&"asym") => Some(pure::shape_asym),
+                                Some(&"fold") => Some(pure::shape_fold),
+                                Some(x) => {
+                                    log::warn!("Unknown transfer curve {}.", x);
+                                    None
+                                }
+                            };
+                            if let Some(f) = f {
+                                push_args!(id, Fn2, f);
+                            }
+                        }
+                        "svf" => {
+                            let mode = match tokens.get(1) {
+                                None | Some(&"lp") => Some(SVFMode::LowPass),
+                                Some(&"hp") => Some(SVFMode::HighPass),
+                                Some(&"bp") => Some(SVFMode::BandPass),
+                                Some(&"notch") => Some(SVFMode::Notch),
+                                Some(x) => {
+                                    log::warn!("Unknown filter mode {}.", x);
+                                    None
+                                }
+                            };
+                            if let Some(mode) = mode {
+                                push_args!(id, SVF, sample_rate, mode);
+                            }
+                        }
                         "wt" | "wtab" | "writetable" => match tokens.get(2) {
                             Some(x) => match x.parse::<Sample>() {
-                                Ok(size) => {
-                                    let table_name = String::from(tokens[1]);
-                                    let table = Arc::new(Mutex::new(vec![
-                                        [0.0; CHANNELS];
-                                        (size * (sample_rate as Sample))
-                                            as _
-                                    ]));
-                                    ctx.tables.insert(table_name, Arc::clone(&table));
-                                    push_args!(id, TableWriter, table);
+                                Ok(_) => {
+                                    // Sized by `bind_written_tables` already.
+                                    let table = bind_table(ctx, tokens[1], sample_rate);
+                                    let latency =
+                                        tokens.get(3).map_or(0.0, |x| parse_duration(x, 0.0));
+                                    let latency = (latency * (sample_rate as Sample)) as usize;
+                                    push_args!(id, TableWriter, table, latency);
                                 }
                                 Err(_) => {
                                     log::warn!("Can't parse {} as table length.", x);
@@ -210,7 +965,11 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                         },
                         "conv" => match tokens.get(1) {
                             Some(x) => match x.parse::<usize>() {
-                                Ok(window_size) => push_args!(id, Convolution, window_size),
+                                Ok(window_size) => push_args!(
+                                    id,
+                                    Convolution,
+                                    window_size.min(MAX_DURATION as usize * sample_rate as usize)
+                                ),
                                 Err(_) => {
                                     log::warn!("Can't parse {} as kernel length.", x);
                                 }
@@ -219,9 +978,33 @@ pub fn compile_program(ops: &[TextOp], sample_rate: u32, ctx: &mut Context) -> P
                                 log::warn!("Missing kernel length parameter.");
                             }
                         },
+                        "convir" => {
+                            // Path could contain colons.
+                            let path = tokens[1..].join(":");
+                            if !ctx.allow_files {
+                                log::warn!("Reading files is not allowed.");
+                            } else if path.is_empty() {
+                                log::warn!("Missing impulse response path parameter.");
+                            } else if let Some(ir) = ctx.impulse_responses.get(&path) {
+                                push_args!(id, ConvolutionIR, Arc::clone(ir));
+                            } else {
+                                match load_impulse_response(&path, sample_rate) {
+                                    Ok(ir) => {
+                                        let ir = Arc::new(ir);
+                                        ctx.impulse_responses.insert(path, Arc::clone(&ir));
+                                        push_args!(id, ConvolutionIR, ir);
+                                    }
+                                    Err(e) => {
+                                        log::warn!("Can't load impulse response {}: {}", path, e);
+                                    }
+                                }
+                            }
+                        }
                         "convm" => match tokens.get(1) {
                             Some(x) => match x.parse::<usize>() {
-                                Ok(window_size) => push_args!(id, ConvolutionM, window_size),
+                                Ok(window_size) => {
+                                    push_args!(id, ConvolutionM, limit_count(window_size))
+                                }
                                 Err(_) => {
                                     log::warn!("Can't parse {} as kernel length.", x);
                                 }
@@ -247,13 +1030,26 @@ pub fn rewrite_terms(stmts: &[TextOp]) -> Vec<TextOp> {
     let mut terms: HashMap<String, Term> = Default::default();
     let mut stack: Vec<TextOp> = Vec::from(stmts.clone());
     stack.reverse();
+    let mut rewritten_ops = 0;
+    // Open `each[` and `poly:<N>:[` brackets, their `]` close them rather than term definitions.
+    let mut block_depth = 0;
     while let Some(stmt) = stack.pop() {
         // This is a known term, let's rewrite it...
         if let Some(term) = terms.get(&stmt.op) {
             // ...but not when we are defining a new term.
             if let Some(term) = new_term.as_mut() {
+                if stmt.op.contains("?") {
+                    term.holes += 1;
+                }
                 term.ops.push(stmt);
+            } else if rewritten_ops + term.ops.len() > MAX_REWRITTEN_OPS {
+                log::warn!(
+                    "Too many ops after rewriting terms, is {} recursive?",
+                    stmt.op
+                );
+                break;
             } else if term.holes <= result.len() {
+                rewritten_ops += term.ops.len();
                 // Steal ops from the output to fill the holes.
                 let mut holes = result.drain((result.len() - term.holes)..);
                 // Not pushing rewrited terms directly onto the stack
@@ -278,6 +1074,25 @@ pub fn rewrite_terms(stmts: &[TextOp]) -> Vec<TextOp> {
                     stack.push(op);
                 }
             }
+        } else if let Some(n) = block_opening(&stmt.op).filter(|&n| n < stmt.op.len()) {
+            stack.push(TextOp {
+                id: stmt.id,
+                op: stmt.op[n..].to_owned(),
+            });
+            stack.push(TextOp {
+                id: stmt.id.wrapping_add(1),
+                op: stmt.op[..n].to_owned(),
+            });
+        } else if block_opening(&stmt.op).is_some() || stmt.op == "]" && block_depth > 0 {
+            if stmt.op == "]" {
+                block_depth -= 1;
+            } else {
+                block_depth += 1;
+            }
+            match new_term.as_mut() {
+                Some(term) => term.ops.push(stmt),
+                None => result.push(stmt),
+            }
         } else if stmt.op.starts_with("[") {
             new_term = Some(Term {
                 holes: 0,
@@ -297,14 +1112,14 @@ pub fn rewrite_terms(stmts: &[TextOp]) -> Vec<TextOp> {
                 }
             }
         } else if stmt.op.ends_with("]") {
-            if new_term.is_some() {
+            if new_term.is_some() || block_depth > 0 {
                 stack.push(TextOp {
                     id: 0,
                     op: "]".to_string(),
                 });
                 stack.push(TextOp {
                     id: stmt.id,
-                    op: stmt.op.chars().take(stmt.op.len() - 1).collect(),
+                    op: stmt.op[..stmt.op.len() - 1].to_owned(),
                 });
             }
         } else {
@@ -321,6 +1136,92 @@ pub fn rewrite_terms(stmts: &[TextOp]) -> Vec<TextOp> {
     result
 }
 
+/// Take Forth-style definitions `: name ops ;` out of the program into `words` and replace each
+/// use of a word with its ops, which could use other words. Definitions apply to the whole
+/// program wherever they are in it, `words` are rebuilt from them. Like `rewrite_terms`, it should
+/// run before the program is compiled.
+pub fn rewrite_words(
+    ops: &[TextOp],
+    words: &mut HashMap<String, Vec<TextOp>, Hash64>,
+) -> Vec<TextOp> {
+    words.clear();
+    let arities = get_arities();
+    let mut stack = Vec::with_capacity(ops.len());
+    let mut ops = ops.iter();
+    while let Some(op) = ops.next() {
+        if op.op != ":" {
+            stack.push(op.clone());
+            continue;
+        }
+        let name = match ops.next() {
+            Some(TextOp { op, .. }) if op != ";" => op.to_owned(),
+            _ => {
+                log::warn!("Missing word name after :.");
+                continue;
+            }
+        };
+        let mut body = Vec::new();
+        let mut closed = false;
+        for op in ops.by_ref() {
+            if op.op == ";" {
+                closed = true;
+                break;
+            }
+            body.push(op.clone());
+        }
+        if !closed {
+            log::warn!(
+                "Missing ; after definition of {}, it runs to the end of the program.",
+                name
+            );
+        }
+        // Numbers and ops keep their meaning.
+        if op_arity(&arities, &name).is_some() {
+            log::warn!("Can't define {}, it's a number or an op.", name);
+            continue;
+        }
+        words.insert(name, body);
+    }
+    expand_words(stack, words)
+}
+
+/// Ops the op stands for, the op itself unless it's one of `words` found by `rewrite_words`.
+pub fn expand_word(op: &TextOp, words: &HashMap<String, Vec<TextOp>, Hash64>) -> Vec<TextOp> {
+    expand_words(vec![op.clone()], words)
+}
+
+fn expand_words(
+    mut stack: Vec<TextOp>,
+    words: &HashMap<String, Vec<TextOp>, Hash64>,
+) -> Vec<TextOp> {
+    stack.reverse();
+    let mut result = Vec::with_capacity(stack.len());
+    let mut rewritten_ops = 0;
+    while let Some(op) = stack.pop() {
+        match words.get(&op.op) {
+            Some(body) if rewritten_ops + body.len() > MAX_REWRITTEN_OPS => {
+                log::warn!(
+                    "Too many ops after expanding words, is {} recursive?",
+                    op.op
+                );
+                break;
+            }
+            Some(body) => {
+                rewritten_ops += body.len();
+                // Ops are salted with the use like literals of terms.
+                for word_op in body.iter().rev() {
+                    stack.push(TextOp {
+                        id: word_op.id.wrapping_add(op.id),
+                        op: word_op.op.to_owned(),
+                    });
+                }
+            }
+            None => result.push(op),
+        }
+    }
+    result
+}
+
 pub fn get_help() -> HashMap<String, String> {
     let mut result = HashMap::new();
     for item in Regex::new(r"(?P<term>(\w+(:<\w+>)?(, )*)+)::(?P<definition>.+)")
@@ -335,6 +1236,245 @@ pub fn get_help() -> HashMap<String, String> {
     result
 }
 
+/// Copies of tables which changed since they were saved or loaded, to save them to `tables_dir`
+/// away from the audio thread. Tables which were never written to are skipped, nothing is saved
+/// when files aren't allowed.
+pub fn snapshot_tables(ctx: &mut Context, sample_rate: u32) -> TablesSnapshot {
+    let mut snapshot = TablesSnapshot {
+        dir: PathBuf::new(),
+        sample_rate,
+        tables: Vec::new(),
+    };
+    let dir = match &ctx.tables_dir {
+        Some(dir) if ctx.allow_files => dir,
+        _ => return snapshot,
+    };
+    snapshot.dir = dir.to_owned();
+    for (name, table) in &ctx.tables {
+        let path = match table_path(dir, name) {
+            Some(path) => path,
+            None => continue,
+        };
+        // Copy to not hold the audio thread while hashing and writing.
+        let frames = table.lock().unwrap().clone();
+        let hash = hash_frames(&frames);
+        if ctx.saved_tables.get(name) == Some(&hash)
+            || !ctx.saved_tables.contains_key(name) && frames.iter().flatten().all(|&x| x == 0.0)
+        {
+            continue;
+        }
+        snapshot.tables.push((name.to_owned(), path, frames, hash));
+    }
+    snapshot
+}
+
+/// Tables taken by `snapshot_tables`.
+pub struct TablesSnapshot {
+    dir: PathBuf,
+    sample_rate: u32,
+    /// Names, paths, contents and hashes of the contents.
+    tables: Vec<(String, PathBuf, Vec<Frame>, u64)>,
+}
+
+/// Hashes of tables written by `TablesSnapshot::save`, for `mark_tables_saved`.
+pub struct SavedTables(Vec<(String, u64)>);
+
+impl TablesSnapshot {
+    /// Write tables as WAV files, could take a while for long ones.
+    pub fn save(self) -> Result<SavedTables, hound::Error> {
+        let mut saved = Vec::new();
+        if self.tables.is_empty() {
+            return Ok(SavedTables(saved));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let spec = hound::WavSpec {
+            channels: CHANNELS as _,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        for (name, path, frames, hash) in self.tables {
+            let mut writer = hound::WavWriter::create(path, spec)?;
+            for x in frames.iter().flatten() {
+                writer.write_sample(*x as f32)?;
+            }
+            writer.finalize()?;
+            saved.push((name, hash));
+        }
+        Ok(SavedTables(saved))
+    }
+}
+
+/// Record tables as they are on disk once their snapshot is saved, so next snapshots skip them
+/// until they change. Tables of failed saves stay in next snapshots.
+pub fn mark_tables_saved(ctx: &mut Context, saved: SavedTables) {
+    ctx.saved_tables.extend(saved.0);
+}
+
+/// The bus with the name, created silent if it doesn't exist yet.
+fn bind_bus(ctx: &mut Context, name: &str) -> Arc<Mutex<Frame>> {
+    Arc::clone(
+        ctx.buses
+            .entry(name.to_owned())
+            .or_insert_with(|| Arc::new(Mutex::new([0.0; CHANNELS]))),
+    )
+}
+
+/// Size tables written by the program before its ops are compiled, so readers before the writer
+/// bind to the same table. Tables of the running program which change their length are replaced
+/// like buses rather than resized under it, writers carry recorded audio over when they migrate.
+/// Returns names of the tables.
+fn bind_written_tables<'a>(
+    ops: &'a [TextOp],
+    sample_rate: u32,
+    ctx: &mut Context,
+) -> HashSet<&'a str> {
+    let mut writes = HashSet::new();
+    for op in ops {
+        let name = match table_access(&op.op) {
+            Some((name, TableAccess::Write)) => name,
+            _ => continue,
+        };
+        let size = match op.op.split(':').nth(2).map(|x| x.parse::<Sample>()) {
+            Some(Ok(size)) => limit_duration(size),
+            _ => continue,
+        };
+        let length = (size * Sample::from(sample_rate)) as usize;
+        // Tables bound by this program or loaded just now aren't used by anything yet.
+        let running = ctx.tables.contains_key(name) && !writes.contains(name);
+        let table = bind_table(ctx, name, sample_rate);
+        if !running {
+            table.lock().unwrap().resize(length, [0.0; CHANNELS]);
+        } else if table.lock().unwrap().len() != length {
+            let table = Arc::new(Mutex::new(vec![[0.0; CHANNELS]; length]));
+            ctx.tables.insert(name.to_owned(), table);
+        }
+        writes.insert(name);
+    }
+    writes
+}
+
+/// The table with the name, loaded from `tables_dir` or created empty if it doesn't exist yet,
+/// so ops reading it start hearing it once a writer appears.
+fn bind_table(ctx: &mut Context, name: &str, sample_rate: u32) -> Arc<Mutex<Vec<Frame>>> {
+    if let Some(table) = ctx.tables.get(name) {
+        return Arc::clone(table);
+    }
+    let table = load_table(ctx, name, sample_rate).unwrap_or_default();
+    ctx.tables.insert(name.to_owned(), Arc::clone(&table));
+    table
+}
+
+/// Load the table saved to `tables_dir`, if there is one and files are allowed.
+fn load_table(ctx: &mut Context, name: &str, sample_rate: u32) -> Option<Arc<Mutex<Vec<Frame>>>> {
+    if !ctx.allow_files {
+        return None;
+    }
+    let path = table_path(ctx.tables_dir.as_ref()?, name)?;
+    if !path.exists() {
+        return None;
+    }
+    match load_wav(&path, sample_rate) {
+        Ok(frames) => {
+            ctx.saved_tables
+                .insert(name.to_owned(), hash_frames(&frames));
+            Some(Arc::new(Mutex::new(frames)))
+        }
+        Err(e) => {
+            log::warn!("Can't load table {} from {}: {}", name, path.display(), e);
+            None
+        }
+    }
+}
+
+/// Names which could escape the directory are not saved.
+fn table_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(&['/', '\\'][..]) {
+        return None;
+    }
+    Some(dir.join(format!("{}.wav", name)))
+}
+
+fn hash_frames(frames: &[Frame]) -> u64 {
+    let mut hasher = Hash64.build_hasher();
+    for x in frames.iter().flatten() {
+        hasher.write_u64(x.to_bits());
+    }
+    hasher.finish()
+}
+
+/// Load WAV impulse response normalized to unit energy of the loudest channel,
+/// so reverberated signal is about as loud as the dry one.
+fn load_impulse_response(path: &str, sample_rate: u32) -> Result<Vec<Frame>, hound::Error> {
+    let mut ir = load_wav(path, sample_rate)?;
+    let energy = (0..CHANNELS)
+        .map(|channel| {
+            ir.iter()
+                .map(|frame| frame[channel] * frame[channel])
+                .sum::<Sample>()
+        })
+        .fold(0.0, Sample::max);
+    if energy > 0.0 {
+        let scale = energy.sqrt().recip();
+        for frame in ir.iter_mut() {
+            for sample in frame.iter_mut() {
+                *sample *= scale;
+            }
+        }
+    }
+    Ok(ir)
+}
+
+/// Load WAV file, resampled to the sample rate if needed. Mono is spread to all channels.
+fn load_wav<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Vec<Frame>, hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|x| x.map(Sample::from))
+            .collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as Sample;
+            reader
+                .samples::<i32>()
+                .map(|x| x.map(|x| Sample::from(x) / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    let channels = usize::from(spec.channels.max(1));
+    let frames = samples
+        .chunks(channels)
+        .map(|chunk| {
+            let mut frame = [0.0; CHANNELS];
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = chunk[channel.min(chunk.len() - 1)];
+            }
+            frame
+        })
+        .collect::<Vec<_>>();
+    let ratio = Sample::from(spec.sample_rate) / Sample::from(sample_rate);
+    Ok(if spec.sample_rate == sample_rate || frames.is_empty() {
+        frames
+    } else {
+        let len = (frames.len() as Sample / ratio) as usize;
+        (0..len)
+            .map(|i| {
+                let z = i as Sample * ratio;
+                let j = z as usize;
+                let k = z.fract();
+                let a = frames[j];
+                let b = frames[(j + 1).min(frames.len() - 1)];
+                let mut frame = [0.0; CHANNELS];
+                for (sample, (a, b)) in frame.iter_mut().zip(a.iter().zip(&b)) {
+                    *sample = (1.0 - k) * a + k * b;
+                }
+                frame
+            })
+            .collect()
+    })
+}
+
 pub fn get_op_groups() -> Vec<(String, Vec<String>)> {
     let mut result = Vec::new();
     let group_re = Regex::new("=== (.+)").unwrap();
@@ -361,6 +1501,180 @@ pub fn get_op_groups() -> Vec<(String, Vec<String>)> {
     result
 }
 
+/// How many frames op takes from the stack and how many it puts back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arity {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+/// Collect arities of ops from their signatures in HELP.
+/// Parametrized ops are keyed by their name without parameters.
+pub fn get_arities() -> HashMap<String, Arity> {
+    let mut result = HashMap::new();
+    for item in Regex::new(r"(?P<term>(\w+(:<\w+>)*(, )*)+):: *\((?P<inputs>[^)]*)\)")
+        .unwrap()
+        .captures_iter(HELP)
+    {
+        let inputs = item
+            .name("inputs")
+            .unwrap()
+            .as_str()
+            .split(',')
+            .filter(|x| !x.trim().is_empty())
+            .count();
+        for term in item.name("term").unwrap().as_str().split(", ") {
+            let name = term.split(':').next().unwrap();
+            result.insert(name.to_owned(), Arity { inputs, outputs: 1 });
+        }
+    }
+    result
+}
+
+/// Arity of the op given its text, using `arities` obtained from `get_arities`
+/// for everything which is not covered by special cases.
+pub fn op_arity(arities: &HashMap<String, Arity>, op: &str) -> Option<Arity> {
+    let arity = |inputs, outputs| Some(Arity { inputs, outputs });
+    if op.parse::<Sample>().is_ok() {
+        return arity(0, 1);
+    }
+    let tokens = op.split(':').collect::<Vec<_>>();
+    match tokens[0] {
+        "*" | "mul" | "+" | "add" | "-" | "sub" | "/" | "div" | "^" | "pow" => arity(2, 1),
+        ">" | "<" | ">=" | "<=" | "==" => arity(2, 1),
+        "\\" => arity(1, 1),
+        "dup" => arity(1, 2),
+        "beat" | "beattrack" | "key" => arity(1, 2),
+        "pitch" if tokens.len() > 1 => arity(1, 2),
+        "pop" => arity(1, 0),
+        "grid" => arity(0, GRID_ROWS),
+        // Each takes and leaves as much as its sub-program does.
+        "each[" | "]" => arity(0, 0),
+        // Poly passes note and gate to its sub-program, which leaves one signal.
+        "poly" if block_opening(op) == Some(op.len()) => arity(2, 2),
+        "swap" => arity(2, 2),
+        "rot" => arity(3, 3),
+        "" | "dig" => tokens
+            .get(1)
+            .and_then(|x| x.parse::<usize>().ok())
+            .and_then(|n| arity(n.min(MAX_COUNT), n.min(MAX_COUNT))),
+        "convm" => tokens
+            .get(1)
+            .and_then(|x| x.parse::<usize>().ok())
+            .and_then(|n| arity(n.min(MAX_COUNT) + 1, 1)),
+        "arp" => match tokens.get(2).map(|x| x.parse::<usize>()) {
+            None => arity(5, 1),
+            Some(Ok(n)) => arity(n.min(MAX_COUNT) + 2, 1),
+            // Notes are in the table.
+            Some(Err(_)) => arity(2, 1),
+        },
+        "mtap" => tokens
+            .get(1)
+            .and_then(|x| x.parse::<usize>().ok())
+            .and_then(|n| arity(2 * n.min(MAX_COUNT) + 1, 1)),
+        "envgen" => match tokens.get(1).map(|x| x.parse::<usize>()) {
+            Some(Ok(n)) => arity(3 * n.min(MAX_COUNT) + 1, 1),
+            // Segments are in the table.
+            Some(Err(_)) if !tokens[1].is_empty() => arity(1, 1),
+            _ => None,
+        },
+        name => arities.get(name).copied(),
+    }
+}
+
+/// Arity of the sequence of ops, None if it's unknown for some of them.
+pub fn ops_arity(arities: &HashMap<String, Arity>, ops: &[TextOp]) -> Option<Arity> {
+    let mut result = Arity {
+        inputs: 0,
+        outputs: 0,
+    };
+    for TextOp { op, .. } in ops {
+        let Arity { inputs, outputs } = op_arity(arities, op)?;
+        // Inputs missing from what previous ops left are inputs of the sequence.
+        result.inputs += inputs.saturating_sub(result.outputs);
+        result.outputs = result.outputs.saturating_sub(inputs) + outputs;
+    }
+    Some(result)
+}
+
+/// Whether an op reads or writes the table it names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableAccess {
+    Read,
+    Write,
+}
+
+/// Name of the table the op uses and how, to find readers of a table without writers
+/// and the other way around.
+pub fn table_access(op: &str) -> Option<(&str, TableAccess)> {
+    let tokens = op.split(':').collect::<Vec<_>>();
+    let (name, access) = match tokens[0] {
+        "wt" | "wtab" | "writetable" => (tokens.get(1)?, TableAccess::Write),
+        "rt" | "rtab" | "readtable" | "spectral_filter" | "stretch" => {
+            (tokens.get(1)?, TableAccess::Read)
+        }
+        // Named scales aren't tables.
+        "scale" | "scaleq" if scale(tokens.get(1)?).is_none() => {
+            (tokens.get(1)?, TableAccess::Read)
+        }
+        // Neither are numbers of notes.
+        "arp" if tokens.get(2)?.parse::<usize>().is_err() => (tokens.get(2)?, TableAccess::Read),
+        "envgen" if tokens.get(1)?.parse::<usize>().is_err() => (tokens.get(1)?, TableAccess::Read),
+        _ => return None,
+    };
+    Some((*name, access)).filter(|(name, _)| !name.is_empty())
+}
+
+/// Things ops share by name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shared {
+    Table,
+    Bus,
+}
+
+/// Table or bus the op uses and how, `send` writes to buses and `recv` reads them.
+pub fn shared_access(op: &str) -> Option<(Shared, &str, TableAccess)> {
+    if let Some((name, access)) = table_access(op) {
+        return Some((Shared::Table, name, access));
+    }
+    bus_name(op, "send")
+        .map(|name| (Shared::Bus, name, TableAccess::Write))
+        .or_else(|| bus_name(op, "recv").map(|name| (Shared::Bus, name, TableAccess::Read)))
+}
+
+/// Parse duration parameter in seconds, limited to `MAX_DURATION`.
+fn parse_duration(x: &str, default: Sample) -> Sample {
+    match x.parse::<Sample>() {
+        Ok(x) if x.is_finite() => limit_duration(x),
+        Ok(x) => {
+            log::warn!("Duration {} is not finite, using {} seconds.", x, default);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+fn limit_duration(x: Sample) -> Sample {
+    if x > MAX_DURATION {
+        log::warn!("Duration {} is limited to {} seconds.", x, MAX_DURATION);
+        MAX_DURATION
+    } else if x < 0.0 {
+        log::warn!("Duration {} is limited to 0 seconds.", x);
+        0.0
+    } else {
+        x
+    }
+}
+
+fn limit_count(n: usize) -> usize {
+    if n > MAX_COUNT {
+        log::warn!("Count {} is limited to {}.", n, MAX_COUNT);
+        MAX_COUNT
+    } else {
+        n
+    }
+}
+
 struct Term {
     holes: usize,
     ops: Vec<TextOp>,
@@ -369,6 +1683,48 @@ struct Term {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use audio_vm::{stack::STACK_SIZE, Stack};
+    use rand::Rng;
+
+    #[test]
+    fn op_arity_covers_help_and_special_cases() {
+        let arities = get_arities();
+        let arity = |op| op_arity(&arities, op).map(|a| (a.inputs, a.outputs));
+        assert_eq!(arity("440"), Some((0, 1)));
+        assert_eq!(arity("s"), Some((1, 1)));
+        assert_eq!(arity("pulse"), Some((3, 1)));
+        assert_eq!(arity("silence"), Some((0, 1)));
+        assert_eq!(arity("dl:2"), Some((2, 1)));
+        assert_eq!(arity("wt:foo:1"), Some((2, 1)));
+        assert_eq!(arity("convm:3"), Some((4, 1)));
+        assert_eq!(arity("mtap:3:2"), Some((7, 1)));
+        assert_eq!(arity("envgen:2"), Some((7, 1)));
+        assert_eq!(arity("envgen:shape"), Some((1, 1)));
+        assert_eq!(arity(":3"), Some((3, 3)));
+        assert_eq!(arity("+"), Some((2, 1)));
+        assert_eq!(arity(">="), Some((2, 1)));
+        assert_eq!(arity("not"), Some((1, 1)));
+        assert_eq!(arity("dup"), Some((1, 2)));
+        assert_eq!(arity("foo"), None);
+    }
+
+    #[test]
+    fn rewrite_terms_handles_non_ascii() {
+        let op = |id, op: &str| TextOp {
+            id,
+            op: op.to_string(),
+        };
+        assert_eq!(
+            rewrite_terms(&[
+                op(1, "[?"),
+                op(10, "тон]"),
+                op(100, "звук"),
+                op(1000, "1"),
+                op(10000, "звук"),
+            ]),
+            vec![op(1000, "1"), op(10010, "тон")]
+        );
+    }
 
     #[test]
     fn rewrite_terms_does_its_thing() {
@@ -447,4 +1803,529 @@ mod tests {
             ]
         );
     }
+
+    fn text_ops(program: &str) -> Vec<TextOp> {
+        program
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_string(),
+            })
+            .collect()
+    }
+
+    /// Run the program for a frame per input, which `in` hears. Returns contents of the stack
+    /// after each frame, bottom first.
+    fn play(ctx: &Context, program: &mut Program, inputs: &[Frame]) -> Vec<Vec<Frame>> {
+        let mut stack = Stack::new();
+        inputs
+            .iter()
+            .map(|input| {
+                *ctx.input.lock().unwrap() = *input;
+                stack.reset();
+                for statement in program.iter_mut() {
+                    statement.op.perform(&mut stack);
+                }
+                let mut frames = Vec::new();
+                while !stack.is_empty() {
+                    frames.push(stack.pop());
+                }
+                frames.reverse();
+                frames
+            })
+            .collect()
+    }
+
+    fn silence(frames: usize) -> Vec<Frame> {
+        vec![[0.0; CHANNELS]; frames]
+    }
+
+    /// Compile the next program and carry state over from the previous one, like commit does.
+    fn commit(ctx: &mut Context, sample_rate: u32, text: &str, previous: &Program) -> Program {
+        let mut program = compile_program(&text_ops(text), sample_rate, ctx);
+        for (statement, previous) in program.iter_mut().zip(previous) {
+            statement.op.migrate(&previous.op);
+        }
+        program
+    }
+
+    /// Compile the program with terms rewritten and run it for the number of frames at 48 kHz,
+    /// noise is seeded. Returns contents of the stack after each frame, bottom first.
+    fn render(text: &str, frames: usize) -> Vec<Vec<Frame>> {
+        let mut ctx = Context::new();
+        ctx.seed = Some(0);
+        render_in(&mut ctx, 48000, text, frames)
+    }
+
+    /// Like `render`, for programs which need tables or other state of the context.
+    fn render_in(
+        ctx: &mut Context,
+        sample_rate: u32,
+        text: &str,
+        frames: usize,
+    ) -> Vec<Vec<Frame>> {
+        let mut program = compile_program(&rewrite_terms(&text_ops(text)), sample_rate, ctx);
+        play(ctx, &mut program, &silence(frames))
+    }
+
+    /// Top of the stack after each frame.
+    fn tops(stacks: &[Vec<Frame>]) -> Vec<Sample> {
+        stacks
+            .iter()
+            .map(|stack| stack[stack.len() - 1][0])
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_terms_stops_on_recursive_terms() {
+        let ops = rewrite_terms(&text_ops("[ a a ] a a"));
+        assert!(ops.len() <= MAX_REWRITTEN_OPS);
+        let ops = rewrite_terms(&text_ops("[ 1 ] x? [ x? ] y y"));
+        assert_eq!(ops.len(), 0);
+    }
+
+    #[test]
+    fn compile_program_survives_bad_parameters() {
+        let ops =
+            text_ops("1 2 dig:0 ch:7 0 wt:t:1e30:1e30 rt:t conv:0 1 convm:0 1 revdl:0 dl:inf");
+        let mut ctx = Context::new();
+        let mut vm = audio_vm::VM::new();
+        vm.load_program(compile_program(&ops, 100, &mut ctx));
+        vm.next_frame();
+        assert_eq!(
+            ctx.tables["t"].lock().unwrap().len(),
+            (MAX_DURATION * 100.0) as usize
+        );
+        assert_eq!(
+            op_arity(&get_arities(), "mtap:18446744073709551615").map(|a| a.inputs),
+            Some(2 * MAX_COUNT + 1)
+        );
+    }
+
+    #[test]
+    fn probes_keep_the_top_of_the_stack() {
+        let mut ctx = Context::new();
+        let mut vm = audio_vm::VM::new();
+        vm.set_probes(vec![1, 3]);
+        vm.load_program(compile_program(&text_ops("2 3 * 4 +"), 48000, &mut ctx));
+        vm.next_frame();
+        assert_eq!(vm.probes(), [(1, [3.0; CHANNELS]), (3, [4.0; CHANNELS])]);
+    }
+
+    #[test]
+    fn wet_mixes_with_the_nearest_dry() {
+        // Inner chain mixes 2 and 20, outer one mixes 1 and that.
+        assert_eq!(
+            render("1 dry 2 * dry 10 * 0.5 wet 0.5 wet", 1)[0],
+            [[6.0; CHANNELS]]
+        );
+    }
+
+    #[test]
+    fn solo_plays_the_probed_signal() {
+        assert_eq!(
+            render("2 probe 3 * 4 + solo", 1)[0],
+            [[10.0; CHANNELS], [2.0; CHANNELS]]
+        );
+    }
+
+    #[test]
+    fn tables_survive_commits_and_restarts() {
+        let dir = std::env::temp_dir().join(format!("tables-{}", std::process::id()));
+        let ops = text_ops("0.5 1 wt:t:0.01");
+        let mut ctx = Context::new();
+        ctx.tables_dir = Some(dir.clone());
+        render_in(&mut ctx, 1000, "0.5 1 wt:t:0.01", 10);
+        compile_program(&ops, 1000, &mut ctx);
+        assert_eq!(*ctx.tables["t"].lock().unwrap(), vec![[0.5; CHANNELS]; 10]);
+        // Until the save is done the table stays in snapshots.
+        assert_eq!(snapshot_tables(&mut ctx, 1000).tables.len(), 1);
+        let saved = snapshot_tables(&mut ctx, 1000).save().unwrap();
+        mark_tables_saved(&mut ctx, saved);
+        assert!(snapshot_tables(&mut ctx, 1000).tables.is_empty());
+        let mut ctx = Context::new();
+        ctx.tables_dir = Some(dir.clone());
+        ctx.allow_files = false;
+        compile_program(&ops, 1000, &mut ctx);
+        assert_eq!(*ctx.tables["t"].lock().unwrap(), vec![[0.0; CHANNELS]; 10]);
+        let mut ctx = Context::new();
+        ctx.tables_dir = Some(dir.clone());
+        compile_program(&ops, 1000, &mut ctx);
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(*ctx.tables["t"].lock().unwrap(), vec![[0.5; CHANNELS]; 10]);
+    }
+
+    #[test]
+    fn impulse_responses_are_dropped_with_their_ops() {
+        let path = std::env::temp_dir().join(format!("ir-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        writer.write_sample(1.0f32).unwrap();
+        writer.finalize().unwrap();
+        let convir = format!("0 convir:{}", path.display());
+        let mut ctx = Context::new();
+        compile_program(&text_ops(&convir), 1000, &mut ctx);
+        compile_program(&text_ops(&convir), 1000, &mut ctx);
+        assert_eq!(ctx.impulse_responses.len(), 1);
+        compile_program(&text_ops("0"), 1000, &mut ctx);
+        std::fs::remove_file(path).unwrap();
+        assert!(ctx.impulse_responses.is_empty());
+    }
+
+    #[test]
+    fn envgen_takes_breakpoints_from_the_stack_or_a_table() {
+        let levels = |program: &str| {
+            let mut ctx = Context::new();
+            let breakpoints = [1.0, 0.01, 0.0, 0.5, 0.01, 4.0];
+            ctx.tables.insert(
+                "shape".to_owned(),
+                Arc::new(Mutex::new(
+                    breakpoints.iter().map(|&x| [x; CHANNELS]).collect(),
+                )),
+            );
+            tops(&render_in(&mut ctx, 1000, program, 30))
+        };
+        assert_eq!(
+            levels("1 envgen:shape"),
+            levels("1 1 0.01 0 0.5 0.01 4 envgen:2")
+        );
+    }
+
+    #[test]
+    fn each_processes_channels_independently() {
+        let text = "1 ch:0 3 each[ * 1 swap ] each[n]";
+        assert_eq!(
+            ops_arity(&get_arities(), &rewrite_terms(&text_ops(text)))
+                .map(|a| (a.inputs, a.outputs)),
+            Some((0, 3))
+        );
+        let stack = &render(text, 1)[0];
+        assert_eq!(stack[..2], [[1.0; CHANNELS], [3.0, 0.0]]);
+        let noise = stack[2];
+        assert_ne!(noise[0], noise[1]);
+        assert_eq!(stack.len(), 3);
+    }
+
+    #[test]
+    fn warp_scales_tempo_but_not_pitch() {
+        let mut ctx = Context::new();
+        let mut program = compile_program(&text_ops("bpm 1 beats warp"), 48000, &mut ctx);
+        // Running program follows the transport rate without recompiling.
+        *ctx.warp.lock().unwrap() = 0.5;
+        assert_eq!(
+            play(&ctx, &mut program, &silence(1))[0],
+            [[60.0; CHANNELS], [1.0; CHANNELS], [0.5; CHANNELS]]
+        );
+    }
+
+    #[test]
+    fn beat_sync_sets_the_tempo() {
+        let mut ctx = Context::new();
+        render_in(&mut ctx, 48000, "1.6 m beat:sync pop pop", 10 * 48000);
+        let bpm = *ctx.bpm.lock().unwrap();
+        assert!((bpm - 96.0).abs() < 3.0, "{}", bpm);
+    }
+
+    #[test]
+    fn tails_stretch_reverbs() {
+        // Energy of the tail of an impulse between 0.5 and 0.75 seconds.
+        let tail = |text: &str, tails: Sample| {
+            let mut ctx = Context::new();
+            let mut program = compile_program(&text_ops(text), 48000, &mut ctx);
+            *ctx.tails.lock().unwrap() = tails;
+            let mut inputs = silence(36000);
+            inputs[0] = [1.0; CHANNELS];
+            play(&ctx, &mut program, &inputs)[24000..]
+                .iter()
+                .flat_map(|stack| stack[stack.len() - 1].iter())
+                .map(|x| x * x)
+                .sum::<Sample>()
+        };
+        for text in &["in 0.5 0 0 1 fdn", "in 0.5 0 1 reverb"] {
+            assert!(tail(text, 2.0) > 10.0 * tail(text, 1.0), "{}", text);
+        }
+    }
+
+    #[test]
+    fn buses_sum_sends() {
+        let mut ctx = Context::new();
+        let text = "recv:a 1 0.5 send:a pop 2 0.25 send:a pop recv:a";
+        for (frame, stack) in render_in(&mut ctx, 48000, text, 2).iter().enumerate() {
+            // Sends add up each frame without piling up, recv before them hears the last frame.
+            assert_eq!(*stack, [[frame as Sample; CHANNELS], [1.0; CHANNELS]]);
+        }
+        // Bus without sends goes silent, though the old program could still write to its bus.
+        assert_eq!(
+            render_in(&mut ctx, 48000, "recv:a", 1)[0],
+            [[0.0; CHANNELS]]
+        );
+    }
+
+    #[test]
+    fn scratch_context_keeps_words_and_buses_to_itself() {
+        let mut ctx = Context::new();
+        let ops = rewrite_words(&text_ops(": lead 1 ; lead send:a"), &mut ctx.words);
+        compile_program(&ops, 48000, &mut ctx);
+        let mut scratch = ctx.scratch();
+        let ops = rewrite_words(
+            &text_ops(": pad 2 ; pad send:a pad send:b"),
+            &mut scratch.words,
+        );
+        compile_program(&ops, 48000, &mut scratch);
+        assert!(Arc::ptr_eq(&scratch.buses["a"], &ctx.buses["a"]));
+        assert!(ctx.words.contains_key("lead") && !ctx.words.contains_key("pad"));
+        assert!(!ctx.buses.contains_key("b"));
+    }
+
+    #[test]
+    fn buses_clear_once_per_frame_in_blocks() {
+        // Both channel copies of the send add up, neither of them piles up frames.
+        assert_eq!(
+            tops(&render("1 each[ 1 send:a ] pop recv:a", 2)),
+            [2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn poly_allocates_voices() {
+        // Note is the left channel of the input and gate is the right one.
+        let render = |body: &str, notes: &[(Sample, Sample)]| {
+            let mut ctx = Context::new();
+            let text = format!("in in swspace poly:2:[{}]", body);
+            let ops = rewrite_terms(&text_ops(&text));
+            assert_eq!(
+                ops_arity(&get_arities(), &ops).map(|a| (a.inputs, a.outputs)),
+                Some((0, 1))
+            );
+            let mut program = compile_program(&ops, 48000, &mut ctx);
+            let inputs = notes
+                .iter()
+                .map(|&(note, gate)| [note, gate])
+                .collect::<Vec<_>>();
+            tops(&play(&ctx, &mut program, &inputs))
+        };
+        let notes = [
+            (60.0, 1.0),
+            (62.0, 1.0),
+            (64.0, 1.0),
+            (64.0, 0.0),
+            (65.0, 1.0),
+        ];
+        // Released voices keep their notes, the oldest one takes the next note.
+        assert_eq!(render("pop", &notes), [60.0, 122.0, 126.0, 126.0, 129.0]);
+        // Only the voice of the held note has its gate open.
+        assert_eq!(render("swap pop", &notes), [1.0, 1.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn words_expand_where_they_are_used() {
+        let mut ctx = Context::new();
+        let ops = rewrite_words(
+            &text_ops("3 quad : twice 2 * ; : quad twice twice ; : s 1 ; : 2 1 ;"),
+            &mut ctx.words,
+        );
+        let mut program = compile_program(&ops, 48000, &mut ctx);
+        assert_eq!(play(&ctx, &mut program, &silence(1))[0], [[12.0; CHANNELS]]);
+        // Ops and numbers can't be redefined.
+        assert_eq!(ctx.words.len(), 2);
+        // Words are forgotten once their definitions are gone.
+        let ops = rewrite_words(&text_ops("5 twice"), &mut ctx.words);
+        assert_eq!(ops.len(), 2);
+        assert!(ctx.words.is_empty());
+        // Recursive words stop expanding.
+        let ops = rewrite_words(&text_ops(": a 1 a ; a"), &mut ctx.words);
+        assert_eq!(ops.len(), MAX_REWRITTEN_OPS / 2);
+    }
+
+    #[test]
+    fn text_programs_skip_comments() {
+        let mut ctx = Context::new();
+        let ops = text::parse_ops(": two 2 ; // 3 4\ntwo 1 +//5\n", &mut ctx);
+        let ops = ops.iter().map(|op| op.op.as_str()).collect::<Vec<_>>();
+        assert_eq!(ops, ["2", "1", "+"]);
+        assert_eq!(ctx.words.len(), 1);
+    }
+
+    #[test]
+    fn readers_hear_tables_written_later() {
+        let mut ctx = Context::new();
+        compile_program(&text_ops("0 rt:t"), 1000, &mut ctx);
+        let mut program = compile_program(&text_ops("0 rt:t 0.5 1 wt:t:0.01 pop"), 1000, &mut ctx);
+        assert_eq!(play(&ctx, &mut program, &silence(12))[1], [[0.5; CHANNELS]]);
+        let table = Arc::clone(&ctx.tables["t"]);
+        commit(&mut ctx, 1000, "0 rt:t 0.5 1 wt:t:0.02 pop", &program);
+        // The running program isn't touched, its table is replaced rather than resized.
+        assert_eq!(table.lock().unwrap().len(), 10);
+        let mut frames = vec![[0.5; CHANNELS]; 10];
+        frames.resize(20, [0.0; CHANNELS]);
+        assert_eq!(*ctx.tables["t"].lock().unwrap(), frames);
+    }
+
+    #[test]
+    fn pitchshift_survives_bad_windows() {
+        let text = "1 0 0 pitchshift:0 1 12 0.001 pitchshift:0.001 1 -12 nan pitchshift:-1 \
+                    1 0 1 pitchshift:nan 1 0 0 pitchshift:0:0";
+        let stack = &render_in(&mut Context::new(), 44100, text, 1000)[999];
+        assert_eq!(stack.len(), 5);
+        assert!(stack.iter().flatten().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn pitch_survives_tiny_windows() {
+        let stack = &render("0 pitch:0 1 pitch:1", 64)[63];
+        assert_eq!(stack.len(), 4);
+        assert!(stack.iter().flatten().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn table_access_of_readers_and_writers() {
+        assert_eq!(table_access("wt:a:1"), Some(("a", TableAccess::Write)));
+        assert_eq!(table_access("rt:a"), Some(("a", TableAccess::Read)));
+        assert_eq!(table_access("stretch:a"), Some(("a", TableAccess::Read)));
+        assert_eq!(table_access("spectral_filter"), None);
+        assert_eq!(table_access("meter:a"), None);
+        assert_eq!(table_access("scale:a"), Some(("a", TableAccess::Read)));
+        assert_eq!(table_access("scale:dorian"), None);
+        assert_eq!(table_access("arp:up:a"), Some(("a", TableAccess::Read)));
+        assert_eq!(table_access("arp:up:4"), None);
+        assert_eq!(table_access("envgen:a"), Some(("a", TableAccess::Read)));
+        assert_eq!(table_access("envgen:4"), None);
+        assert_eq!(
+            shared_access("send:a"),
+            Some((Shared::Bus, "a", TableAccess::Write))
+        );
+        assert_eq!(
+            shared_access("recv:a"),
+            Some((Shared::Bus, "a", TableAccess::Read))
+        );
+        assert_eq!(
+            shared_access("rt:a"),
+            Some((Shared::Table, "a", TableAccess::Read))
+        );
+    }
+
+    #[test]
+    fn meters_publish_levels_by_name() {
+        let mut ctx = Context::new();
+        let mut program = compile_program(&text_ops("0.5 meter:a"), 48000, &mut ctx);
+        let levels = Arc::clone(&ctx.telemetry.lock().unwrap()["a"]);
+        // RMS settles in several of its 300 ms.
+        let stacks = play(&ctx, &mut program, &silence(3 * 48000));
+        assert!(stacks.iter().all(|stack| stack == &[[0.5; CHANNELS]]));
+        assert_eq!(levels.peak(), [0.5; CHANNELS]);
+        assert!((levels.rms()[0] - 0.5).abs() < 1e-3);
+        // Recompiling keeps levels of the same name and drops the missing ones.
+        compile_program(&text_ops("0.5 meter:a 0.5 meter:b"), 48000, &mut ctx);
+        assert!(Arc::ptr_eq(&levels, &ctx.telemetry.lock().unwrap()["a"]));
+        compile_program(&text_ops("0.5 meter:b"), 48000, &mut ctx);
+        assert!(!ctx.telemetry.lock().unwrap().contains_key("a"));
+    }
+
+    /// Every op from the help performs on stacks of any depth with any contents without
+    /// panicking and leaves as many frames as its arity says.
+    #[test]
+    fn ops_keep_stack_balanced() {
+        let arities = get_arities();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut ctx = Context::new();
+        ctx.allow_files = false;
+        // Table readers need a table to read.
+        compile_program(&text_ops("0 0 wt:t:1"), 48000, &mut ctx);
+        let values = [
+            0.0,
+            1.0,
+            -1.0,
+            0.5,
+            440.0,
+            1e9,
+            -1e9,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        for term in get_op_groups().into_iter().flat_map(|(_, terms)| terms) {
+            let op = term
+                .replace("<N>", "1")
+                .replace("<M>", "1")
+                .replace("<RATIO>", "2")
+                .replace("<NAME>", "t")
+                .replace("<MODE>", "lp")
+                .replace("<CURVE>", "tanh")
+                .replace("<PATH>", "ir.wav")
+                .replace("<T>", "0.2")
+                .replace("<F>", "50")
+                .replace("<PATTERN>", "updown")
+                .replace("<FLAG>", "sync");
+            let arity = op_arity(&arities, &op).unwrap_or_else(|| panic!("No arity of {}", op));
+            let mut program = compile_program(&text_ops(&op), 48000, &mut ctx);
+            if op.starts_with("convir") {
+                // Reading files is not allowed.
+                assert!(program.is_empty());
+                continue;
+            }
+            assert_eq!(program.len(), 1, "{} doesn't compile", op);
+            let mut stack = Stack::new();
+            for _ in 0..4096 {
+                stack.reset();
+                let depth = rng.gen_range(0, STACK_SIZE + 1);
+                for _ in 0..depth {
+                    let mut frame = [0.0; CHANNELS];
+                    for x in frame.iter_mut() {
+                        *x = if rng.gen_bool(0.1) {
+                            *values.choose(&mut rng).unwrap()
+                        } else {
+                            rng.gen_range(-1.0, 1.0)
+                        };
+                    }
+                    stack.push(&frame);
+                }
+                program[0].op.perform(&mut stack);
+                let expected = (depth.saturating_sub(arity.inputs) + arity.outputs).min(STACK_SIZE);
+                assert_eq!(stack.len(), expected, "{} at depth {}", op, depth);
+            }
+        }
+    }
+
+    /// Ops with state fail safe on NaN parameters: nothing sticks in the state which the next
+    /// program takes over.
+    #[test]
+    fn ops_survive_nan_parameters() {
+        let arities = get_arities();
+        let finite =
+            |stacks: Vec<Vec<Frame>>| stacks.iter().flatten().flatten().all(|x| x.is_finite());
+        let ops = [
+            "fbcomb",
+            "pingpong",
+            "tape_delay",
+            "fb:1",
+            "phaser",
+            "chorus",
+            "crush",
+            "bow",
+            "blow",
+            "modal",
+            "macro",
+            "width",
+            "adsr",
+            "denoise",
+            "glue",
+            "fdn",
+            "reverb",
+        ];
+        for &op in &ops {
+            let inputs = op_arity(&arities, op).unwrap().inputs;
+            // Input is 1, parameters are all the same.
+            let text = |x| format!("1 {} {}", vec![x; inputs - 1].join(" "), op);
+            let mut ctx = Context::new();
+            let mut program = compile_program(&text_ops(&text("nan")), 1000, &mut ctx);
+            play(&ctx, &mut program, &silence(100));
+            let mut next = commit(&mut ctx, 1000, &text("0.5"), &program);
+            assert!(finite(play(&ctx, &mut next, &silence(100))), "{}", op);
+        }
+    }
 }
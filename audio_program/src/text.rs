@@ -0,0 +1,25 @@
+//! Programs as plain text, like the ones players read from files: ops are separated by
+//! whitespace and `//` comments out the rest of the line.
+use crate::{compile_program, rewrite_terms, rewrite_words, Context, TextOp};
+use audio_vm::Program;
+
+/// Ops of the text with terms rewritten and words expanded, definitions of words go to
+/// `ctx.words`. Ids are positions of ops in the text.
+pub fn parse_ops(text: &str, ctx: &mut Context) -> Vec<TextOp> {
+    let ops = text
+        .split_terminator('\n')
+        .flat_map(|s| s.splitn(2, "//").take(1).flat_map(|s| s.split_whitespace()))
+        .enumerate()
+        .map(|(id, op)| TextOp {
+            id: id as u64,
+            op: op.to_string(),
+        })
+        .collect::<Vec<_>>();
+    rewrite_words(&rewrite_terms(&ops), &mut ctx.words)
+}
+
+/// Compile the program text, see `parse_ops`.
+pub fn parse_program(text: &str, sample_rate: u32, ctx: &mut Context) -> Program {
+    let ops = parse_ops(text, ctx);
+    compile_program(&ops, sample_rate, ctx)
+}
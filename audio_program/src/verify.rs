@@ -0,0 +1,100 @@
+//! Reproducible renders to protect finished pieces from being silently changed, e.g. by an
+//! upgrade of ops. Random ops are seeded, and the hash covers exact bits of every rendered sample.
+use crate::{compile_program, Context, TextOp};
+use audio_vm::VM;
+use fasthash::sea::Hash64;
+use std::hash::{BuildHasher, Hasher};
+
+/// Random ops of verification renders are seeded with it.
+pub const SEED: u64 = 0;
+
+/// What a piece rendered to, stored as `<sample rate> <frames> <hash>` text to compare
+/// later renders with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub sample_rate: u32,
+    pub frames: usize,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    /// Render the program and hash the output.
+    pub fn render(ops: &[TextOp], sample_rate: u32, frames: usize) -> Self {
+        let mut ctx = Context::new();
+        ctx.seed = Some(SEED);
+        let mut vm = VM::new();
+        vm.load_program(compile_program(ops, sample_rate, &mut ctx));
+        let mut hasher = Hash64.build_hasher();
+        for _ in 0..frames {
+            for x in &vm.next_frame() {
+                hasher.write_u64(x.to_bits());
+            }
+        }
+        Fingerprint {
+            sample_rate,
+            frames,
+            hash: hasher.finish(),
+        }
+    }
+}
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {:016x}", self.sample_rate, self.frames, self.hash)
+    }
+}
+
+impl std::str::FromStr for Fingerprint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Can't parse {:?} as <sample rate> <frames> <hash>.", s);
+        let mut tokens = s.split_whitespace();
+        let mut next = || tokens.next().ok_or_else(error);
+        let sample_rate = next()?.parse().map_err(|_| error())?;
+        let frames = next()?.parse().map_err(|_| error())?;
+        let hash = u64::from_str_radix(next()?, 16).map_err(|_| error())?;
+        Ok(Fingerprint {
+            sample_rate,
+            frames,
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(program: &str) -> Vec<TextOp> {
+        program
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_is_reproducible() {
+        let a = Fingerprint::render(&ops("n 440 s +"), 48000, 4800);
+        let b = Fingerprint::render(&ops("n 440 s +"), 48000, 4800);
+        let c = Fingerprint::render(&ops("n 441 s +"), 48000, 4800);
+        assert_eq!(a, b);
+        assert_ne!(a.hash, c.hash);
+    }
+
+    #[test]
+    fn fingerprint_round_trips() {
+        let fingerprint = Fingerprint {
+            sample_rate: 48000,
+            frames: 480000,
+            hash: 0x00ab_cdef_0123_4567,
+        };
+        assert_eq!(fingerprint.to_string(), "48000 480000 00abcdef01234567");
+        assert_eq!(fingerprint.to_string().parse(), Ok(fingerprint));
+        assert!("48000 480000".parse::<Fingerprint>().is_err());
+    }
+}
@@ -1,11 +1,13 @@
 pub mod op;
 pub mod sample;
 pub mod stack;
+pub mod telemetry;
 pub mod vm;
 
 pub use self::{
     op::Op,
     sample::{Frame, Sample, CHANNELS},
     stack::Stack,
+    telemetry::{Levels, Telemetry},
     vm::{Program, Statement, VM},
 };
@@ -54,6 +54,17 @@ impl Stack {
         self.top = new_top;
         frame
     }
+
+    /// Number of frames on the stack.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.top / CHANNELS
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.top == 0
+    }
 }
 
 impl Default for Stack {
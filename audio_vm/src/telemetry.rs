@@ -0,0 +1,53 @@
+use crate::sample::{Frame, Sample, CHANNELS};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Levels published by meters by their names. The map is changed only when programs are
+/// compiled, audio thread holds meters it writes to and never takes the lock.
+pub type Telemetry = Arc<Mutex<HashMap<String, Arc<Levels>>>>;
+
+/// Running RMS and peak of each channel. Samples are stored as bits of atomics,
+/// so the audio thread could publish them and anyone could read them without locks.
+pub struct Levels {
+    rms: [AtomicU64; CHANNELS],
+    peak: [AtomicU64; CHANNELS],
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Levels {
+            rms: Default::default(),
+            peak: Default::default(),
+        }
+    }
+
+    pub fn publish(&self, rms: Frame, peak: Frame) {
+        for channel in 0..CHANNELS {
+            self.rms[channel].store(rms[channel].to_bits(), Ordering::Relaxed);
+            self.peak[channel].store(peak[channel].to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn rms(&self) -> Frame {
+        load(&self.rms)
+    }
+
+    pub fn peak(&self) -> Frame {
+        load(&self.peak)
+    }
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels::new()
+    }
+}
+
+fn load(xs: &[AtomicU64; CHANNELS]) -> Frame {
+    let mut frame = [0.0; CHANNELS];
+    for (y, x) in frame.iter_mut().zip(xs) {
+        *y = Sample::from_bits(x.load(Ordering::Relaxed));
+    }
+    frame
+}
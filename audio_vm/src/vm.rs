@@ -1,5 +1,5 @@
 use crate::op::Op;
-use crate::sample::{Frame, Sample};
+use crate::sample::{Frame, Sample, CHANNELS};
 use crate::stack::Stack;
 use smallvec::SmallVec;
 
@@ -27,6 +27,19 @@ pub struct VM {
     pause_countdown: Sample,
     /// |> / ||
     status: Status,
+    /// Rolling recording of the output to scrub through.
+    history: Vec<Frame>,
+    /// Index in history to write the next frame at.
+    history_cursor: usize,
+    /// How many frames back from the history cursor scrubbing playback is.
+    /// Live synthesis is frozen while scrubbing.
+    scrub_offset: Option<usize>,
+    /// Coefficient of the high-pass filter which blocks DC at the output, if enabled.
+    dc_block: Option<Sample>,
+    /// Previous input and output of the DC blocker.
+    dc_block_state: (Frame, Frame),
+    /// Ids of statements of the active program and the top of the stack after them.
+    probes: Vec<(u64, Frame)>,
 }
 
 impl VM {
@@ -38,6 +51,12 @@ impl VM {
             xfade_duration: 2048.0,
             pause_countdown: 0.0,
             status: Status::Play,
+            history: Vec::new(),
+            history_cursor: 0,
+            scrub_offset: None,
+            dc_block: None,
+            dc_block_state: Default::default(),
+            probes: Vec::new(),
         }
     }
 
@@ -67,6 +86,25 @@ impl VM {
         self.xfade_duration = frames;
     }
 
+    /// Enable one-pole high-pass filter at the output to protect speakers from DC offset
+    /// accumulated by feedback-heavy programs. Time constant is given in frames, it is
+    /// sample_rate / (2 * pi * cut_off_frequency). None disables filter.
+    pub fn set_dc_block(&mut self, frames: Option<Sample>) {
+        self.dc_block = frames.map(|frames| frames / (frames + 1.0));
+    }
+
+    /// Keep the top of the stack after statements with the given ids, to show live values.
+    /// Returns previous probes so they could be deallocated somewhere else.
+    pub fn set_probes(&mut self, ids: Vec<u64>) -> Vec<(u64, Frame)> {
+        let probes = ids.into_iter().map(|id| (id, [0.0; CHANNELS])).collect();
+        std::mem::replace(&mut self.probes, probes)
+    }
+
+    /// Ids of probed statements along with the top of the stack after them in the last frame.
+    pub fn probes(&self) -> &[(u64, Frame)] {
+        &self.probes
+    }
+
     /// Load the new program and crossfade to it from the previous one.
     /// Returns previous value of previous program so it could be deallocated
     /// somewhere else.
@@ -88,16 +126,63 @@ impl VM {
         garbage
     }
 
+    /// Use the given buffer for the rolling recording of the output.
+    /// Its length determines how far back in time scrubbing could go.
+    /// Returns previous buffer so it could be deallocated somewhere else.
+    pub fn set_history(&mut self, history: Vec<Frame>) -> Vec<Frame> {
+        self.history_cursor = 0;
+        self.scrub_offset = None;
+        std::mem::replace(&mut self.history, history)
+    }
+
+    /// Move scrubbing playback `frames` back in history, freezing live synthesis.
+    pub fn scrub_back(&mut self, frames: usize) {
+        let offset = self.scrub_offset.unwrap_or(0) + frames;
+        self.scrub_offset = Some(offset.min(self.history.len()));
+    }
+
+    /// Move scrubbing playback `frames` forward in history.
+    /// Live synthesis resumes when playback reaches the present.
+    pub fn scrub_forward(&mut self, frames: usize) {
+        self.scrub_offset = self
+            .scrub_offset
+            .map(|offset| offset.saturating_sub(frames))
+            .filter(|&offset| offset > 0);
+    }
+
+    /// How many frames back in history scrubbing playback is, if it is active.
+    pub fn scrub_offset(&self) -> Option<usize> {
+        self.scrub_offset
+    }
+
     pub fn next_frame(&mut self) -> Frame {
+        if let Some(offset) = self.scrub_offset {
+            if offset > 0 {
+                let len = self.history.len();
+                self.scrub_offset = Some(offset - 1);
+                return self.history[(self.history_cursor + len - offset) % len];
+            }
+            self.scrub_offset = None;
+        }
+        let frame = self.synthesize();
+        let frame = self.dc_block(frame);
+        if let Some(x) = self.history.get_mut(self.history_cursor) {
+            *x = frame;
+            self.history_cursor = (self.history_cursor + 1) % self.history.len();
+        }
+        frame
+    }
+
+    fn synthesize(&mut self) -> Frame {
         match self.status {
             Status::Play => {
-                let frame = perform(&mut self.active_program);
+                let frame = perform(&mut self.active_program, &mut self.probes);
                 self.xfade(frame);
                 self.play_xfade(frame)
             }
             Status::Pause => {
                 if self.pause_countdown > 0.0 {
-                    let frame = perform(&mut self.active_program);
+                    let frame = perform(&mut self.active_program, &mut self.probes);
                     self.xfade(frame);
                     self.pause_xfade(frame)
                 } else {
@@ -107,6 +192,23 @@ impl VM {
         }
     }
 
+    #[inline]
+    fn dc_block(&mut self, mut frame: Frame) -> Frame {
+        if let Some(a) = self.dc_block {
+            let (x_prime, output) = &mut self.dc_block_state;
+            for ((x, x_prime), output) in frame
+                .iter_mut()
+                .zip(x_prime.iter_mut())
+                .zip(output.iter_mut())
+            {
+                *output = a * (*output + *x - *x_prime);
+                *x_prime = *x;
+                *x = *output;
+            }
+        }
+        frame
+    }
+
     #[inline]
     fn xfade(&mut self, mut frame: Frame) -> Frame {
         if self.xfade_countdown > 0.0 {
@@ -114,7 +216,7 @@ impl VM {
             self.xfade_countdown -= 1.0;
             for (x, &p) in frame
                 .iter_mut()
-                .zip(perform(&mut self.previous_program).iter())
+                .zip(perform(&mut self.previous_program, &mut []).iter())
             {
                 *x *= 1.0 - progress;
                 *x += p * progress;
@@ -147,10 +249,15 @@ impl VM {
 }
 
 #[inline]
-fn perform(program: &mut Program) -> Frame {
+fn perform(program: &mut Program, probes: &mut [(u64, Frame)]) -> Frame {
     let mut stack = Stack::new();
     for stmt in program {
         stmt.op.perform(&mut stack);
+        for (id, frame) in probes.iter_mut() {
+            if *id == stmt.id {
+                *frame = stack.peek();
+            }
+        }
     }
     stack.peek()
 }
@@ -159,3 +266,42 @@ enum Status {
     Play,
     Pause,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source which pushes 1, 2, 3 and so on.
+    struct Counter(Sample);
+
+    impl Op for Counter {
+        fn perform(&mut self, stack: &mut Stack) {
+            self.0 += 1.0;
+            stack.push(&[self.0; CHANNELS]);
+        }
+    }
+
+    #[test]
+    fn scrubbing_plays_history_and_returns_to_live() {
+        let mut vm = VM::new();
+        vm.set_xfade_duration(0.0);
+        vm.set_history(vec![[0.0; CHANNELS]; 4]);
+        let mut program = Program::new();
+        program.push(Statement {
+            id: 0,
+            op: Box::new(Counter(0.0)),
+        });
+        vm.load_program(program);
+        for _ in 0..6 {
+            vm.next_frame();
+        }
+        // History is as long as it goes.
+        vm.scrub_back(10);
+        assert_eq!(vm.scrub_offset(), Some(4));
+        vm.scrub_forward(1);
+        let frames = (0..4).map(|_| vm.next_frame()[0]).collect::<Vec<_>>();
+        // Live synthesis is frozen while scrubbing and resumes where it stopped.
+        assert_eq!(frames, [4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(vm.scrub_offset(), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! Previous versions of saved gardens, kept next to them as <path>.1, <path>.2, …
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Shift <path>.1 to <path>.2 and so on, removing the oldest first, and copy the garden to
+/// <path>.1. Copying keeps the garden in place if saving fails afterwards. Directories of
+/// plain-text gardens are copied file by file.
+pub fn rotate(path: &Path, count: usize) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+    let backup = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let oldest = backup(count);
+    if oldest.is_dir() {
+        std::fs::remove_dir_all(&oldest)?;
+    } else if oldest.exists() {
+        std::fs::remove_file(&oldest)?;
+    }
+    for n in (1..count).rev() {
+        if backup(n).exists() {
+            std::fs::rename(backup(n), backup(n + 1))?;
+        }
+    }
+    if path.is_dir() {
+        std::fs::create_dir_all(backup(1))?;
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+            if let (true, Some(name)) = (file.is_file(), file.file_name()) {
+                std::fs::copy(&file, backup(1).join(name))?;
+            }
+        }
+    } else {
+        std::fs::copy(path, backup(1))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_keeps_the_latest_versions() {
+        let dir = std::env::temp_dir().join(format!("backups-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("garden.sg");
+        let garden = dir.join("garden");
+        std::fs::create_dir_all(&garden).unwrap();
+        for version in 1..=4 {
+            std::fs::write(&file, version.to_string()).unwrap();
+            std::fs::write(garden.join("moss.plant"), version.to_string()).unwrap();
+            rotate(&file, 2).unwrap();
+            rotate(&garden, 2).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(dir.join("garden.sg.1")), "4");
+        assert_eq!(read(dir.join("garden.sg.2")), "3");
+        assert!(!dir.join("garden.sg.3").exists());
+        assert_eq!(read(dir.join("garden.1").join("moss.plant")), "4");
+        assert_eq!(read(dir.join("garden.2").join("moss.plant")), "3");
+        assert!(!dir.join("garden.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
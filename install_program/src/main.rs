@@ -0,0 +1,138 @@
+//! Play a saved garden forever on a headless device, e.g. a Raspberry Pi running a sound
+//! installation.
+//!
+//! The program is fixed at start and there is no UI. The process supervises a worker copy of
+//! itself and restarts it when it dies or its audio stream fails or stalls, e.g. when the USB
+//! interface glitches. With the gpio feature levels of the given GPIO pins are fed to the input
+//! channels, so `in` and `ch:<N>` read them as gates.
+use audio_program::{text::parse_program, Context};
+#[cfg(feature = "gpio")]
+use audio_vm::CHANNELS;
+use audio_vm::{Frame, VM};
+use play_program::Output;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WORKER_FLAG: &str = "--worker";
+/// Worker exits if the audio stream doesn't progress during this time.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause before restarting the worker to not spin when the device is missing.
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+#[cfg(feature = "gpio")]
+const GPIO_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() {
+        eprintln!("Usage: install_program <garden or program file> [GPIO pins...]");
+        std::process::exit(1);
+    }
+    if args[0] == WORKER_FLAG {
+        work(&args[1..]);
+    } else {
+        supervise(&args);
+    }
+}
+
+fn supervise(args: &[String]) {
+    let exe = std::env::current_exe().expect("Failed to locate the executable.");
+    loop {
+        match Command::new(&exe).arg(WORKER_FLAG).args(args).status() {
+            Ok(status) => eprintln!("Worker exited with {}, restarting.", status),
+            Err(err) => eprintln!("Failed to start worker: {}, retrying.", err),
+        }
+        std::thread::sleep(RESTART_DELAY);
+    }
+}
+
+fn work(args: &[String]) {
+    let text = std::fs::read_to_string(&args[0]).expect("Failed to read the program.");
+    let pins = args[1..]
+        .iter()
+        .map(|pin| pin.parse::<u8>().expect("Failed to parse GPIO pin number."))
+        .collect::<Vec<_>>();
+    let mut ctx = Context::new();
+    start_gpio(&pins, Arc::clone(&ctx.input));
+    let frames = Arc::new(AtomicUsize::new(0));
+    {
+        let frames = Arc::clone(&frames);
+        // The audio thread never returns, it's stopped by exit.
+        std::thread::spawn(move || play(&program_text(&text), &mut ctx, &frames));
+    }
+    let mut last = 0;
+    loop {
+        std::thread::sleep(WATCHDOG_TIMEOUT);
+        let current = frames.load(Ordering::Relaxed);
+        if current == last {
+            eprintln!("Audio stream stalled.");
+            std::process::exit(1);
+        }
+        last = current;
+    }
+}
+
+fn play(text: &str, ctx: &mut Context, frames: &AtomicUsize) {
+    let output = Output::default_device();
+
+    let mut vm = VM::new();
+    vm.load_program(parse_program(text, output.sample_rate(), ctx));
+
+    output.run(
+        move || {
+            frames.fetch_add(1, Ordering::Relaxed);
+            let mut frame = vm.next_frame();
+            for x in frame.iter_mut() {
+                *x = x.clamp(-1.0, 1.0);
+            }
+            frame
+        },
+        |err| match err {
+            cpal::StreamError::DeviceNotAvailable => {
+                eprintln!("Device is not available.");
+                std::process::exit(1);
+            }
+            err => eprintln!("an error occurred on stream: {}", err),
+        },
+    )
+}
+
+/// Gardens saved by sound_garden_terminal keep the committed program,
+/// anything else is taken as the program text.
+fn program_text(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|garden| garden.get("program")?.as_str().map(str::to_owned))
+        .unwrap_or_else(|| text.to_owned())
+}
+
+/// Mirror levels of GPIO pins to input channels, the first pin to the first channel and so on.
+#[cfg(feature = "gpio")]
+fn start_gpio(pins: &[u8], input: Arc<Mutex<Frame>>) {
+    let gpio = rppal::gpio::Gpio::new().expect("Failed to access GPIO.");
+    let pins = pins
+        .iter()
+        .take(CHANNELS)
+        .map(|&pin| {
+            gpio.get(pin)
+                .expect("Failed to get GPIO pin.")
+                .into_input_pulldown()
+        })
+        .collect::<Vec<_>>();
+    std::thread::spawn(move || loop {
+        let mut frame = [0.0; CHANNELS];
+        for (x, pin) in frame.iter_mut().zip(&pins) {
+            *x = if pin.is_high() { 1.0 } else { 0.0 };
+        }
+        *input.lock().unwrap() = frame;
+        std::thread::sleep(GPIO_POLL_INTERVAL);
+    });
+}
+
+#[cfg(not(feature = "gpio"))]
+fn start_gpio(pins: &[u8], _input: Arc<Mutex<Frame>>) {
+    if !pins.is_empty() {
+        eprintln!("Built without gpio feature, ignoring GPIO pins.");
+    }
+}
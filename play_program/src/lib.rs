@@ -0,0 +1,85 @@
+//! Output of players to the default device, shared by play_program and install_program.
+use audio_vm::{Frame, Sample};
+use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
+
+pub struct Output {
+    host: cpal::Host,
+    device: cpal::Device,
+    format: cpal::Format,
+}
+
+impl Output {
+    /// The default output device in its default format.
+    pub fn default_device() -> Self {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .expect("Failed to get default output device");
+        let format = device
+            .default_output_format()
+            .expect("Failed to get default output format");
+        Output {
+            host,
+            device,
+            format,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.format.sample_rate.0
+    }
+
+    /// Play frames from `next_frame` forever, stream errors go to `on_error`.
+    pub fn run<F, E>(self, mut next_frame: F, mut on_error: E) -> !
+    where
+        F: FnMut() -> Frame + Send,
+        E: FnMut(cpal::StreamError) + Send,
+    {
+        let event_loop = self.host.event_loop();
+        let stream_id = event_loop
+            .build_output_stream(&self.device, &self.format)
+            .unwrap();
+        event_loop.play_stream(stream_id).unwrap();
+
+        let channels = self.format.channels as usize;
+        event_loop.run(move |_, result| {
+            let data = match result {
+                Ok(data) => data,
+                Err(err) => {
+                    on_error(err);
+                    return;
+                }
+            };
+            match data {
+                cpal::StreamData::Output {
+                    buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
+                } => {
+                    for frame in buffer.chunks_mut(channels) {
+                        for (out, &sample) in frame.iter_mut().zip(&next_frame()) {
+                            *out = ((sample * 0.5 + 0.5) * std::u16::MAX as Sample) as u16;
+                        }
+                    }
+                }
+                cpal::StreamData::Output {
+                    buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
+                } => {
+                    for frame in buffer.chunks_mut(channels) {
+                        for (out, &sample) in frame.iter_mut().zip(&next_frame()) {
+                            *out = (sample * std::i16::MAX as Sample) as i16;
+                        }
+                    }
+                }
+                cpal::StreamData::Output {
+                    buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
+                } => {
+                    for frame in buffer.chunks_mut(channels) {
+                        for (out, &sample) in frame.iter_mut().zip(&next_frame()) {
+                            *out = sample as f32;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        })
+    }
+}
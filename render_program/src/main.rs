@@ -1,5 +1,9 @@
-use audio_program::{compile_program, rewrite_terms, Context, TextOp};
-use audio_vm::{Program, Sample, CHANNELS, VM};
+use audio_program::{
+    text::{parse_ops, parse_program},
+    verify::Fingerprint,
+    Context,
+};
+use audio_vm::{Sample, CHANNELS, VM};
 use hound::{SampleFormat, WavSpec, WavWriter};
 use std::io::Read;
 
@@ -9,7 +13,12 @@ fn main() {
         .read_to_string(&mut text)
         .expect("Failed to read stdin");
 
-    let mut args = std::env::args().skip(1);
+    let mut args = std::env::args().skip(1).peekable();
+
+    let verifying = args.peek().map(String::as_str) == Some("--verify");
+    if verifying {
+        args.next();
+    }
 
     let duration = args
         .next()
@@ -19,6 +28,11 @@ fn main() {
 
     let sample_rate: u32 = 48000;
 
+    if verifying {
+        verify(&text, sample_rate, duration, &output);
+        return;
+    }
+
     let spec = WavSpec {
         channels: CHANNELS as _,
         sample_rate,
@@ -29,7 +43,7 @@ fn main() {
     let mut writer = WavWriter::create(output, spec).expect("Failed to create a file.");
 
     let mut vm = VM::new();
-    vm.load_program(parse_program(&text, sample_rate));
+    vm.load_program(parse_program(&text, sample_rate, &mut Context::new()));
 
     for _ in 0..((duration * (sample_rate as f64)) as _) {
         for &sample in &vm.next_frame() {
@@ -41,18 +55,34 @@ fn main() {
     }
 }
 
-fn parse_program(s: &str, sample_rate: u32) -> Program {
-    let ops = s
-        .split_terminator('\n')
-        .flat_map(|s| s.splitn(2, "//").take(1).flat_map(|s| s.split_whitespace()))
-        .enumerate()
-        .map(|(id, op)| TextOp {
-            id: id as u64,
-            op: op.to_string(),
-        })
-        .collect::<Vec<_>>();
-    let ops = rewrite_terms(&ops);
-    compile_program(&ops, sample_rate, &mut Context::new())
+/// Render the program twice to check that it's reproducible, then compare the result with the
+/// fingerprint stored at path, or store it there if there is none yet.
+fn verify(text: &str, sample_rate: u32, duration: f64, path: &str) {
+    let ops = parse_ops(text, &mut Context::new());
+    let expected = std::fs::read_to_string(path).ok().map(|s| {
+        s.parse::<Fingerprint>()
+            .expect("Failed to parse the fingerprint.")
+    });
+    let (sample_rate, frames) = match expected {
+        Some(expected) => (expected.sample_rate, expected.frames),
+        None => (sample_rate, (duration * (sample_rate as f64)) as _),
+    };
+    let actual = Fingerprint::render(&ops, sample_rate, frames);
+    if Fingerprint::render(&ops, sample_rate, frames) != actual {
+        eprintln!("Program renders differently each time, it can't be verified.");
+        std::process::exit(1);
+    }
+    match expected {
+        Some(expected) if expected == actual => println!("OK {}", actual),
+        Some(expected) => {
+            eprintln!("Expected {}, but got {}.", expected, actual);
+            std::process::exit(1);
+        }
+        None => {
+            std::fs::write(path, format!("{}\n", actual)).expect("Failed to write a file.");
+            println!("Stored {}", actual);
+        }
+    }
 }
 
 fn clip(sample: Sample) -> Sample {
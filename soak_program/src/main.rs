@@ -0,0 +1,174 @@
+//! Soak test of the engine for installations: runs random programs built from ops in the help
+//! through one VM for hours, hot-swapping them like live coding does, and reports programs which
+//! panic or blow up to NaN or infinity, and growth of memory over the baseline.
+use audio_program::{
+    compile_program, get_arities, get_op_groups, op_arity, Arity, Context, TextOp,
+};
+use audio_vm::{Sample, VM};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 48000;
+/// How long (in seconds of audio) each program runs before the next one replaces it.
+const PROGRAM_DURATION: usize = 10;
+const MAX_PROGRAM_SIZE: usize = 32;
+/// Memory use is measured after these programs to let allocators and tables settle.
+const WARMUP_PROGRAMS: usize = 16;
+const MAX_MEMORY_GROWTH: usize = 256 * 1024 * 1024;
+/// Inputs of ops include extremes to provoke instability.
+const CONSTANTS: &[Sample] = &[
+    0.0, 1.0, -1.0, 0.5, 2.0, 3.0, 0.001, 60.0, 440.0, 20000.0, 1e6, -1e6,
+];
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let hours = args
+        .next()
+        .and_then(|x| x.parse::<f64>().ok())
+        .expect("Please provide duration in hours.");
+    let seed = args
+        .next()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or_else(random);
+    println!("Seed {}", seed);
+
+    let deadline = Instant::now() + Duration::from_secs_f64(hours * 3600.0);
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let terms = get_terms();
+    let arities = get_arities();
+    let mut ctx = Context::new();
+    ctx.allow_files = false;
+    ctx.seed = Some(seed);
+    let mut vm = VM::new();
+
+    let mut programs = 0;
+    let mut failures = 0;
+    let mut baseline = None;
+    let mut last_progress = Instant::now();
+    while Instant::now() < deadline {
+        let text = generate_program(&mut rng, &terms, &arities);
+        let ops = text
+            .split_whitespace()
+            .enumerate()
+            .map(|(id, op)| TextOp {
+                id: id as u64,
+                op: op.to_owned(),
+            })
+            .collect::<Vec<_>>();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            vm.load_program(compile_program(&ops, SAMPLE_RATE, &mut ctx));
+            for _ in 0..PROGRAM_DURATION * SAMPLE_RATE as usize {
+                let frame = vm.next_frame();
+                if frame.iter().any(|x| !x.is_finite()) {
+                    return Err(format!("{:?}", frame));
+                }
+            }
+            Ok(())
+        }));
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(frame)) => {
+                failures += 1;
+                println!("NaN {} -> {}", text, frame);
+                // Silence the program to not carry non-finite state over to the next one.
+                vm = VM::new();
+            }
+            Err(_) => {
+                failures += 1;
+                println!("PANIC {}", text);
+                // Program could be left half-performed, start over.
+                vm = VM::new();
+            }
+        }
+        programs += 1;
+
+        let memory = resident_memory();
+        if programs == WARMUP_PROGRAMS {
+            baseline = memory;
+        }
+        if let (Some(baseline), Some(memory)) = (baseline, memory) {
+            if memory > baseline + MAX_MEMORY_GROWTH {
+                println!(
+                    "MEMORY grew from {} to {} bytes after {}",
+                    baseline, memory, text
+                );
+                std::process::exit(1);
+            }
+        }
+        if last_progress.elapsed() > PROGRESS_INTERVAL {
+            println!(
+                "{} programs, {} failures, {} bytes resident",
+                programs,
+                failures,
+                memory.unwrap_or(0)
+            );
+            last_progress = Instant::now();
+        }
+    }
+
+    println!("{} programs, {} failures", programs, failures);
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Ops from the help with numeric parameters filled in by `generate_program`.
+/// Ops which take file paths or named modes are used through their plain aliases only.
+fn get_terms() -> Vec<String> {
+    get_op_groups()
+        .into_iter()
+        .flat_map(|(_, terms)| terms)
+        .filter(|term| {
+            term.split(':')
+                .skip(1)
+                .all(|p| ["<N>", "<M>", "<RATIO>", "<NAME>"].contains(&p))
+        })
+        .collect()
+}
+
+/// Build a program which never takes more from the stack than previous ops put there,
+/// filling parameters with small values so delays and windows stay cheap.
+fn generate_program(
+    rng: &mut SmallRng,
+    terms: &[String],
+    arities: &HashMap<String, Arity>,
+) -> String {
+    let size = rng.gen_range(1, MAX_PROGRAM_SIZE + 1);
+    let mut ops = Vec::new();
+    let mut depth = 0;
+    while ops.len() < size || depth == 0 {
+        let term = terms.choose(rng).unwrap();
+        let op = term
+            .split(':')
+            .map(|p| match p {
+                // All tables share the name to let readers hear writers.
+                "<NAME>" => "t".to_owned(),
+                "<N>" | "<M>" | "<RATIO>" => rng.gen_range(1, 4).to_string(),
+                p => p.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join(":");
+        match op_arity(arities, &op) {
+            Some(Arity { inputs, outputs }) if inputs <= depth => {
+                depth = depth - inputs + outputs;
+                ops.push(op);
+            }
+            _ => {
+                depth += 1;
+                ops.push(CONSTANTS.choose(rng).unwrap().to_string());
+            }
+        }
+    }
+    ops.join(" ")
+}
+
+/// Resident set size in bytes, where the OS tells it.
+fn resident_memory() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(pages * 4096)
+}
@@ -1,7 +1,9 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use druid::{kurbo::Point, Data};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct State {
@@ -96,19 +98,179 @@ impl State {
         }
     }
 
+    /// Paths with .json extension get a single JSON file, others get a plain-text directory.
     // TODO Atomic write.
-    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
-        let f = std::fs::File::create(path)?;
-        serde_json::to_writer_pretty(f, self)?;
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if is_json(path) {
+            let f = std::fs::File::create(path)?;
+            serde_json::to_writer_pretty(f, self)?;
+        } else {
+            self.save_dir(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if is_json(path) {
+            let f = std::fs::File::open(path)?;
+            Ok(serde_json::from_reader(f)?)
+        } else {
+            State::load_dir(path)
+        }
+    }
+
+    /// Write the manifest and a text file per plant, removing files of deleted plants.
+    fn save_dir(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path)?;
+        let mut files = HashSet::new();
+        let plants = self
+            .plants
+            .iter()
+            .map(|plant| ManifestPlant {
+                name: plant.name.to_owned(),
+                position: plant.position,
+                file: plant_file_name(&plant.name, &mut files),
+            })
+            .collect::<Vec<_>>();
+        for (plant, entry) in self.plants.iter().zip(&plants) {
+            std::fs::write(path.join(&entry.file), plant_to_text(plant))?;
+        }
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+            let listed = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| plants.iter().any(|plant| plant.file == name))
+                .unwrap_or(true);
+            if file.extension().and_then(|ext| ext.to_str()) == Some(PLANT_EXTENSION) && !listed {
+                std::fs::remove_file(file)?;
+            }
+        }
+        let manifest = Manifest {
+            sample_rate: self.sample_rate,
+            garden_offset: self.garden_offset,
+            plant_scene: match &self.scene {
+                Scene::Plant(scene) => Some(scene.clone()),
+                Scene::Garden(_) => None,
+            },
+            plants,
+        };
+        // Going through toml::Value puts plain values before tables at every level,
+        // e.g. the mode of the plant scene after its cursor.
+        let manifest = toml::Value::try_from(&manifest)?;
+        std::fs::write(path.join(MANIFEST_FILE), toml::to_string_pretty(&manifest)?)?;
         Ok(())
     }
 
-    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        let f = std::fs::File::open(path)?;
-        Ok(serde_json::from_reader(f)?)
+    fn load_dir(path: &Path) -> Result<Self> {
+        let manifest: Manifest =
+            toml::from_str(&std::fs::read_to_string(path.join(MANIFEST_FILE))?)?;
+        let plants = manifest
+            .plants
+            .into_iter()
+            .map(|entry| {
+                let text = std::fs::read_to_string(path.join(&entry.file))?;
+                Ok(Plant {
+                    position: entry.position,
+                    nodes: plant_from_text(&text)?,
+                    name: entry.name,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(State {
+            scene: match manifest.plant_scene {
+                Some(scene) => Scene::Plant(scene),
+                None => Scene::Garden(GardenScene {}),
+            },
+            plants,
+            garden_offset: manifest.garden_offset,
+            sample_rate: manifest.sample_rate,
+        })
     }
 }
 
+/// Manifest of the plain-text garden, plants are stored in separate files next to it.
+/// Plain values go first because TOML doesn't allow them after tables.
+#[derive(Deserialize, Serialize)]
+struct Manifest {
+    sample_rate: u32,
+    garden_offset: Position,
+    /// The open plant, if any. TOML has no enums with data, so no `Scene` here.
+    plant_scene: Option<PlantScene>,
+    plants: Vec<ManifestPlant>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ManifestPlant {
+    name: String,
+    /// Relative to the garden directory.
+    file: String,
+    position: Position,
+}
+
+const MANIFEST_FILE: &str = "garden.toml";
+/// How many previous versions of the garden to keep as <path>.1, <path>.2, …
+pub const BACKUPS: usize = 3;
+const PLANT_EXTENSION: &str = "plant";
+
+fn is_json(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("json")
+}
+
+/// Files are named after plants, so adding or removing a plant doesn't rename files of others.
+/// Plants with the same name get a numeric suffix, in order.
+fn plant_file_name(name: &str, files: &mut HashSet<String>) -> String {
+    let mut stem = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        // Case-insensitive file systems would mix up names differing only in case.
+        .to_lowercase();
+    if stem.is_empty() {
+        stem = String::from("plant");
+    }
+    let mut file = format!("{}.{}", stem, PLANT_EXTENSION);
+    for n in 2.. {
+        if files.insert(file.to_owned()) {
+            break;
+        }
+        file = format!("{}-{}.{}", stem, n, PLANT_EXTENSION);
+    }
+    file
+}
+
+/// One node per line as `x y op`, ordered by position for stable diffs.
+/// Node ids are random and not saved, so they don't add noise to diffs.
+fn plant_to_text(plant: &Plant) -> String {
+    let mut nodes = plant.nodes.iter().collect::<Vec<_>>();
+    nodes.sort_by_key(|node| (node.position.y, node.position.x));
+    nodes
+        .iter()
+        .map(|node| format!("{} {} {}\n", node.position.x, node.position.y, node.op))
+        .collect()
+}
+
+fn plant_from_text(text: &str) -> Result<Vec<Node>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let mut coordinate = || -> Result<i32> {
+                Ok(parts
+                    .next()
+                    .ok_or_else(|| anyhow!("Malformed node: {}", line))?
+                    .parse()?)
+            };
+            let x = coordinate()?;
+            let y = coordinate()?;
+            let op = parts.next().unwrap_or_default().to_owned();
+            Ok(Node::new(op, (x, y).into()))
+        })
+        .collect()
+}
+
 impl Default for State {
     fn default() -> Self {
         State::new()
@@ -142,3 +304,60 @@ impl Node {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plant(name: &str, x: i32, ops: &[&str]) -> Plant {
+        Plant {
+            position: (x, 0).into(),
+            nodes: ops
+                .iter()
+                .enumerate()
+                .map(|(i, op)| Node::new(op.to_string(), (i as i32, 0).into()))
+                .collect(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Node ids are not saved, compare the rest.
+    fn strip_ids(state: &State) -> State {
+        let mut state = state.clone();
+        for plant in &mut state.plants {
+            for node in &mut plant.nodes {
+                node.id = 0;
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn plain_text_garden_round_trips() {
+        let path = std::env::temp_dir().join(format!("garden-{}", std::process::id()));
+        let mut state = State::new();
+        state.plants = vec![
+            plant("Moss", 0, &["440", "s", "0.2", "*"]),
+            plant("Moss", 10, &["220", "t"]),
+            plant("Fern", 20, &["110 s"]),
+        ];
+        state.scene = Scene::Plant(PlantScene {
+            ix: 1,
+            cursor: (3, 4).into(),
+            mode: PlantSceneMode::Insert,
+        });
+        state.save(&path).unwrap();
+        assert_eq!(strip_ids(&State::load(&path).unwrap()), strip_ids(&state));
+        assert!(path.join("moss.plant").is_file());
+        assert!(path.join("moss-2.plant").is_file());
+
+        // Files of removed plants go away, the rest keep their names.
+        state.plants.remove(1);
+        state.save(&path).unwrap();
+        assert_eq!(strip_ids(&State::load(&path).unwrap()), strip_ids(&state));
+        assert!(!path.join("moss-2.plant").exists());
+        assert!(path.join("fern.plant").is_file());
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
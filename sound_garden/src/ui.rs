@@ -16,7 +16,7 @@ use std::sync::{Arc, Mutex};
 pub fn run(vm: Arc<Mutex<VM>>, sample_rate: u32) -> Result<()> {
     let window = WindowDesc::new(app::Widget::new).title(LocalizedString::new("window-title"));
 
-    let mut state = State::load(constants::STATE_FILE).unwrap_or_default();
+    let mut state = State::load(util::state_path()).unwrap_or_default();
     state.sample_rate = sample_rate;
 
     AppLauncher::with_window(window)
@@ -1,6 +1,7 @@
 use crate::state::{self, Scene};
 use crate::ui::constants::*;
 use crate::ui::scene::*;
+use crate::ui::util;
 use druid::{
     kurbo::{Point, Rect, Size},
     piet::{Color, RenderContext},
@@ -10,6 +11,8 @@ use druid::{
 
 pub struct Widget {
     scene: Option<BoxedWidget<State>>,
+    /// Backups are rotated by the first save of the session, later saves only overwrite.
+    backed_up: bool,
 }
 
 pub type State = state::State;
@@ -56,7 +59,16 @@ impl druid::Widget<State> for Widget {
         if let Some(scene) = &mut self.scene {
             scene.update(ctx, data, env);
         }
-        let _ = data.save(STATE_FILE);
+        let path = std::path::Path::new(util::state_path());
+        if !self.backed_up && path.exists() {
+            self.backed_up = true;
+            if let Err(e) = backups::rotate(path, state::BACKUPS) {
+                log::error!("Failed to back up the garden: {}", e);
+            }
+        }
+        if let Err(e) = data.save(path) {
+            log::error!("Failed to save the garden: {}", e);
+        }
     }
 
     fn layout(
@@ -83,7 +95,10 @@ impl druid::Widget<State> for Widget {
 
 impl Widget {
     pub fn new() -> Self {
-        Widget { scene: None }
+        Widget {
+            scene: None,
+            backed_up: false,
+        }
     }
 
     fn change_scene(&mut self, data: &State) {
@@ -1,6 +1,8 @@
 pub const FONT_NAME: &str = "Agave";
 pub const PLANT_FONT_SIZE: f64 = 20.0;
 pub const STATE_FILE: &str = "garden.json";
+/// Plain-text garden, used instead of STATE_FILE when the directory exists.
+pub const STATE_DIR: &str = "garden";
 pub const DOUBLE_CLICK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
 
 pub mod cmd {
@@ -1,6 +1,8 @@
 use crate::state::*;
 use crate::ui::{constants::*, util};
-use audio_program::{compile_program, Context, TextOp};
+use audio_program::{
+    compile_program, expand_word, shared_access, Context, Shared, TableAccess, TextOp,
+};
 use audio_vm::VM;
 use brotli::{CompressorWriter, Decompressor};
 use druid::{AppDelegate, Application, DelegateCtx, Env, Event, KeyCode};
@@ -61,6 +63,53 @@ impl AppDelegate<State> for Delegate {
                             *plant = new_plant;
                             plant.position = position;
                         }
+                        KeyCode::KeyF => {
+                            let accesses = data
+                                .plants
+                                .iter()
+                                .map(|plant| self.plant_accesses(plant))
+                                .collect::<Vec<_>>();
+                            let shared = accesses[scene.ix]
+                                .iter()
+                                .map(|(kind, name, _)| (*kind, name.as_str()))
+                                .collect::<Vec<_>>();
+                            for (kind, name) in &shared {
+                                let users = data
+                                    .plants
+                                    .iter()
+                                    .zip(&accesses)
+                                    .filter(|(_, x)| {
+                                        x.iter().any(|x| (x.0, x.1.as_str()) == (*kind, *name))
+                                    })
+                                    .map(|(plant, _)| plant.name.as_str())
+                                    .collect::<Vec<_>>();
+                                log::info!("{:?} {} is used by {}.", kind, name, users.join(", "));
+                            }
+                            for (kind, name, access) in &accesses[scene.ix] {
+                                let written = accesses.iter().flatten().any(|x| {
+                                    (x.0, x.1.as_str(), x.2)
+                                        == (*kind, name.as_str(), TableAccess::Write)
+                                });
+                                if *access == TableAccess::Read && !written {
+                                    log::warn!(
+                                        "Nothing writes {:?} {} the plant reads.",
+                                        kind,
+                                        name
+                                    );
+                                }
+                            }
+                            // Jump to the next plant which shares a table or a bus with this one.
+                            let n = data.plants.len();
+                            let next = (1..n).map(|i| (scene.ix + i) % n).find(|&ix| {
+                                accesses[ix]
+                                    .iter()
+                                    .any(|x| shared.contains(&(x.0, x.1.as_str())))
+                            });
+                            match next {
+                                Some(ix) => ctx.submit_command(cmd::zoom_to_plant(ix), None),
+                                None => log::info!("No other plant shares tables or buses."),
+                            }
+                        }
                         _ => {}
                     },
                     _ => {}
@@ -141,4 +190,23 @@ impl Delegate {
             vm,
         }
     }
+
+    /// Tables and buses the plant uses, words defined by the current plant count as their ops.
+    fn plant_accesses(&self, plant: &Plant) -> Vec<(Shared, String, TableAccess)> {
+        plant
+            .nodes
+            .iter()
+            .flat_map(|node| {
+                let op = TextOp {
+                    id: node.id,
+                    op: node.op.clone(),
+                };
+                expand_word(&op, &self.ctx.words)
+            })
+            .filter_map(|op| {
+                let (kind, name, access) = shared_access(&op.op)?;
+                Some((kind, name.to_owned(), access))
+            })
+            .collect()
+    }
 }
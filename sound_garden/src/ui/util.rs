@@ -14,6 +14,14 @@ impl EventExt for MouseEvent {
     }
 }
 
+pub fn state_path() -> &'static str {
+    if std::path::Path::new(STATE_DIR).is_dir() {
+        STATE_DIR
+    } else {
+        STATE_FILE
+    }
+}
+
 // Inefficient as hell, but good enough for the start.
 pub fn find_edges(plant: &state::Plant) -> Vec<(state::NodeIx, state::NodeIx)> {
     let mut edges = Vec::new();
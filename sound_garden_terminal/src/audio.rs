@@ -1,16 +1,104 @@
+use crate::classroom::{self, Desks};
+use crate::stats::{AudioStats, AudioStatsCollector};
 use anyhow::Result;
-use audio_vm::{Sample, CHANNELS, VM};
+use audio_ops::pure;
+use audio_vm::{Frame, Sample, CHANNELS, VM};
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
-use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use ringbuf::Producer;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub fn main(
+/// Input frames queued for output are capped to limit latency when input runs ahead.
+const MAX_INPUT_QUEUE_DURATION: f64 = 0.05;
+/// Output stream is restarted when it doesn't ask for frames during this time.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(2);
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+/// Pause before reopening the device, to not spin while it's unplugged.
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+/// Output fades out before switching the device and fades in after.
+const FADE_DURATION: Duration = Duration::from_millis(50);
+/// Streams which don't stop in this time are abandoned, e.g. stalled ones.
+const STOP_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub enum Message {
+    /// Move the output to the device with this name, keeping the program running.
+    Device(String),
+}
+
+pub struct Monitor {
+    /// Mix input directly into the output, bypassing the program, for the lowest latency.
+    pub direct: bool,
+    pub gain: Sample,
+    /// Output is clipped to -ceiling..ceiling.
+    pub ceiling: Sample,
+    /// Gain of the whole output after clipping.
+    pub master: Sample,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor {
+            direct: false,
+            gain: 1.0,
+            ceiling: 1.0,
+            master: 1.0,
+        }
+    }
+}
+
+/// What the audio thread shares with the rest of the app: the program it plays and where it
+/// feeds the recording, stats, input and the tuner.
+pub struct Taps {
+    pub vm: Arc<Mutex<VM>>,
+    pub producer: Producer<Sample>,
+    pub stats: Arc<Mutex<AudioStats>>,
+    pub input: Arc<Mutex<Frame>>,
+    pub tuner_producer: Producer<Frame>,
+    pub monitor: Arc<Mutex<Monitor>>,
+    pub desks: Desks,
+}
+
+/// State of the audio thread which outlives streams, so restarting a stream keeps everything
+/// from op state to the recording running.
+struct Engine {
     vm: Arc<Mutex<VM>>,
-    mut producer: Producer<Sample>,
-    rx: Receiver<()>,
-    tx: Sender<u32>,
-) -> Result<()> {
+    producer: Producer<Sample>,
+    stats: AudioStatsCollector,
+    input: Arc<Mutex<Frame>>,
+    tuner_producer: Producer<Frame>,
+    monitor: Arc<Mutex<Monitor>>,
+    desks: Desks,
+    input_queue: VecDeque<Frame>,
+    max_input_queue_len: usize,
+    /// Output gain ramps to the target by the step each frame to fade around restarts.
+    gain: Sample,
+    target_gain: Sample,
+    gain_step: Sample,
+}
+
+/// Liveness of the current stream, watched by the audio thread.
+#[derive(Default)]
+struct Health {
+    /// Streams of older generations stop.
+    generation: AtomicUsize,
+    /// Incremented by each output callback.
+    heartbeat: AtomicUsize,
+    failed: AtomicBool,
+}
+
+pub fn main(taps: Taps, rx: Receiver<Message>, tx: Sender<u32>) -> Result<()> {
+    let Taps {
+        vm,
+        producer,
+        stats,
+        input,
+        tuner_producer,
+        monitor,
+        desks,
+    } = taps;
     let host = cpal::default_host();
     let device = host
         .default_output_device()
@@ -30,6 +118,182 @@ pub fn main(
     let sample_rate = format.sample_rate.0;
     tx.send(sample_rate)?;
 
+    let max_input_queue_len = (MAX_INPUT_QUEUE_DURATION * sample_rate as f64) as usize;
+    let engine = Arc::new(Mutex::new(Engine {
+        vm,
+        producer,
+        stats: AudioStatsCollector::new(sample_rate, stats),
+        input,
+        tuner_producer,
+        monitor,
+        desks,
+        input_queue: VecDeque::with_capacity(max_input_queue_len),
+        max_input_queue_len,
+        gain: 0.0,
+        target_gain: 1.0,
+        gain_step: 1.0 / (FADE_DURATION.as_secs_f64() * sample_rate as f64),
+    }));
+    let health = Arc::new(Health::default());
+    // Preferred output device, the default one is used when it's missing.
+    let mut device: Option<String> = None;
+
+    loop {
+        let stream = StreamThread::spawn(sample_rate, device.clone(), &engine, &health);
+        let mut heartbeat = health.heartbeat.load(Ordering::SeqCst);
+        let mut stalled = Duration::default();
+        let switched = loop {
+            match rx.recv_timeout(WATCHDOG_INTERVAL) {
+                Ok(Message::Device(name)) => {
+                    device = Some(name);
+                    engine.lock().unwrap().target_gain = 0.0;
+                    std::thread::sleep(FADE_DURATION);
+                    break true;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    std::process::exit(0);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if health.failed.swap(false, Ordering::SeqCst) {
+                break false;
+            }
+            let next_heartbeat = health.heartbeat.load(Ordering::SeqCst);
+            if next_heartbeat == heartbeat {
+                stalled += WATCHDOG_INTERVAL;
+                if stalled >= WATCHDOG_TIMEOUT {
+                    log::warn!("Output stream stalled, restarting.");
+                    break false;
+                }
+            } else {
+                heartbeat = next_heartbeat;
+                stalled = Duration::default();
+            }
+        };
+        health.generation.fetch_add(1, Ordering::SeqCst);
+        stream.stop();
+        {
+            let mut engine = engine.lock().unwrap();
+            if !switched {
+                engine.stats.restart();
+            }
+            engine.gain = 0.0;
+            engine.target_gain = 1.0;
+        }
+        if !switched {
+            std::thread::sleep(RESTART_DELAY);
+        }
+    }
+}
+
+/// Thread of a stream, cpal doesn't provide a civilized way to stop event loop, so the thread
+/// unwinds out of it once its generation is over, dropping the streams with the device.
+struct StreamThread {
+    /// Disconnects when the thread is done.
+    done: Receiver<()>,
+    handle: std::thread::JoinHandle<()>,
+}
+
+impl StreamThread {
+    fn spawn(
+        sample_rate: u32,
+        device: Option<String>,
+        engine: &Arc<Mutex<Engine>>,
+        health: &Arc<Health>,
+    ) -> Self {
+        let engine = Arc::clone(engine);
+        let health = Arc::clone(health);
+        let generation = health.generation.load(Ordering::SeqCst);
+        let (done_tx, done) = crossbeam_channel::bounded(0);
+        let handle = std::thread::spawn(move || {
+            let _done_tx = done_tx;
+            let result = run_stream(
+                sample_rate,
+                device.as_deref(),
+                generation,
+                engine,
+                Arc::clone(&health),
+            );
+            if let Err(e) = result {
+                log::error!("{}", e);
+                health.failed.store(true, Ordering::SeqCst);
+            }
+        });
+        StreamThread { done, handle }
+    }
+
+    /// Wait for the thread to leave the event loop, after the generation is bumped.
+    fn stop(self) {
+        match self.done.recv_timeout(STOP_TIMEOUT) {
+            Err(RecvTimeoutError::Disconnected) => {
+                self.handle.join().ok();
+            }
+            _ => log::warn!("Output stream doesn't stop, abandoning it."),
+        }
+    }
+}
+
+/// Names of output devices to choose from.
+pub fn output_devices() -> Vec<String> {
+    cpal::default_host()
+        .output_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Preferred output device if it's available, or the default one, or any other one which could
+/// play at the sample rate of the running program.
+fn find_output(
+    host: &cpal::Host,
+    sample_rate: u32,
+    name: Option<&str>,
+) -> Option<(cpal::Device, cpal::Format)> {
+    let preferred = host
+        .output_devices()
+        .ok()?
+        .filter(|device| name.is_some() && device.name().ok().as_deref() == name);
+    let devices = preferred
+        .chain(host.default_output_device())
+        .chain(host.output_devices().ok()?);
+    for device in devices {
+        if let Ok(format) = device.default_output_format() {
+            if format.channels as usize == CHANNELS && format.sample_rate.0 == sample_rate {
+                return Some((device, format));
+            }
+        }
+        let format = device
+            .supported_output_formats()
+            .ok()
+            .and_then(|mut formats| {
+                formats.find(|format| {
+                    format.channels as usize == CHANNELS
+                        && format.min_sample_rate.0 <= sample_rate
+                        && sample_rate <= format.max_sample_rate.0
+                })
+            });
+        if let Some(format) = format {
+            let format = cpal::Format {
+                channels: format.channels,
+                sample_rate: cpal::SampleRate(sample_rate),
+                data_type: format.data_type,
+            };
+            return Some((device, format));
+        }
+    }
+    None
+}
+
+fn run_stream(
+    sample_rate: u32,
+    device: Option<&str>,
+    generation: usize,
+    engine: Arc<Mutex<Engine>>,
+    health: Arc<Health>,
+) -> Result<()> {
+    let host = cpal::default_host();
+    let (device, format) = find_output(&host, sample_rate, device)
+        .ok_or(anyhow::anyhow!("No output device available."))?;
+
     let event_loop = host.event_loop();
     let stream_id = event_loop
         .build_output_stream(&device, &format)
@@ -38,33 +302,97 @@ pub fn main(
         .play_stream(stream_id.clone())
         .map_err(|_| anyhow::anyhow!("Failed to play output stream."))?;
 
+    // Input is optional, play without it if it's not available or incompatible.
+    let input_channels = host.default_input_device().and_then(|device| {
+        let format = device.default_input_format().ok()?;
+        if format.sample_rate.0 != sample_rate {
+            log::warn!("Input sample rate doesn't match the output one, ignoring input.");
+            return None;
+        }
+        let stream_id = event_loop.build_input_stream(&device, &format).ok()?;
+        event_loop.play_stream(stream_id).ok()?;
+        Some(format.channels as usize)
+    });
+
+    let thread = std::thread::current().id();
     event_loop.run(move |id, result| {
-        match rx.try_recv() {
-            Ok(_) => {}
-            Err(TryRecvError::Disconnected) => {
-                // cpal doesn't provide a civilized way to stop event loop.
-                std::thread::sleep(std::time::Duration::from_secs(1));
-                std::process::exit(0);
-            }
-            Err(TryRecvError::Empty) => {}
+        let live = health.generation.load(Ordering::SeqCst) == generation;
+        if !live && std::thread::current().id() == thread {
+            // Unwinding skips the panic hook, so it's not taken for a crash.
+            std::panic::resume_unwind(Box::new(()));
         }
         let data = match result {
-            Ok(data) => data,
+            Ok(data) if live => data,
+            Ok(data) => {
+                // Hosts which call back from their own threads leave abandoned streams
+                // playing silence until they die.
+                silence(data);
+                return;
+            }
             Err(err) => {
-                eprintln!("An error occurred on stream {:?}: {}.", id, err);
+                if live {
+                    log::warn!("An error occurred on stream {:?}: {}.", id, err);
+                    engine.lock().unwrap().stats.stream_error();
+                    if let cpal::StreamError::DeviceNotAvailable = err {
+                        health.failed.store(true, Ordering::SeqCst);
+                    }
+                }
                 return;
             }
         };
+        let mut engine = engine.lock().unwrap();
+        if let cpal::StreamData::Output { .. } = data {
+            health.heartbeat.fetch_add(1, Ordering::SeqCst);
+        }
+        let started = Instant::now();
+        let Engine {
+            vm,
+            producer,
+            stats,
+            input,
+            tuner_producer,
+            monitor,
+            desks,
+            input_queue,
+            max_input_queue_len,
+            gain,
+            target_gain,
+            gain_step,
+        } = &mut *engine;
+        let max_input_queue_len = *max_input_queue_len;
         let mut vm = vm.lock().unwrap();
+        let mut desks = desks.lock().unwrap();
+        let (monitor, ceiling, master) = {
+            let monitor = monitor.lock().unwrap();
+            let gain = if monitor.direct { monitor.gain } else { 0.0 };
+            (gain, monitor.ceiling, monitor.master)
+        };
+        let mut next_frame = || {
+            let input_frame = input_queue.pop_front().unwrap_or([0.0; CHANNELS]);
+            *input.lock().unwrap() = input_frame;
+            tuner_producer.push(input_frame).ok();
+            let mut frame = vm.next_frame();
+            let class_frame = classroom::next_frame(&mut desks);
+            if *gain < *target_gain {
+                *gain = (*gain + *gain_step).min(*target_gain);
+            } else {
+                *gain = (*gain - *gain_step).max(*target_gain);
+            }
+            for ((x, &y), &z) in frame.iter_mut().zip(&input_frame).zip(&class_frame) {
+                *x = *gain * master * pure::clamp(*x + monitor * y + z, -ceiling, ceiling);
+            }
+            frame
+        };
         match data {
             cpal::StreamData::Output {
                 buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
             } => {
                 for frame in buffer.chunks_mut(format.channels as usize) {
-                    let next_frame = vm.next_frame();
+                    let next_frame = next_frame();
+                    stats.frame(&next_frame);
                     for (out, &sample) in frame.iter_mut().zip(&next_frame) {
                         let sample = clip(sample);
-                        *out = ((sample * 0.5 + 0.5) * std::u16::MAX as Sample) as u16;
+                        *out = ((sample * 0.5 + 0.5) * u16::MAX as Sample) as u16;
                         producer.push(sample).ok();
                     }
                 }
@@ -73,10 +401,11 @@ pub fn main(
                 buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
             } => {
                 for frame in buffer.chunks_mut(format.channels as usize) {
-                    let next_frame = vm.next_frame();
+                    let next_frame = next_frame();
+                    stats.frame(&next_frame);
                     for (out, &sample) in frame.iter_mut().zip(&next_frame) {
                         let sample = clip(sample);
-                        *out = (sample * std::i16::MAX as Sample) as i16;
+                        *out = (sample * i16::MAX as Sample) as i16;
                         producer.push(sample).ok();
                     }
                 }
@@ -85,7 +414,8 @@ pub fn main(
                 buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
             } => {
                 for frame in buffer.chunks_mut(format.channels as usize) {
-                    let next_frame = vm.next_frame();
+                    let next_frame = next_frame();
+                    stats.frame(&next_frame);
                     for (out, &sample) in frame.iter_mut().zip(&next_frame) {
                         let sample = clip(sample);
                         *out = sample as f32;
@@ -93,17 +423,86 @@ pub fn main(
                     }
                 }
             }
-            _ => (),
+            cpal::StreamData::Input {
+                buffer: cpal::UnknownTypeInputBuffer::U16(buffer),
+            } => {
+                let channels = input_channels.unwrap_or(CHANNELS);
+                for frame in buffer.chunks(channels) {
+                    queue_input(input_queue, max_input_queue_len, frame, |x| {
+                        Sample::from(x) / Sample::from(u16::MAX) * 2.0 - 1.0
+                    });
+                }
+            }
+            cpal::StreamData::Input {
+                buffer: cpal::UnknownTypeInputBuffer::I16(buffer),
+            } => {
+                let channels = input_channels.unwrap_or(CHANNELS);
+                for frame in buffer.chunks(channels) {
+                    queue_input(input_queue, max_input_queue_len, frame, |x| {
+                        Sample::from(x) / Sample::from(i16::MAX)
+                    });
+                }
+            }
+            cpal::StreamData::Input {
+                buffer: cpal::UnknownTypeInputBuffer::F32(buffer),
+            } => {
+                let channels = input_channels.unwrap_or(CHANNELS);
+                for frame in buffer.chunks(channels) {
+                    queue_input(input_queue, max_input_queue_len, frame, Sample::from);
+                }
+            }
         }
+        stats.busy(started.elapsed());
     });
 }
 
-fn clip(sample: Sample) -> Sample {
-    if sample < -1.0 {
-        -1.0
-    } else if 1.0 < sample {
-        1.0
-    } else {
-        sample
+/// Mono input is sent to all channels, extra input channels are ignored.
+fn queue_input<T: Copy>(
+    queue: &mut VecDeque<Frame>,
+    max_len: usize,
+    samples: &[T],
+    to_sample: impl Fn(T) -> Sample,
+) {
+    if samples.is_empty() {
+        return;
+    }
+    let mut frame = [0.0; CHANNELS];
+    for (channel, x) in frame.iter_mut().enumerate() {
+        *x = to_sample(samples[channel.min(samples.len() - 1)]);
+    }
+    if queue.len() >= max_len {
+        queue.pop_front();
     }
+    queue.push_back(frame);
+}
+
+fn silence(data: cpal::StreamData) {
+    match data {
+        cpal::StreamData::Output {
+            buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
+        } => {
+            for out in buffer.iter_mut() {
+                *out = u16::MAX / 2;
+            }
+        }
+        cpal::StreamData::Output {
+            buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
+        } => {
+            for out in buffer.iter_mut() {
+                *out = 0;
+            }
+        }
+        cpal::StreamData::Output {
+            buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
+        } => {
+            for out in buffer.iter_mut() {
+                *out = 0.0;
+            }
+        }
+        _ => (),
+    }
+}
+
+fn clip(sample: Sample) -> Sample {
+    pure::clamp(sample, -1.0, 1.0)
 }
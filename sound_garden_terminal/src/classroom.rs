@@ -0,0 +1,196 @@
+//! Host a classroom: students edit their own plants and send commits to the teacher, whose machine
+//! renders all of them through its audio device along with its own program.
+//!
+//! Like in a jam only programs travel over the network. Students are told apart by names, a
+//! student rejoining under the same name takes over their desk. Students keep resending their
+//! last commit, so the teacher catches up after lost datagrams or a restart, and commits which
+//! already play are skipped.
+use crate::jam::PatchNode;
+use crate::udp;
+use anyhow::{anyhow, Result};
+use audio_program::{compile_program, rewrite_terms, rewrite_words, Context, TextOp};
+use audio_vm::{Frame, Sample, CHANNELS, VM};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thread_worker::Worker;
+
+/// How often students resend their last commit.
+const RESEND_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Set in the file, the teacher listens on the address and students send commits to it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Teacher { address: String },
+    Student { name: String, teacher: String },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Submission {
+    pub name: String,
+    pub nodes: Vec<PatchNode>,
+}
+
+/// Student's program playing on the teacher's machine.
+pub struct Desk {
+    pub name: String,
+    pub muted: bool,
+    vm: VM,
+}
+
+/// Desks are shared with the audio thread which mixes them into the output.
+pub type Desks = Arc<Mutex<Vec<Desk>>>;
+
+pub struct Teacher {
+    listener: Worker<(), Submission>,
+    desks: Desks,
+    /// Students' programs are compiled by the teacher, each with own tables.
+    contexts: HashMap<String, Context>,
+    /// Nodes of the last accepted submission of each student.
+    accepted: HashMap<String, Vec<PatchNode>>,
+}
+
+impl Teacher {
+    pub fn new<A: ToSocketAddrs>(address: A, desks: Desks) -> Result<Self> {
+        let listener = udp::listen(
+            "Classroom",
+            UdpSocket::bind(address)?,
+            |data, _, submissions| {
+                submissions.push(serde_json::from_slice(data)?);
+                Ok(())
+            },
+        )?;
+        Ok(Teacher {
+            listener,
+            desks,
+            contexts: HashMap::new(),
+            accepted: HashMap::new(),
+        })
+    }
+
+    pub fn receiver(&self) -> &Receiver<Submission> {
+        self.listener.receiver()
+    }
+
+    /// Compile the submission and crossfade student's desk to it, seating a new student if needed.
+    /// Students' ops can't read files on the teacher's machine.
+    pub fn accept(&mut self, submission: Submission, sample_rate: u32, max_feedback_gain: Sample) {
+        let Submission { name, mut nodes } = submission;
+        if self.accepted.get(&name) == Some(&nodes) {
+            return;
+        }
+        self.accepted.insert(name.to_owned(), nodes.clone());
+        nodes.sort_by_key(|node| (node.y, node.x));
+        let ops = rewrite_terms(
+            &nodes
+                .into_iter()
+                .map(|PatchNode { id, op, .. }| TextOp { id, op })
+                .collect::<Vec<_>>(),
+        );
+        let ctx = self.contexts.entry(name.to_owned()).or_insert_with(|| {
+            let mut ctx = Context::new();
+            ctx.allow_files = false;
+            ctx
+        });
+        ctx.max_feedback_gain = max_feedback_gain;
+        let ops = rewrite_words(&ops, &mut ctx.words);
+        let program = compile_program(&ops, sample_rate, ctx);
+        let mut desks = self.desks.lock().unwrap();
+        let garbage = match desks.iter_mut().find(|desk| desk.name == name) {
+            Some(desk) => Some(desk.vm.load_program(program)),
+            None => {
+                let mut vm = VM::new();
+                vm.load_program(program);
+                desks.push(Desk {
+                    name,
+                    muted: false,
+                    vm,
+                });
+                None
+            }
+        };
+        drop(desks);
+        // Deallocate the previous program outside of the lock.
+        drop(garbage);
+    }
+
+    /// Mute or unmute the student at the given desk, fading them out or in.
+    pub fn toggle_mute(&self, ix: usize) {
+        if let Some(desk) = self.desks.lock().unwrap().get_mut(ix) {
+            desk.muted = !desk.muted;
+            if desk.muted {
+                desk.vm.pause();
+            } else {
+                desk.vm.play();
+            }
+        }
+    }
+
+    /// Names of students in the order of their desks along with mute state.
+    pub fn roll_call(&self) -> Vec<(String, bool)> {
+        self.desks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|desk| (desk.name.to_owned(), desk.muted))
+            .collect()
+    }
+}
+
+pub struct Student {
+    name: String,
+    /// Sends submissions it gets and resends the last one.
+    sender: Worker<Vec<u8>, ()>,
+}
+
+impl Student {
+    pub fn new<A: ToSocketAddrs>(name: &str, teacher: A) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let teacher: SocketAddr = teacher
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Teacher address is not resolved."))?;
+        let sender = Worker::spawn("Student", 1, move |rx: Receiver<Vec<u8>>, _| {
+            let mut data = Vec::new();
+            loop {
+                match rx.recv_timeout(RESEND_INTERVAL) {
+                    Ok(submission) => data = submission,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                if !data.is_empty() {
+                    socket.send_to(&data, teacher).ok();
+                }
+            }
+        });
+        Ok(Student {
+            name: name.to_owned(),
+            sender,
+        })
+    }
+
+    pub fn send(&self, nodes: Vec<PatchNode>) {
+        let submission = Submission {
+            name: self.name.to_owned(),
+            nodes,
+        };
+        if let Ok(data) = serde_json::to_vec(&submission) {
+            self.sender.sender().send(data).ok();
+        }
+    }
+}
+
+/// Sum of the next frames of all desks, muted ones fade out and then stay silent.
+pub fn next_frame(desks: &mut [Desk]) -> Frame {
+    let mut frame = [0.0; CHANNELS];
+    for desk in desks {
+        for (x, y) in frame.iter_mut().zip(&desk.vm.next_frame()) {
+            *x += y;
+        }
+    }
+    frame
+}
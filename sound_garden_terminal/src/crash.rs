@@ -0,0 +1,75 @@
+//! Local crash recovery. On panic the garden as of the last save (with its undo history) and a
+//! backtrace are saved to a bundle next to the file, and the next start offers to restore the
+//! garden from it. Nothing leaves the machine.
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::backtrace::Backtrace;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Serialized garden as of the last save, for the panic hook to save.
+pub type Snapshot = Arc<Mutex<Option<Value>>>;
+
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    time: String,
+    message: String,
+    backtrace: String,
+    garden: Option<Value>,
+}
+
+fn bundle_path(filename: &str) -> String {
+    format!("{}.crash.json", filename)
+}
+
+/// Install the panic hook saving crash bundles for the file, the default hook runs after it.
+pub fn install(filename: &str) -> Snapshot {
+    let snapshot = Snapshot::default();
+    let path = bundle_path(filename);
+    let default_hook = std::panic::take_hook();
+    {
+        let snapshot = Arc::clone(&snapshot);
+        std::panic::set_hook(Box::new(move |info| {
+            // Don't wait for the lock, the panicking thread could be holding it.
+            let garden = snapshot
+                .try_lock()
+                .ok()
+                .and_then(|garden| (*garden).clone());
+            let bundle = Bundle {
+                time: Local::now().to_rfc3339(),
+                message: info.to_string(),
+                backtrace: Backtrace::force_capture().to_string(),
+                garden,
+            };
+            if let Ok(f) = std::fs::File::create(&path) {
+                serde_json::to_writer_pretty(f, &bundle).ok();
+            }
+            default_hook(info);
+        }));
+    }
+    snapshot
+}
+
+/// If the previous session crashed, ask whether to restore the garden from the crash bundle.
+/// The bundle is kept either way, renamed after the time of the crash without colons, which
+/// some file systems don't allow.
+pub fn restore(filename: &str) -> Option<Value> {
+    let path = bundle_path(filename);
+    let bundle: Bundle = serde_json::from_reader(std::fs::File::open(&path).ok()?).ok()?;
+    let time = bundle.time.replace(':', "");
+    std::fs::rename(&path, format!("{}-{}.crash.json", filename, time)).ok();
+    let garden = bundle.garden?;
+    print!(
+        "Sound Garden crashed at {}: {}\nRestore the garden as it was before the crash? [y/N] ",
+        bundle.time, bundle.message
+    );
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Some(garden)
+    } else {
+        None
+    }
+}
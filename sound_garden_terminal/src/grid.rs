@@ -0,0 +1,136 @@
+//! Program the `grid` step sequencer from a monome grid over serialosc.
+//!
+//! The device at the address is told to send key presses here. Pads toggle steps of the pattern,
+//! rows are tracks and columns are steps. LEDs show active steps, the column of the playing step
+//! is inverted. Tracks of the sequencer are sent to buses `grid0`, `grid1`, … for `recv`.
+use crate::osc::{self, Arg};
+use anyhow::{anyhow, Result};
+use audio_ops::{GridPattern, SharedGrid, GRID_ROWS, GRID_STEPS};
+use crossbeam_channel::{Receiver, TryRecvError};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use thread_worker::Worker;
+
+const PREFIX: &str = "/sound-garden";
+const MAX_DATAGRAM_SIZE: usize = 1024;
+/// How often LEDs follow the playing step, also how often worker checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Grid {
+    _worker: Worker<(), ()>,
+}
+
+struct Key {
+    x: usize,
+    y: usize,
+    pressed: bool,
+}
+
+impl Grid {
+    pub fn new<A: ToSocketAddrs>(device: A, pattern: SharedGrid) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        // Only the device is heard, and the address it could reach us at is known.
+        socket.connect(device)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local = socket.local_addr()?;
+        socket.send(&osc::encode_message(
+            "/sys/port",
+            &[Arg::Int(local.port().into())],
+        ))?;
+        socket.send(&osc::encode_message(
+            "/sys/host",
+            &[Arg::Str(&local.ip().to_string())],
+        ))?;
+        socket.send(&osc::encode_message("/sys/prefix", &[Arg::Str(PREFIX)]))?;
+        let worker = Worker::spawn("Grid", 1, move |rx, _| run(&socket, &pattern, rx));
+        Ok(Grid { _worker: worker })
+    }
+}
+
+/// Ops which play tracks of the sequencer into their buses, to put in front of the program.
+pub fn sends() -> Vec<String> {
+    let mut ops = vec![String::from("grid")];
+    // The last track is on top.
+    for row in (0..GRID_ROWS).rev() {
+        ops.push(String::from("1"));
+        ops.push(format!("send:grid{}", row));
+        ops.push(String::from("pop"));
+    }
+    ops
+}
+
+/// Steps of each track as a mask, the first step is the lowest bit.
+pub fn masks(pattern: &GridPattern) -> Vec<u16> {
+    pattern
+        .steps
+        .iter()
+        .map(|steps| {
+            steps
+                .iter()
+                .enumerate()
+                .filter(|(_, &active)| active)
+                .fold(0, |mask, (x, _)| mask | 1 << x)
+        })
+        .collect()
+}
+
+pub fn set_masks(pattern: &mut GridPattern, masks: &[u16]) {
+    for (steps, mask) in pattern.steps.iter_mut().zip(masks) {
+        for (x, active) in steps.iter_mut().enumerate() {
+            *active = mask & 1 << x != 0;
+        }
+    }
+}
+
+fn run(socket: &UdpSocket, pattern: &SharedGrid, rx: Receiver<()>) {
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    let mut keys = Vec::new();
+    // What LEDs show now.
+    let mut shown = None;
+    while let Err(TryRecvError::Empty) = rx.try_recv() {
+        if let Ok(size) = socket.recv(&mut buffer) {
+            osc::decode_packet(&buffer[..size], &mut keys, &parse_key).ok();
+        }
+        let mut pattern = pattern.lock().unwrap();
+        for Key { x, y, pressed } in keys.drain(..) {
+            if pressed && x < GRID_STEPS && y < GRID_ROWS {
+                pattern.steps[y][x] = !pattern.steps[y][x];
+            }
+        }
+        let leds = leds(&pattern);
+        drop(pattern);
+        if shown != Some(leds) {
+            for (y, row) in leds.iter().enumerate() {
+                let mut args = vec![Arg::Int(0), Arg::Int(y as _)];
+                args.extend(row.iter().map(|&mask| Arg::Int(mask.into())));
+                let address = format!("{}/grid/led/row", PREFIX);
+                socket.send(&osc::encode_message(&address, &args)).ok();
+            }
+            shown = Some(leds);
+        }
+    }
+}
+
+/// Masks of LEDs by rows, 8 columns per mask.
+fn leds(pattern: &GridPattern) -> [[u8; GRID_STEPS / 8]; GRID_ROWS] {
+    let mut leds = [[0; GRID_STEPS / 8]; GRID_ROWS];
+    for (row, steps) in leds.iter_mut().zip(pattern.steps.iter()) {
+        for (x, &active) in steps.iter().enumerate() {
+            if active != (pattern.playing == Some(x)) {
+                row[x / 8] |= 1 << (x % 8);
+            }
+        }
+    }
+    leds
+}
+
+fn parse_key(address: &str, args: Vec<String>) -> Result<Key> {
+    if address != format!("{}/grid/key", PREFIX) || args.len() != 3 {
+        return Err(anyhow!("Unknown grid message {}.", address));
+    }
+    Ok(Key {
+        x: args[0].parse()?,
+        y: args[1].parse()?,
+        pressed: args[2] != "0",
+    })
+}
@@ -0,0 +1,163 @@
+//! Jam with remote peers by exchanging program commits instead of audio.
+//!
+//! Each machine synthesizes locally. Commits are applied at the start of the next quantum of the
+//! wall clock, thus peers with NTP-synchronized clocks switch programs in unison regardless of the
+//! network latency. When there is an Ableton Link session on the local network, tempo follows it
+//! and commits wait for its next bar line, so the program stays in phase with the session.
+use crate::link::Link;
+use crate::udp;
+use anyhow::Result;
+use audio_vm::{Program, VM};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thread_worker::Worker;
+
+/// Commits are applied at multiples of quantum (in ms) since the Unix epoch.
+const QUANTUM: u64 = 2000;
+/// Minimal time (in ms) between sending a commit and applying it, to let it reach peers.
+const LATENCY: u64 = 500;
+/// Commits wait for bar lines of the Link session, if any.
+const BEATS_PER_BAR: f64 = 4.0;
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+pub struct Patch {
+    /// Milliseconds since the Unix epoch.
+    pub apply_at: u64,
+    pub nodes: Vec<PatchNode>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchNode {
+    /// Sent along to let peers migrate ops state.
+    pub id: u64,
+    pub op: String,
+    pub x: usize,
+    pub y: usize,
+}
+
+pub struct Jam {
+    listener: Worker<(), Patch>,
+    peers: Vec<SocketAddr>,
+    /// Programs to load into VM along with the time to do so.
+    scheduler: Worker<(Program, u64), ()>,
+    socket: UdpSocket,
+    link: Option<Link>,
+}
+
+impl Jam {
+    pub fn new<A: ToSocketAddrs>(address: A, peers: &[String], vm: Arc<Mutex<VM>>) -> Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        let mut peer_addresses = Vec::new();
+        for peer in peers {
+            peer_addresses.extend(peer.to_socket_addrs()?);
+        }
+        // Ports aren't compared, NAT could rewrite the source port of peers' datagrams.
+        let hosts = peer_addresses
+            .iter()
+            .map(|peer: &SocketAddr| peer.ip())
+            .collect::<Vec<_>>();
+        let listener = udp::listen("Jam", socket.try_clone()?, move |data, src, patches| {
+            if !hosts.contains(&src.ip()) {
+                log::warn!("Dropped a datagram from {}, it's not a peer.", src);
+                return Ok(());
+            }
+            patches.push(serde_json::from_slice(data)?);
+            Ok(())
+        })?;
+        let scheduler = Worker::spawn("Jam scheduler", CHANNEL_CAPACITY, move |rx, _| {
+            run_scheduler(&vm, rx)
+        });
+        let link = Link::new()
+            .map_err(|e| log::warn!("Can't join Link sessions: {}", e))
+            .ok();
+        Ok(Jam {
+            listener,
+            peers: peer_addresses,
+            scheduler,
+            socket,
+            link,
+        })
+    }
+
+    /// Send nodes to peers and return the time when they should be applied.
+    pub fn send(&self, nodes: Vec<PatchNode>) -> u64 {
+        let patch = Patch {
+            apply_at: next_quantum(),
+            nodes,
+        };
+        if let Ok(data) = serde_json::to_vec(&patch) {
+            for peer in &self.peers {
+                self.socket.send_to(&data, peer).ok();
+            }
+        }
+        patch.apply_at
+    }
+
+    pub fn receiver(&self) -> &Receiver<Patch> {
+        self.listener.receiver()
+    }
+
+    /// Load program into VM at the given time (in ms since the Unix epoch), or at the next bar
+    /// line of the Link session after it.
+    pub fn schedule(&self, program: Program, apply_at: u64) {
+        let apply_at = match &self.link {
+            Some(link) => link.next_bar(apply_at, now(), BEATS_PER_BAR),
+            None => apply_at,
+        };
+        self.scheduler.sender().send((program, apply_at)).ok();
+    }
+
+    /// Tempo of the Link session, if any.
+    pub fn bpm(&self) -> Option<f64> {
+        self.link.as_ref().and_then(Link::bpm)
+    }
+}
+
+/// Load programs into VM at their time, one thread serves all commits of the session.
+fn run_scheduler(vm: &Mutex<VM>, rx: Receiver<(Program, u64)>) {
+    // Ordered by the time to apply.
+    let mut pending: Vec<(Program, u64)> = Vec::new();
+    loop {
+        let message = match pending.first() {
+            Some((_, apply_at)) => {
+                rx.recv_timeout(Duration::from_millis(apply_at.saturating_sub(now())))
+            }
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match message {
+            Ok((program, apply_at)) => {
+                let ix = pending
+                    .iter()
+                    .position(|(_, other)| *other > apply_at)
+                    .unwrap_or(pending.len());
+                pending.insert(ix, (program, apply_at));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        while let Some((_, apply_at)) = pending.first() {
+            if *apply_at > now() {
+                break;
+            }
+            let (program, _) = pending.remove(0);
+            // Deallocate the previous program outside of the lock.
+            let garbage = vm.lock().unwrap().load_program(program);
+            drop(garbage);
+        }
+    }
+}
+
+fn next_quantum() -> u64 {
+    (now() + LATENCY) / QUANTUM * QUANTUM + QUANTUM
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_millis() as u64)
+        .unwrap_or_default()
+}
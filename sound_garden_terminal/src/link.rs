@@ -0,0 +1,430 @@
+//! Follow the tempo and beats of an Ableton Link session on the local network.
+//!
+//! It's a subset of the Link protocol in plain Rust. Peers are discovered by their multicast
+//! announcements, which carry the session they are in and its timeline: tempo and the beat at a
+//! moment of the session's ghost time. The offset of our clock to the ghost time is measured by
+//! pinging a peer of the session. We don't announce ourselves, thus we follow the session without
+//! changing its tempo, and peers don't count us in.
+use anyhow::Result;
+use crossbeam_channel::{Receiver, TryRecvError};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thread_worker::Worker;
+
+const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+const MULTICAST_PORT: u16 = 20808;
+const DISCOVERY_HEADER: &[u8] = b"_asdp_v\x01";
+const MEASUREMENT_HEADER: &[u8] = b"_link_v\x01";
+const ALIVE: u8 = 1;
+const RESPONSE: u8 = 2;
+const BYEBYE: u8 = 3;
+const PING: u8 = 1;
+const PONG: u8 = 2;
+const TIMELINE: &[u8; 4] = b"tmln";
+const SESSION: &[u8; 4] = b"sess";
+const MEASUREMENT_ENDPOINT: &[u8; 4] = b"mep4";
+const HOST_TIME: &[u8; 4] = b"__ht";
+const GHOST_TIME: &[u8; 4] = b"__gt";
+/// Header, message type, TTL, group id and node id.
+const DISCOVERY_HEADER_SIZE: usize = 20;
+const MAX_DATAGRAM_SIZE: usize = 512;
+/// Median of that many round trips is the offset to the ghost time.
+const MEASUREMENT_SAMPLES: usize = 100;
+/// Measurement is abandoned when the peer doesn't answer a ping for that long.
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Clocks drift apart, the offset is measured again after that long.
+const REMEASURE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the worker checks for pongs and shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Link {
+    /// Our clock, in µs since it.
+    start: Instant,
+    session: Arc<Mutex<Option<Session>>>,
+    _worker: Worker<(), ()>,
+}
+
+#[derive(Clone, Copy)]
+struct Session {
+    timeline: Timeline,
+    /// Ghost time minus our time, in µs.
+    offset: i64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Timeline {
+    micros_per_beat: i64,
+    /// In millionths of a beat.
+    beat_origin: i64,
+    /// Ghost time of the beat origin, in µs.
+    time_origin: i64,
+}
+
+type NodeId = [u8; 8];
+
+struct Peer {
+    session: NodeId,
+    timeline: Timeline,
+    endpoint: Option<SocketAddrV4>,
+    expires: Instant,
+}
+
+struct Measurement {
+    session: NodeId,
+    endpoint: SocketAddrV4,
+    /// Our time of the last ping.
+    sent: i64,
+    sent_at: Instant,
+    offsets: Vec<i64>,
+}
+
+impl Link {
+    pub fn new() -> Result<Self> {
+        // Other Link apps on the machine listen to the same port.
+        let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT)).into())?;
+        let discovery = socket.into_udp_socket();
+        discovery.join_multicast_v4(&MULTICAST_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+        discovery.set_nonblocking(true)?;
+        let pings = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        pings.set_nonblocking(true)?;
+        let start = Instant::now();
+        let session = Arc::new(Mutex::new(None));
+        let worker = {
+            let session = Arc::clone(&session);
+            Worker::spawn("Link", 1, move |rx, _| {
+                follow(start, &session, &discovery, &pings, rx)
+            })
+        };
+        Ok(Link {
+            start,
+            session,
+            _worker: worker,
+        })
+    }
+
+    /// Tempo of the session, if there are peers to follow.
+    pub fn bpm(&self) -> Option<f64> {
+        let session = (*self.session.lock().unwrap())?;
+        Some(60e6 / session.timeline.micros_per_beat as f64)
+    }
+
+    /// The first bar line of the session at or after the moment, both are in ms since the Unix
+    /// epoch along with `now`. The moment is returned as is when there are no peers to follow.
+    pub fn next_bar(&self, at: u64, now: u64, beats_per_bar: f64) -> u64 {
+        let session = match *self.session.lock().unwrap() {
+            Some(session) => session,
+            None => return at,
+        };
+        let Timeline {
+            micros_per_beat,
+            beat_origin,
+            time_origin,
+        } = session.timeline;
+        let micros_per_beat = micros_per_beat as f64;
+        let our_now = micros(self.start);
+        let ghost = (our_now + session.offset) as f64 + (at as f64 - now as f64) * 1e3;
+        let beat = beat_origin as f64 * 1e-6 + (ghost - time_origin as f64) / micros_per_beat;
+        let bar = (beat / beats_per_bar).ceil() * beats_per_bar;
+        let ghost = time_origin as f64 + (bar - beat_origin as f64 * 1e-6) * micros_per_beat;
+        let delay = (ghost - (our_now + session.offset) as f64) * 1e-3;
+        (now as f64 + delay).max(at as f64) as u64
+    }
+}
+
+/// Track peers and their sessions, measure the offset to the ghost time of the session most of
+/// them are in, until the worker is dropped.
+fn follow(
+    start: Instant,
+    shared: &Mutex<Option<Session>>,
+    discovery: &UdpSocket,
+    pings: &UdpSocket,
+    rx: Receiver<()>,
+) {
+    let mut buffer = [0; MAX_DATAGRAM_SIZE];
+    let mut peers: HashMap<NodeId, Peer> = HashMap::new();
+    let mut measurement: Option<Measurement> = None;
+    // Session, offset to its ghost time and when it was measured.
+    let mut measured: Option<(NodeId, i64, Instant)> = None;
+    while let Err(TryRecvError::Empty) = rx.try_recv() {
+        while let Ok(size) = discovery.recv(&mut buffer) {
+            handle_announcement(&buffer[..size], &mut peers);
+        }
+        let now = Instant::now();
+        peers.retain(|_, peer| peer.expires > now);
+        let session = match leading_session(&peers) {
+            Some(session) => session,
+            None => {
+                *shared.lock().unwrap() = None;
+                measurement = None;
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        if measurement.as_ref().map(|m| m.session) != Some(session) {
+            measurement = None;
+        }
+        let fresh = match measured {
+            Some((other, _, at)) => other == session && at.elapsed() < REMEASURE_INTERVAL,
+            None => false,
+        };
+        if !fresh && measurement.is_none() {
+            let endpoint = peers
+                .values()
+                .filter(|peer| peer.session == session)
+                .find_map(|peer| peer.endpoint);
+            if let Some(endpoint) = endpoint {
+                let sent = micros(start);
+                pings.send_to(&ping(sent), endpoint).ok();
+                measurement = Some(Measurement {
+                    session,
+                    endpoint,
+                    sent,
+                    sent_at: Instant::now(),
+                    offsets: Vec::with_capacity(MEASUREMENT_SAMPLES),
+                });
+            }
+        }
+        while let Ok((size, src)) = pings.recv_from(&mut buffer) {
+            let m = match measurement.as_mut() {
+                Some(m) if src == SocketAddr::V4(m.endpoint) => m,
+                _ => continue,
+            };
+            let (pong_session, ghost, sent) = match parse_pong(&buffer[..size]) {
+                Some(pong) => pong,
+                None => continue,
+            };
+            if pong_session != m.session || sent != m.sent {
+                continue;
+            }
+            let received = micros(start);
+            m.offsets.push(ghost - (sent + received) / 2);
+            if m.offsets.len() < MEASUREMENT_SAMPLES {
+                m.sent = micros(start);
+                m.sent_at = Instant::now();
+                pings.send_to(&ping(m.sent), m.endpoint).ok();
+            } else {
+                m.offsets.sort_unstable();
+                measured = Some((m.session, m.offsets[m.offsets.len() / 2], Instant::now()));
+                measurement = None;
+            }
+        }
+        if let Some(m) = &measurement {
+            if m.sent_at.elapsed() > MEASUREMENT_TIMEOUT {
+                log::warn!("Link peer at {} doesn't answer pings.", m.endpoint);
+                measurement = None;
+            }
+        }
+        // Keep the last offset while remeasuring, clocks don't drift apart that fast.
+        *shared.lock().unwrap() = match measured {
+            Some((other, offset, _)) if other == session => peers
+                .values()
+                .find(|peer| peer.session == session)
+                .map(|peer| Session {
+                    timeline: peer.timeline,
+                    offset,
+                }),
+            _ => None,
+        };
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn handle_announcement(data: &[u8], peers: &mut HashMap<NodeId, Peer>) {
+    if !data.starts_with(DISCOVERY_HEADER) || data.len() < DISCOVERY_HEADER_SIZE {
+        return;
+    }
+    let kind = data[8];
+    let ttl = data[9];
+    let id: NodeId = data[12..20].try_into().unwrap();
+    match kind {
+        ALIVE | RESPONSE => {
+            let mut session = None;
+            let mut timeline = None;
+            let mut endpoint = None;
+            for (key, value) in entries(&data[DISCOVERY_HEADER_SIZE..]) {
+                match &key {
+                    SESSION => session = value.try_into().ok(),
+                    TIMELINE if value.len() == 24 => {
+                        timeline = Some(Timeline {
+                            micros_per_beat: read_i64(&value[0..8]),
+                            beat_origin: read_i64(&value[8..16]),
+                            time_origin: read_i64(&value[16..24]),
+                        })
+                    }
+                    MEASUREMENT_ENDPOINT if value.len() == 6 => {
+                        let ip: [u8; 4] = value[0..4].try_into().unwrap();
+                        let port = u16::from_be_bytes(value[4..6].try_into().unwrap());
+                        endpoint = Some(SocketAddrV4::new(ip.into(), port));
+                    }
+                    _ => {}
+                }
+            }
+            if let (Some(session), Some(timeline)) = (session, timeline) {
+                if timeline.micros_per_beat > 0 {
+                    let expires = Instant::now() + Duration::from_secs(ttl.into());
+                    peers.insert(
+                        id,
+                        Peer {
+                            session,
+                            timeline,
+                            endpoint,
+                            expires,
+                        },
+                    );
+                }
+            }
+        }
+        BYEBYE => {
+            peers.remove(&id);
+        }
+        _ => {}
+    }
+}
+
+/// Session most peers are in, the one with the smallest id among equals.
+fn leading_session(peers: &HashMap<NodeId, Peer>) -> Option<NodeId> {
+    let mut counts = HashMap::new();
+    for peer in peers.values() {
+        *counts.entry(peer.session).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a, n), (b, m)| n.cmp(m).then(b.cmp(a)))
+        .map(|(session, _)| session)
+}
+
+fn ping(host_time: i64) -> Vec<u8> {
+    let mut data = MEASUREMENT_HEADER.to_vec();
+    data.push(PING);
+    data.extend_from_slice(HOST_TIME);
+    data.extend_from_slice(&8u32.to_be_bytes());
+    data.extend_from_slice(&host_time.to_be_bytes());
+    data
+}
+
+/// Session of the peer, its ghost time and our time of the ping it answers.
+fn parse_pong(data: &[u8]) -> Option<(NodeId, i64, i64)> {
+    if !data.starts_with(MEASUREMENT_HEADER) || data.get(MEASUREMENT_HEADER.len()) != Some(&PONG) {
+        return None;
+    }
+    let mut session = None;
+    let mut ghost = None;
+    let mut host = None;
+    for (key, value) in entries(&data[MEASUREMENT_HEADER.len() + 1..]) {
+        match &key {
+            SESSION => session = value.try_into().ok(),
+            GHOST_TIME if value.len() == 8 => ghost = Some(read_i64(value)),
+            HOST_TIME if value.len() == 8 => host = Some(read_i64(value)),
+            _ => {}
+        }
+    }
+    Some((session?, ghost?, host?))
+}
+
+/// Payload entries as keys and values, up to the first truncated one.
+fn entries(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut entries = Vec::new();
+    while data.len() >= 8 {
+        let key = data[0..4].try_into().unwrap();
+        let size = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        let end = match 8usize.checked_add(size) {
+            Some(end) if end <= data.len() => end,
+            _ => break,
+        };
+        entries.push((key, &data[8..end]));
+        data = &data[end..];
+    }
+    entries
+}
+
+fn read_i64(data: &[u8]) -> i64 {
+    i64::from_be_bytes(data.try_into().unwrap())
+}
+
+fn micros(start: Instant) -> i64 {
+    start.elapsed().as_micros() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: NodeId = *b"peer0001";
+    const SESSION_ID: NodeId = *b"session1";
+
+    fn entry(key: &[u8; 4], value: &[u8]) -> Vec<u8> {
+        let mut data = key.to_vec();
+        data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        data.extend_from_slice(value);
+        data
+    }
+
+    fn announcement(payload: &[u8]) -> Vec<u8> {
+        let mut data = DISCOVERY_HEADER.to_vec();
+        data.extend_from_slice(&[ALIVE, 5, 0, 0]);
+        data.extend_from_slice(&ID);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn timeline() -> Vec<u8> {
+        [500_000i64, 2_000_000, 1_000]
+            .iter()
+            .flat_map(|x| x.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn valid_announcement_adds_peer() {
+        let mut payload = entry(TIMELINE, &timeline());
+        payload.extend(entry(SESSION, &SESSION_ID));
+        payload.extend(entry(MEASUREMENT_ENDPOINT, &[192, 168, 1, 42, 0x1f, 0x90]));
+        let mut peers = HashMap::new();
+        handle_announcement(&announcement(&payload), &mut peers);
+        let peer = &peers[&ID];
+        assert!(peer.session == SESSION_ID);
+        assert!(
+            peer.timeline
+                == Timeline {
+                    micros_per_beat: 500_000,
+                    beat_origin: 2_000_000,
+                    time_origin: 1_000,
+                }
+        );
+        assert_eq!(
+            peer.endpoint,
+            Some(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 42), 8080))
+        );
+    }
+
+    #[test]
+    fn truncated_announcement_is_ignored() {
+        let mut payload = entry(SESSION, &SESSION_ID);
+        payload.extend(entry(TIMELINE, &timeline()));
+        payload.truncate(payload.len() - 4);
+        let mut peers = HashMap::new();
+        handle_announcement(&announcement(&payload), &mut peers);
+        assert!(peers.is_empty());
+        assert_eq!(entries(&payload).len(), 1);
+    }
+
+    #[test]
+    fn oversized_entry_stops_parsing() {
+        let mut payload = entry(SESSION, &SESSION_ID);
+        payload.extend_from_slice(TIMELINE);
+        payload.extend_from_slice(&u32::MAX.to_be_bytes());
+        payload.extend(timeline());
+        let mut peers = HashMap::new();
+        handle_announcement(&announcement(&payload), &mut peers);
+        assert!(peers.is_empty());
+        let entries = entries(&payload);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0], (*SESSION, &SESSION_ID[..]));
+    }
+}
@@ -0,0 +1,141 @@
+//! Log records of all threads are kept for the log screen and appended to a file next to the
+//! garden once the host allows files, so parse and device warnings are visible without a terminal
+//! attached. Records are passed to a logger thread to keep file IO off the audio thread.
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use crossbeam_channel::{Receiver, Sender};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Older entries are dropped from the journal, the file keeps everything.
+const JOURNAL_SIZE: usize = 1024;
+
+pub struct Entry {
+    pub time: String,
+    pub level: Level,
+    /// Module which logged the record, e.g. audio_program.
+    pub target: String,
+    pub message: String,
+    /// Time with date and zone for the file.
+    stamp: String,
+    /// Full module path and line of the record for the file.
+    location: Option<String>,
+}
+
+impl Entry {
+    /// One JSON object per line to let tools filter the file by fields.
+    fn write(&self, file: &mut File) {
+        let line = json!({
+            "time": self.stamp,
+            "level": self.level.to_string(),
+            "target": self.target,
+            "location": self.location,
+            "message": self.message,
+        });
+        writeln!(file, "{}", line).ok();
+    }
+}
+
+enum Message {
+    Entry(Entry),
+    AllowFile,
+}
+
+pub struct Journal {
+    pub entries: VecDeque<Entry>,
+    /// Number of entries ever logged, to notice new ones.
+    pub total: usize,
+    tx: Sender<Message>,
+}
+
+impl Journal {
+    /// Append entries to the file from now on, starting with the ones logged before.
+    pub fn allow_file(&self) {
+        self.tx.send(Message::AllowFile).ok();
+    }
+}
+
+pub type SharedJournal = Arc<Mutex<Journal>>;
+
+struct Logger {
+    tx: Sender<Message>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = Local::now();
+        let entry = Entry {
+            time: now.format("%H:%M:%S").to_string(),
+            level: record.level(),
+            target: record.target().split("::").next().unwrap().to_owned(),
+            message: record.args().to_string(),
+            stamp: now.to_rfc3339(),
+            location: record
+                .module_path()
+                .map(|path| format!("{}:{}", path, record.line().unwrap_or(0))),
+        };
+        self.tx.send(Message::Entry(entry)).ok();
+    }
+
+    /// The file is written by the logger thread as entries come.
+    fn flush(&self) {}
+}
+
+/// Install the logger which appends to `<filename>.log` once files are allowed.
+pub fn init(filename: &str) -> Result<SharedJournal> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let journal = SharedJournal::new(Mutex::new(Journal {
+        entries: VecDeque::new(),
+        total: 0,
+        tx: tx.clone(),
+    }));
+    let path = format!("{}.log", filename);
+    let thread_journal = Arc::clone(&journal);
+    std::thread::Builder::new()
+        .name("logger".to_owned())
+        .spawn(move || run(&path, &thread_journal, rx))?;
+    log::set_boxed_logger(Box::new(Logger { tx }))
+        .map_err(|e| anyhow!("Failed to set logger: {}", e))?;
+    log::set_max_level(LevelFilter::Info);
+    Ok(journal)
+}
+
+fn run(path: &str, journal: &Mutex<Journal>, rx: Receiver<Message>) {
+    // Opened once the host allows files.
+    let mut file: Option<File> = None;
+    for message in rx {
+        match message {
+            Message::Entry(entry) => {
+                if let Some(file) = file.as_mut() {
+                    entry.write(file);
+                }
+                let mut journal = journal.lock().unwrap();
+                if journal.entries.len() >= JOURNAL_SIZE {
+                    journal.entries.pop_front();
+                }
+                journal.entries.push_back(entry);
+                journal.total += 1;
+            }
+            Message::AllowFile if file.is_none() => {
+                file = OpenOptions::new().create(true).append(true).open(path).ok();
+                if let Some(file) = file.as_mut() {
+                    for entry in &journal.lock().unwrap().entries {
+                        entry.write(file);
+                    }
+                }
+            }
+            Message::AllowFile => {}
+        }
+    }
+}
@@ -1,10 +1,22 @@
 mod audio;
+mod classroom;
+mod crash;
 mod event;
+mod grid;
+mod jam;
+mod link;
+mod logger;
+mod osc;
 mod record;
+mod speech;
+mod stats;
+mod text;
+mod tuner;
+mod udp;
 mod ui;
 
 use anyhow::{anyhow, Result};
-use audio_vm::{Sample, VM};
+use audio_vm::{Frame, Sample, CHANNELS, VM};
 use ringbuf::RingBuffer;
 use std::sync::{Arc, Mutex};
 use thread_worker::Worker;
@@ -12,32 +24,68 @@ use thread_worker::Worker;
 const CHANNEL_CAPACITY: usize = 64;
 /// It's about 500ms, should be more than enough for write cycle of ~10ms.
 const RECORD_BUFFER_CAPACITY: usize = 48000;
+/// About a second of input to analyze between UI updates.
+const TUNER_BUFFER_CAPACITY: usize = 48000;
+/// How far back in time (in seconds) scrubbing could go.
+const HISTORY_DURATION: usize = 60;
 
 pub fn main() -> Result<()> {
-    let filename = std::env::args().skip(1).next();
+    let mut args = std::env::args().skip(1);
+    let filename = args.next();
 
     if filename.is_none() {
         return Err(anyhow!("Filename is required."));
     }
 
     let filename = filename.unwrap();
+    let journal = logger::init(&filename)?;
+    let snapshot = crash::install(&filename);
 
     let vm = Arc::new(Mutex::new(VM::new()));
     {
         vm.lock().unwrap().stop();
     }
+
+    // Optional address to listen for peers' commits followed by peers' addresses.
+    let jam = match args.next() {
+        Some(address) => Some(jam::Jam::new(
+            address,
+            &args.collect::<Vec<_>>(),
+            Arc::clone(&vm),
+        )?),
+        None => None,
+    };
+
+    let stats = Arc::new(Mutex::new(stats::AudioStats::default()));
+    let input = Arc::new(Mutex::new([0.0; CHANNELS]));
+    let monitor = Arc::new(Mutex::new(audio::Monitor::default()));
+    let desks = Arc::new(Mutex::new(Vec::new()));
     let rb = RingBuffer::<Sample>::new(RECORD_BUFFER_CAPACITY);
     let (producer, consumer) = rb.split();
+    let (tuner_producer, tuner_consumer) = RingBuffer::<Frame>::new(TUNER_BUFFER_CAPACITY).split();
 
     let audio_wrk = {
-        let vm = Arc::clone(&vm);
+        let taps = audio::Taps {
+            vm: Arc::clone(&vm),
+            producer,
+            stats: Arc::clone(&stats),
+            input: Arc::clone(&input),
+            tuner_producer,
+            monitor: Arc::clone(&monitor),
+            desks: Arc::clone(&desks),
+        };
         Worker::spawn("Audio", CHANNEL_CAPACITY, move |i, o| {
-            audio::main(vm, producer, i, o).unwrap();
+            audio::main(taps, i, o).unwrap();
         })
     };
 
     let sample_rate = audio_wrk.receiver().recv()?;
 
+    let history = vec![[0.0; CHANNELS]; HISTORY_DURATION * sample_rate as usize];
+    // Deallocate the previous history outside of the lock.
+    let garbage = vm.lock().unwrap().set_history(history);
+    drop(garbage);
+
     let record_wrk = {
         let filename = filename.clone();
         Worker::spawn("Record", CHANNEL_CAPACITY, move |i, o| {
@@ -45,7 +93,24 @@ pub fn main() -> Result<()> {
         })
     };
 
-    ui::main(vm, sample_rate, &filename, record_wrk.sender())?;
+    let host = ui::Host {
+        vm,
+        sample_rate,
+        filename: &filename,
+        stats,
+        monitor,
+        record_tx: record_wrk.sender(),
+        audio_tx: audio_wrk.sender(),
+    };
+    ui::main(
+        host,
+        input,
+        desks,
+        tuner::Tuner::new(sample_rate, tuner_consumer),
+        journal,
+        snapshot,
+        jam,
+    )?;
 
     drop(record_wrk);
     drop(audio_wrk);
@@ -0,0 +1,152 @@
+//! Edit nodes over OSC, so external generative systems and hardware could rewrite parts of the
+//! program during a performance.
+//!
+//! `/node/<id>/set <op>` replaces the op of the node, ids are in the saved file. Multi-word values
+//! are rejected, as each node holds a single op.
+//! Numbers are accepted along with strings. Changes are drafts until `/commit`.
+//! Only UDP transport is supported, bundles are unpacked but their time tags are ignored.
+use crate::udp;
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Receiver;
+use std::convert::TryInto;
+use std::net::{ToSocketAddrs, UdpSocket};
+use thread_worker::Worker;
+
+pub enum Message {
+    Set { id: u64, op: String },
+    Commit,
+}
+
+pub struct Osc {
+    listener: Worker<(), Message>,
+}
+
+impl Osc {
+    pub fn new<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        let listener = udp::listen("OSC", UdpSocket::bind(address)?, |data, _, messages| {
+            decode_packet(data, messages, &parse_message)
+        })?;
+        Ok(Osc { listener })
+    }
+
+    pub fn receiver(&self) -> &Receiver<Message> {
+        self.listener.receiver()
+    }
+}
+
+/// Argument of a message to send.
+pub enum Arg<'a> {
+    Int(i32),
+    Str(&'a str),
+}
+
+pub fn encode_message(address: &str, args: &[Arg]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_string(&mut data, address);
+    let tags = args.iter().map(|arg| match arg {
+        Arg::Int(_) => 'i',
+        Arg::Str(_) => 's',
+    });
+    write_string(
+        &mut data,
+        &std::iter::once(',').chain(tags).collect::<String>(),
+    );
+    for arg in args {
+        match arg {
+            Arg::Int(x) => data.extend_from_slice(&x.to_be_bytes()),
+            Arg::Str(s) => write_string(&mut data, s),
+        }
+    }
+    data
+}
+
+/// Unpack bundles and pass the address and arguments of each message to `parse`, arguments of
+/// all types are turned into strings.
+pub fn decode_packet<T, F>(data: &[u8], messages: &mut Vec<T>, parse: &F) -> Result<()>
+where
+    F: Fn(&str, Vec<String>) -> Result<T>,
+{
+    if data.starts_with(b"#bundle\0") {
+        // Skip the time tag.
+        let mut data = data.get(16..).ok_or_else(|| anyhow!("Truncated bundle."))?;
+        while !data.is_empty() {
+            let size = read_i32(&mut data)? as usize;
+            let element = data
+                .get(..size)
+                .ok_or_else(|| anyhow!("Truncated bundle element."))?;
+            decode_packet(element, messages, parse)?;
+            data = &data[size..];
+        }
+        return Ok(());
+    }
+    let mut data = data;
+    let address = read_string(&mut data)?;
+    let tags = read_string(&mut data)?;
+    let mut args = Vec::new();
+    for tag in tags.trim_start_matches(',').chars() {
+        args.push(match tag {
+            's' => read_string(&mut data)?.to_owned(),
+            'i' => read_i32(&mut data)?.to_string(),
+            'f' => f32::from_bits(read_i32(&mut data)? as u32).to_string(),
+            'h' => i64::from_be_bytes(take(&mut data, 8)?.try_into()?).to_string(),
+            'd' => f64::from_bits(u64::from_be_bytes(take(&mut data, 8)?.try_into()?)).to_string(),
+            _ => return Err(anyhow!("Unsupported argument type {}.", tag)),
+        });
+    }
+    messages.push(parse(address, args)?);
+    Ok(())
+}
+
+fn parse_message(address: &str, args: Vec<String>) -> Result<Message> {
+    let path = address.split('/').skip(1).collect::<Vec<_>>();
+    match path.as_slice() {
+        ["node", id, "set"] => {
+            let id: u64 = id.parse()?;
+            // Each node holds a single op, more words would be mangled into one op.
+            if args.len() > 1 || args.iter().any(|arg| arg.contains(char::is_whitespace)) {
+                log::warn!(
+                    "Ignoring {} with {:?}, a node holds a single op.",
+                    address,
+                    args
+                );
+                return Err(anyhow!("More than one op for the node {}.", id));
+            }
+            Ok(Message::Set {
+                id,
+                op: args.into_iter().next().unwrap_or_default(),
+            })
+        }
+        ["commit"] => Ok(Message::Commit),
+        _ => Err(anyhow!("Unknown address {}.", address)),
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8]> {
+    if data.len() < size {
+        return Err(anyhow!("Truncated message."));
+    }
+    let (head, tail) = data.split_at(size);
+    *data = tail;
+    Ok(head)
+}
+
+fn read_i32(data: &mut &[u8]) -> Result<i32> {
+    Ok(i32::from_be_bytes(take(data, 4)?.try_into()?))
+}
+
+/// Read null-terminated string padded to 4 bytes.
+fn read_string<'a>(data: &mut &'a [u8]) -> Result<&'a str> {
+    let size = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| anyhow!("Unterminated string."))?;
+    let s = std::str::from_utf8(&data[..size])?;
+    take(data, (size / 4 + 1) * 4)?;
+    Ok(s)
+}
+
+/// Write null-terminated string padded to 4 bytes.
+fn write_string(data: &mut Vec<u8>, s: &str) {
+    data.extend_from_slice(s.as_bytes());
+    data.resize(data.len() + 4 - s.len() % 4, 0);
+}
@@ -4,12 +4,64 @@ use chrono::Local;
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use hound::{SampleFormat, WavSpec, WavWriter};
 use ringbuf::Consumer;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::process::Command;
+use std::thread::JoinHandle;
+
+pub enum Message {
+    /// Start or stop recording.
+    Record(bool),
+    /// Program text committed while recording.
+    Commit(String),
+    /// Format of the next recordings.
+    Format(Format),
+    /// When to split recordings into parts.
+    Split(Split),
+}
+
+/// Recordings are always written as WAV first and then encoded with `flac` or `opusenc`
+/// command line tools, WAV is removed on success and kept with a warning otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    Wav,
+    Flac,
+    Opus,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Wav
+    }
+}
+
+impl Format {
+    pub fn next(self) -> Self {
+        match self {
+            Format::Wav => Format::Flac,
+            Format::Flac => Format::Opus,
+            Format::Opus => Format::Wav,
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Format::Wav => "wav",
+            Format::Flac => "flac",
+            Format::Opus => "opus",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 pub fn main(
     base_filename: &str,
     sample_rate: u32,
     mut consumer: Consumer<Sample>,
-    rx: Receiver<bool>,
+    rx: Receiver<Message>,
     _tx: Sender<()>,
 ) -> Result<()> {
     let spec = WavSpec {
@@ -18,28 +70,253 @@ pub fn main(
         bits_per_sample: 16,
         sample_format: SampleFormat::Int,
     };
-    let mut writer: Option<WavWriter<std::io::BufWriter<std::fs::File>>> = None;
+    let mut recording: Option<Recording> = None;
+    let mut format = Format::default();
+    let mut split = Split::default();
+    let mut encoders = Vec::new();
     loop {
         match rx.try_recv() {
-            Ok(on) => {
-                writer.take().and_then(|w| w.finalize().ok());
+            Ok(Message::Record(on)) => {
+                if let Some(r) = recording.take() {
+                    encoders.extend(r.finish(format));
+                }
                 consumer.pop_each(|_| true, None);
                 if on {
-                    let filename = format!("{}-{}.wav", base_filename, Local::now().to_rfc3339());
-                    writer = Some(WavWriter::create(filename, spec)?);
+                    let name = format!("{}-{}", base_filename, Local::now().to_rfc3339());
+                    recording = Some(Recording::new(name, 1, None, spec)?);
                 }
             }
+            Ok(Message::Commit(program)) => {
+                if let Some(r) = recording.as_mut() {
+                    r.commit(program);
+                }
+            }
+            Ok(Message::Format(next_format)) => {
+                format = next_format;
+            }
+            Ok(Message::Split(next_split)) => {
+                split = next_split;
+            }
             Err(TryRecvError::Disconnected) => {
+                if let Some(r) = recording.take() {
+                    encoders.extend(r.finish(format));
+                }
+                for encoder in encoders {
+                    encoder.join().ok();
+                }
                 return Ok(());
             }
             Err(TryRecvError::Empty) => {}
         }
         let write = |sample: Sample| {
-            let sample = (sample * std::i16::MAX as Sample) as i16;
-            writer.as_mut().map(|w| w.write_sample(sample));
+            if let Some(r) = recording.as_mut() {
+                // Split only between frames to keep channels in place.
+                if r.samples_written % CHANNELS as u64 == 0 && split.is_due(r.samples_written, spec)
+                {
+                    // Keep writing to the current part if the next one can't be created.
+                    if let Ok(next) = r.next_part(spec) {
+                        encoders.extend(std::mem::replace(r, next).finish(format));
+                    }
+                }
+                let sample = (sample * std::i16::MAX as Sample) as i16;
+                r.writer.write_sample(sample).ok();
+                r.samples_written += 1;
+            }
             true
         };
         consumer.pop_each(write, None);
         std::thread::sleep(std::time::Duration::from_millis(10));
     }
 }
+
+/// Long recordings are split into parts to keep files manageable, no samples are lost between
+/// parts. Set `record_split` in the program file to configure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Split {
+    /// Max part duration in seconds.
+    pub duration: Option<u64>,
+    /// Max part size in bytes.
+    pub size: Option<u64>,
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Split {
+            duration: None,
+            // WAV can't be larger than 4 GiB anyway.
+            size: Some(2 << 30),
+        }
+    }
+}
+
+impl Split {
+    /// Zero duration or size would split every frame into its own part, ignore it with a warning.
+    pub fn validate(self) -> Self {
+        let positive = |limit: Option<u64>, name| {
+            if limit == Some(0) {
+                log::warn!("Zero record_split {} is ignored.", name);
+                None
+            } else {
+                limit
+            }
+        };
+        Split {
+            duration: positive(self.duration, "duration"),
+            size: positive(self.size, "size"),
+        }
+    }
+
+    fn is_due(&self, samples_written: u64, spec: WavSpec) -> bool {
+        let samples_per_second = u64::from(spec.sample_rate) * u64::from(spec.channels);
+        let bytes_per_sample = u64::from(spec.bits_per_sample / 8);
+        // Header and INFO chunk are small enough to be ignored.
+        self.duration
+            .map(|duration| samples_written >= duration * samples_per_second)
+            .unwrap_or(false)
+            || self
+                .size
+                .map(|size| samples_written * bytes_per_sample >= size)
+                .unwrap_or(false)
+    }
+}
+
+struct Recording {
+    /// Base filename and the start time shared by all parts.
+    name: String,
+    part: usize,
+    writer: WavWriter<std::io::BufWriter<std::fs::File>>,
+    /// Log of commits: offset, program hash and text.
+    commits: Vec<String>,
+    /// The last committed program to continue the log of the next part.
+    program: Option<String>,
+    samples_written: u64,
+}
+
+impl Recording {
+    fn new(name: String, part: usize, program: Option<String>, spec: WavSpec) -> Result<Self> {
+        let writer = WavWriter::create(format!("{}-{:03}.wav", name, part), spec)?;
+        let mut recording = Recording {
+            name,
+            part,
+            writer,
+            commits: Vec::new(),
+            program: None,
+            samples_written: 0,
+        };
+        if let Some(program) = program {
+            recording.commit(program);
+        }
+        Ok(recording)
+    }
+
+    fn next_part(&self, spec: WavSpec) -> Result<Self> {
+        Recording::new(
+            self.name.to_owned(),
+            self.part + 1,
+            self.program.to_owned(),
+            spec,
+        )
+    }
+
+    fn commit(&mut self, program: String) {
+        let spec = self.writer.spec();
+        let offset =
+            self.samples_written as f64 / (f64::from(spec.sample_rate) * f64::from(spec.channels));
+        self.commits.push(format!(
+            "{:.3}s {:016x} {}",
+            offset,
+            hash(&program),
+            program
+        ));
+        self.program = Some(program);
+    }
+
+    /// Finalize WAV, embed commits and start encoding to the target format if needed.
+    fn finish(self, format: Format) -> Option<JoinHandle<()>> {
+        let filename = format!("{}-{:03}.wav", self.name, self.part);
+        self.writer.finalize().ok()?;
+        let comment = self.commits.join("\n");
+        write_info(&filename, &comment).ok();
+        let output = format!("{}-{:03}.{}", self.name, self.part, format);
+        let tag = format!("COMMENT={}", comment);
+        let mut command = match format {
+            Format::Wav => return None,
+            Format::Flac => {
+                let mut command = Command::new("flac");
+                command
+                    .arg("--silent")
+                    .arg("--best")
+                    .arg("--force")
+                    .arg("-T")
+                    .arg(tag)
+                    .arg("-o")
+                    .arg(output)
+                    .arg(&filename);
+                command
+            }
+            Format::Opus => {
+                let mut command = Command::new("opusenc");
+                command
+                    .arg("--quiet")
+                    .arg("--comment")
+                    .arg(tag)
+                    .arg(&filename)
+                    .arg(output);
+                command
+            }
+        };
+        // Encoding could take a while, don't block recording meanwhile.
+        Some(std::thread::spawn(move || match command.status() {
+            Ok(status) if status.success() => {
+                std::fs::remove_file(filename).ok();
+            }
+            Ok(status) => log::warn!(
+                "Failed to encode {} to {}: {:?} {}, keeping WAV.",
+                filename,
+                format,
+                command.get_program(),
+                status
+            ),
+            Err(e) => log::warn!(
+                "Failed to encode {} to {}: can't run {:?}: {}, keeping WAV.",
+                filename,
+                format,
+                command.get_program(),
+                e
+            ),
+        }))
+    }
+}
+
+/// Append LIST/INFO chunk with the log of commits (offset, program hash and text, one per line)
+/// to the finalized WAV file, so the recording could be traced back to the programs played.
+fn write_info(filename: &str, comment: &str) -> Result<()> {
+    let mut info = b"INFO".to_vec();
+    for (id, text) in &[(b"ISFT", "Sound Garden"), (b"ICMT", comment)] {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        info.extend_from_slice(*id);
+        info.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        info.extend_from_slice(&data);
+        // Chunks are word aligned.
+        if data.len() % 2 == 1 {
+            info.push(0);
+        }
+    }
+    let mut f = OpenOptions::new().write(true).open(filename)?;
+    // RIFF size doesn't count its own 8 bytes header which is the same size as LIST header.
+    let riff_size = f.seek(SeekFrom::End(0))? + info.len() as u64;
+    f.write_all(b"LIST")?;
+    f.write_all(&(info.len() as u32).to_le_bytes())?;
+    f.write_all(&info)?;
+    f.seek(SeekFrom::Start(4))?;
+    f.write_all(&(riff_size as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// FNV-1a, stable across platforms and Rust versions unlike DefaultHasher.
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,39 @@
+//! Spoken announcements for screen reader users, because terminal screen readers can't follow
+//! the cursor around the canvas. Speech is synthesized by speech-dispatcher's `spd-say` or
+//! by `say` on macOS, announcements are skipped silently if the tool is not available.
+use std::process::{Child, Command, Stdio};
+
+#[derive(Default)]
+pub struct Speech {
+    child: Option<Child>,
+}
+
+impl Speech {
+    /// Interrupt the previous announcement if it's still going, the latest state matters most.
+    pub fn say(&mut self, text: &str) {
+        if let Some(mut child) = self.child.take() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+        self.child = command(text)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok();
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn command(text: &str) -> Command {
+    let mut command = Command::new("say");
+    command.arg(text);
+    command
+}
+
+#[cfg(not(target_os = "macos"))]
+fn command(text: &str) -> Command {
+    let mut command = Command::new("spd-say");
+    // Messages of text priority cancel each other, spd-say itself returns immediately.
+    command.arg("--priority").arg("text").arg(text);
+    command
+}
@@ -0,0 +1,129 @@
+//! Session statistics to export a report on quit.
+use anyhow::Result;
+use audio_vm::{Frame, Sample, CHANNELS};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Statistics reported by the audio thread.
+#[derive(Default)]
+pub struct AudioStats {
+    /// Share of the real time spent in synthesis, for each second of the session.
+    pub cpu_load: Vec<f64>,
+    /// RMS loudness of the output in dBFS, for each second of the session.
+    pub loudness: Vec<f64>,
+    /// Number of errors reported by the streams, underruns among them when the host reports those.
+    pub stream_errors: usize,
+    /// Number of times the output stream died and was restarted.
+    pub restarts: usize,
+}
+
+/// Accumulates statistics in the audio thread and flushes them to the shared AudioStats
+/// once per second to keep locking rare.
+pub struct AudioStatsCollector {
+    busy: Duration,
+    frames: usize,
+    sample_rate: usize,
+    stats: Arc<Mutex<AudioStats>>,
+    sum_squares: Sample,
+}
+
+impl AudioStatsCollector {
+    pub fn new(sample_rate: u32, stats: Arc<Mutex<AudioStats>>) -> Self {
+        AudioStatsCollector {
+            busy: Duration::default(),
+            frames: 0,
+            sample_rate: sample_rate as _,
+            stats,
+            sum_squares: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn frame(&mut self, frame: &Frame) {
+        self.frames += 1;
+        for x in frame {
+            self.sum_squares += x * x;
+        }
+    }
+
+    pub fn busy(&mut self, duration: Duration) {
+        self.busy += duration;
+        if self.frames >= self.sample_rate {
+            let real_time = self.frames as f64 / self.sample_rate as f64;
+            let rms = (self.sum_squares / (self.frames * CHANNELS) as Sample).sqrt();
+            let mut stats = self.stats.lock().unwrap();
+            stats.cpu_load.push(self.busy.as_secs_f64() / real_time);
+            stats.loudness.push((20.0 * rms.log10()).max(-120.0));
+            self.busy = Duration::default();
+            self.frames = 0;
+            self.sum_squares = 0.0;
+        }
+    }
+
+    pub fn stream_error(&mut self) {
+        self.stats.lock().unwrap().stream_errors += 1;
+    }
+
+    pub fn restart(&mut self) {
+        self.stats.lock().unwrap().restarts += 1;
+    }
+}
+
+/// Statistics collected by UI.
+pub struct Session {
+    pub commits: usize,
+    pub ops: BTreeSet<String>,
+    pub started_at: DateTime<Local>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            commits: 0,
+            ops: Default::default(),
+            started_at: Local::now(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub started_at: String,
+    /// In seconds.
+    pub duration: i64,
+    pub commits: usize,
+    pub stream_errors: usize,
+    pub restarts: usize,
+    pub cpu_load: &'a [f64],
+    pub loudness: &'a [f64],
+    pub ops: &'a BTreeSet<String>,
+}
+
+impl Session {
+    /// Save report next to the recordings, named after the session end time without colons,
+    /// which some file systems don't allow.
+    pub fn save_report(&self, audio_stats: &AudioStats, base_filename: &str) -> Result<()> {
+        let now = Local::now();
+        let report = Report {
+            started_at: self.started_at.to_rfc3339(),
+            duration: (now - self.started_at).num_seconds(),
+            commits: self.commits,
+            stream_errors: audio_stats.stream_errors,
+            restarts: audio_stats.restarts,
+            cpu_load: &audio_stats.cpu_load,
+            loudness: &audio_stats.loudness,
+            ops: &self.ops,
+        };
+        let filename = format!(
+            "{}-{}.report.json",
+            base_filename,
+            now.to_rfc3339().replace(':', "")
+        );
+        let f = std::fs::File::create(filename)?;
+        serde_json::to_writer_pretty(f, &report)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+//! Cursor math on node texts in terminal columns rather than bytes or chars,
+//! because ops and comments could have non-ASCII, wide (e.g. CJK) and composed characters.
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Width of the text in terminal columns.
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// Width of the char in terminal columns, zero for combining ones.
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Byte range of the grapheme which covers the column, if any.
+pub fn grapheme_at(text: &str, column: usize) -> Option<Range<usize>> {
+    let mut x = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        x += grapheme.width();
+        if column < x {
+            return Some(i..(i + grapheme.len()));
+        }
+    }
+    None
+}
+
+/// Byte offset to insert at the column, the end of the text past it.
+pub fn offset(text: &str, column: usize) -> usize {
+    grapheme_at(text, column)
+        .map(|range| range.start)
+        .unwrap_or_else(|| text.len())
+}
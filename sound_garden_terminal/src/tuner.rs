@@ -0,0 +1,75 @@
+//! Tuner for the audio input, handy when performing with acoustic instruments.
+use audio_ops::Yin;
+use audio_vm::{Frame, Op, Sample, Stack};
+use ringbuf::Consumer;
+
+/// Long enough to detect pitch down to ~47 Hz at 48 kHz.
+const WINDOW_SIZE: usize = 2048;
+/// Detect pitch about 10 times per second to save CPU.
+const PERIOD: usize = 4096;
+const THRESHOLD: Sample = 0.2;
+const NOTES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+pub struct Tuner {
+    consumer: Consumer<Frame>,
+    pitch: Option<Sample>,
+    stack: Stack,
+    yin: Yin,
+}
+
+impl Tuner {
+    pub fn new(sample_rate: u32, consumer: Consumer<Frame>) -> Self {
+        Tuner {
+            consumer,
+            pitch: None,
+            stack: Stack::new(),
+            yin: Yin::new(sample_rate, WINDOW_SIZE, PERIOD, THRESHOLD, false),
+        }
+    }
+
+    /// Analyze input received since the last update.
+    /// Last detected pitch is kept while input is unvoiced.
+    pub fn update(&mut self) {
+        let Tuner {
+            consumer,
+            pitch,
+            stack,
+            yin,
+        } = self;
+        consumer.pop_each(
+            |frame| {
+                stack.push(&frame);
+                yin.perform(stack);
+                // Only the first (left) channel is tuned.
+                let frequency = stack.pop()[0];
+                if frequency > 0.0 {
+                    *pitch = Some(frequency);
+                }
+                true
+            },
+            None,
+        );
+    }
+
+    /// Drop input received since the last update without analyzing it.
+    pub fn skip(&mut self) {
+        self.consumer.pop_each(|_| true, None);
+    }
+
+    /// Detected frequency, the nearest note name and the offset from it in cents.
+    pub fn note(&self) -> Option<(Sample, String, Sample)> {
+        self.pitch.map(|frequency| {
+            let midi = 69.0 + 12.0 * (frequency / 440.0).log2();
+            let nearest = midi.round();
+            let note = nearest as i64;
+            let name = format!(
+                "{}{}",
+                NOTES[note.rem_euclid(12) as usize],
+                note.div_euclid(12) - 1
+            );
+            (frequency, name, 100.0 * (midi - nearest))
+        })
+    }
+}
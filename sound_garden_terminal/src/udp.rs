@@ -0,0 +1,41 @@
+//! Datagram listener shared by jams, the classroom and OSC.
+use anyhow::Result;
+use crossbeam_channel::{Receiver, TryRecvError};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+use thread_worker::Worker;
+
+const CHANNEL_CAPACITY: usize = 64;
+/// Max UDP payload size.
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// How often listener checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Receive datagrams on a worker thread and pass along messages `decode` makes of them and their
+/// senders' addresses, until the worker is dropped. Timeouts and datagrams it fails to decode are
+/// ignored alike.
+pub fn listen<T, F>(name: &'static str, socket: UdpSocket, mut decode: F) -> Result<Worker<(), T>>
+where
+    T: Send + 'static,
+    F: FnMut(&[u8], SocketAddr, &mut Vec<T>) -> Result<()> + Send + 'static,
+{
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(Worker::spawn(
+        name,
+        CHANNEL_CAPACITY,
+        move |rx: Receiver<()>, tx| {
+            let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+            let mut messages = Vec::new();
+            while let Err(TryRecvError::Empty) = rx.try_recv() {
+                if let Ok((size, src)) = socket.recv_from(&mut buffer) {
+                    decode(&buffer[..size], src, &mut messages).ok();
+                }
+                for message in messages.drain(..) {
+                    if tx.send(message).is_err() {
+                        return;
+                    }
+                }
+            }
+        },
+    ))
+}
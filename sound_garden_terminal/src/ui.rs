@@ -1,692 +1,525 @@
-use crate::event::{Event, Events};
-use anyhow::{anyhow, Result};
-use audio_program::{compile_program, get_help, get_op_groups, rewrite_terms, Context, TextOp};
-use audio_vm::VM;
-use chrono::prelude::*;
+mod announce;
+mod classroom;
+mod devices;
+mod editor;
+mod export;
+mod gain;
+mod help;
+mod history;
+mod inject;
+mod journal;
+mod ops;
+mod stack;
+mod sunset;
+mod usages;
+mod workshop;
+
+use crate::audio::{self, Monitor};
+use crate::classroom::{Desks, Student, Teacher};
+use crate::crash::{self, Snapshot};
+use crate::event::Events;
+use crate::grid::{self, Grid};
+use crate::jam::{Jam, Patch, PatchNode};
+use crate::logger::SharedJournal;
+use crate::osc::{self, Osc};
+use crate::record;
+use crate::speech::Speech;
+use crate::stats::{AudioStats, Session};
+use crate::text;
+use crate::tuner::Tuner;
+use anyhow::Result;
+use audio_program::{
+    compile_program, gain_staging, get_arities, get_help, get_op_groups, mark_tables_saved,
+    rewrite_terms, rewrite_words, snapshot_tables, Arity, Context, SavedTables, Shared, TextOp,
+};
+use audio_vm::{Frame, Program, VM};
 use crossbeam_channel::Sender;
 use itertools::Itertools;
+use log::Level;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::{self, Write};
+use std::io;
 use std::sync::{Arc, Mutex};
-use termion::cursor;
-use termion::event::Key;
 use termion::input::MouseTerminal;
-use termion::raw::IntoRawMode;
+use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::AlternateScreen;
 use tui::backend::TermionBackend;
-use tui::layout::Rect;
 use tui::style::{Color, Style};
-use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
 use tui::Terminal;
 
 const MIN_X: usize = 2;
 const MIN_Y: usize = 2;
+/// Transport rate changes by it, from it up to 4.
+const WARP_STEP: f64 = 0.05;
+
+/// Terminal the screens are drawn on.
+pub type Term = Terminal<TermionBackend<AlternateScreen<MouseTerminal<RawTerminal<io::Stdout>>>>>;
+
+/// Audio engine, workers and the garden file the screens drive, for the whole session.
+pub struct Host<'a> {
+    pub vm: Arc<Mutex<VM>>,
+    pub sample_rate: u32,
+    pub filename: &'a str,
+    pub stats: Arc<Mutex<AudioStats>>,
+    pub monitor: Arc<Mutex<Monitor>>,
+    pub record_tx: &'a Sender<record::Message>,
+    pub audio_tx: &'a Sender<audio::Message>,
+}
 
 pub fn main(
-    vm: Arc<Mutex<VM>>,
-    sample_rate: u32,
-    filename: &str,
-    record_tx: &Sender<bool>,
+    host: Host,
+    input: Arc<Mutex<Frame>>,
+    desks: Desks,
+    tuner: Tuner,
+    journal: SharedJournal,
+    snapshot: Snapshot,
+    jam: Option<Jam>,
 ) -> Result<()> {
-    let mut app = App::load(&filename).unwrap_or_else(|_| App::new());
-    commit(&mut app, Arc::clone(&vm), sample_rate, filename);
+    let mut app = crash::restore(host.filename)
+        .and_then(|garden| serde_json::from_value(garden).ok())
+        .or_else(|| App::load(host.filename).ok())
+        .unwrap_or_else(App::new);
+    app.ctx.input = input;
+    if app.keep_tables {
+        app.ctx.tables_dir = Some(tables_dir(host.filename));
+    }
+    set_bpm(&mut app);
+    grid::set_masks(&mut app.ctx.grid.lock().unwrap(), &app.grid_steps);
+    // The sequencer is put in front of the program from the initial commit on.
+    app.grid = app
+        .grid_address
+        .as_ref()
+        .map(|address| Grid::new(address, Arc::clone(&app.ctx.grid)))
+        .transpose()?;
+    workshop::start(&mut app);
+    set_monitor(&app, &host.monitor);
+    app.tuner = Some(tuner);
+    if app.ctx.allow_files {
+        journal.lock().unwrap().allow_file();
+    }
+    app.journal = Some(journal);
+    app.snapshot = Some(snapshot);
+    classroom::join(&mut app, desks)?;
+    commit(&mut app, &host);
+    // Join the jam after the initial commit to not override peers' program with ours.
+    app.jam = jam;
+    app.osc = app.osc_address.as_ref().map(Osc::new).transpose()?;
+    host.record_tx
+        .send(record::Message::Format(app.record_format))
+        .ok();
+    app.record_split = app.record_split.validate();
+    host.record_tx
+        .send(record::Message::Split(app.record_split))
+        .ok();
+    if let Some(device) = &app.output_device {
+        host.audio_tx
+            .send(audio::Message::Device(device.to_owned()))
+            .ok();
+    }
+    let mut recorded_program = None;
     let stdout = io::stdout().into_raw_mode()?;
     let stdout = MouseTerminal::from(stdout);
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let mut events = Events::new();
+    if app.rails().is_some() {
+        events.disable_exit_key();
+    }
     loop {
+        let patches = match &app.jam {
+            Some(jam) => jam.receiver().try_iter().collect(),
+            None => Vec::new(),
+        };
+        for patch in patches {
+            apply_patch(&mut app, &host, patch);
+        }
+        // Tempo of the Link session overrides ours while it's there.
+        if let Some(bpm) = app.jam.as_ref().and_then(Jam::bpm) {
+            if app.bpm != bpm {
+                app.bpm = bpm;
+                set_bpm(&mut app);
+            }
+        }
+        let messages = match &app.osc {
+            Some(osc) => osc.receiver().try_iter().collect(),
+            None => Vec::new(),
+        };
+        for message in messages {
+            apply_osc(&mut app, &host, message);
+        }
+        // Pads change the pattern on the thread of the grid, it's saved along with the garden.
+        if app.grid.is_some() {
+            let steps = grid::masks(&app.ctx.grid.lock().unwrap());
+            if steps != app.grid_steps {
+                app.grid_steps = steps;
+                app.save(host.filename).ok();
+            }
+        }
+        classroom::accept_submissions(&mut app, host.sample_rate);
+        if let Some(journal) = &app.journal {
+            let journal = journal.lock().unwrap();
+            if journal.total > app.log_seen {
+                // Show the latest warning to notice it without opening the log.
+                if let Some(entry) = journal
+                    .entries
+                    .iter()
+                    .rev()
+                    .take(journal.total - app.log_seen)
+                    .find(|entry| entry.level <= Level::Warn)
+                {
+                    app.notice = format!("{}: {}", entry.level, entry.message);
+                }
+                app.log_seen = journal.total;
+            }
+        }
+        // Log commits to embed them into the recording.
+        if !app.recording {
+            recorded_program = None;
+        } else if recorded_program.as_ref() != Some(&app.program) {
+            host.record_tx
+                .send(record::Message::Commit(app.program.to_owned()))
+                .ok();
+            recorded_program = Some(app.program.to_owned());
+        }
+        if let Some(tuner) = app.tuner.as_mut() {
+            if app.tuner_enabled {
+                tuner.update();
+            } else {
+                tuner.skip();
+            }
+        }
+        app.scrub = host
+            .vm
+            .lock()
+            .unwrap()
+            .scrub_offset()
+            .map(|offset| offset as f64 / host.sample_rate as f64);
+        stack::update_probes(&mut app, &host.vm);
+        if let Some(Ok(stages)) = app.gain_analysis.as_ref().map(|rx| rx.try_recv()) {
+            app.gain_analysis = None;
+            gain::show_hints(&mut app, &stages);
+        }
+        sunset::update(&mut app, &host.vm, &host.monitor);
+        workshop::update(&mut app, &host.monitor, &mut events);
         app.status = String::new();
         if let Some(ix) = app.node_at_cursor() {
             let node = &app.nodes[ix];
-            if app.cursor.x < node.position.x + node.op.len() {
+            if app.cursor.x < node.position.x + text::width(&node.op) {
                 if let Some(help) = app.op_help.get(&node.op) {
                     app.status = help.to_owned();
                 }
+                if let Some(hint) = app.gain_hints.get(&node.id) {
+                    app.status = hint.to_owned();
+                }
             }
         }
+        if !app.notice.is_empty() {
+            app.status = app.notice.to_owned();
+        }
+        if app.quit_pending {
+            app.status = "Press q again to quit.".to_owned();
+        }
+        if app.announce {
+            announce::focus(&mut app);
+        }
         match app.screen {
-            Screen::Editor => render_editor(&mut app, &mut terminal)?,
-            Screen::Help => render_help(&mut app, sample_rate, &filename, &mut terminal)?,
-            Screen::Ops => render_ops(&mut app, sample_rate, &filename, &mut terminal)?,
+            Screen::Editor => editor::render(&mut app, &mut terminal)?,
+            Screen::Help => help::render(&mut app, host.sample_rate, host.filename, &mut terminal)?,
+            Screen::Ops => ops::render(&mut app, host.sample_rate, host.filename, &mut terminal)?,
+            Screen::History => history::render(&mut app, &mut terminal)?,
+            Screen::Devices => devices::render(&mut app, &mut terminal)?,
+            Screen::Log => journal::render(&mut app, &mut terminal)?,
+            Screen::Usages => usages::render(&mut app, &mut terminal)?,
         };
 
         match app.screen {
-            Screen::Editor => handle_editor(
-                &mut app,
-                Arc::clone(&vm),
-                sample_rate,
-                &filename,
-                &mut events,
-                record_tx,
-            )?,
-            Screen::Help => handle_help(&mut app, &mut events)?,
-            Screen::Ops => handle_ops(&mut app, &mut events)?,
+            Screen::Editor => editor::handle(&mut app, &host, &mut events)?,
+            Screen::Help => help::handle(&mut app, &mut events)?,
+            Screen::Ops => ops::handle(&mut app, &mut events)?,
+            Screen::History => history::handle(&mut app, &host, &mut events)?,
+            Screen::Devices => devices::handle(&mut app, &host, &mut events)?,
+            Screen::Log => journal::handle(&mut app, &mut events)?,
+            Screen::Usages => usages::handle(&mut app, &mut events)?,
         };
     }
 }
 
-fn render_editor(
-    app: &mut App,
-    terminal: &mut Terminal<
-        TermionBackend<AlternateScreen<MouseTerminal<termion::raw::RawTerminal<std::io::Stdout>>>>,
-    >,
-) -> Result<()> {
-    terminal.draw(|mut f| {
-        let size = f.size();
-        let mut nodes_to_drop = Vec::new();
-        for (
-            i,
-            Node {
-                op,
-                draft,
-                position: p,
-                ..
-            },
-        ) in app.nodes.iter().enumerate()
-        {
-            if p.x < MIN_X
-                || p.y < MIN_Y
-                || p.x + op.len() > size.width as _
-                || p.y + 1 > size.height as _
-            {
-                nodes_to_drop.push(i);
-                continue;
+/// High contrast theme avoids dim colors and marks drafts with background.
+pub struct Theme {
+    node: Style,
+    draft: Style,
+    muted: Style,
+    solo: Style,
+    hint: Style,
+    paused: Color,
+    draft_border: Color,
+    live: Color,
+    info: Color,
+}
+
+impl Theme {
+    pub fn new(high_contrast: bool) -> Self {
+        if high_contrast {
+            Theme {
+                node: Style::default().fg(Color::White).bg(Color::Black),
+                draft: Style::default().fg(Color::Black).bg(Color::Yellow),
+                muted: Style::default().fg(Color::Black).bg(Color::White),
+                solo: Style::default().fg(Color::Black).bg(Color::Cyan),
+                hint: Style::default().fg(Color::Black).bg(Color::Magenta),
+                paused: Color::White,
+                draft_border: Color::Yellow,
+                live: Color::White,
+                info: Color::White,
             }
-            let text = [Text::raw(op.to_owned())];
-            Paragraph::new(text.iter())
-                .style(Style::default().fg(if *draft { Color::Red } else { Color::White }))
-                .render(
-                    &mut f,
-                    Rect::new((p.x - 1) as _, (p.y - 1) as _, op.len() as _, 1),
-                );
-        }
-        for ix in nodes_to_drop.drain(..) {
-            app.nodes.swap_remove(ix);
-            app.draft = true;
-        }
-        let color = if !app.play {
-            Color::Gray
-        } else if app.draft || app.nodes.iter().any(|node| node.draft) {
-            Color::Red
         } else {
-            Color::White
-        };
-        Block::default()
-            .title(&format!(
-                "Sound Garden────{}────{}────{}",
-                if app.play { "|>" } else { "||" },
-                if app.recording {
-                    if Utc::now().second() % 2 == 0 {
-                        "•R"
-                    } else {
-                        " R"
-                    }
-                } else {
-                    ""
-                },
-                app.status
-            ))
-            .title_style(Style::default().fg(color))
-            .borders(Borders::ALL)
-            .border_style(Style::default().fg(color))
-            .render(&mut f, size);
-    })?;
-    write!(
-        terminal.backend_mut(),
-        "{}{}",
-        cursor::Show,
-        cursor::Goto(app.cursor.x as _, app.cursor.y as _),
-    )?;
-    match app.input_mode {
-        InputMode::Normal => write!(terminal.backend_mut(), "{}", cursor::SteadyBlock)?,
-        InputMode::Editing => write!(terminal.backend_mut(), "{}", cursor::SteadyUnderline)?,
+            Theme {
+                node: Style::default().fg(Color::White),
+                draft: Style::default().fg(Color::Red),
+                muted: Style::default().fg(Color::DarkGray),
+                solo: Style::default().fg(Color::Cyan),
+                hint: Style::default().fg(Color::Magenta),
+                paused: Color::Gray,
+                draft_border: Color::Red,
+                live: Color::White,
+                info: Color::Green,
+            }
+        }
     }
-    io::stdout().flush()?;
-    Ok(())
 }
 
-fn render_help(
-    app: &mut App,
-    sample_rate: u32,
-    filename: &str,
-    terminal: &mut Terminal<
-        TermionBackend<AlternateScreen<MouseTerminal<termion::raw::RawTerminal<std::io::Stdout>>>>,
-    >,
-) -> Result<()> {
-    terminal.draw(|mut f| {
-        let mut size = f.size();
-        Block::default()
-            .title("Sound Garden────Help")
-            .title_style(Style::default().fg(Color::Green))
-            .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::Green))
-            .render(&mut f, size);
-        let text = [
-            Text::raw(format!("Path: {}\n", filename)),
-            Text::raw(format!("Sample rate: {}\n", sample_rate)),
-            Text::raw(format!(
-                "Cycles: {}\n",
-                app.cycles.iter().map(|cycle| cycle.join("->")).join(", ")
-            )),
-            Text::raw(format!("Program: {}\n", app.program)),
-            Text::raw(format!("\n")),
-            Text::raw(include_str!("help.txt")),
-        ];
-        size.x = MIN_X as u16;
-        size.y = MIN_Y as u16;
-        size.width -= 3;
-        size.height -= 3;
-        Paragraph::new(text.iter())
-            .scroll(app.help_scroll)
-            .wrap(true)
-            .render(&mut f, size);
-    })?;
-    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
-    io::stdout().flush()?;
-    Ok(())
-}
-
-fn render_ops(
-    app: &mut App,
-    sample_rate: u32,
-    filename: &str,
-    terminal: &mut Terminal<
-        TermionBackend<AlternateScreen<MouseTerminal<termion::raw::RawTerminal<std::io::Stdout>>>>,
-    >,
-) -> Result<()> {
-    terminal.draw(|mut f| {
-        let mut size = f.size();
-        Block::default()
-            .title("Sound Garden────Ops")
-            .title_style(Style::default().fg(Color::Green))
-            .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::Green))
-            .render(&mut f, size);
-        let text = [
-            Text::raw(format!("Path: {}\n", filename)),
-            Text::raw(format!("Sample rate: {}\n", sample_rate)),
-            Text::raw(format!(
-                "Cycles: {}\n",
-                app.cycles.iter().map(|cycle| cycle.join("->")).join(", ")
-            )),
-            Text::raw(format!("Program: {}\n", app.program)),
-            Text::raw(format!("\n")),
-            Text::raw(format!("(Press Esc to close, j/k to scroll)\n")),
-            Text::raw(format!("\n")),
-            Text::raw(
-                app.op_groups
-                    .iter()
-                    .map(|(group, ops)| format!("=== {}\n{}\n", group, ops.join(", ")))
-                    .join("\n"),
-            ),
-        ];
-        size.x = 2;
-        size.y = 2;
-        size.width -= 3;
-        size.height -= 3;
-        Paragraph::new(text.iter())
-            .scroll(app.help_scroll)
-            .wrap(true)
-            .render(&mut f, size);
-    })?;
-    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
-    io::stdout().flush()?;
-    Ok(())
+fn set_monitor(app: &App, monitor: &Mutex<Monitor>) {
+    *monitor.lock().unwrap() = Monitor {
+        direct: app.monitor_direct,
+        gain: 10.0f64.powf(app.monitor_gain / 20.0),
+        ceiling: app
+            .rails()
+            .map(|workshop| 10.0f64.powf(workshop.max_level / 20.0).min(1.0))
+            .unwrap_or(1.0),
+        master: match sunset::progress(app) {
+            Some(progress) => 10.0f64.powf(sunset::FLOOR * progress / 20.0),
+            None => 1.0,
+        },
+    };
 }
 
-fn handle_editor(
-    app: &mut App,
-    vm: Arc<Mutex<VM>>,
-    sample_rate: u32,
-    filename: &str,
-    events: &mut Events,
-    record_tx: &Sender<bool>,
-) -> Result<()> {
-    match events.next()? {
-        Event::Input(input) => match app.input_mode {
-            InputMode::Normal => match input {
-                Key::Char('\n') => commit(app, vm, sample_rate, filename),
-                Key::Char('\\') => {
-                    app.play = !app.play;
-                    if app.play {
-                        vm.lock().unwrap().play();
-                    } else {
-                        vm.lock().unwrap().pause();
-                    }
-                }
-                Key::Char('i') => {
-                    app.input_mode = InputMode::Editing;
-                    events.disable_exit_key();
-                }
-                Key::Char('I') => {
-                    app.input_mode = InputMode::Editing;
-                    events.disable_exit_key();
-                    let mut push_left = 1;
-                    let push_right = 1;
-                    if let Some(ix) = app.node_at_cursor() {
-                        let Node {
-                            op, position: p, ..
-                        } = &app.nodes[ix];
-                        push_left += p.x + op.len() - app.cursor.x;
-                    }
-                    let p = app.cursor;
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
-                    {
-                        node.position.x -= push_left;
-                    }
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && node.position.x > p.x)
-                    {
-                        node.position.x += push_right;
-                    }
-                }
-                Key::Char('o') => {
-                    app.input_mode = InputMode::Editing;
-                    events.disable_exit_key();
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| node.position.y > p.y) {
-                        node.position.y += 1;
-                    }
-                    app.cursor.x = app
-                        .nodes
+fn commit(app: &mut App, host: &Host) {
+    // Hints are about the committed program.
+    app.gain_hints.clear();
+    app.nodes.sort_by_key(|node| node.position);
+    if let Some(new_program) = compile_nodes(app, host.sample_rate, host.filename) {
+        classroom::submit(app);
+        match &app.jam {
+            Some(jam) => {
+                let apply_at = jam.send(
+                    app.nodes
                         .iter()
-                        .filter(|node| node.position.y == p.y)
-                        .min_by_key(|node| node.position.x)
-                        .map(|node| node.position.x)
-                        .unwrap_or(MIN_X);
-                    app.cursor.y += 1;
-                }
-                Key::Char('c') => {
-                    app.input_mode = InputMode::Editing;
-                    events.disable_exit_key();
-                    if let Some(ix) = app.node_at_cursor() {
-                        let node = &mut app.nodes[ix];
-                        let push_left = node.position.x + node.op.len() - app.cursor.x;
-                        node.op.truncate(app.cursor.x - node.position.x);
-                        node.draft = true;
-                        let p = app.cursor;
-                        for node in app
-                            .nodes
-                            .iter_mut()
-                            .filter(|node| node.position.y == p.y && node.position.x > p.x)
-                        {
-                            node.position.x -= push_left;
-                        }
-                    }
-                }
-                Key::Char('h') | Key::Left | Key::Backspace => {
-                    app.cursor.x -= 1;
-                }
-                Key::Char('j') | Key::Down => {
-                    app.cursor.y += 1;
-                }
-                Key::Char('k') | Key::Up => {
-                    app.cursor.y -= 1;
-                }
-                Key::Char('l') | Key::Right | Key::Char(' ') => {
-                    app.cursor.x += 1;
-                }
-                Key::Alt('h') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        app.nodes[ix].position.x -= 1;
-                    }
-                    app.cursor.x -= 1;
-                }
-                Key::Alt('j') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        app.nodes[ix].position.y += 1;
-                    }
-                    app.cursor.y += 1;
-                }
-                Key::Alt('k') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        app.nodes[ix].position.y -= 1;
-                    }
-                    app.cursor.y -= 1;
-                }
-                Key::Alt('l') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        app.nodes[ix].position.x += 1;
-                    }
-                    app.cursor.x += 1;
-                }
-                Key::Char('J') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| {
-                        node.position.y > p.y
-                            || node.position.y == p.y && p.x < node.position.x + node.op.len()
-                    }) {
-                        node.position.y += 1;
-                    }
-                    app.cursor.y += 1;
-                }
-                Key::Char('K') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| {
-                        node.position.y < p.y || node.position.y == p.y && node.position.x <= p.x
-                    }) {
-                        node.position.y -= 1;
-                    }
-                    app.cursor.y -= 1;
-                }
-                Key::Char('H') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| node.position.y == p.y) {
-                        node.position.y -= 1;
-                    }
-                    app.cursor.y -= 1;
-                }
-                Key::Char('L') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| node.position.y == p.y) {
-                        node.position.y += 1;
-                    }
-                    app.cursor.y += 1;
-                }
-                Key::Char(',') => {
-                    let p = app.cursor;
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
-                    {
-                        node.position.x -= 1;
-                    }
-                    app.cursor.x -= 1;
-                }
-                Key::Char('<') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| {
-                        node.position.y == p.y && p.x < node.position.x + node.op.len()
-                    }) {
-                        node.position.x -= 1;
-                    }
-                    app.cursor.x -= 1;
-                }
-                Key::Char('.') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| {
-                        node.position.y == p.y && p.x < node.position.x + node.op.len()
-                    }) {
-                        node.position.x += 1;
-                    }
-                    app.cursor.x += 1;
-                }
-                Key::Char('>') => {
-                    let p = app.cursor;
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
-                    {
-                        node.position.x += 1;
-                    }
-                    app.cursor.x += 1;
-                }
-                Key::Char('d') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        app.nodes.swap_remove(ix);
-                        app.draft = true;
-                    }
-                }
-                Key::Char('D') => {
-                    let p = app.cursor;
-                    app.nodes.retain(|node| node.position.y != p.y);
-                }
-                Key::Char('=') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        let node = &mut app.nodes[ix];
-                        let i = app.cursor.x - node.position.x;
-                        if let Some(d) = node.op.get(i..(i + 1)).and_then(|c| c.parse::<u8>().ok())
-                        {
-                            let d = (d + 1) % 10;
-                            node.op.replace_range(i..(i + 1), &d.to_string());
-
-                            commit(app, vm, sample_rate, filename);
-                        } else {
-                            for cycle in &app.cycles {
-                                if let Some(ops) = cycle.windows(2).find(|ops| ops[0] == node.op) {
-                                    node.op = ops[1].to_owned();
-                                    commit(app, vm, sample_rate, filename);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                Key::Char('-') => {
-                    if let Some(ix) = app.node_at_cursor() {
-                        let node = &mut app.nodes[ix];
-                        let i = app.cursor.x - node.position.x;
-                        if let Some(d) = node.op.get(i..(i + 1)).and_then(|c| c.parse::<u8>().ok())
-                        {
-                            let d = (d + 9) % 10;
-                            node.op.replace_range(i..(i + 1), &d.to_string());
-                            commit(app, vm, sample_rate, filename);
-                        } else {
-                            for cycle in &app.cycles {
-                                if let Some(ops) = cycle.windows(2).find(|ops| ops[1] == node.op) {
-                                    node.op = ops[0].to_owned();
-                                    commit(app, vm, sample_rate, filename);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                Key::Char('r') => {
-                    app.recording = !app.recording;
-                    record_tx.send(app.recording).ok();
-                }
-                Key::Char('q') => {
-                    vm.lock().unwrap().pause();
-                    return Err(anyhow!("Quit!"));
-                }
-                Key::Char('?') => app.screen = Screen::Help,
-                Key::Char('/') => app.screen = Screen::Ops,
-                _ => {}
-            },
-            InputMode::Editing => match input {
-                Key::Left => {
-                    app.cursor.x -= 1;
-                }
-                Key::Down => {
-                    app.cursor.y += 1;
-                }
-                Key::Up => {
-                    app.cursor.y -= 1;
-                }
-                Key::Right => {
-                    app.cursor.x += 1;
-                }
-                Key::Char(' ') => {
-                    let p = app.cursor;
-                    for node in app.nodes.iter_mut().filter(|node| {
-                        node.position.y == p.y && p.x < node.position.x + node.op.len()
-                    }) {
-                        node.position.x += 1;
-                    }
-                    app.cursor.x += 1;
-                }
-                Key::Char('\n') => {
-                    app.input_mode = InputMode::Normal;
-                    events.enable_exit_key();
-                    app.draft = app.nodes.iter().any(|node| node.op.is_empty());
-                    app.nodes.retain(|node| !node.op.is_empty());
-                }
-                Key::Char(c) => {
-                    let p = app.cursor;
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && p.x < node.position.x)
-                    {
-                        node.position.x += 1;
-                    }
-                    let node = app.node_at_cursor();
-                    if let Some(ix) = node {
-                        let node = &mut app.nodes[ix];
-                        if app.cursor.x >= node.position.x + node.op.chars().count() {
-                            node.op.push(c);
-                        } else {
-                            let ix = node
-                                .op
-                                .char_indices()
-                                .nth((app.cursor.x - node.position.x) as usize)
-                                .map(|x| x.0)
-                                .unwrap();
-                            node.op.insert(ix, c);
-                        }
-                        node.draft = true;
-                    } else {
-                        let node = Node {
-                            id: random(),
-                            draft: true,
-                            op: c.to_string(),
-                            position: app.cursor,
-                        };
-                        app.nodes.push(node);
-                    };
-                    app.cursor.x += 1;
-                }
-                Key::Backspace => {
-                    let p = app.cursor;
-                    for node in app
-                        .nodes
-                        .iter_mut()
-                        .filter(|node| node.position.y == p.y && p.x < node.position.x)
-                    {
-                        node.position.x -= 1;
-                    }
-                    app.cursor.x -= 1;
-                    let node = app.node_at_cursor();
-                    if let Some(ix) = node {
-                        let node = &mut app.nodes[ix];
-                        if app.cursor.x < node.position.x + node.op.len() {
-                            if node.op.len() > 1 {
-                                let x = (app.cursor.x - node.position.x) as usize;
-                                let ixs = node
-                                    .op
-                                    .char_indices()
-                                    .skip(x)
-                                    .take(2)
-                                    .map(|x| x.0)
-                                    .collect::<Vec<_>>();
-                                node.op.replace_range(
-                                    ixs[0]..*(ixs.get(1).unwrap_or(&node.op.len())),
-                                    &"",
-                                );
-                            } else if app.cursor.x == node.position.x {
-                                node.op.pop();
-                            };
-                            node.draft = true;
-                        }
-                    }
-                }
-                Key::Esc => {
-                    app.input_mode = InputMode::Normal;
-                    events.enable_exit_key();
-                    app.draft = app.nodes.iter().any(|node| node.op.is_empty());
-                    app.nodes.retain(|node| !node.op.is_empty());
-                }
-                _ => {}
-            },
-        },
-        _ => {}
+                        .map(|node| PatchNode {
+                            id: node.id,
+                            op: node.op.to_owned(),
+                            x: node.position.x,
+                            y: node.position.y,
+                        })
+                        .collect(),
+                );
+                jam.schedule(new_program, apply_at);
+            }
+            None => {
+                // Deallocate the previous program outside of the lock.
+                let garbage = host.vm.lock().unwrap().load_program(new_program);
+                drop(garbage);
+            }
+        }
     }
-    Ok(())
 }
 
-fn handle_help(app: &mut App, events: &mut Events) -> Result<()> {
-    match events.next()? {
-        Event::Input(input) => match input {
-            Key::Char('?') => app.screen = Screen::Editor,
-            Key::Esc => app.screen = Screen::Editor,
-            Key::Char('j') | Key::Down => {
-                app.help_scroll += 1;
-            }
-            Key::Char('k') | Key::Up => {
-                if app.help_scroll > 0 {
-                    app.help_scroll -= 1;
-                }
-            }
-            _ => {}
-        },
-        _ => {}
+/// Replace nodes with the ones committed by a peer.
+fn apply_patch(app: &mut App, host: &Host, patch: Patch) {
+    app.nodes = patch
+        .nodes
+        .into_iter()
+        .map(|PatchNode { id, op, x, y }| Node {
+            id,
+            draft: false,
+            // Mutes are local to the performer.
+            muted: app.nodes.iter().any(|node| node.id == id && node.muted),
+            op,
+            position: Position { x, y },
+        })
+        .collect();
+    app.nodes.sort_by_key(|node| node.position);
+    let new_program = compile_nodes(app, host.sample_rate, host.filename);
+    if let (Some(new_program), Some(jam)) = (new_program, &app.jam) {
+        jam.schedule(new_program, patch.apply_at);
     }
-    Ok(())
 }
 
-fn handle_ops(app: &mut App, events: &mut Events) -> Result<()> {
-    match events.next()? {
-        Event::Input(input) => match input {
-            Key::Char('/') => app.screen = Screen::Editor,
-            Key::Esc => app.screen = Screen::Editor,
-            Key::Char('j') | Key::Down => {
-                app.help_scroll += 1;
+/// Replace node's op from OSC, shifting the rest of the line to fit it.
+fn apply_osc(app: &mut App, host: &Host, message: osc::Message) {
+    match message {
+        osc::Message::Set { id, op } => {
+            let ix = match app.nodes.iter().position(|node| node.id == id) {
+                Some(ix) => ix,
+                None => return,
+            };
+            let p = app.nodes[ix].position;
+            let old_width = text::width(&app.nodes[ix].op);
+            let new_width = text::width(&op);
+            for node in app
+                .nodes
+                .iter_mut()
+                .filter(|node| node.position.y == p.y && p.x < node.position.x)
+            {
+                node.position.x = node.position.x + new_width - old_width;
             }
-            Key::Char('k') | Key::Up => {
-                if app.help_scroll > 0 {
-                    app.help_scroll -= 1;
-                }
+            if op.is_empty() {
+                app.nodes.remove(ix);
+                app.draft = true;
+            } else {
+                let node = &mut app.nodes[ix];
+                node.op = op;
+                node.draft = true;
             }
-            _ => {}
-        },
-        _ => {}
+        }
+        osc::Message::Commit => commit(app, host),
     }
-    Ok(())
 }
 
-fn commit(app: &mut App, vm: Arc<Mutex<VM>>, sample_rate: u32, filename: &str) {
-    app.nodes.sort_by_key(|node| node.position);
-    app.program = app.nodes.iter().map(|node| node.op.to_owned()).join(" ");
+/// Compile sorted nodes, return None if ops didn't change since the last commit.
+fn compile_nodes(app: &mut App, sample_rate: u32, filename: &str) -> Option<Program> {
+    let ops = inject::text_ops(app, &app.nodes);
+    // Mutes are part of the program which is saved and recorded, solo isn't.
+    app.program = ops.iter().map(|op| &op.op).join(" ");
     app.nodes.iter_mut().for_each(|node| node.draft = false);
     app.draft = false;
-    let next_ops = rewrite_terms(
-        &app.nodes
+    let next_ops = rewrite_terms(&ops);
+    let mut next_ops = rewrite_words(&next_ops, &mut app.ctx.words);
+    if let Some(solo) = app.solo {
+        if !inject::solo(&mut next_ops, solo) {
+            app.notice = String::from("Can't solo a node which is rewritten into other ops.");
+            app.solo = None;
+        }
+    }
+    if app.grid.is_some() {
+        inject::grid(&mut next_ops);
+    }
+    if app.ops == next_ops {
+        return None;
+    }
+    app.ops = next_ops;
+    app.session.commits += 1;
+    app.session.ops.extend(
+        app.ops
             .iter()
-            .map(|Node { id, op, .. }| TextOp {
-                id: *id,
-                op: op.to_owned(),
-            })
-            .collect::<Vec<_>>(),
+            .filter(|TextOp { op, .. }| op.parse::<f64>().is_err())
+            .map(|TextOp { op, .. }| op.to_owned()),
     );
-    if app.ops != next_ops {
-        app.ops = next_ops;
-        app.save(&filename).ok();
-        let new_program = compile_program(&app.ops, sample_rate, &mut app.ctx);
-        // Ensure the smallest possible scope to limit locking time.
-        let garbage = {
-            vm.lock().unwrap().load_program(new_program);
-        };
-        drop(garbage);
-    }
+    history::push(app);
+    app.save(filename).ok();
+    Some(compile_program(&app.ops, sample_rate, &mut app.ctx))
 }
 
 #[derive(Serialize, Deserialize)]
-struct App {
+pub struct App {
+    /// Speak mode changes and ops under the cursor.
+    #[serde(default)]
+    announce: bool,
+    #[serde(skip, default = "get_arities")]
+    arities: HashMap<String, Arity>,
+    #[serde(skip, default)]
+    auditioning: bool,
+    /// How many previous versions of the file to keep as file.1, file.2, …
+    #[serde(default = "default_backups")]
+    backups: usize,
+    #[serde(skip, default)]
+    backed_up: bool,
+    /// Tempo for `beats` and `bpm` ops.
+    #[serde(default = "default_bpm")]
+    bpm: f64,
+    /// Host students or send commits to the teacher.
+    #[serde(default)]
+    classroom: Option<crate::classroom::Role>,
     #[serde(skip, default)]
     ctx: Context,
     cursor: Position,
     #[serde(skip, default = "default_cycles")]
     cycles: Vec<Vec<String>>,
     #[serde(skip, default)]
+    dc_block: bool,
+    #[serde(skip, default)]
+    device_cursor: usize,
+    /// Names of output devices listed to choose from.
+    #[serde(skip, default)]
+    devices: Vec<String>,
+    #[serde(skip, default)]
     draft: bool,
+    /// The last announced state.
+    #[serde(skip, default)]
+    focus: Option<announce::Focus>,
+    #[serde(skip, default)]
+    grid: Option<Grid>,
+    /// Address of the serialosc port of a monome grid to program the `grid` sequencer from.
+    #[serde(default)]
+    grid_address: Option<String>,
+    /// Pattern of the `grid` sequencer as masks of steps by tracks.
+    #[serde(default)]
+    grid_steps: Vec<u16>,
+    /// Stages of the running gain staging analysis.
+    #[serde(skip, default)]
+    gain_analysis: Option<crossbeam_channel::Receiver<Vec<gain_staging::Stage>>>,
+    /// Gain staging hints by ids of nodes they are about.
+    #[serde(skip, default)]
+    gain_hints: HashMap<u64, String>,
     #[serde(skip, default)]
     help_scroll: u16,
+    #[serde(default)]
+    high_contrast: bool,
+    /// Committed revisions, the latest is the last.
+    #[serde(default)]
+    history: Vec<history::Revision>,
+    /// Index of the revision selected in the history browser.
+    #[serde(skip, default)]
+    history_cursor: usize,
     #[serde(skip, default)]
     input_mode: InputMode,
+    #[serde(skip, default)]
+    jam: Option<Jam>,
+    #[serde(skip, default)]
+    journal: Option<SharedJournal>,
+    /// Save tables recorded with `wt` next to the file to play them in the next session.
+    #[serde(default)]
+    keep_tables: bool,
+    /// Show only log entries of this level and worse.
+    #[serde(skip, default)]
+    log_level: Option<Level>,
+    /// Number of log entries already noticed.
+    #[serde(skip, default)]
+    log_seen: usize,
+    /// Show only log entries of this module.
+    #[serde(skip, default)]
+    log_target: Option<String>,
+    #[serde(skip, default)]
+    monitor_direct: bool,
+    /// In decibels.
+    #[serde(skip, default)]
+    monitor_gain: f64,
+    /// Typing the name of the selected revision.
+    #[serde(skip, default)]
+    naming: bool,
     nodes: Vec<Node>,
+    /// Shown in the status until the next key press.
+    #[serde(skip, default)]
+    notice: String,
+    #[serde(skip, default)]
+    osc: Option<Osc>,
+    /// Address to listen for OSC node edits on.
+    #[serde(default)]
+    osc_address: Option<String>,
+    /// Name of the output device, the default one is used when it's missing.
+    #[serde(default)]
+    output_device: Option<String>,
     #[serde(skip, default = "get_op_groups")]
     op_groups: Vec<(String, Vec<String>)>,
     #[serde(skip, default = "get_help")]
@@ -695,34 +528,140 @@ struct App {
     ops: Vec<TextOp>,
     #[serde(skip, default)]
     play: bool,
+    /// Live values after nodes of the line under the cursor, for the stack panel.
+    #[serde(skip, default)]
+    probes: Vec<(u64, Frame)>,
     #[serde(default)]
     program: String,
+    #[serde(default)]
+    record_format: record::Format,
+    #[serde(default)]
+    record_split: record::Split,
+    #[serde(skip, default)]
+    quit_pending: bool,
     #[serde(skip, default)]
     recording: bool,
     #[serde(skip, default)]
     screen: Screen,
     #[serde(skip, default)]
+    session: Session,
+    #[serde(skip, default)]
+    scrub: Option<f64>,
+    /// Garden as of the last save for the crash bundle.
+    #[serde(skip, default)]
+    snapshot: Option<Snapshot>,
+    /// Node whose output is played instead of the program's one.
+    #[serde(skip, default)]
+    solo: Option<u64>,
+    #[serde(skip, default)]
+    speech: Speech,
+    #[serde(skip, default)]
+    stack_panel: bool,
+    #[serde(skip, default)]
     status: String,
+    #[serde(skip, default)]
+    student: Option<Student>,
+    #[serde(skip, default)]
+    sunset: Option<sunset::Sunset>,
+    /// In seconds.
+    #[serde(default = "sunset::default_duration")]
+    sunset_duration: f64,
+    /// Worker which saves tables, joined before the next save and on quit.
+    #[serde(skip, default)]
+    tables_saver: Option<std::thread::JoinHandle<Option<SavedTables>>>,
+    #[serde(skip, default)]
+    teacher: Option<Teacher>,
+    #[serde(skip, default)]
+    tuner: Option<Tuner>,
+    #[serde(skip, default)]
+    tuner_enabled: bool,
+    /// Ids of nodes using the table or the bus, listed to jump to them.
+    #[serde(skip, default)]
+    usages: Vec<u64>,
+    #[serde(skip, default)]
+    usages_cursor: usize,
+    /// Table or bus of the usages, orphan readers are listed without it.
+    #[serde(skip, default)]
+    usages_of: Option<(Shared, String)>,
+    /// Transport rate, a performance gesture which isn't saved.
+    #[serde(skip, default = "default_warp")]
+    warp: f64,
+    #[serde(default)]
+    workshop: Option<workshop::Workshop>,
 }
 
 impl App {
     pub fn new() -> Self {
         App {
+            announce: Default::default(),
+            arities: get_arities(),
+            auditioning: Default::default(),
+            backups: default_backups(),
+            backed_up: Default::default(),
+            bpm: default_bpm(),
+            classroom: Default::default(),
             ctx: Default::default(),
             cursor: Position { y: MIN_X, x: MIN_Y },
             cycles: default_cycles(),
+            dc_block: Default::default(),
+            device_cursor: Default::default(),
+            devices: Default::default(),
             draft: Default::default(),
+            focus: Default::default(),
+            grid: Default::default(),
+            grid_address: Default::default(),
+            grid_steps: Default::default(),
+            gain_analysis: Default::default(),
+            gain_hints: Default::default(),
             help_scroll: 0,
+            high_contrast: Default::default(),
+            history: Default::default(),
+            history_cursor: Default::default(),
             input_mode: Default::default(),
+            jam: Default::default(),
+            journal: Default::default(),
+            keep_tables: Default::default(),
+            log_level: Default::default(),
+            log_seen: Default::default(),
+            log_target: Default::default(),
+            monitor_direct: Default::default(),
+            monitor_gain: Default::default(),
+            naming: Default::default(),
             nodes: Default::default(),
+            notice: Default::default(),
+            osc: Default::default(),
+            osc_address: Default::default(),
+            output_device: Default::default(),
             op_groups: get_op_groups(),
             op_help: get_help(),
             ops: Default::default(),
             play: Default::default(),
+            probes: Default::default(),
             program: Default::default(),
+            record_format: Default::default(),
+            record_split: Default::default(),
+            quit_pending: Default::default(),
             recording: Default::default(),
             screen: Default::default(),
+            session: Default::default(),
+            scrub: Default::default(),
+            snapshot: Default::default(),
+            solo: Default::default(),
+            speech: Default::default(),
+            stack_panel: Default::default(),
             status: Default::default(),
+            student: Default::default(),
+            sunset: Default::default(),
+            sunset_duration: sunset::default_duration(),
+            tables_saver: Default::default(),
+            teacher: Default::default(),
+            tuner: Default::default(),
+            tuner_enabled: Default::default(),
+            usages: Default::default(),
+            usages_cursor: Default::default(),
+            usages_of: Default::default(),
+            warp: default_warp(),
+            workshop: Default::default(),
         }
     }
 
@@ -736,15 +675,32 @@ impl App {
                 *y == self.cursor.y
                     && *x <= self.cursor.x
                     // space after node is counted as a part of the node
-                    && self.cursor.x <= *x + op.len()
+                    && self.cursor.x <= *x + text::width(op)
             },
         )
     }
 
+    /// Safety rails of the workshop until they are lifted.
+    fn rails(&self) -> Option<&workshop::Workshop> {
+        self.workshop.as_ref().filter(|workshop| !workshop.lifted)
+    }
+
     // TODO Atomic write.
-    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+    /// Backups are rotated by the first save of the session, later saves only overwrite.
+    pub fn save<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        // Workshop rails leave backups on, the teacher's garden is worth keeping.
+        if path.exists() && !self.backed_up {
+            backups::rotate(path, self.backups)?;
+            self.backed_up = true;
+        }
+        let garden = serde_json::to_value(&*self)?;
         let f = std::fs::File::create(path)?;
-        serde_json::to_writer_pretty(f, self)?;
+        serde_json::to_writer_pretty(f, &garden)?;
+        // Keep the garden with its history at hand for the crash bundle.
+        if let Some(snapshot) = &self.snapshot {
+            *snapshot.lock().unwrap() = Some(garden);
+        }
         Ok(())
     }
 
@@ -755,23 +711,27 @@ impl App {
 }
 
 #[derive(Serialize, Deserialize)]
-enum InputMode {
+pub enum InputMode {
     Normal,
     Editing,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Node {
-    #[serde(skip, default = "random")]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Node {
+    /// Saved to let external tools address nodes over OSC.
+    #[serde(default = "random")]
     id: u64,
     #[serde(skip, default)]
     draft: bool,
+    /// Any muted node mutes its line.
+    #[serde(default)]
+    muted: bool,
     op: String,
     position: Position,
 }
 
 #[derive(Clone, Copy, Default, PartialEq, Eq, Ord, Deserialize, Serialize)]
-struct Position {
+pub struct Position {
     x: usize,
     y: usize,
 }
@@ -793,10 +753,14 @@ impl Default for InputMode {
     }
 }
 
-enum Screen {
+pub enum Screen {
+    Devices,
     Editor,
     Help,
+    History,
+    Log,
     Ops,
+    Usages,
 }
 
 impl Default for Screen {
@@ -805,6 +769,53 @@ impl Default for Screen {
     }
 }
 
+fn default_bpm() -> f64 {
+    120.0
+}
+
+/// Running programs follow the tempo without recompiling.
+fn set_bpm(app: &mut App) {
+    app.bpm = app.bpm.max(1.0);
+    *app.ctx.bpm.lock().unwrap() = app.bpm;
+}
+
+fn default_warp() -> f64 {
+    1.0
+}
+
+/// Rounded to steps to come back to exactly 1 after going down and up.
+fn set_warp(app: &mut App) {
+    app.warp = ((app.warp / WARP_STEP).round() * WARP_STEP).clamp(WARP_STEP, 4.0);
+    *app.ctx.warp.lock().unwrap() = app.warp;
+}
+
+/// Recorded tables are kept next to the file as file.tables/<name>.wav.
+/// Save tables which changed on a worker thread, after the previous save is done and recorded.
+fn save_tables(app: &mut App, sample_rate: u32) {
+    if let Some(saver) = app.tables_saver.take() {
+        if let Ok(Some(saved)) = saver.join() {
+            mark_tables_saved(&mut app.ctx, saved);
+        }
+    }
+    let snapshot = snapshot_tables(&mut app.ctx, sample_rate);
+    app.tables_saver = Some(std::thread::spawn(move || {
+        snapshot
+            .save()
+            .map_err(|e| log::warn!("Can't save tables: {}", e))
+            .ok()
+    }));
+}
+
+fn tables_dir(filename: &str) -> std::path::PathBuf {
+    let mut name = std::ffi::OsString::from(filename);
+    name.push(".tables");
+    std::path::PathBuf::from(name)
+}
+
+fn default_backups() -> usize {
+    3
+}
+
 fn default_cycles() -> Vec<Vec<String>> {
     // NOTE Always repeat the first element at the end.
     vec![vec!["s", "t", "w", "c", "s"]]
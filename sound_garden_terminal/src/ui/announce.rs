@@ -0,0 +1,77 @@
+//! Speak what changes on the screen for performers who can't see it.
+use super::{App, InputMode, Screen, MIN_X, MIN_Y};
+
+/// What is announced when it changes.
+#[derive(Clone, PartialEq)]
+pub struct Focus {
+    mode: &'static str,
+    play: bool,
+    recording: bool,
+    /// Id and text of the node under the cursor.
+    node: Option<(u64, String)>,
+}
+
+pub fn focus(app: &mut App) {
+    let focus = Focus {
+        mode: match app.screen {
+            Screen::Editor => match app.input_mode {
+                InputMode::Normal => "Normal mode",
+                InputMode::Editing => "Edit mode",
+            },
+            Screen::Devices => "Output devices",
+            Screen::Help => "Help",
+            Screen::History => "History",
+            Screen::Log => "Log",
+            Screen::Ops => "Ops",
+            Screen::Usages => "Usages",
+        },
+        play: app.play,
+        recording: app.recording,
+        node: app
+            .node_at_cursor()
+            .map(|ix| (app.nodes[ix].id, app.nodes[ix].op.to_owned())),
+    };
+    let previous = app.focus.as_ref();
+    let mut text = Vec::new();
+    if previous.map(|x| x.mode) != Some(focus.mode) {
+        text.push(focus.mode.to_owned());
+    }
+    if previous.map(|x| x.play) != Some(focus.play) {
+        text.push(String::from(if focus.play { "Playing" } else { "Paused" }));
+    }
+    if previous.map(|x| x.recording) != Some(focus.recording) {
+        text.push(String::from(if focus.recording {
+            "Recording"
+        } else {
+            "Not recording"
+        }));
+    }
+    // Compare ids only to not repeat the node on each keystroke while editing it.
+    let node_id = |focus: &Focus| focus.node.as_ref().map(|(id, _)| *id);
+    if previous.map(node_id) != Some(node_id(&focus)) {
+        if let Some((_, op)) = &focus.node {
+            text.push(op.to_owned());
+        }
+    }
+    if !text.is_empty() {
+        app.speech.say(&text.join(". "));
+    }
+    app.focus = Some(focus);
+}
+
+/// Position of the cursor counted from 1, the node under it and its help.
+pub fn describe_cursor(app: &App) -> String {
+    let mut text = format!(
+        "Line {}, column {}",
+        app.cursor.y + 1 - MIN_Y,
+        app.cursor.x + 1 - MIN_X
+    );
+    if let Some(ix) = app.node_at_cursor() {
+        let op = &app.nodes[ix].op;
+        text.push_str(&format!(", {}", op));
+        if let Some(help) = app.op_help.get(op) {
+            text.push_str(&format!(", {}", help));
+        }
+    }
+    text
+}
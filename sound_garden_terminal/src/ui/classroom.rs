@@ -0,0 +1,60 @@
+//! Classroom as the teacher and students see it.
+use super::App;
+use crate::classroom::{Desks, Role, Student, Teacher};
+use crate::jam::PatchNode;
+use anyhow::Result;
+use itertools::Itertools;
+
+/// Host the classroom or join it as a student, as set in the file.
+pub fn join(app: &mut App, desks: Desks) -> Result<()> {
+    match &app.classroom {
+        Some(Role::Teacher { address }) => {
+            app.teacher = Some(Teacher::new(address, desks)?);
+        }
+        // Student joins before the initial commit to show up in the classroom right away.
+        Some(Role::Student { name, teacher }) => {
+            app.student = Some(Student::new(name, teacher)?);
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Play the latest commits of students.
+pub fn accept_submissions(app: &mut App, sample_rate: u32) {
+    if let Some(teacher) = app.teacher.as_mut() {
+        let submissions = teacher.receiver().try_iter().collect::<Vec<_>>();
+        for submission in submissions {
+            teacher.accept(submission, sample_rate, app.ctx.max_feedback_gain);
+        }
+    }
+}
+
+/// Send the committed nodes to the teacher.
+pub fn submit(app: &App) {
+    if let Some(student) = &app.student {
+        student.send(
+            app.nodes
+                .iter()
+                .map(|node| PatchNode {
+                    id: node.id,
+                    op: node.op.to_owned(),
+                    x: node.position.x,
+                    y: node.position.y,
+                })
+                .collect(),
+        );
+    }
+}
+
+/// Students are listed by the function keys which mute them.
+pub fn render(teacher: &Teacher) -> String {
+    teacher
+        .roll_call()
+        .iter()
+        .enumerate()
+        .map(|(i, (name, muted))| {
+            format!("F{}:{}{}", i + 1, name, if *muted { "(muted)" } else { "" })
+        })
+        .join(" ")
+}
@@ -0,0 +1,88 @@
+//! Choose the output device.
+use super::{App, Host, Screen, Term, Theme, MIN_X, MIN_Y};
+use crate::audio;
+use crate::event::{Event, Events};
+use anyhow::Result;
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+/// List output devices, the current one is selected.
+pub fn open(app: &mut App) {
+    app.devices = audio::output_devices();
+    app.device_cursor = app
+        .output_device
+        .as_ref()
+        .and_then(|device| app.devices.iter().position(|x| x == device))
+        .unwrap_or_default();
+    app.screen = Screen::Devices;
+}
+
+pub fn render(app: &mut App, terminal: &mut Term) -> Result<()> {
+    let theme = Theme::new(app.high_contrast);
+    terminal.draw(|mut f| {
+        let mut size = f.size();
+        Block::default()
+            .title("Sound Garden────Output devices")
+            .title_style(Style::default().fg(theme.info))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.info))
+            .render(&mut f, size);
+        let mut text = vec![
+            Text::raw("(Press Esc to close, j/k to select, Return to switch the output)\n"),
+            Text::raw("\n"),
+        ];
+        for (i, device) in app.devices.iter().enumerate() {
+            let line = format!(
+                "{} {}{}\n",
+                if i == app.device_cursor { ">" } else { " " },
+                device,
+                if app.output_device.as_ref() == Some(device) {
+                    " (current)"
+                } else {
+                    ""
+                },
+            );
+            text.push(if i == app.device_cursor {
+                Text::styled(line, theme.draft)
+            } else {
+                Text::raw(line)
+            });
+        }
+        size.x = MIN_X as u16;
+        size.y = MIN_Y as u16;
+        size.width -= 3;
+        size.height -= 3;
+        Paragraph::new(text.iter()).render(&mut f, size);
+    })?;
+    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
+    io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn handle(app: &mut App, host: &Host, events: &mut Events) -> Result<()> {
+    if let Event::Input(input) = events.next()? {
+        match input {
+            Key::Char('O') | Key::Esc => app.screen = Screen::Editor,
+            Key::Char('j') | Key::Down => {
+                app.device_cursor =
+                    (app.device_cursor + 1).min(app.devices.len().saturating_sub(1));
+            }
+            Key::Char('k') | Key::Up => {
+                app.device_cursor = app.device_cursor.saturating_sub(1);
+            }
+            Key::Char('\n') if app.device_cursor < app.devices.len() => {
+                let device = app.devices[app.device_cursor].to_owned();
+                host.audio_tx
+                    .send(audio::Message::Device(device.to_owned()))
+                    .ok();
+                app.output_device = Some(device);
+                app.save(host.filename).ok();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
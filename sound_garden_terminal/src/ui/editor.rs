@@ -0,0 +1,735 @@
+//! Editor of the garden, the main screen.
+use super::{
+    announce, classroom, commit, devices, export, gain, save_tables, set_bpm, set_monitor,
+    set_warp, stack, sunset, usages, App, Host, InputMode, Node, Screen, Term, Theme, MIN_X, MIN_Y,
+    WARP_STEP,
+};
+use crate::event::{Event, Events};
+use crate::record;
+use crate::text;
+use anyhow::{anyhow, Result};
+use audio_vm::Frame;
+use chrono::prelude::*;
+use itertools::Itertools;
+use rand::prelude::*;
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::layout::Rect;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+pub fn render(app: &mut App, terminal: &mut Term) -> Result<()> {
+    let stack_rows = if app.stack_panel {
+        stack::simulate(app)
+    } else {
+        Vec::new()
+    };
+    let theme = Theme::new(app.high_contrast);
+    // Beat trackers may set the tempo too.
+    let bpm = *app.ctx.bpm.lock().unwrap();
+    terminal.draw(|mut f| {
+        let size = f.size();
+        let panel_height = if app.stack_panel {
+            (stack_rows.len() as u16 + 2).min(size.height / 2)
+        } else {
+            0
+        };
+        let mut nodes_to_drop = Vec::new();
+        for (
+            i,
+            Node {
+                id,
+                op,
+                draft,
+                muted,
+                position: p,
+            },
+        ) in app.nodes.iter().enumerate()
+        {
+            if p.x < MIN_X
+                || p.y < MIN_Y
+                || p.x + text::width(op) > size.width as _
+                || p.y + 1 > size.height as _
+            {
+                nodes_to_drop.push(i);
+                continue;
+            }
+            // Don't drop nodes hidden behind the stack panel.
+            if p.y + panel_height as usize > size.height as _ {
+                continue;
+            }
+            let text = [Text::raw(op.to_owned())];
+            Paragraph::new(text.iter())
+                .style(if *draft {
+                    theme.draft
+                } else if app.gain_hints.contains_key(id) {
+                    theme.hint
+                } else if app.solo == Some(*id) {
+                    theme.solo
+                } else if *muted {
+                    theme.muted
+                } else {
+                    theme.node
+                })
+                .render(
+                    &mut f,
+                    Rect::new((p.x - 1) as _, (p.y - 1) as _, text::width(op) as _, 1),
+                );
+        }
+        for ix in nodes_to_drop.drain(..) {
+            app.nodes.swap_remove(ix);
+            app.draft = true;
+        }
+        let color = if !app.play {
+            theme.paused
+        } else if app.draft || app.nodes.iter().any(|node| node.draft) {
+            theme.draft_border
+        } else {
+            theme.live
+        };
+        Block::default()
+            .title(&format!(
+                "Sound Garden────{}────{}────{}────{}────{}────{}────{}────{}",
+                match (app.scrub, &app.sunset) {
+                    (Some(t), _) => format!("<<-{:.1}s", t),
+                    (None, Some(sunset)) => format!(
+                        "|>sunset:{:.0}s",
+                        (app.sunset_duration - sunset.start.elapsed().as_secs_f64()).max(0.0)
+                    ),
+                    (None, None) => String::from(if app.play { "|>" } else { "||" }),
+                },
+                if app.warp == 1.0 {
+                    format!("{:.0}bpm", bpm)
+                } else {
+                    format!("{:.0}bpm×{:.2}", bpm, app.warp)
+                },
+                if app.recording {
+                    format!(
+                        "{}R:{}",
+                        if Utc::now().second() % 2 == 1 {
+                            " "
+                        } else {
+                            "•"
+                        },
+                        app.record_format
+                    )
+                } else {
+                    String::new()
+                },
+                if app.monitor_direct {
+                    format!("M:{:+.0}dB", app.monitor_gain)
+                } else {
+                    String::new()
+                },
+                if app.tuner_enabled {
+                    render_tuner(app)
+                } else {
+                    String::new()
+                },
+                app.teacher
+                    .as_ref()
+                    .map(classroom::render)
+                    .unwrap_or_default(),
+                render_meters(app),
+                app.status
+            ))
+            .title_style(Style::default().fg(color))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(color))
+            .render(&mut f, size);
+        if app.stack_panel {
+            let area = Rect::new(0, size.height - panel_height, size.width, panel_height);
+            let width = stack_rows
+                .iter()
+                .map(|(op, _, _)| text::width(op))
+                .max()
+                .unwrap_or(0);
+            let text = stack_rows
+                .iter()
+                .map(|(op, stack, live)| {
+                    // Pad by columns, format! pads by chars.
+                    Text::raw(format!(
+                        "{}{} │ {}{}\n",
+                        " ".repeat(width - text::width(op)),
+                        op,
+                        stack
+                            .as_ref()
+                            .map(|xs| xs.join(" "))
+                            .unwrap_or_else(|| "?".to_owned()),
+                        live.map(|frame| format!(
+                            " = {}",
+                            frame.iter().map(|x| format!("{:.3}", x)).join(" ")
+                        ))
+                        .unwrap_or_default(),
+                    ))
+                })
+                .collect::<Vec<_>>();
+            Paragraph::new(text.iter())
+                .block(
+                    Block::default()
+                        .title("Stack")
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(color)),
+                )
+                .render(&mut f, area);
+        }
+    })?;
+    write!(
+        terminal.backend_mut(),
+        "{}{}",
+        cursor::Show,
+        cursor::Goto(app.cursor.x as _, app.cursor.y as _),
+    )?;
+    match app.input_mode {
+        InputMode::Normal => write!(terminal.backend_mut(), "{}", cursor::SteadyBlock)?,
+        InputMode::Editing => write!(terminal.backend_mut(), "{}", cursor::SteadyUnderline)?,
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
+
+/// Note, frequency and a meter of the offset from the note like `A4 441.2Hz ···│●·· +8¢`.
+fn render_tuner(app: &App) -> String {
+    match app.tuner.as_ref().and_then(|tuner| tuner.note()) {
+        Some((frequency, note, cents)) => {
+            // Each meter step is 10 cents.
+            let position = (cents / 10.0).round() as i64;
+            let meter = (-5..=5)
+                .map(|i| {
+                    if i == position {
+                        '●'
+                    } else if i == 0 {
+                        '│'
+                    } else {
+                        '·'
+                    }
+                })
+                .collect::<String>();
+            format!("{} {:.1}Hz {} {:+.0}¢", note, frequency, meter, cents)
+        }
+        None => String::from("Tuner: no pitch"),
+    }
+}
+
+/// RMS and peak in dB of the loudest channel of each `meter` op, sorted by names.
+fn render_meters(app: &App) -> String {
+    let db = |xs: Frame| 20.0 * xs.iter().cloned().fold(0.0, f64::max).log10();
+    app.ctx
+        .telemetry
+        .lock()
+        .unwrap()
+        .iter()
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(name, levels)| {
+            format!(
+                "{}:{:.0}/{:.0}dB",
+                name,
+                db(levels.rms()),
+                db(levels.peak())
+            )
+        })
+        .join(" ")
+}
+
+pub fn handle(app: &mut App, host: &Host, events: &mut Events) -> Result<()> {
+    let event = events.next()?;
+    if let Event::Input(_) = event {
+        app.notice.clear();
+    }
+    if app.quit_pending {
+        if let Event::Input(input) = event {
+            if input != Key::Char('q') {
+                app.quit_pending = false;
+                events.disable_exit_key();
+            }
+        }
+    }
+    if let Event::Input(input) = event {
+        match app.input_mode {
+            InputMode::Normal => match input {
+                Key::Char('\n') => commit(app, host),
+                Key::Char('\\') => {
+                    app.play = !app.play;
+                    if app.play {
+                        host.vm.lock().unwrap().play();
+                    } else {
+                        host.vm.lock().unwrap().pause();
+                    }
+                }
+                Key::Char('i') => {
+                    app.input_mode = InputMode::Editing;
+                    events.disable_exit_key();
+                }
+                Key::Char('I') => {
+                    app.input_mode = InputMode::Editing;
+                    events.disable_exit_key();
+                    let mut push_left = 1;
+                    let push_right = 1;
+                    if let Some(ix) = app.node_at_cursor() {
+                        let Node {
+                            op, position: p, ..
+                        } = &app.nodes[ix];
+                        push_left += p.x + text::width(op) - app.cursor.x;
+                    }
+                    let p = app.cursor;
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
+                    {
+                        node.position.x -= push_left;
+                    }
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && node.position.x > p.x)
+                    {
+                        node.position.x += push_right;
+                    }
+                }
+                Key::Char('o') => {
+                    app.input_mode = InputMode::Editing;
+                    events.disable_exit_key();
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| node.position.y > p.y) {
+                        node.position.y += 1;
+                    }
+                    app.cursor.x = app
+                        .nodes
+                        .iter()
+                        .filter(|node| node.position.y == p.y)
+                        .min_by_key(|node| node.position.x)
+                        .map(|node| node.position.x)
+                        .unwrap_or(MIN_X);
+                    app.cursor.y += 1;
+                }
+                Key::Char('c') => {
+                    app.input_mode = InputMode::Editing;
+                    events.disable_exit_key();
+                    if let Some(ix) = app.node_at_cursor() {
+                        let node = &mut app.nodes[ix];
+                        let push_left = node.position.x + text::width(&node.op) - app.cursor.x;
+                        node.op
+                            .truncate(text::offset(&node.op, app.cursor.x - node.position.x));
+                        node.draft = true;
+                        let p = app.cursor;
+                        for node in app
+                            .nodes
+                            .iter_mut()
+                            .filter(|node| node.position.y == p.y && node.position.x > p.x)
+                        {
+                            node.position.x -= push_left;
+                        }
+                    }
+                }
+                Key::Char('h') | Key::Left | Key::Backspace => {
+                    app.cursor.x -= 1;
+                }
+                Key::Char('j') | Key::Down => {
+                    app.cursor.y += 1;
+                }
+                Key::Char('k') | Key::Up => {
+                    app.cursor.y -= 1;
+                }
+                Key::Char('l') | Key::Right | Key::Char(' ') => {
+                    app.cursor.x += 1;
+                }
+                Key::Alt('h') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        app.nodes[ix].position.x -= 1;
+                    }
+                    app.cursor.x -= 1;
+                }
+                Key::Alt('j') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        app.nodes[ix].position.y += 1;
+                    }
+                    app.cursor.y += 1;
+                }
+                Key::Alt('k') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        app.nodes[ix].position.y -= 1;
+                    }
+                    app.cursor.y -= 1;
+                }
+                Key::Alt('l') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        app.nodes[ix].position.x += 1;
+                    }
+                    app.cursor.x += 1;
+                }
+                Key::Char('J') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| {
+                        node.position.y > p.y
+                            || node.position.y == p.y
+                                && p.x < node.position.x + text::width(&node.op)
+                    }) {
+                        node.position.y += 1;
+                    }
+                    app.cursor.y += 1;
+                }
+                Key::Char('K') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| {
+                        node.position.y < p.y || node.position.y == p.y && node.position.x <= p.x
+                    }) {
+                        node.position.y -= 1;
+                    }
+                    app.cursor.y -= 1;
+                }
+                Key::Char('H') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| node.position.y == p.y) {
+                        node.position.y -= 1;
+                    }
+                    app.cursor.y -= 1;
+                }
+                Key::Char('L') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| node.position.y == p.y) {
+                        node.position.y += 1;
+                    }
+                    app.cursor.y += 1;
+                }
+                Key::Char(',') => {
+                    let p = app.cursor;
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
+                    {
+                        node.position.x -= 1;
+                    }
+                    app.cursor.x -= 1;
+                }
+                Key::Char('<') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| {
+                        node.position.y == p.y && p.x < node.position.x + text::width(&node.op)
+                    }) {
+                        node.position.x -= 1;
+                    }
+                    app.cursor.x -= 1;
+                }
+                Key::Char('.') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| {
+                        node.position.y == p.y && p.x < node.position.x + text::width(&node.op)
+                    }) {
+                        node.position.x += 1;
+                    }
+                    app.cursor.x += 1;
+                }
+                Key::Char('>') => {
+                    let p = app.cursor;
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && node.position.x <= p.x)
+                    {
+                        node.position.x += 1;
+                    }
+                    app.cursor.x += 1;
+                }
+                Key::Char('d') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        app.nodes.swap_remove(ix);
+                        app.draft = true;
+                    }
+                }
+                Key::Char('D') => {
+                    let p = app.cursor;
+                    app.nodes.retain(|node| node.position.y != p.y);
+                }
+                Key::Char('s') if app.solo.is_some() => {
+                    app.solo = None;
+                    commit(app, host);
+                }
+                Key::Char('s') => {
+                    app.nodes.sort_by_key(|node| node.position);
+                    if let Some(ix) = app.node_at_cursor() {
+                        if stack::depth(app, ix) == Some(0) {
+                            app.notice = String::from("Nothing on the stack to solo.");
+                        } else {
+                            app.solo = Some(app.nodes[ix].id);
+                            commit(app, host);
+                        }
+                    }
+                }
+                Key::Char('m') => {
+                    let p = app.cursor;
+                    let muted = !app
+                        .nodes
+                        .iter()
+                        .any(|node| node.position.y == p.y && node.muted);
+                    for node in app.nodes.iter_mut().filter(|node| node.position.y == p.y) {
+                        node.muted = muted;
+                    }
+                    commit(app, host);
+                }
+                Key::Char('=') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        let node = &mut app.nodes[ix];
+                        let i = text::offset(&node.op, app.cursor.x - node.position.x);
+                        if let Some(d) = node.op.get(i..(i + 1)).and_then(|c| c.parse::<u8>().ok())
+                        {
+                            let d = (d + 1) % 10;
+                            node.op.replace_range(i..(i + 1), &d.to_string());
+
+                            commit(app, host);
+                        } else {
+                            for cycle in &app.cycles {
+                                if let Some(ops) = cycle.windows(2).find(|ops| ops[0] == node.op) {
+                                    node.op = ops[1].to_owned();
+                                    commit(app, host);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Key::Char('-') => {
+                    if let Some(ix) = app.node_at_cursor() {
+                        let node = &mut app.nodes[ix];
+                        let i = text::offset(&node.op, app.cursor.x - node.position.x);
+                        if let Some(d) = node.op.get(i..(i + 1)).and_then(|c| c.parse::<u8>().ok())
+                        {
+                            let d = (d + 9) % 10;
+                            node.op.replace_range(i..(i + 1), &d.to_string());
+                            commit(app, host);
+                        } else {
+                            for cycle in &app.cycles {
+                                if let Some(ops) = cycle.windows(2).find(|ops| ops[1] == node.op) {
+                                    node.op = ops[0].to_owned();
+                                    commit(app, host);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Key::Char('{') => {
+                    app.bpm = app.ctx.bpm.lock().unwrap().round() - 1.0;
+                    set_bpm(app);
+                }
+                Key::Char('}') => {
+                    app.bpm = app.ctx.bpm.lock().unwrap().round() + 1.0;
+                    set_bpm(app);
+                }
+                Key::Char('(') => {
+                    app.warp -= WARP_STEP;
+                    set_warp(app);
+                }
+                Key::Char(')') => {
+                    app.warp += WARP_STEP;
+                    set_warp(app);
+                }
+                Key::Char('Z') if app.sunset.is_some() => {
+                    sunset::stop(app, &host.monitor);
+                    app.notice = "Sunset is cancelled.".to_owned();
+                }
+                Key::Char('Z') if app.play => sunset::start(app),
+                Key::Char('[') => host.vm.lock().unwrap().scrub_back(host.sample_rate as _),
+                Key::Char(']') => host.vm.lock().unwrap().scrub_forward(host.sample_rate as _),
+                Key::Char('r') if !app.ctx.allow_files => {
+                    app.notice = "Recording is off during the workshop.".to_owned();
+                }
+                Key::Char('r') => {
+                    app.recording = !app.recording;
+                    host.record_tx
+                        .send(record::Message::Record(app.recording))
+                        .ok();
+                }
+                Key::Char('R') => {
+                    app.record_format = app.record_format.next();
+                    host.record_tx
+                        .send(record::Message::Format(app.record_format))
+                        .ok();
+                }
+                Key::Char('q') if app.rails().is_some() && !app.quit_pending => {
+                    // The next q stops the input worker.
+                    app.quit_pending = true;
+                    events.enable_exit_key();
+                }
+                Key::Char('q') => {
+                    host.vm.lock().unwrap().pause();
+                    save_tables(app, host.sample_rate);
+                    if let Some(saver) = app.tables_saver.take() {
+                        saver.join().ok();
+                    }
+                    app.session
+                        .save_report(&host.stats.lock().unwrap(), host.filename)
+                        .ok();
+                    return Err(anyhow!("Quit!"));
+                }
+                Key::Char('w') => save_tables(app, host.sample_rate),
+                Key::Char('S') => app.stack_panel = !app.stack_panel,
+                Key::Char('A') => {
+                    app.announce = !app.announce;
+                    // Announce everything from scratch.
+                    app.focus = None;
+                    app.speech.say(if app.announce {
+                        "Announcements on"
+                    } else {
+                        "Announcements off"
+                    });
+                }
+                Key::Char('W') => {
+                    let text = announce::describe_cursor(app);
+                    app.speech.say(&text);
+                }
+                Key::Char('C') => app.high_contrast = !app.high_contrast,
+                Key::Char('T') => app.tuner_enabled = !app.tuner_enabled,
+                Key::Char('M') => {
+                    app.monitor_direct = !app.monitor_direct;
+                    set_monitor(app, &host.monitor);
+                }
+                Key::Char('v') => {
+                    app.monitor_gain -= 3.0;
+                    set_monitor(app, &host.monitor);
+                }
+                Key::Char('V') => {
+                    app.monitor_gain = (app.monitor_gain + 3.0).min(0.0);
+                    set_monitor(app, &host.monitor);
+                }
+                Key::Char('B') => {
+                    app.dc_block = !app.dc_block;
+                    // Cut-off frequency is 10 Hz.
+                    let frames = host.sample_rate as f64 / (2.0 * std::f64::consts::PI * 10.0);
+                    host.vm.lock().unwrap().set_dc_block(if app.dc_block {
+                        Some(frames)
+                    } else {
+                        None
+                    });
+                }
+                Key::F(n) if n > 0 => {
+                    if let Some(teacher) = &app.teacher {
+                        teacher.toggle_mute(n as usize - 1);
+                    }
+                }
+                Key::Char('?') => app.screen = Screen::Help,
+                Key::Char('/') => app.screen = Screen::Ops,
+                Key::Char('E') if !app.ctx.allow_files => {
+                    app.notice = "Export is off during the workshop.".to_owned();
+                }
+                Key::Char('E') => app.notice = export::write_program(app, host.filename),
+                Key::Char('G') if !app.gain_hints.is_empty() => app.gain_hints.clear(),
+                Key::Char('G') => gain::analyze(app, host.sample_rate),
+                Key::Char('u') => {
+                    app.history_cursor = app.history.len().saturating_sub(1);
+                    app.screen = Screen::History;
+                }
+                Key::Char('g') => {
+                    app.help_scroll = 0;
+                    app.screen = Screen::Log;
+                }
+                Key::Char('f') => usages::open(app),
+                Key::Char('O') => devices::open(app),
+                _ => {}
+            },
+            InputMode::Editing => match input {
+                Key::Left => {
+                    app.cursor.x -= 1;
+                }
+                Key::Down => {
+                    app.cursor.y += 1;
+                }
+                Key::Up => {
+                    app.cursor.y -= 1;
+                }
+                Key::Right => {
+                    app.cursor.x += 1;
+                }
+                Key::Char(' ') => {
+                    let p = app.cursor;
+                    for node in app.nodes.iter_mut().filter(|node| {
+                        node.position.y == p.y && p.x < node.position.x + text::width(&node.op)
+                    }) {
+                        node.position.x += 1;
+                    }
+                    app.cursor.x += 1;
+                }
+                Key::Char('\n') => {
+                    app.input_mode = InputMode::Normal;
+                    if app.rails().is_none() {
+                        events.enable_exit_key();
+                    }
+                    app.draft = app.nodes.iter().any(|node| node.op.is_empty());
+                    app.nodes.retain(|node| !node.op.is_empty());
+                }
+                Key::Char(c) => {
+                    // Combining chars join the previous one without moving anything.
+                    let width = text::char_width(c);
+                    let p = app.cursor;
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && p.x < node.position.x)
+                    {
+                        node.position.x += width;
+                    }
+                    let node = app.node_at_cursor();
+                    if let Some(ix) = node {
+                        let node = &mut app.nodes[ix];
+                        let ix = text::offset(&node.op, app.cursor.x - node.position.x);
+                        node.op.insert(ix, c);
+                        node.draft = true;
+                    } else {
+                        let node = Node {
+                            id: random(),
+                            draft: true,
+                            muted: false,
+                            op: c.to_string(),
+                            position: app.cursor,
+                        };
+                        app.nodes.push(node);
+                    };
+                    app.cursor.x += width;
+                }
+                Key::Backspace => {
+                    let p = app.cursor;
+                    // Width of the character before the cursor, or a column of space.
+                    let width = app
+                        .nodes
+                        .iter()
+                        .filter(|node| node.position.y == p.y && node.position.x < p.x)
+                        .find_map(|node| {
+                            text::grapheme_at(&node.op, p.x - 1 - node.position.x)
+                                .map(|range| text::width(&node.op[range]))
+                        })
+                        .unwrap_or(1);
+                    for node in app
+                        .nodes
+                        .iter_mut()
+                        .filter(|node| node.position.y == p.y && p.x < node.position.x)
+                    {
+                        node.position.x -= width;
+                    }
+                    app.cursor.x -= width;
+                    let node = app.node_at_cursor();
+                    if let Some(ix) = node {
+                        let node = &mut app.nodes[ix];
+                        if let Some(range) =
+                            text::grapheme_at(&node.op, app.cursor.x - node.position.x)
+                        {
+                            node.op.replace_range(range, "");
+                            node.draft = true;
+                        }
+                    }
+                }
+                Key::Esc => {
+                    app.input_mode = InputMode::Normal;
+                    if app.rails().is_none() {
+                        events.enable_exit_key();
+                    }
+                    app.draft = app.nodes.iter().any(|node| node.op.is_empty());
+                    app.nodes.retain(|node| !node.op.is_empty());
+                }
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+//! Export the committed program to other languages.
+use super::App;
+use audio_program::export;
+use itertools::Itertools;
+
+/// Write the committed program as Sporth and Faust code next to the file, return the outcome.
+pub fn write_program(app: &App, filename: &str) -> String {
+    let path = std::path::Path::new(filename);
+    vec![
+        ("sp", export::to_sporth(&app.ops)),
+        ("dsp", export::to_faust(&app.ops)),
+    ]
+    .into_iter()
+    .map(|(extension, result)| match result {
+        Ok(code) => match std::fs::write(path.with_extension(extension), code) {
+            Ok(_) => format!("Exported .{}.", extension),
+            Err(err) => format!("Can't write .{}: {}.", extension, err),
+        },
+        Err(ops) => format!("Can't export .{}: {}.", extension, ops.join(" ")),
+    })
+    .join(" ")
+}
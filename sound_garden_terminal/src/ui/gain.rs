@@ -0,0 +1,44 @@
+//! Hints about levels between ops, from offline renders of the committed program.
+use super::App;
+use audio_program::{gain_staging, Context};
+use std::sync::{Arc, Mutex};
+
+/// Render a few seconds of the committed program offline on a worker thread, its stages come
+/// to `show_gain_hints`. Analysis gets its own context to not disturb tables and meters of the
+/// running program.
+pub fn analyze(app: &mut App, sample_rate: u32) {
+    let mut ctx = Context::new();
+    ctx.allow_files = app.ctx.allow_files;
+    ctx.max_feedback_gain = app.ctx.max_feedback_gain;
+    ctx.bpm = Arc::new(Mutex::new(*app.ctx.bpm.lock().unwrap()));
+    ctx.warp = Arc::new(Mutex::new(app.warp));
+    ctx.seed = Some(0);
+    let ops = app.ops.clone();
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    std::thread::spawn(move || {
+        let frames = 2 * sample_rate as usize;
+        tx.send(gain_staging::analyze(&ops, sample_rate, frames, &mut ctx))
+            .ok();
+    });
+    app.gain_analysis = Some(rx);
+    app.notice = String::from("Analyzing gain staging...");
+}
+
+/// Hint stages with bad levels.
+pub fn show_hints(app: &mut App, stages: &[gain_staging::Stage]) {
+    let ids = app.nodes.iter().map(|node| node.id).collect::<Vec<_>>();
+    // Stages of ops expanded from terms don't belong to nodes.
+    app.gain_hints = stages
+        .iter()
+        .filter(|stage| ids.contains(&stage.id))
+        .filter_map(|stage| stage.hint().map(|hint| (stage.id, hint)))
+        .collect();
+    app.notice = if app.gain_hints.is_empty() {
+        String::from("Gain staging is fine.")
+    } else {
+        format!(
+            "Gain staging: {} stages to fix, see highlighted nodes.",
+            app.gain_hints.len()
+        )
+    };
+}
@@ -0,0 +1,62 @@
+//! Help screen.
+use super::{App, Screen, Term, Theme, MIN_X, MIN_Y};
+use crate::event::{Event, Events};
+use anyhow::Result;
+use itertools::Itertools;
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+pub fn render(app: &mut App, sample_rate: u32, filename: &str, terminal: &mut Term) -> Result<()> {
+    let theme = Theme::new(app.high_contrast);
+    terminal.draw(|mut f| {
+        let mut size = f.size();
+        Block::default()
+            .title("Sound Garden────Help")
+            .title_style(Style::default().fg(theme.info))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.info))
+            .render(&mut f, size);
+        let text = [
+            Text::raw(format!("Path: {}\n", filename)),
+            Text::raw(format!("Sample rate: {}\n", sample_rate)),
+            Text::raw(format!(
+                "Cycles: {}\n",
+                app.cycles.iter().map(|cycle| cycle.join("->")).join(", ")
+            )),
+            Text::raw(format!("Program: {}\n", app.program)),
+            Text::raw("\n"),
+            Text::raw(include_str!("../help.txt")),
+        ];
+        size.x = MIN_X as u16;
+        size.y = MIN_Y as u16;
+        size.width -= 3;
+        size.height -= 3;
+        Paragraph::new(text.iter())
+            .scroll(app.help_scroll)
+            .wrap(true)
+            .render(&mut f, size);
+    })?;
+    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
+    io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn handle(app: &mut App, events: &mut Events) -> Result<()> {
+    if let Event::Input(input) = events.next()? {
+        match input {
+            Key::Char('?') => app.screen = Screen::Editor,
+            Key::Esc => app.screen = Screen::Editor,
+            Key::Char('j') | Key::Down => {
+                app.help_scroll += 1;
+            }
+            Key::Char('k') | Key::Up if app.help_scroll > 0 => {
+                app.help_scroll -= 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
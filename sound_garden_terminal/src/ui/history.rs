@@ -0,0 +1,185 @@
+//! History of commits: audition revisions, roll back to them and name the ones to keep.
+use super::{commit, inject, App, Host, Node, Screen, Term, Theme, MIN_X, MIN_Y};
+use crate::event::{Event, Events};
+use anyhow::Result;
+use audio_program::{compile_program, rewrite_terms, rewrite_words};
+use audio_vm::Program;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+/// Unnamed revisions beyond it are forgotten, oldest first.
+const HISTORY_SIZE: usize = 256;
+
+/// Committed version of the program, to audition and roll back to.
+#[derive(Serialize, Deserialize)]
+pub struct Revision {
+    /// Local time of the commit.
+    time: String,
+    #[serde(default)]
+    name: Option<String>,
+    program: String,
+    nodes: Vec<Node>,
+}
+
+pub fn render(app: &mut App, terminal: &mut Term) -> Result<()> {
+    let theme = Theme::new(app.high_contrast);
+    terminal.draw(|mut f| {
+        let mut size = f.size();
+        Block::default()
+            .title(if app.auditioning {
+                "Sound Garden────History────Auditioning"
+            } else {
+                "Sound Garden────History"
+            })
+            .title_style(Style::default().fg(theme.info))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.info))
+            .render(&mut f, size);
+        let mut text = vec![
+            Text::raw(
+                "(Press Esc to close, j/k to select, Space to audition, Return to roll back, n to name)\n",
+            ),
+            Text::raw("\n"),
+        ];
+        // The latest revision goes first.
+        for (i, revision) in app.history.iter().enumerate().rev() {
+            let line = format!(
+                "{} {} {}{}\n",
+                if i == app.history_cursor { ">" } else { " " },
+                revision.time,
+                revision
+                    .name
+                    .as_ref()
+                    .map(|name| format!("[{}] ", name))
+                    .unwrap_or_default(),
+                revision.program,
+            );
+            text.push(if i == app.history_cursor {
+                Text::styled(line, theme.draft)
+            } else {
+                Text::raw(line)
+            });
+        }
+        size.x = MIN_X as u16;
+        size.y = MIN_Y as u16;
+        size.width -= 3;
+        size.height -= 3;
+        // Keep the selected revision in the middle of the screen.
+        let selected = (app.history.len() - app.history_cursor.min(app.history.len())) as u16;
+        Paragraph::new(text.iter())
+            .scroll(selected.saturating_sub(size.height / 2))
+            .render(&mut f, size);
+    })?;
+    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
+    io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn handle(app: &mut App, host: &Host, events: &mut Events) -> Result<()> {
+    let input = match events.next()? {
+        Event::Input(input) => input,
+        _ => return Ok(()),
+    };
+    if app.naming {
+        let revision = &mut app.history[app.history_cursor];
+        match input {
+            Key::Char('\n') | Key::Esc => {
+                app.naming = false;
+                if app.rails().is_none() {
+                    events.enable_exit_key();
+                }
+                app.save(host.filename).ok();
+            }
+            Key::Char(c) => revision.name.get_or_insert_with(String::new).push(c),
+            Key::Backspace => {
+                if let Some(name) = revision.name.as_mut() {
+                    name.pop();
+                    if name.is_empty() {
+                        revision.name = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+    match input {
+        Key::Char('u') | Key::Esc => {
+            stop_audition(app, host);
+            app.screen = Screen::Editor;
+        }
+        Key::Char('j') | Key::Down => {
+            app.history_cursor = app.history_cursor.saturating_sub(1);
+        }
+        Key::Char('k') | Key::Up => {
+            app.history_cursor = (app.history_cursor + 1).min(app.history.len().saturating_sub(1));
+        }
+        Key::Char(' ') if app.history_cursor < app.history.len() => {
+            let ix = app.history_cursor;
+            let program = compile_revision(app, ix, host.sample_rate);
+            // Deallocate the previous program outside of the lock.
+            let garbage = host.vm.lock().unwrap().load_program(program);
+            drop(garbage);
+            app.auditioning = true;
+        }
+        Key::Char('\n') if app.history_cursor < app.history.len() => {
+            stop_audition(app, host);
+            app.nodes = app.history[app.history_cursor].nodes.clone();
+            app.screen = Screen::Editor;
+            commit(app, host);
+        }
+        Key::Char('n') if app.history_cursor < app.history.len() => {
+            app.naming = true;
+            events.disable_exit_key();
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Remember the committed program as a revision.
+pub fn push(app: &mut App) {
+    // Reopening the file commits the last revision again.
+    if app.history.last().map(|revision| &revision.program) != Some(&app.program) {
+        app.history.push(Revision {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            name: None,
+            program: app.program.to_owned(),
+            nodes: app.nodes.clone(),
+        });
+        if app.history.len() > HISTORY_SIZE {
+            if let Some(ix) = app
+                .history
+                .iter()
+                .position(|revision| revision.name.is_none())
+            {
+                app.history.remove(ix);
+            }
+        }
+    }
+}
+
+/// Compile nodes of the revision with the given index without committing them. Words, tables
+/// and buses of the revision stay in a scratch context, the committed program keeps its own.
+pub fn compile_revision(app: &App, ix: usize, sample_rate: u32) -> Program {
+    let mut ctx = app.ctx.scratch();
+    let ops = rewrite_terms(&inject::text_ops(app, &app.history[ix].nodes));
+    let ops = rewrite_words(&ops, &mut ctx.words);
+    compile_program(&ops, sample_rate, &mut ctx)
+}
+
+/// Switch back to the committed program after auditioning a revision.
+pub fn stop_audition(app: &mut App, host: &Host) {
+    if app.auditioning {
+        app.auditioning = false;
+        let program = compile_program(&app.ops, host.sample_rate, &mut app.ctx);
+        // Deallocate the previous program outside of the lock.
+        let garbage = host.vm.lock().unwrap().load_program(program);
+        drop(garbage);
+    }
+}
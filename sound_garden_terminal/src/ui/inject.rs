@@ -0,0 +1,100 @@
+//! Ops the editor adds to the program around the ones of nodes: zeros in place of muted lines,
+//! the probe of the solo node and the grid sequencer.
+use super::{App, Node};
+use crate::grid;
+use audio_program::{expand_word, ops_arity, Arity, TextOp};
+use itertools::Itertools;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Ops of sorted nodes. Signals a muted line leaves on the stack are replaced with zeros after
+/// its last node, ops of the line keep running to unmute without clicks and with tails in place.
+/// Lines which only process signals of lines before them have nothing of their own to mute.
+pub fn text_ops(app: &App, nodes: &[Node]) -> Vec<TextOp> {
+    let mut ops = Vec::new();
+    for (_, line) in &nodes.iter().group_by(|node| node.position.y) {
+        let line = line.collect::<Vec<_>>();
+        for node in &line {
+            ops.push(TextOp {
+                id: node.id,
+                op: node.op.to_owned(),
+            });
+        }
+        if line.iter().any(|node| node.muted) {
+            let id = line[line.len() - 1].id;
+            let outputs = line.iter().try_fold(0isize, |depth, node| {
+                let Arity { inputs, outputs } = node_arity(app, node)?;
+                Some(depth - inputs as isize + outputs as isize)
+            });
+            match outputs {
+                Some(outputs) => {
+                    for i in 0..outputs.max(0) {
+                        ops.push(TextOp {
+                            id: injected_id(id, &format!("mute pop {}", i)),
+                            op: "pop".to_owned(),
+                        });
+                    }
+                    for i in 0..outputs.max(0) {
+                        ops.push(TextOp {
+                            id: injected_id(id, &format!("mute zero {}", i)),
+                            op: "0".to_owned(),
+                        });
+                    }
+                }
+                None => log::warn!(
+                    "Can't mute the line of {}, arity of its ops is unknown.",
+                    line[0].op
+                ),
+            }
+        }
+    }
+    ops
+}
+
+/// Arity of the op of the node, words defined by the last commit count as their ops.
+fn node_arity(app: &App, node: &Node) -> Option<Arity> {
+    let op = TextOp {
+        id: node.id,
+        op: node.op.to_owned(),
+    };
+    ops_arity(&app.arities, &expand_word(&op, &app.ctx.words))
+}
+
+/// Probe the signal left by the node and play it instead of the output. Ops are rewritten
+/// already, so the probe doesn't end up in terms or fill their holes. Returns false when the node
+/// isn't there, i.e. it's rewritten into other ops.
+pub fn solo(ops: &mut Vec<TextOp>, id: u64) -> bool {
+    let ix = match ops.iter().rposition(|op| op.id == id) {
+        Some(ix) => ix,
+        None => return false,
+    };
+    ops.insert(
+        ix + 1,
+        TextOp {
+            id: injected_id(id, "probe"),
+            op: "probe".to_owned(),
+        },
+    );
+    ops.push(TextOp {
+        id: injected_id(id, "solo"),
+        op: "solo".to_owned(),
+    });
+    true
+}
+
+/// Put the grid sequencer in front of the program, its tracks are sent to buses.
+pub fn grid(ops: &mut Vec<TextOp>) {
+    let sends = grid::sends().into_iter().enumerate().map(|(i, op)| TextOp {
+        id: injected_id(0, &format!("grid {}", i)),
+        op,
+    });
+    ops.splice(0..0, sends);
+}
+
+/// Ids of ops the editor adds to the program are hashed from the node and the role of the op,
+/// so they don't collide with ids which rewriting salts by adding.
+fn injected_id(id: u64, role: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (id, role).hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,111 @@
+//! Log of the session, filtered by severity and module.
+use super::{App, Screen, Term, Theme, MIN_X, MIN_Y};
+use crate::event::{Event, Events};
+use anyhow::Result;
+use itertools::Itertools;
+use log::Level;
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+pub fn render(app: &mut App, terminal: &mut Term) -> Result<()> {
+    let theme = Theme::new(app.high_contrast);
+    let journal = match &app.journal {
+        Some(journal) => journal.lock().unwrap(),
+        None => return Ok(()),
+    };
+    terminal.draw(|mut f| {
+        let mut size = f.size();
+        Block::default()
+            .title(&format!(
+                "Sound Garden────Log────{}────{}",
+                app.log_level
+                    .map(|level| format!("{} and worse", level))
+                    .unwrap_or_else(|| "All levels".to_owned()),
+                app.log_target.as_deref().unwrap_or("All modules"),
+            ))
+            .title_style(Style::default().fg(theme.info))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.info))
+            .render(&mut f, size);
+        let mut text = vec![
+            Text::raw(
+                "(Press Esc to close, j/k to scroll, s to filter by severity, m by module)\n",
+            ),
+            Text::raw("\n"),
+        ];
+        // The latest entry goes first.
+        for entry in journal.entries.iter().rev().filter(|entry| {
+            app.log_level.is_none_or(|level| entry.level <= level)
+                && app
+                    .log_target
+                    .as_ref()
+                    .is_none_or(|target| &entry.target == target)
+        }) {
+            let line = format!(
+                "{} {:5} {} {}\n",
+                entry.time, entry.level, entry.target, entry.message
+            );
+            text.push(if entry.level <= Level::Warn {
+                Text::styled(line, theme.draft)
+            } else {
+                Text::raw(line)
+            });
+        }
+        size.x = MIN_X as u16;
+        size.y = MIN_Y as u16;
+        size.width -= 3;
+        size.height -= 3;
+        Paragraph::new(text.iter())
+            .scroll(app.help_scroll)
+            .render(&mut f, size);
+    })?;
+    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
+    io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn handle(app: &mut App, events: &mut Events) -> Result<()> {
+    if let Event::Input(input) = events.next()? {
+        match input {
+            Key::Char('g') | Key::Esc => app.screen = Screen::Editor,
+            Key::Char('j') | Key::Down => app.help_scroll += 1,
+            Key::Char('k') | Key::Up => app.help_scroll = app.help_scroll.saturating_sub(1),
+            Key::Char('s') => {
+                app.log_level = match app.log_level {
+                    None => Some(Level::Warn),
+                    Some(Level::Warn) => Some(Level::Error),
+                    _ => None,
+                };
+                app.help_scroll = 0;
+            }
+            Key::Char('m') => {
+                // Cycle through modules which logged something, then back to all of them.
+                let targets = app
+                    .journal
+                    .as_ref()
+                    .map(|journal| {
+                        journal
+                            .lock()
+                            .unwrap()
+                            .entries
+                            .iter()
+                            .map(|entry| entry.target.to_owned())
+                            .sorted()
+                            .dedup()
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                app.log_target = match &app.log_target {
+                    None => targets.first().cloned(),
+                    Some(target) => targets.iter().skip_while(|x| *x != target).nth(1).cloned(),
+                };
+                app.help_scroll = 0;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
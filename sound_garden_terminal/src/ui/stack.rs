@@ -0,0 +1,114 @@
+//! Simulated stack of the line under the cursor along with live values, for the stack panel.
+use super::{App, Node};
+use audio_program::{expand_word, op_arity, rewrite_terms, Arity, TextOp};
+use audio_vm::{stack::STACK_SIZE, Frame, VM};
+use std::sync::Mutex;
+
+/// Simulated stack after each node of the line under the cursor along with its live value.
+pub fn simulate(app: &App) -> Vec<(String, Option<Vec<String>>, Option<Frame>)> {
+    let mut nodes = app
+        .nodes
+        .iter()
+        .filter(|node| node.position.y <= app.cursor.y)
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.position);
+    nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.position.y == app.cursor.y)
+        .map(|(i, node)| {
+            let live = app
+                .probes
+                .iter()
+                .find(|(id, _)| *id == node.id)
+                .map(|(_, frame)| *frame);
+            (node.op.to_owned(), simulate_ops(app, &nodes[..=i]), live)
+        })
+        .collect()
+}
+
+/// Stack after ops of the nodes, with terms rewritten and words defined by the last commit
+/// expanded. None when arity of some op is unknown, as nothing after it could be told then.
+fn simulate_ops(app: &App, nodes: &[&Node]) -> Option<Vec<String>> {
+    let ops = nodes
+        .iter()
+        .map(|node| TextOp {
+            id: node.id,
+            op: node.op.to_owned(),
+        })
+        .collect::<Vec<_>>();
+    let mut stack: Vec<String> = Vec::new();
+    let mut definition = false;
+    for op in rewrite_terms(&ops) {
+        // Word definitions push nothing.
+        match op.op.as_str() {
+            ":" => definition = true,
+            ";" => definition = false,
+            _ if definition => {}
+            _ => {
+                for TextOp { op, .. } in expand_word(&op, &app.ctx.words) {
+                    let Arity { inputs, outputs } = op_arity(&app.arities, &op)?;
+                    // Underflow produces zeros.
+                    let mut args = stack.split_off(stack.len().saturating_sub(inputs));
+                    while args.len() < inputs {
+                        args.insert(0, "0".to_owned());
+                    }
+                    let results = match op.split(':').next().unwrap() {
+                        "pop" => Vec::new(),
+                        "dup" => vec![args[0].to_owned(), args[0].to_owned()],
+                        // Take the deepest argument and put it on the top.
+                        "swap" | "rot" | "" | "dig" => {
+                            if !args.is_empty() {
+                                args.rotate_left(1);
+                            }
+                            args
+                        }
+                        _ => vec![op.to_owned(); outputs],
+                    };
+                    for x in results {
+                        // Overflow is ignored.
+                        if stack.len() < STACK_SIZE {
+                            stack.push(x);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Some(stack)
+}
+
+/// Stack depth after the node with the given index of sorted nodes, None when arity of some
+/// op before it is unknown.
+pub fn depth(app: &App, ix: usize) -> Option<usize> {
+    let nodes = app.nodes[..=ix].iter().collect::<Vec<_>>();
+    simulate_ops(app, &nodes).map(|stack| stack.len())
+}
+
+/// Probe live values after nodes of the line under the cursor while the stack panel is open.
+pub fn update_probes(app: &mut App, vm: &Mutex<VM>) {
+    let ids = if app.stack_panel {
+        app.nodes
+            .iter()
+            .filter(|node| node.position.y == app.cursor.y)
+            .map(|node| node.id)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut vm = vm.lock().unwrap();
+    let garbage = if vm
+        .probes()
+        .iter()
+        .map(|(id, _)| *id)
+        .ne(ids.iter().copied())
+    {
+        Some(vm.set_probes(ids))
+    } else {
+        None
+    };
+    app.probes.clear();
+    app.probes.extend_from_slice(vm.probes());
+    drop(vm);
+    drop(garbage);
+}
@@ -0,0 +1,71 @@
+//! Sunset, the slow fade out at the end of the set.
+use super::{set_monitor, set_warp, App};
+use crate::audio::Monitor;
+use audio_vm::VM;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// By the end of the sunset the master is that many dB down, transport is slowed by the factor
+/// and reverb tails are stretched by the other one.
+pub const FLOOR: f64 = -60.0;
+const WARP: f64 = 0.5;
+const TAILS: f64 = 4.0;
+
+/// End of the set: over its duration the master fades out, transport slows down and reverb
+/// tails grow longer, then playback pauses.
+pub struct Sunset {
+    pub start: Instant,
+    /// Transport rate before the sunset to come back to.
+    warp: f64,
+}
+
+pub fn default_duration() -> f64 {
+    120.0
+}
+
+/// Start the sunset from the current transport rate.
+pub fn start(app: &mut App) {
+    app.sunset = Some(Sunset {
+        start: Instant::now(),
+        warp: app.warp,
+    });
+}
+
+/// From 0 to 1 while the sunset goes.
+pub fn progress(app: &App) -> Option<f64> {
+    app.sunset.as_ref().map(|sunset| {
+        (sunset.start.elapsed().as_secs_f64() / app.sunset_duration.max(1.0)).min(1.0)
+    })
+}
+
+pub fn update(app: &mut App, vm: &Mutex<VM>, monitor: &Mutex<Monitor>) {
+    let progress = match progress(app) {
+        Some(progress) => progress,
+        None => return,
+    };
+    if progress < 1.0 {
+        if let Some(sunset) = &app.sunset {
+            // Not rounded to steps to slow down smoothly.
+            app.warp = sunset.warp * (1.0 - (1.0 - WARP) * progress);
+        }
+        *app.ctx.warp.lock().unwrap() = app.warp;
+        *app.ctx.tails.lock().unwrap() = 1.0 + (TAILS - 1.0) * progress;
+        set_monitor(app, monitor);
+    } else {
+        // Output is silent by now, so it stops without a fade.
+        app.play = false;
+        vm.lock().unwrap().stop();
+        stop(app, monitor);
+        app.notice = "Sunset is over.".to_owned();
+    }
+}
+
+/// Bring back the master, transport rate and reverb tails.
+pub fn stop(app: &mut App, monitor: &Mutex<Monitor>) {
+    if let Some(sunset) = app.sunset.take() {
+        app.warp = sunset.warp;
+        set_warp(app);
+        *app.ctx.tails.lock().unwrap() = 1.0;
+        set_monitor(app, monitor);
+    }
+}
@@ -0,0 +1,174 @@
+//! Usages of tables and buses, and readers of ones which nothing writes.
+use super::{App, Node, Screen, Term, Theme, MIN_X, MIN_Y};
+use crate::event::{Event, Events};
+use anyhow::Result;
+use audio_program::{expand_word, shared_access, Shared, TableAccess, TextOp};
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+
+/// Open the usages of the table or the bus under the cursor.
+pub fn open(app: &mut App) {
+    app.nodes.sort_by_key(|node| node.position);
+    // Without a table or a bus under the cursor list readers of missing ones.
+    app.usages_of = app.node_at_cursor().and_then(|ix| {
+        let accesses = node_accesses(app, &app.nodes[ix]);
+        let (kind, name, _) = accesses.into_iter().next()?;
+        Some((kind, name))
+    });
+    let accesses = app
+        .nodes
+        .iter()
+        .map(|node| (node.id, node_accesses(app, node)))
+        .collect::<Vec<_>>();
+    let written = |kind, name: &str| {
+        accesses
+            .iter()
+            .flat_map(|(_, x)| x)
+            .any(|x| (x.0, x.1.as_str(), x.2) == (kind, name, TableAccess::Write))
+    };
+    app.usages = accesses
+        .iter()
+        .filter(|(_, accesses)| {
+            accesses
+                .iter()
+                .any(|(kind, name, access)| match &app.usages_of {
+                    Some(shared) => (*kind, name) == (shared.0, &shared.1),
+                    None => *access == TableAccess::Read && !written(*kind, name),
+                })
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    app.usages_cursor = 0;
+    app.screen = Screen::Usages;
+}
+
+pub fn render(app: &mut App, terminal: &mut Term) -> Result<()> {
+    let theme = Theme::new(app.high_contrast);
+    let usages = app
+        .usages
+        .iter()
+        .filter_map(|id| app.nodes.iter().find(|node| node.id == *id))
+        .filter_map(|node| {
+            let access = node_accesses(app, node)
+                .into_iter()
+                .find(|(kind, name, access)| match &app.usages_of {
+                    Some(shared) => (*kind, name) == (shared.0, &shared.1),
+                    None => *access == TableAccess::Read,
+                })?;
+            Some((node, access.2))
+        })
+        .collect::<Vec<_>>();
+    let has = |access| usages.iter().any(|(_, x)| *x == access);
+    let (title, warning) = match &app.usages_of {
+        Some((Shared::Table, name)) => (
+            format!("Usages of table {}", name),
+            if !has(TableAccess::Write) {
+                "Nothing writes the table, its readers are silent.\n"
+            } else if !has(TableAccess::Read) {
+                "Nothing reads the table.\n"
+            } else {
+                ""
+            },
+        ),
+        Some((Shared::Bus, name)) => (
+            format!("Usages of bus {}", name),
+            if !has(TableAccess::Write) {
+                "Nothing sends to the bus, its receivers are silent.\n"
+            } else if !has(TableAccess::Read) {
+                "Nothing receives from the bus.\n"
+            } else {
+                ""
+            },
+        ),
+        None => (
+            String::from("Orphan readers"),
+            if usages.is_empty() {
+                "Every table and bus which is read is written too.\n"
+            } else {
+                "Nothing writes tables and buses they read.\n"
+            },
+        ),
+    };
+    terminal.draw(|mut f| {
+        let mut size = f.size();
+        Block::default()
+            .title(&format!("Sound Garden────{}", title))
+            .title_style(Style::default().fg(theme.info))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.info))
+            .render(&mut f, size);
+        let mut text = vec![
+            Text::raw("(Press Esc to close, j/k to select, Return to jump to the node)\n"),
+            Text::raw("\n"),
+            Text::styled(warning, theme.draft),
+        ];
+        for (i, (node, access)) in usages.iter().enumerate() {
+            let line = format!(
+                "{} {:>4}:{:<4} {} {}\n",
+                if i == app.usages_cursor { ">" } else { " " },
+                node.position.y,
+                node.position.x,
+                match access {
+                    TableAccess::Read => "reads ",
+                    TableAccess::Write => "writes",
+                },
+                node.op,
+            );
+            text.push(if i == app.usages_cursor {
+                Text::styled(line, theme.draft)
+            } else {
+                Text::raw(line)
+            });
+        }
+        size.x = MIN_X as u16;
+        size.y = MIN_Y as u16;
+        size.width -= 3;
+        size.height -= 3;
+        Paragraph::new(text.iter()).render(&mut f, size);
+    })?;
+    write!(terminal.backend_mut(), "{}", cursor::Hide,)?;
+    io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn handle(app: &mut App, events: &mut Events) -> Result<()> {
+    if let Event::Input(input) = events.next()? {
+        match input {
+            Key::Char('f') | Key::Esc => app.screen = Screen::Editor,
+            Key::Char('j') | Key::Down => {
+                app.usages_cursor = (app.usages_cursor + 1).min(app.usages.len().saturating_sub(1));
+            }
+            Key::Char('k') | Key::Up => {
+                app.usages_cursor = app.usages_cursor.saturating_sub(1);
+            }
+            Key::Char('\n') => {
+                if let Some(node) = app
+                    .usages
+                    .get(app.usages_cursor)
+                    .and_then(|id| app.nodes.iter().find(|node| node.id == *id))
+                {
+                    app.cursor = node.position;
+                    app.screen = Screen::Editor;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Tables and buses the node uses, words defined by the last commit count as their ops.
+pub fn node_accesses(app: &App, node: &Node) -> Vec<(Shared, String, TableAccess)> {
+    let op = TextOp {
+        id: node.id,
+        op: node.op.to_owned(),
+    };
+    expand_word(&op, &app.ctx.words)
+        .iter()
+        .filter_map(|op| shared_access(&op.op))
+        .map(|(kind, name, access)| (kind, name.to_owned(), access))
+        .collect()
+}
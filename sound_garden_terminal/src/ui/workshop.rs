@@ -0,0 +1,68 @@
+//! Teaching workshops, which keep safety rails on until they are over.
+use super::{set_monitor, App};
+use crate::audio::Monitor;
+use crate::event::Events;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Feedback gain of workshops is capped by it, loops at 1 and above run away.
+const MAX_FEEDBACK_GAIN: f64 = 0.99;
+
+/// Safety rails for teaching workshops, set by the host in the file.
+/// Ops can't read files, recordings, exports, tables and the log aren't written
+/// and quitting asks for confirmation.
+#[derive(Serialize, Deserialize)]
+pub struct Workshop {
+    /// Output level cap in dB.
+    pub max_level: f64,
+    /// Capped at 0.99 to keep feedback loops from running away, sign is ignored.
+    max_feedback_gain: f64,
+    /// Rails are lifted after that many seconds since the start, or hold for the whole session.
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(skip, default = "Instant::now")]
+    start: Instant,
+    #[serde(skip, default)]
+    pub lifted: bool,
+}
+
+/// Put the rails on for the session, until the workshop is over.
+pub fn start(app: &mut App) {
+    if let Some(workshop) = &app.workshop {
+        app.ctx.allow_files = false;
+        // Negative gain would flip the bounds of feedback clamping.
+        let gain = workshop.max_feedback_gain.abs();
+        if gain.is_nan() || gain > MAX_FEEDBACK_GAIN {
+            log::warn!(
+                "Max feedback gain {} of the workshop should be below 1, using {}.",
+                workshop.max_feedback_gain,
+                MAX_FEEDBACK_GAIN
+            );
+        }
+        // NaN is replaced by the cap too.
+        app.ctx.max_feedback_gain = gain.min(MAX_FEEDBACK_GAIN);
+    }
+}
+
+/// Lift safety rails once the workshop is over, the next commit lets ops read files again.
+pub fn update(app: &mut App, monitor: &Mutex<Monitor>, events: &mut Events) {
+    let workshop = match app.workshop.as_mut() {
+        Some(workshop) if !workshop.lifted => workshop,
+        _ => return,
+    };
+    match workshop.duration {
+        Some(duration) if workshop.start.elapsed().as_secs_f64() >= duration => {}
+        _ => return,
+    }
+    workshop.lifted = true;
+    app.ctx.allow_files = true;
+    app.ctx.max_feedback_gain = f64::INFINITY;
+    app.quit_pending = false;
+    events.enable_exit_key();
+    set_monitor(app, monitor);
+    if let Some(journal) = &app.journal {
+        journal.lock().unwrap().allow_file();
+    }
+    app.notice = "Workshop is over, safety rails are lifted.".to_owned();
+}